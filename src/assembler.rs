@@ -1,19 +1,41 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, mem};
 
-use object::elf::{STB_GLOBAL, STT_NOTYPE, STT_SECTION};
+use object::elf::{
+    R_ARM_ABS32, R_ARM_CALL, R_ARM_JUMP24, R_ARM_LDC_PC_G0, R_ARM_THM_JUMP19, R_ARM_THM_JUMP24,
+    R_ARM_THM_PC11, R_ARM_THM_PC22, R_ARM_THM_PC9, SHN_ABS, STB_GLOBAL, STT_FUNC, STT_NOTYPE,
+    STT_SECTION,
+};
 
 use crate::{
     elf::{
+        attributes::{eabi_attribute_directive, Attributes},
+        byte_order::ByteOrder,
+        cfi::{cfi_directive, CallFrames},
+        dwarf::{DebugInfo, DebugSection},
         elf_writer::ElfWriter,
         section_data::{self, SectionData},
+        unwind::{unwind_directive, UnwindTables},
+    },
+    image::{write_image, OutputFormat},
+    lexer::{
+        instruction_set_directive,
+        it_block::ImplicitIt,
+        org_directive, org_padding,
+        symbolizer::{Scope, SymbolTable},
+        target::{target_directive, Target},
+        vfp::fpu_directive,
+        InstructionSet, Lexer,
     },
-    lexer::{symbolizer::SymbolTable, Lexer},
+    listing::{ListedSymbol, Listing},
+    source::Source,
+    token::{instruction::ConditionCode, instruction::Width, instruction_name::InstructionName},
     token::{Directive, Token},
     tokenizer::Tokenizer,
 };
 
+// Symbol, offset in its section, section and relocation type
 pub struct UnknownRefs {
-    pub refs: Vec<(String, u32, Section)>,
+    pub refs: Vec<(String, u32, Section, u32)>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -21,7 +43,6 @@ pub enum Section {
     Text,
     Data,
     Bss,
-    NotDefined,
 }
 
 impl Section {
@@ -30,7 +51,23 @@ impl Section {
             Section::Text => ".text".to_string(),
             Section::Data => ".data".to_string(),
             Section::Bss => ".bss".to_string(),
-            Section::NotDefined => panic!("Section not defined"),
+        }
+    }
+}
+
+// What the bytes from a mapping symbol on are, so disassemblers don't decode data as code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mapping {
+    Code(InstructionSet),
+    Data,
+}
+
+impl Mapping {
+    fn symbol_name(&self) -> &'static str {
+        match self {
+            Mapping::Code(InstructionSet::Arm) => "$a",
+            Mapping::Code(InstructionSet::Thumb) => "$t",
+            Mapping::Data => "$d",
         }
     }
 }
@@ -51,48 +88,223 @@ pub struct Assembler {
     current_section: Section,
     elf_writer: ElfWriter,
     buffer: Vec<u8>,
+    // Bytes of the sections switched away from, in the order they were opened. Going back to
+    // one continues it.
+    section_buffers: Vec<(Section, Vec<u8>)>,
     section_lookup_table: SectionLookupTable,
     symbol_lookup_table: SymbolLookupTable,
     section_symbol_lookup_table: SectionSymbolLookupTable,
     unknown_refs: UnknownRefs,
+    // References to defined symbols the linker resolves: .word operands, which flat images fill
+    // in with the address, and instructions reaching into another section
+    defined_refs: Vec<(String, u32, Section, u32)>,
+    // $a/$t/$d symbols, marking where ARM code, Thumb code and data start in each section
+    mapping_symbols: Vec<(String, u32, Section)>,
+    // What was last emitted in each section
+    mappings: HashMap<Section, Mapping>,
+    // Set with .eabi_attribute, they override the ones derived from the target
+    attributes: Attributes,
+    byte_order: ByteOrder,
+    listing: Option<Listing>,
+    format: OutputFormat,
+    // Load address of flat images, from --base or a leading .org
+    base: Option<u32>,
+    // Set once any section has bytes, see org_padding
+    started: bool,
+    // Line table of -g, the frames of the .cfi_* directives and the EHABI tables of .fnstart
+    debug_info: Option<DebugInfo>,
+    // Files and lines the rows of the line table point at
+    source: Option<Source>,
+    call_frames: CallFrames,
+    unwind_tables: UnwindTables,
+    // Section, offset, target section or symbol and type of the relocations of the DWARF and
+    // unwind sections
+    section_refs: Vec<(&'static str, u32, String, u32)>,
 }
 
 impl Assembler {
-    pub fn new(tokenizer: Tokenizer, symbol: SymbolTable) -> Self {
-        let lexer = Lexer::new(symbol.clone());
+    pub fn new(
+        tokenizer: Tokenizer,
+        symbol: SymbolTable,
+        implicit_it: ImplicitIt,
+        target: Target,
+    ) -> Self {
+        let mut lexer = Lexer::new(symbol.clone(), implicit_it);
+        lexer.set_target(target);
         Assembler {
             lexer,
             symbol_table: symbol,
             tokenizer,
-            current_section: Section::NotDefined,
+            // Code before any section directive goes to .text, as with GNU as
+            current_section: Section::Text,
             elf_writer: ElfWriter::new(),
             buffer: vec![],
+            section_buffers: vec![],
             section_lookup_table: SectionLookupTable(HashMap::new()),
             symbol_lookup_table: SymbolLookupTable(HashMap::new()),
             section_symbol_lookup_table: SectionSymbolLookupTable(HashMap::new()),
             unknown_refs: UnknownRefs { refs: vec![] },
+            defined_refs: vec![],
+            mapping_symbols: vec![],
+            mappings: HashMap::new(),
+            attributes: Attributes::new(),
+            byte_order: ByteOrder::default(),
+            listing: None,
+            format: OutputFormat::default(),
+            base: None,
+            started: false,
+            debug_info: None,
+            source: None,
+            call_frames: CallFrames::new(),
+            unwind_tables: UnwindTables::new(),
+            section_refs: vec![],
         }
     }
 
-    fn clear_buffer(&mut self) {
-        self.buffer.clear();
+    // Without --base, a leading .org gives the base of flat images, see org_padding
+    pub fn set_output_format(&mut self, format: OutputFormat, base: Option<u32>) {
+        self.format = format;
+        self.base = base;
+    }
+
+    pub fn set_listing(&mut self, listing: Listing) {
+        self.listing = Some(listing);
+    }
+
+    pub fn set_debug_info(&mut self, source: Source) {
+        self.debug_info = Some(DebugInfo::new(source.name()));
+        self.source = Some(source);
+    }
+
+    pub fn set_byte_order(&mut self, byte_order: ByteOrder) {
+        self.byte_order = byte_order;
+        self.lexer.set_byte_order(byte_order);
+        self.elf_writer.set_byte_order(byte_order);
     }
 
     pub fn assemble(&mut self, output: Option<&String>) {
         while !self.tokenizer.is_eof() {
             let line = self.tokenizer.consume_line();
+            let section = self.current_section.clone();
+            let offset = self.buffer.len();
+
             self.parse_line(line);
+            self.list_line(section, offset);
         }
-        self.create_current_section();
+        self.lexer.finish();
+        self.call_frames.finish();
+        self.unwind_tables.finish();
+        self.create_sections();
+        self.create_attributes_section();
+        self.create_debug_sections();
+        self.create_unwind_sections();
 
         self.create_symbol_entry();
+        self.write_listing();
 
-        self.commit_elf(output);
+        let output = output.cloned().unwrap_or("a.out".to_owned());
+        match self.format {
+            OutputFormat::Elf => self.commit_elf(output),
+            format => self.commit_image(format, &output),
+        }
     }
 
-    fn commit_elf(&mut self, output: Option<&String>) {
-        self.elf_writer
-            .write_elf(output.unwrap_or(&"a.out".to_owned()).to_owned())
+    // Flat images have no relocations, .text is followed by the word aligned .data
+    fn commit_image(&self, format: OutputFormat, output: &str) {
+        if let Some((name, _, _, _)) = self.unknown_refs.refs.first() {
+            panic!(
+                "{} is not defined, {:?} output needs every reference resolved",
+                name, format
+            );
+        }
+
+        let base = self.base.unwrap_or_else(|| {
+            panic!(
+                "{:?} output needs a base address, see --base or .org",
+                format
+            )
+        });
+
+        let mut image = self
+            .elf_writer
+            .section_bytes(".text")
+            .unwrap_or_default()
+            .to_vec();
+        let data_offset = image.len().next_multiple_of(4);
+        if let Some(data) = self.elf_writer.section_bytes(".data") {
+            image.resize(data_offset, 0);
+            image.extend(data);
+        }
+
+        let image_offset = |section: &Section| match section {
+            Section::Text => 0,
+            Section::Data => data_offset,
+            section => panic!("{:?} output has no {}", format, section.to_name()),
+        };
+        for (name, offset, section, _) in &self.defined_refs {
+            let symbol_offset = image_offset(self.symbol_table.get_section(name).unwrap()) as u32;
+            let value = self.symbol_table.get_address(name).unwrap().value;
+            let thumb_bit = self.symbol_table.is_thumb_function(name) as u32;
+            let address = (base + symbol_offset + value) | thumb_bit;
+
+            let place = image_offset(section) + *offset as usize;
+            image[place..place + 4].copy_from_slice(&self.byte_order.data_u32(address));
+        }
+
+        write_image(format, base, &image, output);
+    }
+
+    // Records the bytes the last line added to its section
+    fn list_line(&mut self, section: Section, offset: usize) {
+        let Some(listing) = &mut self.listing else {
+            return;
+        };
+
+        let bytes = if section == self.current_section {
+            &self.buffer[offset..]
+        } else {
+            &[]
+        };
+        listing.add_line(self.tokenizer.line_number(), offset as u32, bytes);
+    }
+
+    // Rewrites an IT generated for earlier instructions once the last line joined it
+    fn patch_it(&mut self, offset: usize, it: &[u8]) {
+        self.buffer[offset..offset + it.len()].copy_from_slice(it);
+        if let Some(listing) = &mut self.listing {
+            listing.patch(offset as u32, it);
+        }
+    }
+
+    fn write_listing(&self) {
+        let Some(listing) = &self.listing else {
+            return;
+        };
+
+        let mut defined: Vec<ListedSymbol> = self
+            .symbol_table
+            .iter()
+            .map(|(symbol, row)| ListedSymbol {
+                name: symbol.name.clone(),
+                section: row.section.to_name(),
+                value: row.address.value,
+                line: row.line,
+            })
+            .collect();
+        defined.sort_by_key(|symbol| symbol.line);
+
+        let mut undefined: Vec<String> = vec![];
+        for (name, _, _, _) in &self.unknown_refs.refs {
+            if !undefined.contains(name) {
+                undefined.push(name.clone());
+            }
+        }
+
+        listing.write(&defined, &undefined);
+    }
+
+    fn commit_elf(&mut self, output: String) {
+        self.elf_writer.write_elf(output)
     }
 
     fn parse_line(&mut self, line: Vec<Token>) {
@@ -100,87 +312,328 @@ impl Assembler {
             return;
         }
 
+        self.lexer.close_generated_it(&line);
+
         if let Token::DIRECTIVE(directive) = &line[0] {
             if is_section_directive(&directive.value) {
-                self.create_current_section();
-
                 self.change_section(directive);
             }
         }
 
-        self.find_unknown_refs(&line);
+        if let Some(instruction_set) = instruction_set_directive(&line) {
+            let padding = self.lexer.set_instruction_set(instruction_set);
+            self.buffer.extend(padding);
+        }
+
+        if let Some(fpu) = fpu_directive(&line) {
+            self.lexer.set_fpu(fpu);
+        }
+
+        if let Some(target) = target_directive(&line, self.lexer.target()) {
+            self.lexer.set_target(target);
+        }
+
+        if let Some((tag, value)) = eabi_attribute_directive(&line) {
+            self.attributes.set(tag, value);
+        }
+
+        if let Some(directive) = cfi_directive(&line) {
+            if self.current_section != Section::Text {
+                panic!("CFI directives are only supported in .text");
+            }
+            self.call_frames.apply(directive, self.buffer.len() as u32);
+        }
+
+        if let Some(directive) = unwind_directive(&line) {
+            if self.current_section != Section::Text {
+                panic!("Unwind directives are only supported in .text");
+            }
+            self.unwind_tables
+                .apply(directive, self.buffer.len() as u32);
+        }
+
+        if let Some(org) = org_directive(&line) {
+            let absolute = self.format != OutputFormat::Elf;
+            if absolute && self.current_section != Section::Text {
+                panic!(".org is only supported in .text of flat images");
+            }
+            let started = self.started || !self.buffer.is_empty();
+            let padding = org_padding(
+                org,
+                self.buffer.len() as u32,
+                started,
+                absolute,
+                &mut self.base,
+            );
+            self.buffer.extend(vec![0; padding as usize]);
+            self.lexer.increment_addr(padding);
+        }
 
         if has_instruction(&line) {
-            let op = self.lexer.parse_line(line).unwrap();
+            self.find_unknown_refs(&line);
+            self.add_mapping_symbol(Mapping::Code(self.lexer.instruction_set));
+            self.add_line_row();
 
-            self.buffer.extend(&op.to_machine_code().to_u8_buff());
+            let code = self.lexer.assemble_line(line);
+            self.buffer.extend(code);
+            if let Some((addr, it)) = self.lexer.take_it_patch() {
+                self.patch_it(addr as usize, &it);
+            }
         } else if has_word_directive(&line) {
-            self.parse_word_directive(&line);
+            let data = self.parse_word_directive(&line);
+            if self.unwind_tables.in_handler_data() {
+                self.unwind_tables.add_handler_data(data);
+            } else {
+                self.add_mapping_symbol(Mapping::Data);
+                self.lexer.increment_addr(data.len() as u32);
+                self.buffer.extend(data);
+            }
+        }
+    }
+
+    // Keeps the bytes of the current section until the sections are created
+    fn store_current_section(&mut self) {
+        self.started |= !self.buffer.is_empty();
+        let bytes = mem::take(&mut self.buffer);
+
+        let stored = self
+            .section_buffers
+            .iter_mut()
+            .find(|(section, _)| *section == self.current_section);
+        match stored {
+            Some((_, stored)) => *stored = bytes,
+            None => self
+                .section_buffers
+                .push((self.current_section.clone(), bytes)),
+        }
+    }
+
+    fn create_sections(&mut self) {
+        self.store_current_section();
+
+        for (section, bytes) in mem::take(&mut self.section_buffers) {
+            let section_data = section_data::SectionData::Bytes(bytes);
+            let id = self.elf_writer.add_section(section.to_name(), section_data);
+
+            self.section_symbol_lookup_table
+                .0
+                .insert(section.to_name(), id);
+
+            self.section_lookup_table.0.insert(section.to_name(), id);
         }
     }
 
-    fn create_current_section(&mut self) {
-        if self.current_section == Section::NotDefined {
+    fn create_attributes_section(&mut self) {
+        self.elf_writer
+            .set_flags(self.lexer.target().e_flags() | self.byte_order.e_flags());
+
+        let mut attributes = self.lexer.target().attributes();
+        attributes.extend(&self.attributes);
+        if attributes.is_empty() {
             return;
         }
-        let section_data = section_data::SectionData::Bytes(self.buffer.clone());
-        let id = self
-            .elf_writer
-            .add_section(self.current_section.to_name(), section_data);
 
-        self.section_symbol_lookup_table
-            .0
-            .insert(self.current_section.to_name(), id);
+        let _ = self.elf_writer.add_section(
+            ".ARM.attributes".to_string(),
+            SectionData::Bytes(attributes.to_bytes(self.byte_order)),
+        );
+    }
 
-        self.section_lookup_table
-            .0
-            .insert(self.current_section.to_name(), id);
+    // Before the symbol table, so the DWARF sections get section symbols to relocate against
+    fn create_debug_sections(&mut self) {
+        let mut sections: Vec<DebugSection> = vec![];
+        if let Some(debug_info) = self.debug_info.as_ref().filter(|info| !info.is_empty()) {
+            let text_size = self
+                .elf_writer
+                .section_bytes(".text")
+                .map_or(0, |bytes| bytes.len() as u32);
+            sections.extend(debug_info.sections(text_size, self.byte_order));
+        }
+        sections.extend(self.call_frames.sections(self.byte_order));
+
+        for section in sections {
+            let id = self
+                .elf_writer
+                .add_section(section.name.to_string(), SectionData::Bytes(section.bytes));
+            self.section_lookup_table
+                .0
+                .insert(section.name.to_string(), id);
+            self.section_symbol_lookup_table
+                .0
+                .insert(section.name.to_string(), id);
 
-        self.clear_buffer();
+            for (offset, target, r_type) in section.relocations {
+                self.section_refs
+                    .push((section.name, offset, target.to_string(), r_type));
+            }
+        }
     }
 
-    fn parse_word_directive(&mut self, line: &[Token]) -> Vec<u8> {
-        let words = line
-            .iter()
-            .filter(|token| token.is_number())
-            .map(|token| token.extract_number().unwrap().value)
-            .collect::<Vec<u32>>();
+    fn create_unwind_sections(&mut self) {
+        if self.unwind_tables.is_empty() {
+            return;
+        }
 
-        // Collect them in a u8 buffer
-        let mut buffer = vec![];
-        for word in words {
-            buffer.push((word >> 24) as u8);
-            buffer.push((word >> 16) as u8);
-            buffer.push((word >> 8) as u8);
-            buffer.push(word as u8);
+        let sections = self.unwind_tables.sections(self.byte_order);
+        for (name, bytes) in [
+            (".ARM.extab", sections.extab),
+            (".ARM.exidx", sections.exidx),
+        ] {
+            if bytes.is_empty() {
+                continue;
+            }
+            let id = self
+                .elf_writer
+                .add_section(name.to_string(), SectionData::Bytes(bytes));
+            self.section_lookup_table.0.insert(name.to_string(), id);
+            self.section_symbol_lookup_table
+                .0
+                .insert(name.to_string(), id);
+        }
+        self.section_refs.extend(sections.relocations);
+    }
+
+    fn add_line_row(&mut self) {
+        let (Some(debug_info), Some(source)) = (&mut self.debug_info, &self.source) else {
+            return;
+        };
+        if self.current_section == Section::Text {
+            let (file, line) = source.location(self.tokenizer.line_number());
+            debug_info.add_row(self.buffer.len() as u32, file, line);
         }
+    }
+
+    // Only at transitions, each section keeps its mapping while another one is entered
+    fn add_mapping_symbol(&mut self, mapping: Mapping) {
+        if self.mappings.get(&self.current_section) == Some(&mapping) {
+            return;
+        }
+
+        self.mapping_symbols.push((
+            mapping.symbol_name().to_string(),
+            self.buffer.len() as u32,
+            self.current_section.clone(),
+        ));
+        self.mappings.insert(self.current_section.clone(), mapping);
+    }
+
+    fn parse_word_directive(&mut self, line: &[Token]) -> Vec<u8> {
+        // Data follows the byte order of the object, even in BE8 code
+        let mut buffer = vec![];
+        for token in line {
+            match token {
+                Token::NUMBER(number) => buffer.extend(self.byte_order.data_u32(number.value)),
+                // The address of a symbol is left to R_ARM_ABS32, with an addend of 0
+                Token::LABELREF(label) => {
+                    if self.unwind_tables.in_handler_data() {
+                        panic!(".word {} can't be relocated in .handlerdata", label);
+                    }
 
-        self.lexer.increment_addr((buffer.len() * 4) as u32);
+                    let offset = (self.buffer.len() + buffer.len()) as u32;
+                    let reference = (
+                        label.clone(),
+                        offset,
+                        self.current_section.clone(),
+                        R_ARM_ABS32,
+                    );
+                    match self.symbol_table.get_address(label) {
+                        Some(_) => self.defined_refs.push(reference),
+                        None => self.unknown_refs.refs.push(reference),
+                    }
+                    buffer.extend(self.byte_order.data_u32(0));
+                }
+                _ => {}
+            }
+        }
 
         buffer
     }
 
+    // Going back to a section continues it where it was left
     fn change_section(&mut self, directive: &Directive) {
         let Directive { value } = directive;
-
-        match value.as_str() {
-            ".text" => self.current_section = Section::Text,
-            ".data" => self.current_section = Section::Data,
-            ".bss" => self.current_section = Section::Bss,
+        let section = match value.as_str() {
+            ".text" => Section::Text,
+            ".data" => Section::Data,
+            ".bss" => Section::Bss,
             _ => panic!("Unknown section directive"),
         };
+
+        self.store_current_section();
+        self.buffer = self
+            .section_buffers
+            .iter_mut()
+            .find(|(stored, _)| *stored == section)
+            .map(|(_, bytes)| mem::take(bytes))
+            .unwrap_or_default();
+        self.lexer.addr = self.buffer.len() as u32;
+        self.lexer.section = section.clone();
+        self.current_section = section;
     }
 
     fn find_unknown_refs(&mut self, tokens: &[Token]) {
         for token in tokens {
             if let Token::LABELREF(label) = token {
-                let unknown = self.symbol_table.get_address(label);
-                if unknown.is_none() {
-                    self.unknown_refs.refs.push((
+                let reference = || {
+                    (
                         label.clone(),
-                        self.lexer.addr,
+                        self.buffer.len() as u32,
                         self.current_section.clone(),
-                    ));
+                        self.relocation_type(tokens),
+                    )
+                };
+
+                match self.symbol_table.get_section(label) {
+                    None => self.unknown_refs.refs.push(reference()),
+                    Some(section) if *section == self.current_section => {}
+                    // The lexer only knows offsets within the section being assembled
+                    Some(section) if self.format != OutputFormat::Elf => panic!(
+                        "{} is in {}, {:?} output can't reach it from {}",
+                        label,
+                        section.to_name(),
+                        self.format,
+                        self.current_section.to_name()
+                    ),
+                    Some(_) => self.defined_refs.push(reference()),
+                }
+            }
+        }
+    }
+
+    // Branch relocation matching the encoding the lexer picks for the instruction
+    fn relocation_type(&self, tokens: &[Token]) -> u32 {
+        let instruction = tokens
+            .iter()
+            .find_map(|token| match token {
+                Token::INSTRUCTION(istr) => Some(*istr),
+                _ => None,
+            })
+            .expect("Expected an instruction");
+
+        let conditional = instruction.condition != ConditionCode::Al;
+
+        match self.lexer.instruction_set {
+            InstructionSet::Arm => match instruction.value {
+                InstructionName::VLDR | InstructionName::VSTR => R_ARM_LDC_PC_G0,
+                InstructionName::BL if !conditional => R_ARM_CALL,
+                InstructionName::B | InstructionName::BL => R_ARM_JUMP24,
+                // bl and blx, the linker picks the one matching the state of the target
+                _ => R_ARM_CALL,
+            },
+            InstructionSet::Thumb => {
+                let conditional = conditional && !self.lexer.in_it_block();
+                let narrow = instruction.width == Some(Width::Narrow);
+
+                match instruction.value {
+                    InstructionName::VLDR | InstructionName::VSTR => {
+                        panic!("vldr of an undefined label is only supported in ARM code")
+                    }
+                    InstructionName::B if narrow && conditional => R_ARM_THM_PC9,
+                    InstructionName::B if narrow => R_ARM_THM_PC11,
+                    InstructionName::B if conditional => R_ARM_THM_JUMP19,
+                    InstructionName::B => R_ARM_THM_JUMP24,
+                    // R_ARM_THM_CALL, the linker turns bl into blx for ARM targets and back
+                    _ => R_ARM_THM_PC22,
                 }
             }
         }
@@ -188,6 +641,7 @@ impl Assembler {
 
     fn create_symbol_entry(&mut self) {
         let mut section_data = SectionData::Symbols(vec![]);
+        let mut section_symbols = HashMap::new();
 
         for section_symbol in self.section_symbol_lookup_table.0.iter() {
             let section_id = self
@@ -198,7 +652,7 @@ impl Assembler {
                 .unwrap()
                 .to_owned();
 
-            let _ = section_data.add_symbol(
+            let symbol_id = section_data.add_symbol(
                 section_id.to_owned(),
                 section_symbol.0.clone(),
                 0,
@@ -206,9 +660,25 @@ impl Assembler {
                 STT_SECTION,
                 None,
             );
+            section_symbols.insert(section_symbol.0.clone(), symbol_id.unwrap());
+        }
+
+        for (name, offset, section) in &self.mapping_symbols {
+            let section_id = self.section_lookup_table.0[&section.to_name()];
+
+            let _ = section_data.add_symbol(section_id, name.clone(), *offset, 0, STT_NOTYPE, None);
+        }
+
+        // --defsym symbols are local and absolute, as GNU as makes them
+        for (name, value) in self.tokenizer.defined_symbols() {
+            let _ = section_data.add_symbol(0, name.clone(), *value, 0, STT_NOTYPE, Some(SHN_ABS));
         }
 
-        for symbol in self.symbol_table.iter() {
+        // Locals have to come before globals in the symbol table
+        let mut symbols: Vec<_> = self.symbol_table.iter().collect();
+        symbols.sort_by_key(|(symbol, row)| (matches!(row.scope, Scope::Global), &symbol.name));
+
+        for symbol in symbols {
             let section_id = self
                 .section_lookup_table
                 .clone()
@@ -217,17 +687,35 @@ impl Assembler {
                 .unwrap()
                 .to_owned();
 
-            let _ = section_data.add_symbol(
+            // Thumb functions have bit 0 of their address set
+            let (st_type, thumb_bit) = if symbol.1.thumb_function {
+                (STT_FUNC, 1)
+            } else {
+                (STT_NOTYPE, 0)
+            };
+            let st_info = match symbol.1.scope {
+                Scope::Global => STB_GLOBAL << 4 | st_type,
+                Scope::Local => st_type,
+            };
+
+            let symbol_id = section_data.add_symbol(
                 section_id.to_owned(),
                 symbol.0.name.clone(),
-                symbol.1.address.value,
+                symbol.1.address.value | thumb_bit,
                 0,
-                STT_NOTYPE,
+                st_info,
                 None,
             );
+            self.symbol_lookup_table
+                .0
+                .insert(symbol.0.name.clone(), symbol_id.unwrap());
         }
 
         for unknown_ref in &self.unknown_refs.refs {
+            if self.symbol_lookup_table.0.contains_key(&unknown_ref.0) {
+                continue;
+            }
+
             let symbol_id = section_data.add_symbol(
                 0,
                 unknown_ref.0.to_owned(),
@@ -242,29 +730,59 @@ impl Assembler {
                 .insert(unknown_ref.0.to_owned(), symbol_id.unwrap());
         }
 
+        // Personality routines the unwind tables refer to
+        for (_, _, target, _) in &self.section_refs {
+            if section_symbols.contains_key(target)
+                || self.symbol_lookup_table.0.contains_key(target)
+            {
+                continue;
+            }
+
+            let symbol_id = section_data.add_symbol(
+                0,
+                target.clone(),
+                0,
+                0,
+                STB_GLOBAL << 4 | STT_NOTYPE,
+                Some(0),
+            );
+            self.symbol_lookup_table
+                .0
+                .insert(target.clone(), symbol_id.unwrap());
+        }
+
         let _ = self
             .elf_writer
             .add_section(".symtab".to_string(), section_data);
 
-        let sections: Vec<Section> = self
+        let references: Vec<_> = self
             .unknown_refs
             .refs
-            .clone()
             .iter()
-            .map(|(_, _, section)| section.clone())
+            .chain(&self.defined_refs)
             .collect();
+        let mut sections: Vec<Section> = vec![];
+        for (_, _, section, _) in &references {
+            if !sections.contains(section) {
+                sections.push(section.clone());
+            }
+        }
 
         let mut sections_data = vec![];
 
         sections.iter().for_each(|section| {
             let mut section_data = SectionData::RelocationEntries(vec![]);
-            self.unknown_refs
-                .refs
+            references
                 .iter()
-                .filter(|(_, _, s)| s == section)
+                .filter(|(_, _, s, _)| s == section)
                 .for_each(|unknown_ref| {
                     let symbol_id = self.symbol_lookup_table.0.get(&unknown_ref.0).unwrap();
-                    section_data.add_relocation_entry(*symbol_id, unknown_ref.1, None, 28);
+                    section_data.add_relocation_entry(
+                        *symbol_id,
+                        unknown_ref.1,
+                        None,
+                        unknown_ref.3,
+                    );
                 });
 
             sections_data.push(section_data);
@@ -276,6 +794,31 @@ impl Assembler {
                 section_data.clone(),
             );
         }
+
+        // The DWARF and unwind sections point into .text and each other through their section
+        // symbols
+        let mut debug_sections: Vec<(&str, SectionData)> = vec![];
+        for (section, offset, target, r_type) in &self.section_refs {
+            let position = match debug_sections.iter().position(|(name, _)| name == section) {
+                Some(position) => position,
+                None => {
+                    debug_sections.push((section, SectionData::RelocationEntries(vec![])));
+                    debug_sections.len() - 1
+                }
+            };
+            let symbol_id = section_symbols
+                .get(target)
+                .unwrap_or_else(|| &self.symbol_lookup_table.0[target]);
+            debug_sections[position]
+                .1
+                .add_relocation_entry(*symbol_id, *offset, None, *r_type);
+        }
+
+        for (section, section_data) in debug_sections {
+            let _ = self
+                .elf_writer
+                .add_section(".rel".to_string() + section, section_data);
+        }
     }
 }
 
@@ -291,6 +834,332 @@ fn has_word_directive(line: &[Token]) -> bool {
     line.iter().any(|token| {
         token
             .extract_directive()
-            .map_or(false, |d| d.value == ".word")
+            .is_some_and(|d| d.value == ".word")
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use object::{Object, ObjectSection, ObjectSymbol};
+
+    use crate::{lexer::symbolizer::Symbolizer, reader::Reader};
+
+    use super::*;
+
+    // Bytes of the output assembled from text, named after the test to keep runs apart
+    fn assemble_source(name: &str, text: &str, format: OutputFormat, base: Option<u32>) -> Vec<u8> {
+        let output = env::temp_dir().join(format!("{}_{}.out", name, process::id()));
+
+        let tokenizer = Tokenizer::new(Reader::from_source(text));
+        let mut symbolizer =
+            Symbolizer::new(tokenizer.clone(), ImplicitIt::default(), Target::default());
+        symbolizer.set_output_format(format, base);
+        symbolizer.symbolize();
+        let mut assembler = Assembler::new(
+            tokenizer,
+            symbolizer.symbol_table,
+            ImplicitIt::default(),
+            Target::default(),
+        );
+        assembler.set_output_format(format, base);
+        assembler.assemble(Some(&output.to_str().unwrap().to_string()));
+
+        let bytes = fs::read(&output).unwrap();
+        fs::remove_file(output).unwrap();
+        bytes
+    }
+
+    fn text_section(object: &[u8]) -> Vec<u8> {
+        let file = object::File::parse(object).unwrap();
+        let text = file.section_by_name(".text").unwrap();
+        text.data().unwrap().to_vec()
+    }
+
+    const NOP: [u8; 4] = [0x00, 0xf0, 0x20, 0xe3];
+
+    // nop at offset and zeros up to it
+    fn padded_nops(offsets: &[usize]) -> Vec<u8> {
+        let mut bytes = vec![];
+        for offset in offsets {
+            bytes.resize(*offset, 0);
+            bytes.extend(NOP);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_consecutive_orgs() {
+        let source = ".text\n.org 0x1000\nnop\n.org 0x1010\nnop\n";
+
+        // Absolute in flat images, the first .org gives the base
+        let image = assemble_source("orgs_image", source, OutputFormat::Binary, None);
+        assert_eq!(image, padded_nops(&[0, 0x10]));
+
+        let object = assemble_source("orgs_elf", source, OutputFormat::Elf, None);
+        assert_eq!(text_section(&object), padded_nops(&[0x1000, 0x1010]));
+    }
+
+    #[test]
+    fn test_org_with_base() {
+        let base = Some(0x40000000);
+
+        let source = ".text\nnop\n.org 0x40000100\nnop\n";
+        let image = assemble_source("org_base_image", source, OutputFormat::Binary, base);
+        assert_eq!(image, padded_nops(&[0, 0x100]));
+
+        // --base only places flat images, objects keep .org as a section offset
+        let source = ".text\n.org 0x100\nnop\n";
+        let object = assemble_source("org_base_elf", source, OutputFormat::Elf, base);
+        assert_eq!(text_section(&object), padded_nops(&[0x100]));
+    }
+
+    #[test]
+    #[should_panic(expected = ".org can't move back")]
+    fn test_org_below_base() {
+        let source = ".text\n.org 0x100\nnop\n";
+        assemble_source(
+            "org_below_base",
+            source,
+            OutputFormat::Binary,
+            Some(0x40000000),
+        );
+    }
+
+    #[test]
+    fn test_word_of_symbols() {
+        let source = ".text\n\
+                      start: .word start, ext, 7, after\n\
+                      after: nop\n\
+                      .data\n\
+                      .word after\n";
+
+        // The words take their room in the first pass, the linker fills them in
+        let object = assemble_source("word_symbols_elf", source, OutputFormat::Elf, None);
+        let file = object::File::parse(&*object).unwrap();
+        let text = file.section_by_name(".text").unwrap();
+        let mut relocations: Vec<(u64, String)> = text
+            .relocations()
+            .map(|(offset, relocation)| {
+                let object::RelocationTarget::Symbol(index) = relocation.target() else {
+                    panic!("Expected a symbol");
+                };
+                let symbol = file.symbol_by_index(index).unwrap();
+                assert_eq!(
+                    relocation.flags(),
+                    object::RelocationFlags::Elf {
+                        r_type: R_ARM_ABS32
+                    }
+                );
+                (offset, symbol.name().unwrap().to_string())
+            })
+            .collect();
+        relocations.sort();
+        assert_eq!(
+            relocations,
+            [
+                (0, "start".to_string()),
+                (4, "ext".to_string()),
+                (12, "after".to_string())
+            ]
+        );
+        let mut expected = vec![0; 8];
+        expected.extend([7, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend(NOP);
+        assert_eq!(text_section(&object), expected);
+
+        // Flat images hold the addresses themselves
+        let source = source.replace(" ext,", "");
+        let image = assemble_source(
+            "word_symbols_image",
+            &source,
+            OutputFormat::Binary,
+            Some(0x1000),
+        );
+        let mut expected = vec![0x00, 0x10, 0, 0, 7, 0, 0, 0, 0x0c, 0x10, 0, 0];
+        expected.extend(NOP);
+        expected.extend([0x0c, 0x10, 0, 0]);
+        assert_eq!(image, expected);
+    }
+
+    #[test]
+    fn test_branch_to_other_section() {
+        let source = ".text\nbl data_thing\n.data\ndata_thing: .word 1\n";
+
+        // Relocated against the symbol, the offset between the sections is up to the linker
+        let object = assemble_source("other_section_elf", source, OutputFormat::Elf, None);
+        assert_eq!(text_section(&object), [0xfe, 0xff, 0xff, 0xeb]);
+        let file = object::File::parse(&*object).unwrap();
+        let text = file.section_by_name(".text").unwrap();
+        let (_, relocation) = text.relocations().next().unwrap();
+        let object::RelocationTarget::Symbol(index) = relocation.target() else {
+            panic!("Expected a symbol");
+        };
+        assert_eq!(
+            file.symbol_by_index(index).unwrap().name(),
+            Ok("data_thing")
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "data_thing is in .data, Binary output can't reach it from .text")]
+    fn test_image_branch_to_other_section() {
+        let source = ".text\nbl data_thing\n.data\ndata_thing: .word 1\n";
+        assemble_source(
+            "other_section_image",
+            source,
+            OutputFormat::Binary,
+            Some(0x1000),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "ADR target dlabel is in a different section")]
+    fn test_adr_other_section() {
+        let source = ".text\nadr r4, dlabel\n.data\ndlabel: .word 1\n";
+        assemble_source("adr_other_section", source, OutputFormat::Elf, None);
+    }
+
+    #[test]
+    fn test_thumb_label_width() {
+        let text = ".text\n\
+                    .thumb\n\
+                    back:\n\
+                    b back\n\
+                    b.n back\n\
+                    beq forward\n\
+                    cbz r0, forward\n\
+                    adr r1, forward\n\
+                    forward:\n\
+                    nop\n";
+        let bytes = assemble_source("thumb_label_width", text, OutputFormat::Elf, None);
+
+        // Label references stay wide even in range of the narrow encoding, unless .n says so
+        let expected = [
+            0xff, 0xf7, 0xfe, 0xbf, // b.w back
+            0xfc, 0xe7, // b.n back
+            0x00, 0xf0, 0x03, 0x80, // beq.w forward
+            0x08, 0xb1, // cbz r0, forward
+            0x0f, 0xf2, 0x00, 0x01, // adr.w r1, forward
+            0x00, 0xbf, // nop
+        ];
+        assert_eq!(text_section(&bytes), expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "Branch target out of range (+/-32MB)")]
+    fn test_branch_out_of_range() {
+        // one word past the furthest forward branch
+        let source = ".text\nb far\n.org 0x2000008\nfar: nop\n";
+        assemble_source("branch_out_of_range", source, OutputFormat::Elf, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "B can't change the instruction set, use BL, BLX or BX")]
+    fn test_branch_to_thumb_label() {
+        let source = ".text\n.arm\nb far\n.thumb\nnop\nfar: nop\n";
+        assemble_source("branch_to_thumb_label", source, OutputFormat::Elf, None);
+    }
+
+    #[test]
+    fn test_mapping_symbols() {
+        let text = ".text\n\
+                    mov r0, #1\n\
+                    .word 1\n\
+                    .word 2\n\
+                    .thumb\n\
+                    mov r1, r2\n\
+                    .arm\n\
+                    mov r0, r1\n\
+                    .data\n\
+                    .word 3\n";
+        let bytes = assemble_source("mapping", text, OutputFormat::Elf, None);
+
+        assert_mapping_symbols(
+            &bytes,
+            &[
+                (".data", "$d", 0),
+                (".text", "$a", 0),
+                (".text", "$a", 16),
+                (".text", "$d", 4),
+                (".text", "$t", 12),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_reopened_section() {
+        let text = ".text\n\
+                    mov r0, #1\n\
+                    .data\n\
+                    .word 1\n\
+                    .text\n\
+                    mov r1, #2\n\
+                    .word 2\n\
+                    .data\n\
+                    .word 3\n\
+                    .text\n\
+                    .word 4\n";
+        let bytes = assemble_source("reopened", text, OutputFormat::Elf, None);
+
+        // Going back to a section continues it, along with what it was mapped as
+        assert_eq!(text_section(&bytes).len(), 16);
+        assert_mapping_symbols(
+            &bytes,
+            &[(".data", "$d", 0), (".text", "$a", 0), (".text", "$d", 8)],
+        );
+    }
+
+    #[test]
+    fn test_no_section_directive() {
+        let text = "start: mov r0, #1\n\
+                    b start\n\
+                    .data\n\
+                    .word 1\n";
+        let bytes = assemble_source("no_section", text, OutputFormat::Elf, None);
+
+        // Without a directive the code goes to .text
+        assert_eq!(
+            text_section(&bytes),
+            [0x01, 0x00, 0xa0, 0xe3, 0xfd, 0xff, 0xff, 0xea]
+        );
+        assert_mapping_symbols(&bytes, &[(".data", "$d", 0), (".text", "$a", 0)]);
+    }
+
+    #[test]
+    fn test_cfi_before_section_directive() {
+        let text = ".cfi_sections .debug_frame\n\
+                    f:\n\
+                    .cfi_startproc\n\
+                    push {r4, lr}\n\
+                    .cfi_def_cfa_offset 8\n\
+                    pop {r4, pc}\n\
+                    .cfi_endproc\n";
+        let bytes = assemble_source("cfi_no_section", text, OutputFormat::Elf, None);
+
+        let file = object::File::parse(&*bytes).unwrap();
+        assert!(file.section_by_name(".debug_frame").is_some());
+        assert_eq!(text_section(&bytes).len(), 8);
+    }
+
+    // Section, name and address of the $a/$t/$d symbols, sorted
+    fn assert_mapping_symbols(object: &[u8], expected: &[(&str, &str, u64)]) {
+        let file = object::File::parse(object).unwrap();
+        let mut symbols: Vec<(&str, &str, u64)> = file
+            .symbols()
+            .filter(|symbol| symbol.name().unwrap().starts_with('$'))
+            .map(|symbol| {
+                let section = file.section_by_index(symbol.section_index().unwrap());
+                (
+                    section.unwrap().name().unwrap(),
+                    symbol.name().unwrap(),
+                    symbol.address(),
+                )
+            })
+            .collect();
+        symbols.sort();
+
+        assert_eq!(symbols, expected);
+    }
+}
@@ -0,0 +1,875 @@
+// A32 words back into the operations the assembler builds, the inverse of
+// CpuOperation::to_machine_code. Encodings the assembler can't produce decode to None, as do
+// the VFP and multiply instructions.
+
+use crate::{
+    lexer::{
+        cpu_op::CpuOperation,
+        expression::{
+            barrel_shifter::{
+                BarrealShifterShiftAmount, BarrelShifterExpression, BarrelShifterOperation,
+            },
+            coprocessor::{CoprocessorExpression, CoprocessorLoadStoreExpression},
+            cps::CpsExpression,
+            immediate::ImmediateExpression,
+            ls_exclusive::LoadStoreExclusiveExpression,
+            ls_imm_index::{IndexMode, LoadStoreImmediateExpression, PreIndex},
+            ls_multiple::LoadStoreMultipleExpression,
+            ls_reg_index::LoadStoreRegisterExpression,
+            media::MediaExpression,
+            reg::RegExpression,
+            reg_literal::{check_immediate_possible, RegLiteralExpression},
+            status_register::StatusRegisterExpression,
+            three_regs::ThreeRegsExpression,
+            two_regs::TwoRegsExpression,
+            two_regs_literal::TwoRegsLiteralExpression,
+            Expression,
+        },
+        media::{extend_operation, parallel_operation, parallel_prefix},
+    },
+    token::{
+        immediate::Immediate,
+        instruction::{ConditionCode, Instruction},
+        instruction_name::InstructionName,
+        register::{Register, StatusRegister, StatusRegisterName},
+    },
+};
+
+const CONDITIONS: [ConditionCode; 15] = [
+    ConditionCode::Eq,
+    ConditionCode::Ne,
+    ConditionCode::Cs,
+    ConditionCode::Cc,
+    ConditionCode::Mi,
+    ConditionCode::Pl,
+    ConditionCode::Vs,
+    ConditionCode::Vc,
+    ConditionCode::Hi,
+    ConditionCode::Ls,
+    ConditionCode::Ge,
+    ConditionCode::Lt,
+    ConditionCode::Gt,
+    ConditionCode::Le,
+    ConditionCode::Al,
+];
+
+const PROC_OPERATIONS: [InstructionName; 16] = {
+    use InstructionName::*;
+    [
+        AND, EOR, SUB, RSB, ADD, ADC, SBC, RSC, TST, TEQ, CMP, CMN, ORR, MOV, BIC, MVN,
+    ]
+};
+
+const PARALLEL_OPERATIONS: [InstructionName; 36] = {
+    use InstructionName::*;
+    [
+        SADD16, SASX, SSAX, SSUB16, SADD8, SSUB8, QADD16, QASX, QSAX, QSUB16, QADD8, QSUB8,
+        SHADD16, SHASX, SHSAX, SHSUB16, SHADD8, SHSUB8, UADD16, UASX, USAX, USUB16, UADD8, USUB8,
+        UQADD16, UQASX, UQSAX, UQSUB16, UQADD8, UQSUB8, UHADD16, UHASX, UHSAX, UHSUB16, UHADD8,
+        UHSUB8,
+    ]
+};
+
+// Extends as (without, with) an accumulator
+const EXTEND_OPERATIONS: [(InstructionName, InstructionName); 6] = {
+    use InstructionName::*;
+    [
+        (SXTB16, SXTAB16),
+        (SXTB, SXTAB),
+        (SXTH, SXTAH),
+        (UXTB16, UXTAB16),
+        (UXTB, UXTAB),
+        (UXTH, UXTAH),
+    ]
+};
+
+fn bits(word: u32, high: u32, low: u32) -> u32 {
+    (word >> low) & ((1 << (high - low + 1)) - 1)
+}
+
+fn bit(word: u32, position: u32) -> bool {
+    word >> position & 1 == 1
+}
+
+fn reg(word: u32, low: u32) -> Register {
+    Register::from_num(bits(word, low + 3, low) as u8).unwrap()
+}
+
+fn immediate(value: u32) -> Immediate {
+    Immediate::new(format!("0x{:x}", value)).unwrap()
+}
+
+fn operation(value: InstructionName, condition: ConditionCode, set_flags: bool) -> Instruction {
+    Instruction {
+        value,
+        set_flags,
+        condition,
+        width: None,
+        data_type: None,
+        source_type: None,
+    }
+}
+
+pub fn decode(word: u32) -> Option<CpuOperation> {
+    let condition = match word >> 28 {
+        0xf => return decode_unconditional(word),
+        condition => CONDITIONS[condition as usize],
+    };
+
+    match bits(word, 27, 25) {
+        0b000 => decode_misc(word, condition)
+            .or_else(|| decode_extra_load_store(word, condition))
+            .or_else(|| decode_proc(word, condition)),
+        0b001 => decode_status_immediate(word, condition).or_else(|| decode_proc(word, condition)),
+        0b010 => decode_load_store(word, condition),
+        0b011 if !bit(word, 4) => decode_load_store(word, condition),
+        0b011 => decode_media(word, condition),
+        0b100 => decode_load_store_multiple(word, condition),
+        0b101 => {
+            let name = match bit(word, 24) {
+                true => InstructionName::BL,
+                false => InstructionName::B,
+            };
+            let offset = ((word << 8) as i32 >> 6) as u32;
+            Some(CpuOperation::new(
+                operation(name, condition, false),
+                Expression::Immediate(ImmediateExpression::new(immediate(offset))),
+            ))
+        }
+        // coprocessors 10 and 11 are the VFP
+        0b110 if bits(word, 11, 9) == 0b101 => None,
+        0b111 if bits(word, 11, 9) == 0b101 && !bit(word, 24) => None,
+        0b111 if bit(word, 24) => Some(CpuOperation::new(
+            operation(InstructionName::SVC, condition, false),
+            Expression::Immediate(ImmediateExpression::new(immediate(bits(word, 23, 0)))),
+        )),
+        _ => decode_coprocessor(word, condition, false),
+    }
+}
+
+fn decode_unconditional(word: u32) -> Option<CpuOperation> {
+    use InstructionName::*;
+    let al = ConditionCode::Al;
+
+    // blx label, bit 1 of the offset is the H bit
+    if bits(word, 27, 25) == 0b101 {
+        let offset = ((word << 8) as i32 >> 6) as u32 | (bit(word, 24) as u32) << 1;
+        return Some(CpuOperation::new(
+            operation(BLX, al, false),
+            Expression::Immediate(ImmediateExpression::new(immediate(offset))),
+        ));
+    }
+
+    if word & 0xfff1fe20 == 0xf1000000 {
+        let mode = bit(word, 17).then(|| bits(word, 4, 0) as u8);
+        if mode.is_none() && bits(word, 4, 0) != 0 {
+            return None;
+        }
+        let flags = bits(word, 8, 6) as u8;
+        let name = match (bits(word, 19, 18), mode, flags) {
+            (0b10, _, 1..) => CPSIE,
+            (0b11, _, 1..) => CPSID,
+            (0b00, Some(_), 0) => CPS,
+            _ => return None,
+        };
+        return Some(CpuOperation::new(
+            operation(name, al, false),
+            Expression::ChangeProcessorState(CpsExpression::new(flags, mode)),
+        ));
+    }
+
+    if word == 0xf57ff01f {
+        return Some(CpuOperation::new(
+            operation(CLREX, al, false),
+            Expression::Empty,
+        ));
+    }
+
+    if word & 0xffffffc0 == 0xf57ff040 {
+        let name = match bits(word, 5, 4) {
+            0b00 => DSB,
+            0b01 => DMB,
+            0b10 => ISB,
+            _ => return None,
+        };
+        return Some(CpuOperation::new(
+            operation(name, al, false),
+            Expression::Immediate(ImmediateExpression::new(immediate(bits(word, 3, 0)))),
+        ));
+    }
+
+    if bits(word, 27, 26) == 0b11 && bits(word, 27, 24) != 0b1111 && bits(word, 11, 9) != 0b101 {
+        return decode_coprocessor(word, al, true);
+    }
+
+    None
+}
+
+// Branch and exchange, status register moves, bkpt and the saturating additions
+fn decode_misc(word: u32, condition: ConditionCode) -> Option<CpuOperation> {
+    use InstructionName::*;
+
+    let name = match word & 0x0ffffff0 {
+        0x012fff10 => Some(BX),
+        0x012fff20 => Some(BXJ),
+        0x012fff30 => Some(BLX),
+        _ => None,
+    };
+    if let Some(name) = name {
+        if name == BLX && bits(word, 3, 0) == 15 {
+            return None;
+        }
+        return Some(CpuOperation::new(
+            operation(name, condition, false),
+            Expression::Register(RegExpression::new(reg(word, 0))),
+        ));
+    }
+
+    if word & 0x0fbf0fff == 0x010f0000 {
+        return Some(CpuOperation::new(
+            operation(MRS, condition, false),
+            Expression::StatusRegister(StatusRegisterExpression::new(
+                status_register(word, None),
+                Some(reg(word, 12)),
+                None,
+            )),
+        ));
+    }
+
+    if word & 0x0fb0fff0 == 0x0120f000 && bits(word, 19, 16) != 0 {
+        let fields = Some(bits(word, 19, 16) as u8);
+        return Some(CpuOperation::new(
+            operation(MSR, condition, false),
+            Expression::StatusRegister(StatusRegisterExpression::new(
+                status_register(word, fields),
+                Some(reg(word, 0)),
+                None,
+            )),
+        ));
+    }
+
+    if word & 0x0ff000f0 == 0x01200070 && condition == ConditionCode::Al {
+        let value = bits(word, 19, 8) << 4 | bits(word, 3, 0);
+        return Some(CpuOperation::new(
+            operation(BKPT, condition, false),
+            Expression::Immediate(ImmediateExpression::new(immediate(value))),
+        ));
+    }
+
+    if word & 0x0f900ff0 == 0x01000050 {
+        let name = [QADD, QSUB, QDADD, QDSUB][bits(word, 22, 21) as usize];
+        let registers = vec![reg(word, 12), reg(word, 0), reg(word, 16)];
+        if registers.iter().any(|reg| reg.to_num() == 15) {
+            return None;
+        }
+        return Some(CpuOperation::new(
+            operation(name, condition, false),
+            Expression::Media(MediaExpression::new(registers, None, None)),
+        ));
+    }
+
+    if word & 0x0f8003f0 == 0x01800290 || word & 0x0f8000f0 == 0x01800090 {
+        return decode_exclusive(word, condition);
+    }
+
+    None
+}
+
+fn status_register(word: u32, fields: Option<u8>) -> StatusRegister {
+    let register = match bit(word, 22) {
+        true => StatusRegisterName::SPSR,
+        false => StatusRegisterName::CPSR,
+    };
+    StatusRegister { register, fields }
+}
+
+// msr with an immediate, and the hints that share its encoding with an empty field mask
+fn decode_status_immediate(word: u32, condition: ConditionCode) -> Option<CpuOperation> {
+    use InstructionName::*;
+
+    if word & 0x0fb0f000 != 0x0320f000 {
+        return None;
+    }
+
+    let fields = bits(word, 19, 16);
+    if fields == 0 {
+        let name = match word & 0x0fffffff {
+            0x0320f000 => NOP,
+            0x0320f001 => YIELD,
+            0x0320f002 => WFE,
+            0x0320f003 => WFI,
+            0x0320f004 => SEV,
+            _ => return None,
+        };
+        return Some(CpuOperation::new(
+            operation(name, condition, false),
+            Expression::Empty,
+        ));
+    }
+
+    let value = bits(word, 7, 0).rotate_right(bits(word, 11, 8) * 2);
+    if check_immediate_possible(value) != Some((bits(word, 11, 8) as u8, bits(word, 7, 0) as u8)) {
+        return None;
+    }
+
+    Some(CpuOperation::new(
+        operation(MSR, condition, false),
+        Expression::StatusRegister(StatusRegisterExpression::new(
+            status_register(word, Some(fields as u8)),
+            None,
+            Some(immediate(value)),
+        )),
+    ))
+}
+
+fn decode_proc(word: u32, condition: ConditionCode) -> Option<CpuOperation> {
+    use InstructionName::*;
+
+    let name = PROC_OPERATIONS[bits(word, 24, 21) as usize];
+    let set_flags = bit(word, 20);
+    let (rd, rn) = (reg(word, 12), reg(word, 16));
+
+    let compare = matches!(name, TST | TEQ | CMP | CMN);
+    let single = matches!(name, MOV | MVN);
+    // the compares without S are other instructions, and unused fields must be zero
+    if (compare && (!set_flags || rd.to_num() != 0)) || (single && rn.to_num() != 0) {
+        return None;
+    }
+
+    let instruction = operation(name, condition, set_flags);
+
+    if bit(word, 25) {
+        let (rotation, byte) = (bits(word, 11, 8), bits(word, 7, 0));
+        let value = byte.rotate_right(rotation * 2);
+        let (literal, rotation) = match check_immediate_possible(value) {
+            Some(canonical) if canonical == (rotation as u8, byte as u8) => {
+                (immediate(value), None)
+            }
+            _ => (immediate(byte), Some(immediate(rotation * 2))),
+        };
+
+        let expression = match (compare, single) {
+            (true, _) => Expression::RegLiteral(RegLiteralExpression::new(rn, literal, rotation)),
+            (_, true) => Expression::RegLiteral(RegLiteralExpression::new(rd, literal, rotation)),
+            _ => {
+                Expression::TwoRegsLiteral(TwoRegsLiteralExpression::new(rd, rn, literal, rotation))
+            }
+        };
+        return Some(CpuOperation::new(instruction, expression));
+    }
+
+    // bit 7 set with a register shift is the multiply and extra load/store space, and pc
+    // can't take part in a register shift
+    let pc = [0, 8, 12, 16]
+        .iter()
+        .any(|low| bits(word, low + 3, *low) == 15);
+    if bit(word, 4) && (bit(word, 7) || pc) {
+        return None;
+    }
+
+    let shift = operand_shift(word);
+    let rm = reg(word, 0);
+    let expression = match (compare, single) {
+        (true, _) => Expression::TwoRegs(TwoRegsExpression::new(rn, rm, shift)),
+        (_, true) => Expression::TwoRegs(TwoRegsExpression::new(rd, rm, shift)),
+        _ => Expression::ThreeRegs(ThreeRegsExpression::new(rd, rn, rm, shift)),
+    };
+    Some(CpuOperation::new(instruction, expression))
+}
+
+// Shift of a register operand, by an immediate or, with bit 4 set, by a register
+fn operand_shift(word: u32) -> Option<BarrelShifterExpression> {
+    use BarrelShifterOperation::*;
+
+    let operation = [LSL, LSR, ASR, ROR][bits(word, 6, 5) as usize];
+
+    if bit(word, 4) {
+        return Some(BarrelShifterExpression {
+            operation,
+            shift_amount: BarrealShifterShiftAmount::Register(reg(word, 8)),
+        });
+    }
+
+    // lsr #32 and asr #32 are encoded as 0, ror #0 is rrx
+    let amount = bits(word, 11, 7) as u8;
+    let (operation, amount) = match (operation, amount) {
+        (LSL, 0) => return None,
+        (LSR | ASR, 0) => (operation, 32),
+        (ROR, 0) => (RRX, 0),
+        _ => (operation, amount),
+    };
+
+    Some(BarrelShifterExpression {
+        operation,
+        shift_amount: BarrealShifterShiftAmount::Number(amount),
+    })
+}
+
+fn index_mode(word: u32) -> IndexMode {
+    match bit(word, 24) {
+        true => IndexMode::Pre(PreIndex {
+            write_back: bit(word, 21),
+        }),
+        false => IndexMode::Post,
+    }
+}
+
+fn decode_load_store(word: u32, condition: ConditionCode) -> Option<CpuOperation> {
+    use InstructionName::*;
+
+    // post-indexed with W set are the unprivileged ldrt and strt
+    let unprivileged = !bit(word, 24) && bit(word, 21);
+    let name = match (bit(word, 20), bit(word, 22), unprivileged) {
+        (true, false, false) => LDR,
+        (true, true, false) => LDRB,
+        (false, false, false) => STR,
+        (false, true, false) => STRB,
+        (true, false, true) => LDRT,
+        (true, true, true) => LDRBT,
+        (false, false, true) => STRT,
+        (false, true, true) => STRBT,
+    };
+    let instruction = operation(name, condition, false);
+    let (rt, rn) = (reg(word, 12), reg(word, 16));
+    let up = bit(word, 23);
+    let index_mode = index_mode(word);
+
+    if bit(word, 25) {
+        // only shifts by an immediate
+        let shift = operand_shift(word & !(1 << 4));
+        return Some(CpuOperation::new(
+            instruction,
+            Expression::LoadStoreRegister(LoadStoreRegisterExpression::new(
+                rt,
+                rn,
+                reg(word, 0),
+                !up,
+                index_mode,
+                shift,
+            )),
+        ));
+    }
+
+    let offset = bits(word, 11, 0);
+    load_store_immediate(instruction, rt, rn, offset, up, index_mode)
+}
+
+fn load_store_immediate(
+    instruction: Instruction,
+    rt: Register,
+    rn: Register,
+    offset: u32,
+    up: bool,
+    index_mode: IndexMode,
+) -> Option<CpuOperation> {
+    // #-0 has no expression
+    if !up && offset == 0 {
+        return None;
+    }
+
+    let (offset, index_mode) = match index_mode {
+        IndexMode::Pre(PreIndex { write_back: false }) if offset == 0 => (None, IndexMode::None),
+        _ if up => (Some(immediate(offset)), index_mode),
+        _ => (Some(immediate(offset.wrapping_neg())), index_mode),
+    };
+
+    Some(CpuOperation::new(
+        instruction,
+        Expression::LoadStoreImmediate(LoadStoreImmediateExpression::new(
+            rt, rn, offset, index_mode,
+        )),
+    ))
+}
+
+// ldrh, strh, ldrsb, ldrsh, ldrd and strd
+fn decode_extra_load_store(word: u32, condition: ConditionCode) -> Option<CpuOperation> {
+    use InstructionName::*;
+
+    if !bit(word, 7) || !bit(word, 4) || bits(word, 6, 5) == 0 {
+        return None;
+    }
+    if !bit(word, 24) && bit(word, 21) {
+        return None;
+    }
+
+    let name = match (bits(word, 6, 5), bit(word, 20)) {
+        (0b01, false) => STRH,
+        (0b01, true) => LDRH,
+        (0b10, false) => LDRD,
+        (0b10, true) => LDRSB,
+        (0b11, false) => STRD,
+        (0b11, true) => LDRSH,
+        _ => return None,
+    };
+    let instruction = operation(name, condition, false);
+    let (rt, rn) = (reg(word, 12), reg(word, 16));
+    let up = bit(word, 23);
+
+    if bit(word, 22) {
+        let offset = bits(word, 11, 8) << 4 | bits(word, 3, 0);
+        return load_store_immediate(instruction, rt, rn, offset, up, index_mode(word));
+    }
+
+    if bits(word, 11, 8) != 0 {
+        return None;
+    }
+    Some(CpuOperation::new(
+        instruction,
+        Expression::LoadStoreRegister(LoadStoreRegisterExpression::new(
+            rt,
+            rn,
+            reg(word, 0),
+            !up,
+            index_mode(word),
+            None,
+        )),
+    ))
+}
+
+fn decode_exclusive(word: u32, condition: ConditionCode) -> Option<CpuOperation> {
+    use InstructionName::*;
+
+    if bits(word, 11, 10) != 0b11 {
+        return None;
+    }
+
+    let load = bit(word, 20);
+    let names = match bits(word, 9, 8) {
+        0b11 => [
+            (LDREX, STREX),
+            (LDREXD, STREXD),
+            (LDREXB, STREXB),
+            (LDREXH, STREXH),
+        ],
+        0b10 => [
+            (LDAEX, STLEX),
+            (LDAEXD, STLEXD),
+            (LDAEXB, STLEXB),
+            (LDAEXH, STLEXH),
+        ],
+        0b00 if bits(word, 22, 21) != 0b01 => [(LDA, STL), (LDA, STL), (LDAB, STLB), (LDAH, STLH)],
+        _ => return None,
+    };
+    let (load_name, store_name) = names[bits(word, 22, 21) as usize];
+    let double = bits(word, 22, 21) == 0b01;
+    let exclusive = bits(word, 9, 8) != 0;
+    let base = reg(word, 16);
+
+    let second = |rt: Register| {
+        double
+            .then(|| Register::from_num(rt.to_num() + 1))
+            .flatten()
+    };
+
+    let (name, expression) = match (load, exclusive) {
+        (true, _) if bits(word, 3, 0) == 0xf => {
+            let rt = reg(word, 12);
+            (
+                load_name,
+                LoadStoreExclusiveExpression::new(None, rt, second(rt), base),
+            )
+        }
+        (false, true) => {
+            let rt = reg(word, 0);
+            (
+                store_name,
+                LoadStoreExclusiveExpression::new(Some(reg(word, 12)), rt, second(rt), base),
+            )
+        }
+        (false, false) if bits(word, 15, 12) == 0xf => {
+            let rt = reg(word, 0);
+            (
+                store_name,
+                LoadStoreExclusiveExpression::new(None, rt, None, base),
+            )
+        }
+        _ => return None,
+    };
+
+    // the same restrictions as load_store_op, pairs start at an even register below r14 and
+    // the status register is a different one
+    let destination = expression.destination.to_num();
+    let registers: Vec<Register> = [Some(expression.destination), expression.destination2]
+        .into_iter()
+        .flatten()
+        .chain([base])
+        .collect();
+    let status = expression.status;
+    let pc = registers
+        .iter()
+        .chain(&status)
+        .any(|reg| reg.to_num() == 15);
+    if pc
+        || double && (destination % 2 == 1 || destination == 14)
+        || status.is_some_and(|status| registers.contains(&status))
+    {
+        return None;
+    }
+
+    Some(CpuOperation::new(
+        operation(name, condition, false),
+        Expression::LoadStoreExclusive(expression),
+    ))
+}
+
+fn decode_load_store_multiple(word: u32, condition: ConditionCode) -> Option<CpuOperation> {
+    use InstructionName::*;
+
+    let name = match (bit(word, 20), bit(word, 24), bit(word, 23)) {
+        (true, false, true) => LDMIA,
+        (true, true, true) => LDMIB,
+        (true, false, false) => LDMDA,
+        (true, true, false) => LDMDB,
+        (false, false, true) => STMIA,
+        (false, true, true) => STMIB,
+        (false, false, false) => STMDA,
+        (false, true, false) => STMDB,
+    };
+
+    if bits(word, 19, 16) == 15 {
+        return None;
+    }
+
+    let registers: Vec<Register> = (0..16)
+        .filter(|number| bit(word, *number))
+        .map(|number| Register::from_num(number as u8).unwrap())
+        .collect();
+    if registers.is_empty() {
+        return None;
+    }
+
+    Some(CpuOperation::new(
+        operation(name, condition, false),
+        Expression::LoadStoreMultiple(LoadStoreMultipleExpression::new(
+            reg(word, 16),
+            registers,
+            bit(word, 21),
+            bit(word, 22),
+        )),
+    ))
+}
+
+// Parallel add and subtract, saturation, packing, extends, usad8 and the divides
+fn decode_media(word: u32, condition: ConditionCode) -> Option<CpuOperation> {
+    use InstructionName::*;
+
+    if word & 0x0ff000f0 == 0x07f000f0 {
+        if condition != ConditionCode::Al {
+            return None;
+        }
+        let value = bits(word, 19, 8) << 4 | bits(word, 3, 0);
+        return Some(CpuOperation::new(
+            operation(UDF, condition, false),
+            Expression::Immediate(ImmediateExpression::new(immediate(value))),
+        ));
+    }
+
+    let (rd, rn, rm) = (reg(word, 12), reg(word, 16), reg(word, 0));
+    let media = |name, registers: Vec<Register>, saturate, shift| {
+        if registers.iter().any(|reg| reg.to_num() == 15) {
+            return None;
+        }
+        Some(CpuOperation::new(
+            operation(name, condition, false),
+            Expression::Media(MediaExpression::new(registers, saturate, shift)),
+        ))
+    };
+    let shift = |operation, amount: u32| {
+        Some(BarrelShifterExpression {
+            operation,
+            shift_amount: BarrealShifterShiftAmount::Number(amount as u8),
+        })
+    };
+
+    if word & 0x0f800f10 == 0x06000f10 {
+        let (op1, op2) = (bits(word, 22, 20), bits(word, 7, 5));
+        let name = PARALLEL_OPERATIONS
+            .iter()
+            .find(|name| parallel_prefix(name).0 == op1 && parallel_operation(name).0 == op2)?;
+        return media(*name, vec![rd, rn, rm], None, None);
+    }
+
+    if word & 0x0fa00030 == 0x06a00010 {
+        let position = bits(word, 20, 16);
+        let (name, saturate) = match bit(word, 22) {
+            false => (SSAT, position + 1),
+            true => (USAT, position),
+        };
+        let amount = bits(word, 11, 7);
+        let shift = match (bit(word, 6), amount) {
+            (false, 0) => None,
+            (false, _) => shift(BarrelShifterOperation::LSL, amount),
+            (true, 0) => shift(BarrelShifterOperation::ASR, 32),
+            (true, _) => shift(BarrelShifterOperation::ASR, amount),
+        };
+        return media(name, vec![rd, rm], Some(saturate), shift);
+    }
+
+    if word & 0x0fb00ff0 == 0x06a00f30 {
+        let position = bits(word, 19, 16);
+        let (name, saturate) = match bit(word, 22) {
+            false => (SSAT16, position + 1),
+            true => (USAT16, position),
+        };
+        return media(name, vec![rd, rm], Some(saturate), None);
+    }
+
+    if word & 0x0ff00030 == 0x06800010 {
+        let amount = bits(word, 11, 7);
+        let (name, shift) = match (bit(word, 6), amount) {
+            (false, 0) => (PKHBT, None),
+            (false, _) => (PKHBT, shift(BarrelShifterOperation::LSL, amount)),
+            (true, 0) => (PKHTB, shift(BarrelShifterOperation::ASR, 32)),
+            (true, _) => (PKHTB, shift(BarrelShifterOperation::ASR, amount)),
+        };
+        return media(name, vec![rd, rn, rm], None, shift);
+    }
+
+    if word & 0x0ff00ff0 == 0x06800fb0 {
+        return media(SEL, vec![rd, rn, rm], None, None);
+    }
+
+    if word & 0x0f8003f0 == 0x06800070 {
+        let (without, with) = EXTEND_OPERATIONS
+            .iter()
+            .find(|(name, _)| extend_operation(name).0 == bits(word, 22, 20))?;
+        let rotation = bits(word, 11, 10) * 8;
+        let rotation = (rotation != 0)
+            .then(|| shift(BarrelShifterOperation::ROR, rotation))
+            .flatten();
+        return match rn.to_num() {
+            15 => media(*without, vec![rd, rm], None, rotation),
+            _ => media(*with, vec![rd, rn, rm], None, rotation),
+        };
+    }
+
+    // sdiv rd, rn, rm and udiv rd, rn, rm, rd is in bits 19:16 as well
+    if word & 0x0fd0f0f0 == 0x0710f010 {
+        let name = if word & 1 << 21 == 0 { SDIV } else { UDIV };
+        return media(
+            name,
+            vec![reg(word, 16), reg(word, 0), reg(word, 8)],
+            None,
+            None,
+        );
+    }
+
+    // usad8 rd, rn, rm and usada8 rd, rn, rm, ra, rd is in bits 19:16
+    if word & 0x0ff000f0 == 0x07800010 {
+        let (rd, rn, rm, ra) = (reg(word, 16), reg(word, 0), reg(word, 8), reg(word, 12));
+        return match ra.to_num() {
+            15 => media(USAD8, vec![rd, rn, rm], None, None),
+            _ => media(USADA8, vec![rd, rn, rm, ra], None, None),
+        };
+    }
+
+    None
+}
+
+// mcr, mrc, mcrr, mrrc, cdp, ldc and stc, the 2 variants when unconditional
+fn decode_coprocessor(
+    word: u32,
+    condition: ConditionCode,
+    unconditional: bool,
+) -> Option<CpuOperation> {
+    use InstructionName::*;
+
+    let pick = |name, name2| match unconditional {
+        true => name2,
+        false => name,
+    };
+    let coprocessor = bits(word, 11, 8) as u8;
+
+    if bits(word, 27, 24) == 0b1110 {
+        let (name, expression) = match bit(word, 4) {
+            true => {
+                let name = match bit(word, 20) {
+                    true => pick(MRC, MRC2),
+                    false if bits(word, 15, 12) == 15 => return None,
+                    false => pick(MCR, MCR2),
+                };
+                let expression = CoprocessorExpression::new(
+                    coprocessor,
+                    bits(word, 23, 21) as u8,
+                    vec![reg(word, 12)],
+                    vec![bits(word, 19, 16) as u8, bits(word, 3, 0) as u8],
+                    bits(word, 7, 5) as u8,
+                );
+                (name, expression)
+            }
+            false => {
+                let expression = CoprocessorExpression::new(
+                    coprocessor,
+                    bits(word, 23, 20) as u8,
+                    vec![],
+                    vec![
+                        bits(word, 15, 12) as u8,
+                        bits(word, 19, 16) as u8,
+                        bits(word, 3, 0) as u8,
+                    ],
+                    bits(word, 7, 5) as u8,
+                );
+                (pick(CDP, CDP2), expression)
+            }
+        };
+        return Some(CpuOperation::new(
+            operation(name, condition, false),
+            Expression::Coprocessor(expression),
+        ));
+    }
+
+    if bits(word, 27, 21) == 0b1100010 {
+        let name = match bit(word, 20) {
+            true => pick(MRRC, MRRC2),
+            false => pick(MCRR, MCRR2),
+        };
+        let (rt, rt2) = (bits(word, 15, 12), bits(word, 19, 16));
+        if rt == 15 || rt2 == 15 || bit(word, 20) && rt == rt2 {
+            return None;
+        }
+        return Some(CpuOperation::new(
+            operation(name, condition, false),
+            Expression::Coprocessor(CoprocessorExpression::new(
+                coprocessor,
+                bits(word, 7, 4) as u8,
+                vec![reg(word, 12), reg(word, 16)],
+                vec![bits(word, 3, 0) as u8],
+                0,
+            )),
+        ));
+    }
+
+    let (pre, up, write_back) = (bit(word, 24), bit(word, 23), bit(word, 21));
+    let (index_mode, option) = match (pre, write_back) {
+        (true, _) => (IndexMode::Pre(PreIndex { write_back }), None),
+        (false, true) => (IndexMode::Post, None),
+        (false, false) if up => (IndexMode::None, Some(bits(word, 7, 0) as u8)),
+        _ => return None,
+    };
+    let offset = (bits(word, 7, 0) * 4) as i32;
+    let offset = match (option, up) {
+        (Some(_), _) => 0,
+        (None, true) => offset,
+        (None, false) if offset == 0 => return None,
+        (None, false) => -offset,
+    };
+
+    let name = match (bit(word, 20), bit(word, 22)) {
+        (true, false) => pick(LDC, LDC2),
+        (true, true) => pick(LDCL, LDC2L),
+        (false, false) => pick(STC, STC2),
+        (false, true) => pick(STCL, STC2L),
+    };
+
+    Some(CpuOperation::new(
+        operation(name, condition, false),
+        Expression::CoprocessorLoadStore(CoprocessorLoadStoreExpression::new(
+            coprocessor,
+            bits(word, 15, 12) as u8,
+            reg(word, 16),
+            offset,
+            index_mode,
+            option,
+        )),
+    ))
+}
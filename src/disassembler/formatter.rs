@@ -0,0 +1,390 @@
+// Canonical UAL text of a decoded operation, as llvm-objdump prints it: signed decimal
+// immediates, the shift mnemonics instead of mov with a shift and push/pop for the stack.
+
+use crate::{
+    lexer::{
+        cpu_op::CpuOperation,
+        expression::{
+            barrel_shifter::{
+                BarrealShifterShiftAmount, BarrelShifterExpression, BarrelShifterOperation,
+            },
+            coprocessor::CoprocessorLoadStoreExpression,
+            ls_imm_index::{IndexMode, PreIndex},
+            ls_multiple::LoadStoreMultipleExpression,
+            Expression,
+        },
+    },
+    token::{
+        immediate::Immediate,
+        instruction_name::{FlagSuffix, InstructionName},
+        register::{Register, StatusRegisterName},
+    },
+};
+
+fn register(register: &Register) -> String {
+    match register.to_num() {
+        13 => "sp".to_string(),
+        14 => "lr".to_string(),
+        15 => "pc".to_string(),
+        number => format!("r{}", number),
+    }
+}
+
+fn registers(registers: &[Register]) -> String {
+    let names: Vec<String> = registers.iter().map(register).collect();
+    names.join(", ")
+}
+
+fn signed(immediate: &Immediate) -> String {
+    format!("#{}", immediate.to_num() as i32)
+}
+
+fn shift(shift: &BarrelShifterExpression) -> String {
+    let name = format!("{:?}", shift.operation).to_lowercase();
+    match (shift.operation, shift.shift_amount) {
+        (BarrelShifterOperation::RRX, _) => name,
+        (_, BarrealShifterShiftAmount::Number(amount)) => format!("{} #{}", name, amount),
+        (_, BarrealShifterShiftAmount::Register(reg)) => format!("{} {}", name, register(&reg)),
+    }
+}
+
+fn with_shift(text: String, barrel_shifter: &Option<BarrelShifterExpression>) -> String {
+    match barrel_shifter {
+        Some(barrel_shifter) => format!("{}, {}", text, shift(barrel_shifter)),
+        None => text,
+    }
+}
+
+fn rotation(text: String, rotation: &Option<Immediate>) -> String {
+    match rotation {
+        Some(rotation) => format!("{}, #{}", text, rotation.to_num()),
+        None => text,
+    }
+}
+
+// push and pop of the full descending stack, one register is a str or ldr instead
+fn is_stack(name: InstructionName, expr: &LoadStoreMultipleExpression) -> bool {
+    let stack = expr.base.to_num() == 13 && expr.write_back && !expr.user_mode;
+    stack
+        && expr.registers.len() > 1
+        && matches!(name, InstructionName::STMDB | InstructionName::LDMIA)
+}
+
+// str rt, [sp, #-4]! and ldr rt, [sp], #4, the push and pop of one register
+fn single_stack_register(op: &CpuOperation) -> Option<Register> {
+    let Expression::LoadStoreImmediate(expr) = &op.expression else {
+        return None;
+    };
+    let offset = expr.offset.as_ref()?.to_num() as i32;
+    let stack = match (op.instruction.value, expr.index_mode) {
+        (InstructionName::STR, IndexMode::Pre(PreIndex { write_back: true })) => offset == -4,
+        (InstructionName::LDR, IndexMode::Post) => offset == 4,
+        _ => false,
+    };
+    (stack && expr.base.to_num() == 13).then_some(expr.destination)
+}
+
+fn mnemonic(op: &CpuOperation) -> String {
+    use InstructionName::*;
+
+    let instruction = &op.instruction;
+    let name = match (instruction.value, &op.expression) {
+        (STMDB, Expression::LoadStoreMultiple(expr)) if is_stack(STMDB, expr) => "push".into(),
+        (LDMIA, Expression::LoadStoreMultiple(expr)) if is_stack(LDMIA, expr) => "pop".into(),
+        (STR, _) if single_stack_register(op).is_some() => "push".into(),
+        (LDR, _) if single_stack_register(op).is_some() => "pop".into(),
+        (LDMIA, _) => "ldm".into(),
+        (STMIA, _) => "stm".into(),
+        (MOV, Expression::TwoRegs(expr)) if expr.barrel_shifter.is_some() => {
+            let operation = expr.barrel_shifter.unwrap().operation;
+            format!("{:?}", operation).to_lowercase()
+        }
+        (name, _) => format!("{:?}", name).to_lowercase(),
+    };
+
+    let flags = instruction.set_flags && instruction.value.flag_suffix() == FlagSuffix::Optional;
+    let flags = if flags { "s" } else { "" };
+
+    format!("{}{}{}", name, flags, instruction.condition.to_string())
+}
+
+// Address a branch with an immediate offset goes to, from the PC of the instruction
+pub fn branch_target(op: &CpuOperation, address: u32) -> Option<u32> {
+    use InstructionName::*;
+
+    match (op.instruction.value, &op.expression) {
+        (B | BL | BLX, Expression::Immediate(expr)) => {
+            Some(address.wrapping_add(8).wrapping_add(expr.literal.to_num()))
+        }
+        _ => None,
+    }
+}
+
+fn barrier_option(option: u32) -> String {
+    let name = match option {
+        15 => "sy",
+        14 => "st",
+        13 => "ld",
+        11 => "ish",
+        10 => "ishst",
+        9 => "ishld",
+        7 => "nsh",
+        6 => "nshst",
+        5 => "nshld",
+        3 => "osh",
+        2 => "oshst",
+        1 => "oshld",
+        _ => return format!("#{}", option),
+    };
+    name.to_string()
+}
+
+fn coprocessor_address(expr: &CoprocessorLoadStoreExpression) -> String {
+    let base = register(&expr.base);
+    match (expr.index_mode, expr.option) {
+        (_, Some(option)) => format!("[{}], {{{}}}", base, option),
+        (IndexMode::Pre(PreIndex { write_back: false }), _) if expr.offset == 0 => {
+            format!("[{}]", base)
+        }
+        (IndexMode::Pre(PreIndex { write_back }), _) => {
+            let write_back = if write_back { "!" } else { "" };
+            format!("[{}, #{}]{}", base, expr.offset, write_back)
+        }
+        _ => format!("[{}], #{}", base, expr.offset),
+    }
+}
+
+fn operands(op: &CpuOperation, address: u32) -> String {
+    use InstructionName::*;
+
+    let name = op.instruction.value;
+    match &op.expression {
+        Expression::ThreeRegs(expr) => with_shift(
+            format!(
+                "{}, {}, {}",
+                register(&expr.reg_d),
+                register(&expr.reg_m),
+                register(&expr.reg_n)
+            ),
+            &expr.barrel_shifter,
+        ),
+        // mov with a shift is printed as the shift itself
+        Expression::TwoRegs(expr) if name == MOV && expr.barrel_shifter.is_some() => {
+            let registers = format!("{}, {}", register(&expr.reg_d), register(&expr.reg_m));
+            let barrel_shifter = expr.barrel_shifter.unwrap();
+            match barrel_shifter.shift_amount {
+                _ if barrel_shifter.operation == BarrelShifterOperation::RRX => registers,
+                BarrealShifterShiftAmount::Number(amount) => format!("{}, #{}", registers, amount),
+                BarrealShifterShiftAmount::Register(reg) => {
+                    format!("{}, {}", registers, register(&reg))
+                }
+            }
+        }
+        Expression::TwoRegs(expr) => with_shift(
+            format!("{}, {}", register(&expr.reg_d), register(&expr.reg_m)),
+            &expr.barrel_shifter,
+        ),
+        Expression::TwoRegsLiteral(expr) => rotation(
+            format!(
+                "{}, {}, {}",
+                register(&expr.reg_d),
+                register(&expr.reg_m),
+                signed(&expr.literal)
+            ),
+            &expr.rotation,
+        ),
+        Expression::RegLiteral(expr) => rotation(
+            format!("{}, {}", register(&expr.register), signed(&expr.literal)),
+            &expr.rotation,
+        ),
+        Expression::Immediate(expr) => match name {
+            B | BL | BLX => format!("0x{:x}", branch_target(op, address).unwrap()),
+            DMB | DSB => barrier_option(expr.literal.to_num()),
+            ISB if expr.literal.to_num() == 15 => "sy".to_string(),
+            _ => format!("#{}", expr.literal.to_num()),
+        },
+        Expression::Register(expr) => register(&expr.register),
+        Expression::LoadStoreImmediate(_) if single_stack_register(op).is_some() => {
+            format!("{{{}}}", register(&single_stack_register(op).unwrap()))
+        }
+        Expression::LoadStoreImmediate(expr) => {
+            // ldrd and strd name the second register of the pair too
+            let destination = match name {
+                LDRD | STRD => format!(
+                    "{}, {}",
+                    register(&expr.destination),
+                    register(&Register::from_num(expr.destination.to_num() + 1).unwrap())
+                ),
+                _ => register(&expr.destination),
+            };
+            let base = register(&expr.base);
+            let offset = expr.offset.as_ref().map(signed);
+            let address = match (expr.index_mode, offset) {
+                (IndexMode::None, _) | (_, None) => format!("[{}]", base),
+                (IndexMode::Pre(PreIndex { write_back }), Some(offset)) => {
+                    let write_back = if write_back { "!" } else { "" };
+                    format!("[{}, {}]{}", base, offset, write_back)
+                }
+                (IndexMode::Post, Some(offset)) => format!("[{}], {}", base, offset),
+            };
+            format!("{}, {}", destination, address)
+        }
+        Expression::LoadStoreRegister(expr) => {
+            let destination = match name {
+                LDRD | STRD => format!(
+                    "{}, {}",
+                    register(&expr.destination),
+                    register(&Register::from_num(expr.destination.to_num() + 1).unwrap())
+                ),
+                _ => register(&expr.destination),
+            };
+            let sign = if expr.negative { "-" } else { "" };
+            let offset = with_shift(
+                format!("{}{}", sign, register(&expr.offset)),
+                &expr.barrel_shifter,
+            );
+            let base = register(&expr.base);
+            let address = match expr.index_mode {
+                IndexMode::Pre(PreIndex { write_back }) => {
+                    let write_back = if write_back { "!" } else { "" };
+                    format!("[{}, {}]{}", base, offset, write_back)
+                }
+                _ => format!("[{}], {}", base, offset),
+            };
+            format!("{}, {}", destination, address)
+        }
+        Expression::LoadStoreMultiple(expr) if is_stack(name, expr) => {
+            format!("{{{}}}", registers(&expr.registers))
+        }
+        Expression::LoadStoreMultiple(expr) => {
+            let write_back = if expr.write_back { "!" } else { "" };
+            let user_mode = if expr.user_mode { " ^" } else { "" };
+            format!(
+                "{}{}, {{{}}}{}",
+                register(&expr.base),
+                write_back,
+                registers(&expr.registers),
+                user_mode
+            )
+        }
+        Expression::LoadStoreExclusive(expr) => {
+            let mut list: Vec<Register> = expr.status.into_iter().collect();
+            list.push(expr.destination);
+            list.extend(expr.destination2);
+            format!("{}, [{}]", registers(&list), register(&expr.base))
+        }
+        Expression::StatusRegister(expr) => {
+            let status_register = &expr.status_register;
+            let name_of = match status_register.register {
+                StatusRegisterName::CPSR => "CPSR",
+                StatusRegisterName::SPSR => "SPSR",
+            };
+            if name == MRS {
+                let source = match status_register.register {
+                    StatusRegisterName::CPSR => "apsr",
+                    StatusRegisterName::SPSR => "spsr",
+                };
+                return format!("{}, {}", register(&expr.register.unwrap()), source);
+            }
+
+            let mask = status_register.field_mask();
+            let destination = match (status_register.register, mask) {
+                (StatusRegisterName::CPSR, 0b1000) => "APSR_nzcvq".to_string(),
+                (StatusRegisterName::CPSR, 0b0100) => "APSR_g".to_string(),
+                (StatusRegisterName::CPSR, 0b1100) => "APSR_nzcvqg".to_string(),
+                _ => {
+                    let fields: String =
+                        [(0b1000, 'f'), (0b0100, 's'), (0b0010, 'x'), (0b0001, 'c')]
+                            .iter()
+                            .filter(|(bit, _)| mask & bit != 0)
+                            .map(|(_, field)| *field)
+                            .collect();
+                    format!("{}_{}", name_of, fields)
+                }
+            };
+            let source = match (&expr.register, &expr.literal) {
+                (Some(reg), _) => register(reg),
+                (None, Some(literal)) => format!("#{}", literal.to_num()),
+                (None, None) => unreachable!(),
+            };
+            format!("{}, {}", destination, source)
+        }
+        Expression::ChangeProcessorState(expr) => {
+            let flags: String = [(0b100, 'a'), (0b010, 'i'), (0b001, 'f')]
+                .iter()
+                .filter(|(bit, _)| expr.flags & bit != 0)
+                .map(|(_, flag)| *flag)
+                .collect();
+            match (flags.is_empty(), expr.mode) {
+                (false, Some(mode)) => format!("{}, #{}", flags, mode),
+                (false, None) => flags,
+                (true, mode) => format!("#{}", mode.unwrap_or(0)),
+            }
+        }
+        Expression::Coprocessor(expr) => {
+            let coprocessor = format!("p{}, #{}", expr.coprocessor, expr.opc1);
+            let cp_registers: Vec<String> = expr
+                .cp_registers
+                .iter()
+                .map(|number| format!("c{}", number))
+                .collect();
+            match name {
+                CDP | CDP2 => format!(
+                    "{}, {}, #{}",
+                    coprocessor,
+                    cp_registers.join(", "),
+                    expr.opc2
+                ),
+                MCRR | MCRR2 | MRRC | MRRC2 => format!(
+                    "{}, {}, {}",
+                    coprocessor,
+                    registers(&expr.registers),
+                    cp_registers.join(", ")
+                ),
+                // mrc to pc moves the flags
+                MRC | MRC2 if expr.registers[0].to_num() == 15 => format!(
+                    "{}, apsr_nzcv, {}, #{}",
+                    coprocessor,
+                    cp_registers.join(", "),
+                    expr.opc2
+                ),
+                _ => format!(
+                    "{}, {}, {}, #{}",
+                    coprocessor,
+                    registers(&expr.registers),
+                    cp_registers.join(", "),
+                    expr.opc2
+                ),
+            }
+        }
+        Expression::CoprocessorLoadStore(expr) => format!(
+            "p{}, c{}, {}",
+            expr.coprocessor,
+            expr.crd,
+            coprocessor_address(expr)
+        ),
+        Expression::Media(expr) => match (name, expr.saturate) {
+            (_, Some(saturate)) => with_shift(
+                format!(
+                    "{}, #{}, {}",
+                    register(&expr.registers[0]),
+                    saturate,
+                    register(&expr.registers[1])
+                ),
+                &expr.shift,
+            ),
+            _ => with_shift(registers(&expr.registers), &expr.shift),
+        },
+        Expression::IfThen(_) | Expression::Vfp(_) => String::new(),
+        Expression::VfpLoadStore(_) | Expression::VfpLoadStoreMultiple(_) => String::new(),
+        Expression::Empty => String::new(),
+    }
+}
+
+pub fn format_operation(op: &CpuOperation, address: u32) -> String {
+    let operands = operands(op, address);
+    match operands.is_empty() {
+        true => mnemonic(op),
+        false => format!("{}\t{}", mnemonic(op), operands),
+    }
+}
@@ -0,0 +1,202 @@
+// objdump -d for the objects and executables we write. ARM code is decoded back into UAL,
+// Thumb code and data are printed as raw .inst and .word, following the $a, $t and $d
+// mapping symbols.
+
+pub mod decoder;
+pub mod formatter;
+#[cfg(test)]
+mod round_trip;
+
+use std::fs;
+
+use object::{FileFlags, Object, ObjectSection, ObjectSymbol, SectionFlags, SectionKind};
+
+use crate::elf::byte_order::ByteOrder;
+
+use self::{
+    decoder::decode,
+    formatter::{branch_target, format_operation},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mapping {
+    Arm,
+    Thumb,
+    Data,
+}
+
+// $a, $t and $d, optionally followed by a .suffix
+fn mapping(name: &str) -> Option<Mapping> {
+    let (kind, _) = name.split_once('.').unwrap_or((name, ""));
+    match kind {
+        "$a" => Some(Mapping::Arm),
+        "$t" => Some(Mapping::Thumb),
+        "$d" => Some(Mapping::Data),
+        _ => None,
+    }
+}
+
+// 32-bit Thumb instructions start with 0b11101, 0b11110 or 0b11111
+fn is_wide_thumb(halfword: u16) -> bool {
+    halfword >> 11 >= 0b11101
+}
+
+// One instruction as text, with the branch target named after the closest label before it
+pub fn disassemble_word(word: u32, address: u32, labels: &[(u32, String)]) -> Option<String> {
+    let op = decode(word)?;
+    let text = format_operation(&op, address);
+
+    let label = branch_target(&op, address).and_then(|target| {
+        labels
+            .iter()
+            .rev()
+            .find(|(address, _)| *address <= target)
+            .map(|(address, name)| match target - address {
+                0 => format!(" <{}>", name),
+                offset => format!(" <{}+0x{:x}>", name, offset),
+            })
+    });
+
+    Some(text + &label.unwrap_or_default())
+}
+
+pub fn disassemble(file_name: &str) {
+    let data = fs::read(file_name).unwrap_or_else(|_| panic!("Can't read {}", file_name));
+    let file = object::File::parse(data.as_slice())
+        .unwrap_or_else(|_| panic!("{} is not an object file", file_name));
+    if file.architecture() != object::Architecture::Arm {
+        panic!("{} is not an ARM object", file_name);
+    }
+
+    let e_flags = match file.flags() {
+        FileFlags::Elf { e_flags, .. } => e_flags,
+        _ => 0,
+    };
+    let byte_order = ByteOrder::from_elf(!file.is_little_endian(), e_flags);
+    let format = match byte_order.is_big_endian() {
+        true => "elf32-bigarm",
+        false => "elf32-littlearm",
+    };
+    println!("\n{}:     file format {}", file_name, format);
+
+    let executable = |section: &object::Section| match section.flags() {
+        SectionFlags::Elf { sh_flags } => sh_flags & object::elf::SHF_EXECINSTR as u64 != 0,
+        _ => section.kind() == SectionKind::Text,
+    };
+
+    for section in file.sections().filter(executable) {
+        let name = section.name().unwrap_or_default();
+        let bytes = section.data().unwrap_or_default();
+        let start = section.address() as u32;
+
+        let mut labels = vec![];
+        let mut mappings = vec![];
+        for symbol in file.symbols() {
+            if symbol.section_index() != Some(section.index()) {
+                continue;
+            }
+            let name = symbol.name().unwrap_or_default();
+            let address = symbol.address() as u32 & !1;
+            match mapping(name) {
+                Some(kind) => mappings.push((address, kind)),
+                None if !name.is_empty() && symbol.kind() != object::SymbolKind::Section => {
+                    labels.push((address, name.to_string()))
+                }
+                None => {}
+            }
+        }
+        labels.sort();
+        mappings.sort_by_key(|(address, _)| *address);
+
+        println!("\nDisassembly of section {}:", name);
+
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let address = start + offset as u32;
+            for (_, label) in labels.iter().filter(|(at, _)| *at == address) {
+                println!("\n{:08x} <{}>:", address, label);
+            }
+
+            let kind = mappings
+                .iter()
+                .rev()
+                .find(|(at, _)| *at <= address)
+                .map(|(_, kind)| *kind)
+                .unwrap_or(Mapping::Arm);
+            let rest = &bytes[offset..];
+
+            let (size, text) = match kind {
+                _ if rest.len() < 2 => (
+                    1,
+                    format!("{:02x}      \t.byte\t0x{:02x}", rest[0], rest[0]),
+                ),
+                Mapping::Thumb => {
+                    let first = byte_order.read_code_u16(rest);
+                    if is_wide_thumb(first) && rest.len() >= 4 {
+                        let second = byte_order.read_code_u16(&rest[2..]);
+                        let word = (first as u32) << 16 | second as u32;
+                        (
+                            4,
+                            format!("{:04x} {:04x}\t.inst.w\t0x{:08x}", first, second, word),
+                        )
+                    } else {
+                        (2, format!("{:04x}     \t.inst.n\t0x{:04x}", first, first))
+                    }
+                }
+                _ if rest.len() < 4 => {
+                    let halfword = byte_order.read_code_u16(rest);
+                    (
+                        2,
+                        format!("{:04x}     \t.short\t0x{:04x}", halfword, halfword),
+                    )
+                }
+                Mapping::Data => {
+                    let word = match byte_order.endianness() {
+                        object::Endianness::Big => {
+                            u32::from_be_bytes(rest[..4].try_into().unwrap())
+                        }
+                        object::Endianness::Little => {
+                            u32::from_le_bytes(rest[..4].try_into().unwrap())
+                        }
+                    };
+                    (4, format!("{:08x} \t.word\t0x{:08x}", word, word))
+                }
+                Mapping::Arm => {
+                    let word = byte_order.read_code_u32(rest);
+                    let text = disassemble_word(word, address, &labels)
+                        .unwrap_or_else(|| format!(".word\t0x{:08x}", word));
+                    (4, format!("{:08x} \t{}", word, text))
+                }
+            };
+
+            println!("{:>8x}:\t{}", address, text);
+            offset += size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble_word() {
+        let labels = vec![(0, "_start".to_string())];
+        let cases = [
+            (0xe3a0020f, 0, "mov\tr0, #-268435456"),
+            (0xe7310102, 0, "ldr\tr0, [r1, -r2, lsl #2]!"),
+            (0xe92d0003, 0, "push\t{r0, r1}"),
+            (0xe1a00021, 0, "lsr\tr0, r1, #32"),
+            (0x1afffffd, 0x10, "bne\t0xc <_start+0xc>"),
+            (0xee010f10, 0, "mcr\tp15, #0, r0, c1, c0, #0"),
+        ];
+
+        for (word, address, text) in cases {
+            assert_eq!(
+                disassemble_word(word, address, &labels).as_deref(),
+                Some(text)
+            );
+        }
+        assert_eq!(disassemble_word(0xe0000291, 0, &labels), None);
+    }
+}
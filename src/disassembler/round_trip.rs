@@ -0,0 +1,193 @@
+// Encoder and decoder checked against each other. Random words of every ARM form the
+// assembler supports must survive decode, encode and decode, format, assemble unchanged, and
+// golden.txt pins encodings from the ARM ARM to their canonical text.
+
+use std::{env, fs, process};
+
+use crate::{
+    elf::byte_order::ByteOrder,
+    lexer::{cpu_op::CpuOperation, it_block::ImplicitIt, symbolizer::SymbolTable, Lexer},
+    reader::Reader,
+    token::instruction_name::InstructionName,
+    tokenizer::Tokenizer,
+};
+
+use super::{decoder::decode, formatter::format_operation};
+
+// xorshift32, enough to spread the words over each form
+struct Random(u32);
+
+impl Random {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
+
+enum Condition {
+    Any,
+    Always,
+    Never,
+}
+
+// Fixed bits and the bits drawn at random of each form, as (name, fixed, random, condition)
+const FORMS: [(&str, u32, u32, Condition); 35] = {
+    use Condition::*;
+    [
+        ("data processing immediate", 0x02000000, 0x01ffffff, Any),
+        (
+            "data processing shift by immediate",
+            0x00000000,
+            0x01ffffef,
+            Any,
+        ),
+        (
+            "data processing shift by register",
+            0x00000010,
+            0x01ffff6f,
+            Any,
+        ),
+        ("branch and exchange", 0x012fff10, 0x0000003f, Any),
+        ("branch", 0x0a000000, 0x01ffffff, Any),
+        ("blx immediate", 0x0a000000, 0x01ffffff, Never),
+        ("load store immediate", 0x04000000, 0x01bfffff, Any),
+        ("load store register", 0x06000000, 0x01bfffef, Any),
+        ("load store multiple", 0x08000000, 0x01ffffff, Any),
+        ("push pop", 0x049d0004, 0x0100f000, Any),
+        ("load store exclusive", 0x01800c90, 0x007ff30f, Any),
+        ("mrs", 0x010f0000, 0x0040f000, Any),
+        ("msr register", 0x0120f000, 0x004f000f, Any),
+        ("msr immediate", 0x0320f000, 0x004f0fff, Any),
+        ("hints", 0x0320f000, 0x00000007, Any),
+        ("cps", 0x01000000, 0x000e01df, Never),
+        ("barriers", 0x057ff040, 0x0000003f, Never),
+        ("clrex", 0x057ff01f, 0x00000000, Never),
+        ("svc", 0x0f000000, 0x00ffffff, Any),
+        ("bkpt", 0x01200070, 0x000fff0f, Always),
+        ("udf", 0x07f000f0, 0x000fff0f, Always),
+        ("mcr mrc", 0x0e000010, 0x00fff7ef, Any),
+        ("cdp", 0x0e000000, 0x00fff7ef, Any),
+        ("mcrr mrrc", 0x0c400000, 0x001ff7ff, Any),
+        ("ldc stc", 0x0c000000, 0x01fff7ff, Any),
+        ("coprocessor 2", 0x0c000000, 0x03fff7ff, Never),
+        ("parallel add subtract", 0x06000f10, 0x007ff0ef, Any),
+        ("saturating add subtract", 0x01000050, 0x006ff00f, Any),
+        ("ssat usat", 0x06a00010, 0x005fffcf, Any),
+        ("ssat16 usat16", 0x06a00f30, 0x004ff00f, Any),
+        ("pkhbt pkhtb", 0x06800010, 0x000fffcf, Any),
+        ("sel", 0x06800fb0, 0x000ff00f, Any),
+        ("extends", 0x06800070, 0x007ffc0f, Any),
+        ("usad8 usada8", 0x07800010, 0x000fff0f, Any),
+        ("sdiv udiv", 0x0710f010, 0x002f0f0f, Any),
+    ]
+};
+
+const SAMPLES: usize = 400;
+
+// Loads and stores of bytes, halfwords and pairs are decoded but not assembled in ARM state
+fn is_encodable(op: &CpuOperation) -> bool {
+    use InstructionName::*;
+    !matches!(
+        op.instruction.value,
+        LDRB | STRB | LDRT | STRT | LDRBT | STRBT | LDRH | STRH | LDRSB | LDRSH | LDRD | STRD
+    )
+}
+
+fn is_branch(op: &CpuOperation) -> bool {
+    use crate::lexer::expression::Expression;
+    use InstructionName::*;
+    matches!(
+        (op.instruction.value, &op.expression),
+        (B | BL | BLX, Expression::Immediate(_))
+    )
+}
+
+fn encode(op: &CpuOperation) -> u32 {
+    let bytes = op.to_machine_code().to_u8_buff(ByteOrder::Little);
+    u32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+// Assembles each line on its own in ARM state, as the assembler does on its second pass
+fn assemble(lines: &[String]) -> Vec<Vec<u8>> {
+    let path = env::temp_dir().join(format!("round_trip_{}.s", process::id()));
+    fs::write(&path, lines.join("\n") + "\n").unwrap();
+
+    let mut tokenizer = Tokenizer::new(Reader::new(path.to_str().unwrap()));
+    let mut lexer = Lexer::new(SymbolTable::new(), ImplicitIt::default());
+    let mut words = vec![];
+    while !tokenizer.is_eof() {
+        words.push(lexer.assemble_line(tokenizer.consume_line()));
+    }
+
+    fs::remove_file(path).unwrap();
+    words
+}
+
+#[test]
+fn test_random_round_trip() {
+    let mut random = Random(0x2545f491);
+    let mut texts = vec![];
+
+    for (name, fixed, mask, condition) in &FORMS {
+        let mut decoded = 0;
+        for _ in 0..SAMPLES {
+            let condition = match condition {
+                Condition::Any => random.next() % 15,
+                Condition::Always => 0xe,
+                Condition::Never => 0xf,
+            };
+            let word = condition << 28 | fixed | random.next() & mask;
+
+            let Some(op) = decode(word) else {
+                continue;
+            };
+            decoded += 1;
+            if !is_encodable(&op) {
+                continue;
+            }
+
+            let text = format_operation(&op, 0);
+            assert_eq!(
+                encode(&op),
+                word,
+                "{:08x} {} encodes differently",
+                word,
+                text
+            );
+            if !is_branch(&op) {
+                texts.push((word, text));
+            }
+        }
+        assert!(decoded > 0, "No {} word decoded", name);
+    }
+
+    let lines: Vec<String> = texts.iter().map(|(_, text)| text.clone()).collect();
+    for ((word, text), bytes) in texts.iter().zip(assemble(&lines)) {
+        assert_eq!(bytes, word.to_le_bytes(), "{} assembles differently", text);
+    }
+}
+
+#[test]
+fn test_golden_corpus() {
+    let corpus: Vec<(u32, &str)> = include_str!("golden.txt")
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let (word, text) = line.split_once(char::is_whitespace).unwrap();
+            (u32::from_str_radix(word, 16).unwrap(), text.trim())
+        })
+        .collect();
+
+    for (word, text) in &corpus {
+        let op = decode(*word).unwrap_or_else(|| panic!("{:08x} doesn't decode", word));
+        let disassembly = format_operation(&op, 0).replacen('\t', " ", 1);
+        assert_eq!(&disassembly, text, "{:08x} disassembles differently", word);
+    }
+
+    let lines: Vec<String> = corpus.iter().map(|(_, text)| text.to_string()).collect();
+    for ((word, text), bytes) in corpus.iter().zip(assemble(&lines)) {
+        assert_eq!(bytes, word.to_le_bytes(), "{} assembles differently", text);
+    }
+}
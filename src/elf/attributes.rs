@@ -0,0 +1,217 @@
+// Contents of .ARM.attributes, the build attributes of the aeabi vendor. The section is a
+// version byte followed by the "aeabi" subsection, which holds one Tag_File list.
+
+use crate::token::Token;
+
+use super::byte_order::ByteOrder;
+
+pub const TAG_FILE: u32 = 1;
+pub const TAG_CPU_NAME: u32 = 5;
+pub const TAG_CPU_ARCH: u32 = 6;
+pub const TAG_CPU_ARCH_PROFILE: u32 = 7;
+pub const TAG_ARM_ISA_USE: u32 = 8;
+pub const TAG_THUMB_ISA_USE: u32 = 9;
+pub const TAG_FP_ARCH: u32 = 10;
+pub const TAG_ADVANCED_SIMD_ARCH: u32 = 12;
+pub const TAG_ABI_VFP_ARGS: u32 = 28;
+pub const TAG_COMPATIBILITY: u32 = 32;
+pub const TAG_MPEXTENSION_USE: u32 = 42;
+pub const TAG_DIV_USE: u32 = 44;
+
+// Names accepted by .eabi_attribute
+const TAG_NAMES: [(&str, u32); 40] = [
+    ("tag_cpu_raw_name", 4),
+    ("tag_cpu_name", TAG_CPU_NAME),
+    ("tag_cpu_arch", TAG_CPU_ARCH),
+    ("tag_cpu_arch_profile", TAG_CPU_ARCH_PROFILE),
+    ("tag_arm_isa_use", TAG_ARM_ISA_USE),
+    ("tag_thumb_isa_use", TAG_THUMB_ISA_USE),
+    ("tag_fp_arch", TAG_FP_ARCH),
+    ("tag_vfp_arch", TAG_FP_ARCH),
+    ("tag_wmmx_arch", 11),
+    ("tag_advanced_simd_arch", TAG_ADVANCED_SIMD_ARCH),
+    ("tag_pcs_config", 13),
+    ("tag_abi_pcs_r9_use", 14),
+    ("tag_abi_pcs_rw_data", 15),
+    ("tag_abi_pcs_ro_data", 16),
+    ("tag_abi_pcs_got_use", 17),
+    ("tag_abi_pcs_wchar_t", 18),
+    ("tag_abi_fp_rounding", 19),
+    ("tag_abi_fp_denormal", 20),
+    ("tag_abi_fp_exceptions", 21),
+    ("tag_abi_fp_user_exceptions", 22),
+    ("tag_abi_fp_number_model", 23),
+    ("tag_abi_align_needed", 24),
+    ("tag_abi_align8_needed", 24),
+    ("tag_abi_align_preserved", 25),
+    ("tag_abi_align8_preserved", 25),
+    ("tag_abi_enum_size", 26),
+    ("tag_abi_hardfp_use", 27),
+    ("tag_abi_vfp_args", TAG_ABI_VFP_ARGS),
+    ("tag_abi_wmmx_args", 29),
+    ("tag_abi_optimization_goals", 30),
+    ("tag_abi_fp_optimization_goals", 31),
+    ("tag_compatibility", TAG_COMPATIBILITY),
+    ("tag_cpu_unaligned_access", 34),
+    ("tag_fp_hp_extension", 36),
+    ("tag_vfp_hp_extension", 36),
+    ("tag_abi_fp_16bit_format", 38),
+    ("tag_mpextension_use", TAG_MPEXTENSION_USE),
+    ("tag_div_use", TAG_DIV_USE),
+    ("tag_nodefaults", 64),
+    ("tag_virtualization_use", 68),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeValue {
+    Number(u32),
+    Text(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Attributes {
+    // Kept sorted by tag
+    entries: Vec<(u32, AttributeValue)>,
+}
+
+impl Attributes {
+    pub fn new() -> Self {
+        Attributes::default()
+    }
+
+    // Replaces an earlier value of the same tag
+    pub fn set(&mut self, tag: u32, value: AttributeValue) {
+        match self.entries.binary_search_by_key(&tag, |(tag, _)| *tag) {
+            Ok(index) => self.entries[index].1 = value,
+            Err(index) => self.entries.insert(index, (tag, value)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Values of other take precedence
+    pub fn extend(&mut self, other: &Attributes) {
+        for (tag, value) in &other.entries {
+            self.set(*tag, value.clone());
+        }
+    }
+
+    pub fn to_bytes(&self, byte_order: ByteOrder) -> Vec<u8> {
+        // Tag_conformance and Tag_nodefaults have to come before the others
+        let mut entries: Vec<&(u32, AttributeValue)> = self.entries.iter().collect();
+        entries.sort_by_key(|(tag, _)| match tag {
+            67 => 0,
+            64 => 1,
+            _ => 2,
+        });
+
+        let mut attributes = vec![];
+        for (tag, value) in entries {
+            push_uleb128(&mut attributes, *tag);
+            match value {
+                AttributeValue::Number(number) => push_uleb128(&mut attributes, *number),
+                AttributeValue::Text(text) => {
+                    attributes.extend(text.as_bytes());
+                    attributes.push(0);
+                }
+            }
+        }
+
+        // Tag_File and its size, which counts the tag and the size field
+        let mut file = vec![TAG_FILE as u8];
+        file.extend(byte_order.data_u32(attributes.len() as u32 + 5));
+        file.extend(attributes);
+
+        // the subsection length counts itself and the vendor name
+        let vendor = b"aeabi\0";
+        let mut buffer = vec![b'A'];
+        buffer.extend(byte_order.data_u32((4 + vendor.len() + file.len()) as u32));
+        buffer.extend(vendor);
+        buffer.extend(file);
+
+        buffer
+    }
+}
+
+pub fn push_uleb128(buffer: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buffer.push(byte);
+            break;
+        }
+        buffer.push(byte | 0x80);
+    }
+}
+
+// Tags 4, 5, 65 and 67 are strings, above 32 odd tags are strings too
+fn is_text_tag(tag: u32) -> bool {
+    match tag {
+        4 | 5 => true,
+        0..=32 => false,
+        tag => tag % 2 == 1,
+    }
+}
+
+// .eabi_attribute Tag_ABI_FP_denormal, 1 or .eabi_attribute 5, "cortex-a9"
+pub fn eabi_attribute_directive(tokens: &[Token]) -> Option<(u32, AttributeValue)> {
+    let is_attribute = tokens.iter().any(|token| {
+        token
+            .extract_directive()
+            .is_some_and(|directive| directive.value == ".eabi_attribute")
+    });
+
+    if !is_attribute {
+        return None;
+    }
+
+    let (tag, value) = match tokens {
+        [_, tag, Token::COMMA, value] => (tag, value),
+        _ => panic!("Invalid .eabi_attribute, expected a tag and a value"),
+    };
+
+    let tag = match tag {
+        Token::NUMBER(number) => number.value,
+        Token::OPTION(name) => TAG_NAMES
+            .iter()
+            .find(|(tag_name, _)| tag_name == name)
+            .map(|(_, tag)| *tag)
+            .unwrap_or_else(|| panic!("Unknown attribute {}", name)),
+        _ => panic!("Invalid .eabi_attribute tag"),
+    };
+
+    // Tag_compatibility is a number followed by a string, which isn't supported
+    if tag == TAG_COMPATIBILITY {
+        panic!("Tag_compatibility is not supported by .eabi_attribute");
+    }
+
+    let value = match (value, is_text_tag(tag)) {
+        (Token::NUMBER(number), false) => AttributeValue::Number(number.value),
+        (Token::OPTION(text), true) => AttributeValue::Text(text.clone()),
+        (_, true) => panic!("Attribute {} takes a string", tag),
+        (_, false) => panic!("Attribute {} takes a number", tag),
+    };
+
+    Some((tag, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attributes_section() {
+        let mut attributes = Attributes::new();
+        attributes.set(TAG_CPU_ARCH, AttributeValue::Number(10));
+        attributes.set(TAG_CPU_NAME, AttributeValue::Text("7-A".to_string()));
+        attributes.set(TAG_CPU_ARCH, AttributeValue::Number(14));
+
+        let mut expected = vec![b'A', 22, 0, 0, 0];
+        expected.extend(b"aeabi\0");
+        expected.extend([1, 12, 0, 0, 0, 5, b'7', b'-', b'A', 0, 6, 14]);
+        assert_eq!(attributes.to_bytes(ByteOrder::Little), expected);
+    }
+}
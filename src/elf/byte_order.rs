@@ -0,0 +1,96 @@
+// Byte order of the object file, selected with -EL, -EB and -mbe8/-mbe32.
+// BE32 stores everything big-endian. BE8 keeps instructions little-endian and only data is
+// big-endian, as ARMv6 and later big-endian systems expect.
+
+use object::{elf::EF_ARM_BE8, Endianness};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteOrder {
+    #[default]
+    Little,
+    Be8,
+    Be32,
+}
+
+impl ByteOrder {
+    // Byte order of an existing object, from its header and the BE8 flag
+    pub fn from_elf(big_endian: bool, e_flags: u32) -> ByteOrder {
+        match (big_endian, e_flags & EF_ARM_BE8 != 0) {
+            (false, _) => ByteOrder::Little,
+            (true, true) => ByteOrder::Be8,
+            (true, false) => ByteOrder::Be32,
+        }
+    }
+
+    pub fn is_big_endian(&self) -> bool {
+        *self != ByteOrder::Little
+    }
+
+    // Byte order of the ELF header, tables and data
+    pub fn endianness(&self) -> Endianness {
+        match self {
+            ByteOrder::Little => Endianness::Little,
+            _ => Endianness::Big,
+        }
+    }
+
+    pub fn e_flags(&self) -> u32 {
+        match self {
+            ByteOrder::Be8 => EF_ARM_BE8,
+            _ => 0,
+        }
+    }
+
+    // ARM instructions and Thumb halfwords
+    pub fn code_u32(&self, code: u32) -> [u8; 4] {
+        match self {
+            ByteOrder::Be32 => code.to_be_bytes(),
+            _ => code.to_le_bytes(),
+        }
+    }
+
+    pub fn code_u16(&self, code: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::Be32 => code.to_be_bytes(),
+            _ => code.to_le_bytes(),
+        }
+    }
+
+    pub fn data_u32(&self, value: u32) -> [u8; 4] {
+        match self.endianness() {
+            Endianness::Big => value.to_be_bytes(),
+            Endianness::Little => value.to_le_bytes(),
+        }
+    }
+
+    pub fn data_u16(&self, value: u16) -> [u8; 2] {
+        match self.endianness() {
+            Endianness::Big => value.to_be_bytes(),
+            Endianness::Little => value.to_le_bytes(),
+        }
+    }
+
+    pub fn read_code_u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes[..4].try_into().unwrap();
+        match self {
+            ByteOrder::Be32 => u32::from_be_bytes(bytes),
+            _ => u32::from_le_bytes(bytes),
+        }
+    }
+
+    pub fn read_code_u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = bytes[..2].try_into().unwrap();
+        match self {
+            ByteOrder::Be32 => u16::from_be_bytes(bytes),
+            _ => u16::from_le_bytes(bytes),
+        }
+    }
+
+    pub fn read_data_u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = bytes[..4].try_into().unwrap();
+        match self.endianness() {
+            Endianness::Big => u32::from_be_bytes(bytes),
+            Endianness::Little => u32::from_le_bytes(bytes),
+        }
+    }
+}
@@ -0,0 +1,388 @@
+// Call frame information of the .cfi_* directives. Every .cfi_startproc/.cfi_endproc pair is
+// an FDE of .debug_frame, .eh_frame or both, as .cfi_sections selects. They share one CIE,
+// where the CFA is sp and the return address is in lr.
+
+use crate::token::{register::FpRegister, Token};
+
+use super::{byte_order::ByteOrder, dwarf::DebugSection};
+
+const DW_CFA_ADVANCE_LOC: u8 = 0x40;
+const DW_CFA_OFFSET: u8 = 0x80;
+const DW_CFA_RESTORE: u8 = 0xc0;
+const DW_CFA_NOP: u8 = 0x00;
+const DW_CFA_ADVANCE_LOC1: u8 = 0x02;
+const DW_CFA_ADVANCE_LOC2: u8 = 0x03;
+const DW_CFA_ADVANCE_LOC4: u8 = 0x04;
+const DW_CFA_OFFSET_EXTENDED: u8 = 0x05;
+const DW_CFA_RESTORE_EXTENDED: u8 = 0x06;
+const DW_CFA_REMEMBER_STATE: u8 = 0x0a;
+const DW_CFA_RESTORE_STATE: u8 = 0x0b;
+const DW_CFA_DEF_CFA: u8 = 0x0c;
+const DW_CFA_DEF_CFA_REGISTER: u8 = 0x0d;
+const DW_CFA_DEF_CFA_OFFSET: u8 = 0x0e;
+const DW_CFA_OFFSET_EXTENDED_SF: u8 = 0x11;
+
+// pc_begin of .eh_frame FDEs is a signed word relative to itself
+const DW_EH_PE_PCREL_SDATA4: u8 = 0x1b;
+
+// Thumb instructions are halfword aligned, saved registers are words
+const CODE_ALIGNMENT_FACTOR: u32 = 2;
+const DATA_ALIGNMENT_FACTOR: i32 = -4;
+const SP: u32 = 13;
+const LR: u8 = 14;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CfiDirective {
+    StartProc,
+    EndProc,
+    DefCfa(u32, i32),
+    DefCfaOffset(i32),
+    DefCfaRegister(u32),
+    Offset(u32, i32),
+    Restore(u32),
+    RememberState,
+    RestoreState,
+    // .debug_frame and .eh_frame
+    Sections(bool, bool),
+}
+
+// One function, from .cfi_startproc to .cfi_endproc, as .text offsets, with the instructions
+// of each directive and where it applies
+struct Fde {
+    start: u32,
+    end: u32,
+    instructions: Vec<(u32, Vec<u8>)>,
+}
+
+pub struct CallFrames {
+    fdes: Vec<Fde>,
+    // The FDE of the function being assembled
+    current: Option<Fde>,
+    debug_frame: bool,
+    eh_frame: bool,
+}
+
+impl Default for CallFrames {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CallFrames {
+    pub fn new() -> Self {
+        CallFrames {
+            fdes: vec![],
+            current: None,
+            debug_frame: true,
+            eh_frame: false,
+        }
+    }
+
+    // offset is where the directive sits in .text
+    pub fn apply(&mut self, directive: CfiDirective, offset: u32) {
+        match directive {
+            CfiDirective::StartProc => {
+                if self.current.is_some() {
+                    panic!(".cfi_startproc without .cfi_endproc of the function before");
+                }
+                self.current = Some(Fde {
+                    start: offset,
+                    end: offset,
+                    instructions: vec![],
+                });
+                return;
+            }
+            CfiDirective::EndProc => {
+                let mut fde = self
+                    .current
+                    .take()
+                    .expect(".cfi_endproc without .cfi_startproc");
+                fde.end = offset;
+                self.fdes.push(fde);
+                return;
+            }
+            CfiDirective::Sections(debug_frame, eh_frame) => {
+                self.debug_frame = debug_frame;
+                self.eh_frame = eh_frame;
+                return;
+            }
+            _ => {}
+        }
+
+        let Some(fde) = &mut self.current else {
+            panic!("CFI directive outside of .cfi_startproc and .cfi_endproc");
+        };
+
+        let mut section = DebugSection::new("");
+        match directive {
+            CfiDirective::DefCfa(register, cfa_offset) => {
+                section.u8(DW_CFA_DEF_CFA);
+                section.uleb128(register);
+                section.uleb128(cfa_offset_value(cfa_offset));
+            }
+            CfiDirective::DefCfaOffset(cfa_offset) => {
+                section.u8(DW_CFA_DEF_CFA_OFFSET);
+                section.uleb128(cfa_offset_value(cfa_offset));
+            }
+            CfiDirective::DefCfaRegister(register) => {
+                section.u8(DW_CFA_DEF_CFA_REGISTER);
+                section.uleb128(register);
+            }
+            CfiDirective::Offset(register, cfa_offset) => {
+                if cfa_offset % DATA_ALIGNMENT_FACTOR != 0 {
+                    panic!(".cfi_offset {} is not a multiple of 4", cfa_offset);
+                }
+                let factored = cfa_offset / DATA_ALIGNMENT_FACTOR;
+                match (register, factored) {
+                    (0..64, 0..) => {
+                        section.u8(DW_CFA_OFFSET | register as u8);
+                        section.uleb128(factored as u32);
+                    }
+                    (_, 0..) => {
+                        section.u8(DW_CFA_OFFSET_EXTENDED);
+                        section.uleb128(register);
+                        section.uleb128(factored as u32);
+                    }
+                    _ => {
+                        section.u8(DW_CFA_OFFSET_EXTENDED_SF);
+                        section.uleb128(register);
+                        section.sleb128(factored);
+                    }
+                }
+            }
+            CfiDirective::Restore(register) if register < 64 => {
+                section.u8(DW_CFA_RESTORE | register as u8)
+            }
+            CfiDirective::Restore(register) => {
+                section.u8(DW_CFA_RESTORE_EXTENDED);
+                section.uleb128(register);
+            }
+            CfiDirective::RememberState => section.u8(DW_CFA_REMEMBER_STATE),
+            CfiDirective::RestoreState => section.u8(DW_CFA_RESTORE_STATE),
+            _ => unreachable!(),
+        }
+        fde.instructions.push((offset, section.bytes));
+    }
+
+    pub fn finish(&self) {
+        if self.current.is_some() {
+            panic!(".cfi_startproc without .cfi_endproc at the end of the file");
+        }
+    }
+
+    pub fn sections(&self, byte_order: ByteOrder) -> Vec<DebugSection> {
+        if self.fdes.is_empty() {
+            return vec![];
+        }
+
+        let mut sections = vec![];
+        if self.debug_frame {
+            sections.push(self.frame_section(".debug_frame", byte_order));
+        }
+        if self.eh_frame {
+            sections.push(self.frame_section(".eh_frame", byte_order));
+        }
+        sections
+    }
+
+    // .debug_frame points at its CIE and code with absolute words, .eh_frame relative to itself
+    fn frame_section(&self, name: &'static str, byte_order: ByteOrder) -> DebugSection {
+        let eh_frame = name == ".eh_frame";
+        let mut section = DebugSection::new(name);
+
+        section.u32(0, byte_order);
+        section.u32(if eh_frame { 0 } else { 0xffffffff }, byte_order);
+        section.u8(1); // version
+        section.string(if eh_frame { "zR" } else { "" });
+        section.uleb128(CODE_ALIGNMENT_FACTOR);
+        section.sleb128(DATA_ALIGNMENT_FACTOR);
+        section.u8(LR);
+        if eh_frame {
+            section.uleb128(1);
+            section.u8(DW_EH_PE_PCREL_SDATA4);
+        }
+        section.u8(DW_CFA_DEF_CFA);
+        section.uleb128(SP);
+        section.uleb128(0);
+        pad(&mut section, 0, byte_order);
+
+        for fde in &self.fdes {
+            let start = section.bytes.len();
+            section.u32(0, byte_order);
+            if eh_frame {
+                // distance back to the CIE, from the CIE pointer
+                section.u32(start as u32 + 4, byte_order);
+                section.pc_relative(fde.start, ".text", byte_order);
+            } else {
+                section.address(0, ".debug_frame", byte_order);
+                section.address(fde.start, ".text", byte_order);
+            }
+            section.u32(fde.end - fde.start, byte_order);
+            if eh_frame {
+                section.uleb128(0);
+            }
+
+            let mut location = fde.start;
+            for (offset, instructions) in &fde.instructions {
+                advance_loc(&mut section, offset - location, byte_order);
+                section.bytes.extend(instructions);
+                location = *offset;
+            }
+            pad(&mut section, start, byte_order);
+        }
+        section
+    }
+}
+
+// The length of each entry covers it padded to a word
+fn pad(section: &mut DebugSection, start: usize, byte_order: ByteOrder) {
+    let end = section.bytes.len().next_multiple_of(4);
+    section.bytes.resize(end, DW_CFA_NOP);
+    section.patch_length(start, byte_order);
+}
+
+fn advance_loc(section: &mut DebugSection, delta: u32, byte_order: ByteOrder) {
+    let delta = delta / CODE_ALIGNMENT_FACTOR;
+    match delta {
+        0 => {}
+        1..64 => section.u8(DW_CFA_ADVANCE_LOC | delta as u8),
+        64..256 => {
+            section.u8(DW_CFA_ADVANCE_LOC1);
+            section.u8(delta as u8);
+        }
+        256..65536 => {
+            section.u8(DW_CFA_ADVANCE_LOC2);
+            section.u16(delta as u16, byte_order);
+        }
+        _ => {
+            section.u8(DW_CFA_ADVANCE_LOC4);
+            section.u32(delta, byte_order);
+        }
+    }
+}
+
+fn cfa_offset_value(offset: i32) -> u32 {
+    u32::try_from(offset).unwrap_or_else(|_| panic!("Negative CFA offset {}", offset))
+}
+
+// r0-r15 and their aliases, d0-d31 as 256-287 and s0-s31 as 64-95, or a DWARF number
+fn register_number(token: &Token) -> Option<u32> {
+    match token {
+        Token::REGISTER(register) => Some(register.to_num() as u32),
+        Token::FPREGISTER(FpRegister::Double(number)) => Some(256 + *number as u32),
+        Token::FPREGISTER(FpRegister::Single(number)) => Some(64 + *number as u32),
+        Token::NUMBER(number) => Some(number.value),
+        _ => None,
+    }
+}
+
+// .cfi_offset lr, -4: a register and a number, which may follow a minus
+fn register_and_offset(tokens: &[Token], name: &str) -> (u32, i32) {
+    let register = tokens
+        .first()
+        .and_then(register_number)
+        .unwrap_or_else(|| panic!("{} expects a register", name));
+    (register, offset(&tokens[1..], name))
+}
+
+fn offset(tokens: &[Token], name: &str) -> i32 {
+    let tokens: Vec<&Token> = tokens
+        .iter()
+        .filter(|token| !matches!(token, Token::COMMA))
+        .collect();
+    match tokens.as_slice() {
+        [Token::NUMBER(number)] => number.value as i32,
+        [Token::MINUS, Token::NUMBER(number)] => -(number.value as i32),
+        _ => panic!("{} expects an offset", name),
+    }
+}
+
+pub fn cfi_directive(tokens: &[Token]) -> Option<CfiDirective> {
+    let position = tokens.iter().position(|token| {
+        token
+            .extract_directive()
+            .is_some_and(|directive| directive.value.starts_with(".cfi_"))
+    })?;
+    let name = tokens[position].extract_directive().unwrap().value.as_str();
+    let operands = &tokens[position + 1..];
+    let register = || {
+        operands
+            .first()
+            .and_then(register_number)
+            .unwrap_or_else(|| panic!("{} expects a register", name))
+    };
+
+    let directive = match name {
+        ".cfi_startproc" => CfiDirective::StartProc,
+        ".cfi_endproc" => CfiDirective::EndProc,
+        ".cfi_def_cfa" => {
+            let (register, offset) = register_and_offset(operands, name);
+            CfiDirective::DefCfa(register, offset)
+        }
+        ".cfi_def_cfa_offset" => CfiDirective::DefCfaOffset(offset(operands, name)),
+        ".cfi_def_cfa_register" => CfiDirective::DefCfaRegister(register()),
+        ".cfi_offset" => {
+            let (register, offset) = register_and_offset(operands, name);
+            CfiDirective::Offset(register, offset)
+        }
+        ".cfi_restore" => CfiDirective::Restore(register()),
+        ".cfi_remember_state" => CfiDirective::RememberState,
+        ".cfi_restore_state" => CfiDirective::RestoreState,
+        ".cfi_sections" => {
+            let sections: Vec<&str> = operands
+                .iter()
+                .filter_map(|token| token.extract_directive())
+                .map(|directive| directive.value.as_str())
+                .collect();
+            if let Some(section) = sections
+                .iter()
+                .find(|section| !matches!(**section, ".debug_frame" | ".eh_frame"))
+            {
+                panic!("Unknown .cfi_sections section {}", section);
+            }
+            CfiDirective::Sections(
+                sections.contains(&".debug_frame"),
+                sections.contains(&".eh_frame"),
+            )
+        }
+        _ => panic!("Unsupported CFI directive {}", name),
+    };
+    Some(directive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_frame() {
+        let mut call_frames = CallFrames::new();
+        call_frames.apply(CfiDirective::StartProc, 8);
+        call_frames.apply(CfiDirective::DefCfaOffset(8), 12);
+        call_frames.apply(CfiDirective::Offset(14, -4), 12);
+        call_frames.apply(CfiDirective::Offset(4, -8), 12);
+        call_frames.apply(CfiDirective::EndProc, 16);
+        call_frames.finish();
+
+        let sections = call_frames.sections(ByteOrder::Little);
+        assert_eq!(sections.len(), 1);
+        let section = &sections[0];
+        assert_eq!(
+            section.bytes,
+            [
+                // CIE
+                0x0c, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 1, 0, 2, 0x7c, 14, 0x0c, 13, 0, //
+                // FDE
+                0x14, 0, 0, 0, 0, 0, 0, 0, 8, 0, 0, 0, 8, 0, 0, 0, //
+                0x42, 0x0e, 8, 0x8e, 1, 0x84, 2, 0
+            ]
+        );
+        assert_eq!(
+            section.relocations,
+            vec![
+                (20, ".debug_frame", object::elf::R_ARM_ABS32),
+                (24, ".text", object::elf::R_ARM_ABS32)
+            ]
+        );
+    }
+}
@@ -0,0 +1,335 @@
+// DWARF debug information of -g. The sources are one compile unit covering .text, and its
+// line table maps the offset of every instruction to the file and line it was assembled from.
+
+use object::elf::{R_ARM_ABS32, R_ARM_REL32};
+
+use super::{attributes::push_uleb128, byte_order::ByteOrder};
+
+const DW_TAG_COMPILE_UNIT: u32 = 0x11;
+const DW_AT_NAME: u32 = 0x03;
+const DW_AT_STMT_LIST: u32 = 0x10;
+const DW_AT_LOW_PC: u32 = 0x11;
+const DW_AT_HIGH_PC: u32 = 0x12;
+const DW_AT_LANGUAGE: u32 = 0x13;
+const DW_AT_COMP_DIR: u32 = 0x1b;
+const DW_AT_PRODUCER: u32 = 0x25;
+const DW_FORM_ADDR: u32 = 0x01;
+const DW_FORM_DATA2: u32 = 0x05;
+const DW_FORM_DATA4: u32 = 0x06;
+const DW_FORM_STRING: u32 = 0x08;
+const DW_LANG_MIPS_ASSEMBLER: u16 = 0x8001;
+
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+
+// Line program parameters, addresses advance in halfwords so Thumb code fits too
+const MIN_INSTRUCTION_LENGTH: u32 = 2;
+const LINE_BASE: i32 = -5;
+const LINE_RANGE: i32 = 14;
+const OPCODE_BASE: u8 = 13;
+const STANDARD_OPCODE_LENGTHS: [u8; 12] = [0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+
+// Bytes of one DWARF section, with the offset of each relocated word, the section it points
+// into and the relocation type
+pub struct DebugSection {
+    pub name: &'static str,
+    pub bytes: Vec<u8>,
+    pub relocations: Vec<(u32, &'static str, u32)>,
+}
+
+impl DebugSection {
+    pub fn new(name: &'static str) -> Self {
+        DebugSection {
+            name,
+            bytes: vec![],
+            relocations: vec![],
+        }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16, byte_order: ByteOrder) {
+        self.bytes.extend(byte_order.data_u16(value));
+    }
+
+    pub fn u32(&mut self, value: u32, byte_order: ByteOrder) {
+        self.bytes.extend(byte_order.data_u32(value));
+    }
+
+    pub fn uleb128(&mut self, value: u32) {
+        push_uleb128(&mut self.bytes, value);
+    }
+
+    pub fn sleb128(&mut self, mut value: i32) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+                self.bytes.push(byte);
+                break;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    pub fn string(&mut self, value: &str) {
+        self.bytes.extend(value.as_bytes());
+        self.bytes.push(0);
+    }
+
+    // Offset into another section, the addend stays in place as relocations are REL
+    pub fn address(&mut self, value: u32, section: &'static str, byte_order: ByteOrder) {
+        self.relocations
+            .push((self.bytes.len() as u32, section, R_ARM_ABS32));
+        self.u32(value, byte_order);
+    }
+
+    // Offset into another section relative to this word, for the pcrel pointers of .eh_frame
+    pub fn pc_relative(&mut self, value: u32, section: &'static str, byte_order: ByteOrder) {
+        self.relocations
+            .push((self.bytes.len() as u32, section, R_ARM_REL32));
+        self.u32(value, byte_order);
+    }
+
+    // unit_length is patched once the rest of the unit is written
+    pub fn patch_length(&mut self, at: usize, byte_order: ByteOrder) {
+        let length = (self.bytes.len() - at - 4) as u32;
+        self.bytes[at..at + 4].copy_from_slice(&byte_order.data_u32(length));
+    }
+}
+
+pub struct DebugInfo {
+    // The compile unit is named after the first, includes and other inputs follow
+    files: Vec<String>,
+    comp_dir: String,
+    // .text offset, file index and source line of each instruction
+    rows: Vec<(u32, usize, u32)>,
+}
+
+impl DebugInfo {
+    pub fn new(file_name: &str) -> Self {
+        let comp_dir = std::env::current_dir()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        DebugInfo {
+            files: vec![file_name.to_string()],
+            comp_dir,
+            rows: vec![],
+        }
+    }
+
+    pub fn add_row(&mut self, offset: u32, file_name: &str, line: u32) {
+        if self.rows.last().is_some_and(|(last, _, _)| *last >= offset) {
+            return;
+        }
+
+        let file = match self.files.iter().position(|file| file == file_name) {
+            Some(file) => file,
+            None => {
+                self.files.push(file_name.to_string());
+                self.files.len() - 1
+            }
+        };
+        self.rows.push((offset, file, line));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    // .debug_line, .debug_info, .debug_abbrev and .debug_aranges for a .text of text_size bytes
+    pub fn sections(&self, text_size: u32, byte_order: ByteOrder) -> Vec<DebugSection> {
+        vec![
+            self.line_section(text_size, byte_order),
+            self.info_section(text_size, byte_order),
+            abbrev_section(),
+            aranges_section(text_size, byte_order),
+        ]
+    }
+
+    fn line_section(&self, text_size: u32, byte_order: ByteOrder) -> DebugSection {
+        let mut section = DebugSection::new(".debug_line");
+        section.u32(0, byte_order);
+        section.u16(3, byte_order);
+
+        // header_length counts from after itself to the first opcode
+        section.u32(0, byte_order);
+        let header_start = section.bytes.len();
+        section.u8(MIN_INSTRUCTION_LENGTH as u8);
+        section.u8(1); // default_is_stmt
+        section.u8(LINE_BASE as u8);
+        section.u8(LINE_RANGE as u8);
+        section.u8(OPCODE_BASE);
+        section.bytes.extend(STANDARD_OPCODE_LENGTHS);
+        section.u8(0); // no include directories
+
+        // files are relative to DW_AT_comp_dir, without mtime and size
+        for file in &self.files {
+            section.string(file);
+            section.bytes.extend([0, 0, 0]);
+        }
+        section.u8(0);
+        let header_length = (section.bytes.len() - header_start) as u32;
+        section.bytes[header_start - 4..header_start]
+            .copy_from_slice(&byte_order.data_u32(header_length));
+
+        section.bytes.extend([0, 5, DW_LNE_SET_ADDRESS]);
+        section.address(0, ".text", byte_order);
+
+        let (mut address, mut file, mut line) = (0, 0, 1);
+        for (offset, row_file, row_line) in &self.rows {
+            if *row_file != file {
+                section.u8(DW_LNS_SET_FILE);
+                section.uleb128(*row_file as u32 + 1);
+                file = *row_file;
+            }
+
+            let address_advance = (offset - address) / MIN_INSTRUCTION_LENGTH;
+            let line_advance = *row_line as i32 - line as i32;
+
+            let opcode = (line_advance - LINE_BASE)
+                + LINE_RANGE * address_advance as i32
+                + OPCODE_BASE as i32;
+            if (LINE_BASE..LINE_BASE + LINE_RANGE).contains(&line_advance) && opcode <= 255 {
+                section.u8(opcode as u8);
+            } else {
+                if line_advance != 0 {
+                    section.u8(DW_LNS_ADVANCE_LINE);
+                    section.sleb128(line_advance);
+                }
+                if address_advance != 0 {
+                    section.u8(DW_LNS_ADVANCE_PC);
+                    section.uleb128(address_advance);
+                }
+                section.u8(DW_LNS_COPY);
+            }
+
+            (address, line) = (*offset, *row_line);
+        }
+
+        // the sequence ends past the last instruction
+        let address_advance = (text_size - address).div_ceil(MIN_INSTRUCTION_LENGTH);
+        if address_advance != 0 {
+            section.u8(DW_LNS_ADVANCE_PC);
+            section.uleb128(address_advance);
+        }
+        section.bytes.extend([0, 1, DW_LNE_END_SEQUENCE]);
+
+        section.patch_length(0, byte_order);
+        section
+    }
+
+    fn info_section(&self, text_size: u32, byte_order: ByteOrder) -> DebugSection {
+        let mut section = DebugSection::new(".debug_info");
+        section.u32(0, byte_order);
+        section.u16(3, byte_order);
+        section.address(0, ".debug_abbrev", byte_order);
+        section.u8(4); // address_size
+
+        section.uleb128(1);
+        section.address(0, ".debug_line", byte_order);
+        section.address(0, ".text", byte_order);
+        section.address(text_size, ".text", byte_order);
+        section.string(&self.files[0]);
+        section.string(&self.comp_dir);
+        section.string(concat!("poli-as ", env!("CARGO_PKG_VERSION")));
+        section.u16(DW_LANG_MIPS_ASSEMBLER, byte_order);
+
+        section.patch_length(0, byte_order);
+        section
+    }
+}
+
+// Abbreviation 1, the compile unit without children
+fn abbrev_section() -> DebugSection {
+    let mut section = DebugSection::new(".debug_abbrev");
+    section.uleb128(1);
+    section.uleb128(DW_TAG_COMPILE_UNIT);
+    section.u8(0);
+
+    for (attribute, form) in [
+        (DW_AT_STMT_LIST, DW_FORM_DATA4),
+        (DW_AT_LOW_PC, DW_FORM_ADDR),
+        (DW_AT_HIGH_PC, DW_FORM_ADDR),
+        (DW_AT_NAME, DW_FORM_STRING),
+        (DW_AT_COMP_DIR, DW_FORM_STRING),
+        (DW_AT_PRODUCER, DW_FORM_STRING),
+        (DW_AT_LANGUAGE, DW_FORM_DATA2),
+    ] {
+        section.uleb128(attribute);
+        section.uleb128(form);
+    }
+    section.bytes.extend([0, 0, 0]);
+    section
+}
+
+// One address range, .text, aligned to twice the address size after the header
+fn aranges_section(text_size: u32, byte_order: ByteOrder) -> DebugSection {
+    let mut section = DebugSection::new(".debug_aranges");
+    section.u32(0, byte_order);
+    section.u16(2, byte_order);
+    section.address(0, ".debug_info", byte_order);
+    section.u8(4); // address_size
+    section.u8(0); // segment_size
+    section.u32(0, byte_order);
+
+    section.address(0, ".text", byte_order);
+    section.u32(text_size, byte_order);
+    section.u32(0, byte_order);
+    section.u32(0, byte_order);
+
+    section.patch_length(0, byte_order);
+    section
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_program() {
+        let mut debug_info = DebugInfo::new("hello.s");
+        debug_info.add_row(0, "hello.s", 2);
+        debug_info.add_row(4, "hello.s", 3);
+        debug_info.add_row(8, "hello.s", 40);
+
+        let section = debug_info.line_section(12, ByteOrder::Little);
+        let header_end = 10 + 17 + 1 + 8 + 3 + 1;
+        assert_eq!(
+            &section.bytes[..4],
+            &(section.bytes.len() as u32 - 4).to_le_bytes()
+        );
+        assert_eq!(
+            &section.bytes[header_end..header_end + 7],
+            [0, 5, 2, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            &section.bytes[header_end + 7..],
+            [
+                0x13, // line 2, address 0
+                0x2f, // line 3, address 4
+                DW_LNS_ADVANCE_LINE,
+                37,
+                DW_LNS_ADVANCE_PC,
+                2,
+                DW_LNS_COPY,
+                DW_LNS_ADVANCE_PC,
+                2,
+                0,
+                1,
+                DW_LNE_END_SEQUENCE
+            ]
+        );
+        assert_eq!(
+            section.relocations,
+            vec![(header_end as u32 + 3, ".text", R_ARM_ABS32)]
+        );
+    }
+}
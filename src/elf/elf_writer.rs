@@ -1,12 +1,15 @@
+use object::elf::EF_ARM_EABI_VER5;
 use object::elf::ELFOSABI_SYSV;
 use object::elf::EM_ARM;
 use object::elf::ET_REL;
 use object::elf::SHF_ALLOC;
 use object::elf::SHF_EXECINSTR;
 use object::elf::SHF_INFO_LINK;
+use object::elf::SHF_LINK_ORDER;
 use object::elf::SHF_MERGE;
 use object::elf::SHF_STRINGS;
 use object::elf::SHF_WRITE;
+use object::elf::SHT_ARM_EXIDX;
 use object::elf::SHT_DYNSYM;
 use object::elf::SHT_NOBITS;
 use object::elf::SHT_NULL;
@@ -27,12 +30,15 @@ use object::{Architecture, Endianness};
 use std::fs::File;
 use std::io::BufWriter;
 
+use super::byte_order::ByteOrder;
 use super::section_data::IntermediateSectionId;
 use super::section_data::SectionData;
 
 #[derive(Debug)]
 pub struct ElfWriter {
     num_local: u32, // number of local references
+    e_flags: u32,
+    byte_order: ByteOrder,
     sections: Vec<(IntermediateSectionId, String, SectionHeader, SectionData)>,
 }
 
@@ -40,6 +46,8 @@ impl Clone for ElfWriter {
     fn clone(&self) -> ElfWriter {
         ElfWriter {
             num_local: 0,
+            e_flags: self.e_flags,
+            byte_order: self.byte_order,
             sections: self.sections.clone(),
         }
     }
@@ -61,14 +69,35 @@ impl ElfWriter {
 
         ElfWriter {
             num_local: 0,
+            e_flags: EF_ARM_EABI_VER5,
+            byte_order: ByteOrder::default(),
             sections: Vec::new(),
         }
     }
 
+    // EABI version, float ABI and BE8 flag of the object
+    pub fn set_flags(&mut self, e_flags: u32) {
+        self.e_flags = e_flags;
+    }
+
+    pub fn section_bytes(&self, name: &str) -> Option<&[u8]> {
+        self.sections
+            .iter()
+            .find_map(|(_, section_name, _, data)| match data {
+                SectionData::Bytes(bytes) if section_name == name => Some(bytes.as_slice()),
+                _ => None,
+            })
+    }
+
+    pub fn set_byte_order(&mut self, byte_order: ByteOrder) {
+        self.byte_order = byte_order;
+    }
+
     #[must_use]
     pub fn add_section(&mut self, sh_name: String, data: SectionData) -> IntermediateSectionId {
         let sh_type = match sh_name.as_str() {
-            ".text" | ".data" | ".rodata" | ".comment" => SHT_PROGBITS,
+            ".text" | ".data" | ".rodata" | ".comment" | ".eh_frame" | ".ARM.extab" => SHT_PROGBITS,
+            ".ARM.exidx" => SHT_ARM_EXIDX,
             ".bss" => SHT_NOBITS,
             ".ARM.attributes" => 0x70000003,
             ".strtab" | ".shstrtab" => SHT_STRTAB,
@@ -81,14 +110,16 @@ impl ElfWriter {
         let sh_flags = match sh_name.as_str() {
             ".text" => SHF_ALLOC | SHF_EXECINSTR,
             ".data" | ".bss" => SHF_WRITE | SHF_ALLOC,
-            ".rodata" => SHF_ALLOC,
+            ".rodata" | ".eh_frame" | ".ARM.extab" => SHF_ALLOC,
+            ".ARM.exidx" => SHF_ALLOC | SHF_LINK_ORDER,
             ".debug_str" | ".comment" => SHF_MERGE | SHF_STRINGS,
             s if s.starts_with(".rel") => SHF_INFO_LINK,
             _ => 0,
         };
 
         let sh_addralign = match sh_name.as_str() {
-            ".text" | ".bss" | ".rodata" | ".debug_frame" | ".symtab" => 0x4,
+            ".text" | ".bss" | ".rodata" | ".debug_frame" | ".eh_frame" | ".symtab" => 0x4,
+            ".ARM.exidx" | ".ARM.extab" => 0x4,
             ".data" | ".comment" | ".strtab" | ".shstrtab" => 0x1,
             ".ARM.attributes" => 0x1,
             s if s.starts_with(".rel") => 0x4,
@@ -119,26 +150,18 @@ impl ElfWriter {
             sh_entsize,
         };
 
-        let section_id;
-        if sh_name.starts_with(".rel") {
-            if let Some(pos) = self
+        // Ids stay unique when a relocation section is inserted after the one it applies to
+        let section_id = self.sections.len();
+        let position = match sh_name.strip_prefix(".rel") {
+            Some(target) => self
                 .sections
                 .iter()
-                .position(|(_, name, _, _)| sh_name.ends_with(name.as_str()))
-            {
-                section_id = pos + 1;
-                self.sections
-                    .insert(pos + 1, (section_id, sh_name, section_header, data));
-            } else {
-                section_id = self.sections.len();
-                self.sections
-                    .push((section_id, sh_name, section_header, data));
-            }
-        } else {
-            section_id = self.sections.len();
-            self.sections
-                .push((section_id, sh_name, section_header, data));
-        }
+                .position(|(_, name, _, _)| name == target)
+                .map_or(self.sections.len(), |pos| pos + 1),
+            None => self.sections.len(),
+        };
+        self.sections
+            .insert(position, (section_id, sh_name, section_header, data));
         section_id
     }
 
@@ -177,7 +200,7 @@ impl ElfWriter {
                     if let SectionData::Symbols(symbols) = &section.3 {
                         let last_local_index = symbols
                             .iter()
-                            .rposition(|(_, _, _, sym)| sym.st_info == STB_LOCAL);
+                            .rposition(|(_, _, _, sym)| sym.st_info >> 4 == STB_LOCAL);
                         if let Some(last_local_index) = last_local_index {
                             last_local_index + 2
                         } else {
@@ -191,6 +214,15 @@ impl ElfWriter {
                 };
 
                 updates.push((i, sh_link, sh_info as u32));
+            } else if section.2.sh_type == SHT_ARM_EXIDX {
+                // The index table is ordered by the code it describes
+                if let Some(text_index) = self
+                    .sections
+                    .iter()
+                    .position(|(_, name, _, _)| name == ".text")
+                {
+                    updates.push((i, section_indexes[text_index + 1].0, 0));
+                }
             }
         }
 
@@ -221,7 +253,7 @@ impl ElfWriter {
         for section in &mut self.sections {
             if section.2.sh_type == SHT_REL || section.2.sh_type == SHT_RELA {
                 section.2.sh_offset = writer
-                    .reserve_relocations(section.3.len(), section.2.sh_type == SHT_RELA)
+                    .reserve_relocations(section.3.count(), section.2.sh_type == SHT_RELA)
                     as u64;
             } else if section.2.sh_type != SHT_SYMTAB {
                 section.2.sh_offset =
@@ -240,7 +272,7 @@ impl ElfWriter {
             e_type: ET_REL,
             e_machine: EM_ARM,
             e_entry: 0,
-            e_flags: 0x5000000,
+            e_flags: self.e_flags,
         };
 
         if let Err(e) = writer.write_file_header(&file_header) {
@@ -261,7 +293,7 @@ impl ElfWriter {
                     SectionIndex(section.2.sh_info),
                     SectionIndex(section.2.sh_link),
                     section.2.sh_offset as usize,
-                    section.3.len(),
+                    section.3.count(),
                     section.2.sh_type == SHT_RELA,
                 );
             } else if section.2.sh_type != SHT_SYMTAB {
@@ -278,6 +310,8 @@ impl ElfWriter {
         for (i, section) in self.sections.clone().iter().enumerate() {
             match section.2.sh_type {
                 SHT_REL | SHT_RELA => {
+                    // the data before may end on a halfword, as reserved the entries are aligned
+                    writer.write_align_relocation();
                     if let SectionData::RelocationEntries(relocations) = &section.3 {
                         for rel in relocations {
                             writer.write_relocation(section.2.sh_type == SHT_RELA, &rel.2);
@@ -289,6 +323,7 @@ impl ElfWriter {
                 }
                 _ => {
                     if let SectionData::Bytes(vec) = &section.3 {
+                        writer.write_align(section.2.sh_addralign as usize);
                         writer.write(vec.as_slice());
                     } else {
                         panic!("section data is not SectionData::Bytes");
@@ -312,12 +347,13 @@ impl ElfWriter {
         let file = File::create(file_name.clone()).expect("Was not able to create output file");
         let buf_writer = BufWriter::new(file);
         let mut streaming_buffer = StreamingBuffer::new(buf_writer);
-        let mut writer = Writer::new(Endianness::Little, false, &mut streaming_buffer);
+        let endianness = self.byte_order.endianness();
+        let mut writer = Writer::new(endianness, false, &mut streaming_buffer);
 
         let temp_file = File::create(file_name).expect("Was not able to create output file");
         let temp_buf_writer = BufWriter::new(temp_file);
         let mut temp_streaming_buffer = StreamingBuffer::new(temp_buf_writer);
-        let mut temp_writer = Writer::new(Endianness::Little, false, &mut temp_streaming_buffer);
+        let mut temp_writer = Writer::new(endianness, false, &mut temp_streaming_buffer);
         let mut elf_clone = self.clone();
 
         let (_, _, _, _) = reserve_section_indexes(&mut elf_clone, &mut writer);
@@ -354,6 +390,16 @@ fn reserve_section_indexes<'b>(
 
     section_indexes.push(writer.reserve_null_section_index());
 
+    // Symbols name their section by id, which is not its position once relocations are inserted
+    let positions: Vec<usize> = (0..elf.sections.len())
+        .map(|id| {
+            elf.sections
+                .iter()
+                .position(|(section_id, _, _, _)| *section_id == id)
+                .unwrap()
+        })
+        .collect();
+
     for section in &mut elf.sections {
         match section.2.sh_type {
             SHT_SYMTAB => {
@@ -368,8 +414,9 @@ fn reserve_section_indexes<'b>(
                         string_indexes.push(symbol_name_index);
                         symbol_indexes.push(writer.reserve_symbol_index(None));
                         if !sym.2 {
-                            sym.3.section = Some(section_indexes[sym.0 + 1]);
-                            sym.3.st_shndx = section_indexes[sym.0 + 1].0 as u16;
+                            let index = section_indexes[positions[sym.0] + 1];
+                            sym.3.section = Some(index);
+                            sym.3.st_shndx = index.0 as u16;
                         }
                     }
                 }
@@ -394,3 +441,48 @@ fn reserve_section_indexes<'b>(
         symbol_indexes,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use object::elf::{R_ARM_ABS32, STB_GLOBAL, STT_OBJECT};
+    use object::{Object, ObjectSection, ObjectSymbol};
+
+    use super::*;
+
+    #[test]
+    fn test_symbol_section_after_relocations() {
+        let mut elf = ElfWriter::new();
+        let _ = elf.add_section(".text".to_string(), SectionData::Bytes(vec![0; 8]));
+        let data = elf.add_section(".data".to_string(), SectionData::Bytes(vec![0; 4]));
+
+        let mut symbols = SectionData::Symbols(vec![]);
+        let value = symbols.add_symbol(
+            data,
+            "value".to_string(),
+            0,
+            0,
+            STB_GLOBAL << 4 | STT_OBJECT,
+            None,
+        );
+        let _ = elf.add_section(".symtab".to_string(), symbols);
+
+        // .rel.text lands between .text and .data, which must not move the symbol
+        let mut relocations = SectionData::RelocationEntries(vec![]);
+        relocations.add_relocation_entry(value.unwrap(), 4, None, R_ARM_ABS32);
+        let _ = elf.add_section(".rel.text".to_string(), relocations);
+
+        let path = env::temp_dir().join(format!("elf_writer_{}.o", process::id()));
+        elf.write_elf(path.to_string_lossy().into_owned());
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let file = object::File::parse(&*bytes).unwrap();
+        let symbol = file.symbols().find(|s| s.name() == Ok("value")).unwrap();
+        let section = file
+            .section_by_index(symbol.section_index().unwrap())
+            .unwrap();
+        assert_eq!(section.name(), Ok(".data"));
+    }
+}
@@ -1,2 +1,7 @@
+pub mod attributes;
+pub mod byte_order;
+pub mod cfi;
+pub mod dwarf;
 pub mod elf_writer;
 pub mod section_data;
+pub mod unwind;
@@ -22,6 +22,15 @@ impl SectionData {
         }
     }
 
+    // Number of entries, the object writer sizes symbol and relocation tables by count
+    pub fn count(&self) -> usize {
+        match self {
+            SectionData::Bytes(v) => v.len(),
+            SectionData::Symbols(v) => v.len(),
+            SectionData::RelocationEntries(v) => v.len(),
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
@@ -0,0 +1,559 @@
+// Exception handling tables of the ARM EHABI, from the .fnstart/.fnend directives. Each
+// function gets an .ARM.exidx entry, where up to three unwind opcodes fit inline for
+// __aeabi_unwind_cpp_pr0. Longer sequences, other personality routines and the .handlerdata
+// words go to an .ARM.extab entry instead.
+
+use object::elf::{R_ARM_NONE, R_ARM_PREL31};
+
+use crate::{
+    lexer::operations::vfp_op::parse_register_list,
+    token::{register::FpRegister, Token},
+};
+
+use super::byte_order::ByteOrder;
+
+const EXIDX_CANTUNWIND: u32 = 1;
+const FINISH: u8 = 0xb0;
+const SP: u32 = 13;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnwindDirective {
+    FnStart,
+    FnEnd,
+    CantUnwind,
+    // r0-r15, a bit each
+    Save(u16),
+    // First d register and count
+    VSave(u8, u8),
+    // Frame pointer, the register it is set from and the offset
+    SetFp(u32, u32, i32),
+    Pad(i32),
+    Personality(String),
+    PersonalityIndex(u32),
+    HandlerData,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Personality {
+    Index(u32),
+    Routine(String),
+}
+
+#[derive(Default)]
+struct Function {
+    start: u32,
+    // Opcodes in the order the directives add them, unwinding runs them backwards
+    opcodes: Vec<Vec<u8>>,
+    // Bytes pushed so far, and the stack adjustment not yet turned into opcodes
+    frame_size: i32,
+    pending_offset: i32,
+    // Frame pointer and its distance from the top of the frame
+    fp: Option<u32>,
+    fp_offset: i32,
+    personality: Option<Personality>,
+    cant_unwind: bool,
+    handler_data: Option<Vec<u8>>,
+}
+
+impl Function {
+    // vsp += offset as the shortest opcodes do it
+    fn flush_pending(&mut self) {
+        let offset = self.pending_offset;
+        self.pending_offset = 0;
+
+        match offset {
+            0 => {}
+            0x204.. => {
+                let mut opcode = vec![0xb2];
+                let mut value = (offset - 0x204) as u32 >> 2;
+                loop {
+                    let byte = (value & 0x7f) as u8;
+                    value >>= 7;
+                    if value == 0 {
+                        opcode.push(byte);
+                        break;
+                    }
+                    opcode.push(byte | 0x80);
+                }
+                self.opcodes.push(opcode);
+            }
+            0x104.. => {
+                self.opcodes.push(vec![0x3f]);
+                self.opcodes.push(vec![((offset - 0x104) >> 2) as u8]);
+            }
+            1.. => self.opcodes.push(vec![((offset - 4) >> 2) as u8]),
+            _ => {
+                let mut offset = -offset;
+                while offset > 0x100 {
+                    self.opcodes.push(vec![0x7f]);
+                    offset -= 0x100;
+                }
+                self.opcodes.push(vec![0x40 | ((offset - 4) >> 2) as u8]);
+            }
+        }
+    }
+
+    // pop r4-r[4+n] and lr when they are a block, otherwise the register mask, then r0-r3
+    fn save(&mut self, registers: u16) {
+        self.flush_pending();
+
+        let high = registers & 0xfff0;
+        if high != 0 {
+            let block = (4..12).take_while(|r| registers & 1 << r != 0).count() as u16;
+            if block == 0 || high & (0xfff0 << block) & 0xbff0 != 0 {
+                let opcode = 0x8000 | (registers >> 4);
+                self.opcodes.push(opcode.to_be_bytes().to_vec());
+            } else {
+                let lr = if registers & 1 << 14 != 0 { 0x08 } else { 0 };
+                self.opcodes.push(vec![0xa0 | lr | (block - 1) as u8]);
+            }
+        } else if registers == 0 {
+            panic!(".save needs at least one register");
+        }
+        if registers & 0xf != 0 {
+            self.opcodes.push(vec![0xb1, (registers & 0xf) as u8]);
+        }
+
+        self.frame_size += 4 * registers.count_ones() as i32;
+    }
+
+    // vpop of d16-d31 and of d0-d15, the lower ones are popped first
+    fn vsave(&mut self, first: u8, count: u8) {
+        self.flush_pending();
+
+        let last = first + count;
+        if last > 16 {
+            let start = first.max(16) - 16;
+            let count = last - first.max(16);
+            self.opcodes.push(vec![0xc8, start << 4 | (count - 1)]);
+        }
+        if first < 16 {
+            let count = last.min(16) - first;
+            self.opcodes.push(vec![0xc9, first << 4 | (count - 1)]);
+        }
+
+        self.frame_size += 8 * count as i32;
+    }
+
+    // sp is restored from the frame pointer before the registers are popped
+    fn finish(&mut self) {
+        match self.fp {
+            Some(fp) => {
+                self.pending_offset += self.fp_offset - self.frame_size;
+                self.flush_pending();
+                self.opcodes.push(vec![0x90 | fp as u8]);
+            }
+            None => self.flush_pending(),
+        }
+    }
+
+    fn set_personality(&mut self, personality: Personality) {
+        if self.cant_unwind {
+            panic!("A personality routine can't be set after .cantunwind");
+        }
+        if self.personality.is_some() {
+            panic!("Duplicate personality routine of the function");
+        }
+        self.personality = Some(personality);
+    }
+}
+
+// .ARM.exidx and .ARM.extab, with the offset, symbol and type of their relocations. Offsets
+// into .text and .ARM.extab are relative to their section symbols.
+pub struct UnwindSections {
+    pub exidx: Vec<u8>,
+    pub extab: Vec<u8>,
+    pub relocations: Vec<(&'static str, u32, String, u32)>,
+}
+
+#[derive(Default)]
+pub struct UnwindTables {
+    functions: Vec<Function>,
+    current: Option<Function>,
+}
+
+impl UnwindTables {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // offset is where the directive sits in .text
+    pub fn apply(&mut self, directive: UnwindDirective, offset: u32) {
+        if directive == UnwindDirective::FnStart {
+            if self.current.is_some() {
+                panic!(".fnstart without .fnend of the function before");
+            }
+            self.current = Some(Function {
+                start: offset,
+                ..Default::default()
+            });
+            return;
+        }
+
+        let Some(function) = &mut self.current else {
+            panic!("Unwind directive outside of .fnstart and .fnend");
+        };
+        if function.handler_data.is_some() && directive != UnwindDirective::FnEnd {
+            panic!("Only .fnend can follow .handlerdata");
+        }
+
+        match directive {
+            UnwindDirective::FnEnd => {
+                let mut function = self.current.take().unwrap();
+                function.finish();
+                self.functions.push(function);
+            }
+            UnwindDirective::CantUnwind => {
+                if function.personality.is_some() {
+                    panic!(".cantunwind can't be used with a personality routine");
+                }
+                function.cant_unwind = true;
+            }
+            UnwindDirective::Save(registers) => function.save(registers),
+            UnwindDirective::VSave(first, count) => function.vsave(first, count),
+            UnwindDirective::SetFp(fp, base, offset) => {
+                if base == SP {
+                    function.fp_offset = function.frame_size - offset;
+                } else if function.fp == Some(base) {
+                    function.fp_offset -= offset;
+                } else {
+                    panic!(".setfp has to be relative to sp or the frame pointer");
+                }
+                function.fp = Some(fp);
+            }
+            UnwindDirective::Pad(offset) => {
+                function.frame_size += offset;
+                function.pending_offset += offset;
+            }
+            UnwindDirective::Personality(name) => {
+                function.set_personality(Personality::Routine(name))
+            }
+            UnwindDirective::PersonalityIndex(index) if index < 3 => {
+                function.set_personality(Personality::Index(index))
+            }
+            UnwindDirective::PersonalityIndex(index) => {
+                panic!("Invalid .personalityindex {}", index)
+            }
+            UnwindDirective::HandlerData => {
+                if function.cant_unwind {
+                    panic!(".handlerdata can't be used with .cantunwind");
+                }
+                function.handler_data = Some(vec![]);
+            }
+            UnwindDirective::FnStart => unreachable!(),
+        }
+    }
+
+    // The .word data after .handlerdata belongs to the .ARM.extab entry
+    pub fn in_handler_data(&self) -> bool {
+        self.current
+            .as_ref()
+            .is_some_and(|function| function.handler_data.is_some())
+    }
+
+    pub fn add_handler_data(&mut self, bytes: Vec<u8>) {
+        if let Some(handler_data) = self
+            .current
+            .as_mut()
+            .and_then(|function| function.handler_data.as_mut())
+        {
+            handler_data.extend(bytes);
+        }
+    }
+
+    pub fn finish(&self) {
+        if self.current.is_some() {
+            panic!(".fnstart without .fnend at the end of the file");
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    pub fn sections(&self, byte_order: ByteOrder) -> UnwindSections {
+        let mut sections = UnwindSections {
+            exidx: vec![],
+            extab: vec![],
+            relocations: vec![],
+        };
+
+        for function in &self.functions {
+            let entry = sections.exidx.len() as u32;
+            sections
+                .relocations
+                .push((".ARM.exidx", entry, ".text".to_string(), R_ARM_PREL31));
+            sections.exidx.extend(byte_order.data_u32(function.start));
+
+            if function.cant_unwind {
+                sections.exidx.extend(byte_order.data_u32(EXIDX_CANTUNWIND));
+                continue;
+            }
+
+            let opcodes: Vec<u8> = function.opcodes.iter().rev().flatten().copied().collect();
+            let personality = function.personality.clone().unwrap_or(match opcodes.len() {
+                0..=3 => Personality::Index(0),
+                _ => Personality::Index(1),
+            });
+
+            // The routines of the compact model are pulled in by a reference from the entry
+            let header = match &personality {
+                Personality::Index(0) if opcodes.len() > 3 => {
+                    panic!("Too many unwind opcodes for __aeabi_unwind_cpp_pr0")
+                }
+                Personality::Index(index) => {
+                    sections.relocations.push((
+                        ".ARM.exidx",
+                        entry,
+                        format!("__aeabi_unwind_cpp_pr{}", index),
+                        R_ARM_NONE,
+                    ));
+                    match index {
+                        0 => vec![0x80],
+                        _ => vec![0x80 | *index as u8, 0],
+                    }
+                }
+                Personality::Routine(_) => vec![0],
+            };
+
+            if personality == Personality::Index(0) && function.handler_data.is_none() {
+                let word = pack(&[header, opcodes].concat());
+                sections.exidx.extend(byte_order.data_u32(word[0]));
+                continue;
+            }
+
+            let table = sections.extab.len() as u32;
+            sections.relocations.push((
+                ".ARM.exidx",
+                entry + 4,
+                ".ARM.extab".to_string(),
+                R_ARM_PREL31,
+            ));
+            sections.exidx.extend(byte_order.data_u32(table));
+
+            if let Personality::Routine(name) = &personality {
+                sections
+                    .relocations
+                    .push((".ARM.extab", table, name.clone(), R_ARM_PREL31));
+                sections.extab.extend(byte_order.data_u32(0));
+            }
+
+            // The byte after the header counts the words after the first one
+            let mut bytes = [header.clone(), opcodes].concat();
+            let words = bytes.len().div_ceil(4) as u8;
+            if header.len() == 2 {
+                bytes[1] = words - 1;
+            } else if let Personality::Routine(_) = personality {
+                bytes[0] = words - 1;
+            }
+            for word in pack(&bytes) {
+                sections.extab.extend(byte_order.data_u32(word));
+            }
+            sections
+                .extab
+                .extend(function.handler_data.clone().unwrap_or_default());
+        }
+
+        sections
+    }
+}
+
+// Unwind opcodes fill words from the most significant byte, the last one padded with finish
+fn pack(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks(4)
+        .map(|chunk| {
+            let mut word = [FINISH; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u32::from_be_bytes(word)
+        })
+        .collect()
+}
+
+// r0-r15 with the fp, ip, sb and sl aliases the compilers use
+fn core_register(token: &Token) -> Option<u32> {
+    match token {
+        Token::REGISTER(register) => Some(register.to_num() as u32),
+        Token::LABELREF(name) => match name.to_lowercase().as_str() {
+            "sb" => Some(9),
+            "sl" => Some(10),
+            "fp" => Some(11),
+            "ip" => Some(12),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn register_list(name: &str, tokens: &[Token]) -> u16 {
+    let list = match tokens {
+        [Token::LBRACE, list @ .., Token::RBRACE] => list,
+        _ => panic!("{} expects a register list", name),
+    };
+
+    let mut registers = 0;
+    let mut i = 0;
+    while i < list.len() {
+        let first =
+            core_register(&list[i]).unwrap_or_else(|| panic!("Invalid register list of {}", name));
+        let last = match (list.get(i + 1), list.get(i + 2).and_then(core_register)) {
+            (Some(Token::MINUS), Some(last)) if last >= first => {
+                i += 2;
+                last
+            }
+            (Some(Token::MINUS), _) => panic!("Invalid register range of {}", name),
+            _ => first,
+        };
+        for register in first..=last {
+            registers |= 1 << register;
+        }
+        i += 1;
+    }
+    registers
+}
+
+fn immediate(name: &str, token: Option<&Token>) -> i32 {
+    match token {
+        Some(Token::IMMEDIATE(immediate)) => immediate.number as i32,
+        _ => panic!("{} expects an immediate", name),
+    }
+}
+
+pub fn unwind_directive(tokens: &[Token]) -> Option<UnwindDirective> {
+    let position = tokens.iter().position(|token| {
+        token.extract_directive().is_some_and(|directive| {
+            matches!(
+                directive.value.as_str(),
+                ".fnstart"
+                    | ".fnend"
+                    | ".cantunwind"
+                    | ".save"
+                    | ".vsave"
+                    | ".setfp"
+                    | ".pad"
+                    | ".personality"
+                    | ".personalityindex"
+                    | ".handlerdata"
+            )
+        })
+    })?;
+    let name = tokens[position].extract_directive().unwrap().value.as_str();
+    let operands = &tokens[position + 1..];
+
+    let directive = match name {
+        ".fnstart" => UnwindDirective::FnStart,
+        ".fnend" => UnwindDirective::FnEnd,
+        ".cantunwind" => UnwindDirective::CantUnwind,
+        ".handlerdata" => UnwindDirective::HandlerData,
+        ".save"
+            if operands
+                .iter()
+                .any(|token| matches!(token, Token::FPREGISTER(_))) =>
+        {
+            panic!(".save of VFP registers is not supported, use .vsave")
+        }
+        ".save" => UnwindDirective::Save(register_list(name, operands)),
+        ".vsave" => {
+            let list = match operands {
+                [Token::LBRACE, list @ .., Token::RBRACE] => list,
+                _ => panic!(".vsave expects a register list"),
+            };
+            let registers: Vec<u8> = parse_register_list(list)
+                .iter()
+                .map(|register| match register {
+                    FpRegister::Double(number) => *number,
+                    _ => panic!(".vsave only takes d registers"),
+                })
+                .collect();
+            let first = registers[0];
+            if registers
+                .iter()
+                .zip(first..)
+                .any(|(register, n)| *register != n)
+            {
+                panic!(".vsave registers have to be consecutive");
+            }
+            UnwindDirective::VSave(first, registers.len() as u8)
+        }
+        ".setfp" => {
+            let fp = operands.first().and_then(core_register);
+            let base = operands.get(1).and_then(core_register);
+            let (Some(fp), Some(base)) = (fp, base) else {
+                panic!(".setfp expects two registers");
+            };
+            let offset = match operands.get(2) {
+                Some(token) => immediate(name, Some(token)),
+                None => 0,
+            };
+            UnwindDirective::SetFp(fp, base, offset)
+        }
+        ".pad" => UnwindDirective::Pad(immediate(name, operands.first())),
+        ".personality" => match operands {
+            [Token::LABELREF(routine)] => UnwindDirective::Personality(routine.clone()),
+            _ => panic!(".personality expects a symbol"),
+        },
+        ".personalityindex" => match operands {
+            [Token::NUMBER(number)] => UnwindDirective::PersonalityIndex(number.value),
+            _ => panic!(".personalityindex expects a number"),
+        },
+        _ => unreachable!(),
+    };
+    Some(directive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unwind_opcodes() {
+        let mut tables = UnwindTables::new();
+
+        // push {r4, r5, r11, lr}; add r11, sp, #8; sub sp, sp, #16
+        tables.apply(UnwindDirective::FnStart, 0);
+        tables.apply(UnwindDirective::Save(0x4830), 4);
+        tables.apply(UnwindDirective::SetFp(11, 13, 8), 8);
+        tables.apply(UnwindDirective::Pad(16), 12);
+        tables.apply(UnwindDirective::FnEnd, 16);
+
+        // vpush {d8-d9}; push {r0, r4, lr}
+        tables.apply(UnwindDirective::FnStart, 16);
+        tables.apply(UnwindDirective::VSave(8, 2), 20);
+        tables.apply(UnwindDirective::Save(0x4011), 24);
+        tables.apply(UnwindDirective::FnEnd, 28);
+
+        tables.apply(UnwindDirective::FnStart, 28);
+        tables.apply(UnwindDirective::CantUnwind, 28);
+        tables.apply(UnwindDirective::FnEnd, 32);
+
+        // push {r4, lr}; sub sp, sp, #8
+        tables.apply(UnwindDirective::FnStart, 32);
+        tables.apply(UnwindDirective::Save(0x4010), 36);
+        tables.apply(UnwindDirective::Pad(8), 40);
+        tables.apply(UnwindDirective::FnEnd, 44);
+        tables.finish();
+
+        let sections = tables.sections(ByteOrder::Little);
+        let words: Vec<u32> = sections
+            .exidx
+            .chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        assert_eq!(words, [0, 0, 16, 8, 28, EXIDX_CANTUNWIND, 32, 0x8001a8b0]);
+
+        let words: Vec<u32> = sections
+            .extab
+            .chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        assert_eq!(words, [0x81019b41, 0x8483b0b0, 0x8101b101, 0xa8c981b0]);
+
+        let none = (
+            ".ARM.exidx",
+            8,
+            "__aeabi_unwind_cpp_pr1".to_string(),
+            R_ARM_NONE,
+        );
+        assert!(sections.relocations.contains(&none));
+    }
+}
@@ -107,7 +107,10 @@ impl Cpu {
                         InstructionName::MVN => {
                             self.regs.set(expr.reg_d.to_num(), negate_u32(rm));
                         }
-                        InstructionName::CMP => {}
+                        InstructionName::CMP
+                        | InstructionName::CMN
+                        | InstructionName::TST
+                        | InstructionName::TEQ => {}
                         _ => {
                             panic!("Invalid instruction")
                         }
@@ -0,0 +1,151 @@
+// Flat images for flashing tools: raw binary, Intel HEX and Motorola S-records. They hold the
+// bytes of a fully resolved program loaded at a base address, there is nothing to relocate.
+
+use std::fs;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Elf,
+    Binary,
+    IntelHex,
+    Srec,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "elf" => Some(OutputFormat::Elf),
+            "binary" | "bin" => Some(OutputFormat::Binary),
+            "ihex" | "hex" => Some(OutputFormat::IntelHex),
+            "srec" => Some(OutputFormat::Srec),
+            _ => None,
+        }
+    }
+}
+
+// Data bytes in each HEX and S-record line
+const RECORD_SIZE: usize = 16;
+
+pub fn write_image(format: OutputFormat, base: u32, image: &[u8], file_name: &str) {
+    let contents = match format {
+        OutputFormat::Binary => image.to_vec(),
+        OutputFormat::IntelHex => intel_hex(base, image).into_bytes(),
+        OutputFormat::Srec => srec(base, image).into_bytes(),
+        OutputFormat::Elf => panic!("ELF files are written by the ElfWriter"),
+    };
+
+    fs::write(file_name, contents).expect("Was not able to create output file");
+}
+
+fn end_address(base: u32, image: &[u8]) -> u32 {
+    let end = base as u64 + image.len().saturating_sub(1) as u64;
+    u32::try_from(end).expect("Image doesn't fit in the 32-bit address space")
+}
+
+// :LLAAAATT data CC, the checksum makes the bytes of the record add up to 0
+fn hex_record(record_type: u8, address: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(address.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend(data);
+
+    let checksum = bytes
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+        .wrapping_neg();
+    bytes.push(checksum);
+
+    let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!(":{}\n", hex)
+}
+
+// Addresses above 64K go through extended linear address records, which give the upper
+// halfword of the data records that follow
+fn intel_hex(base: u32, image: &[u8]) -> String {
+    end_address(base, image);
+
+    let mut output = String::new();
+    let mut upper = None;
+
+    for (index, chunk) in image.chunks(RECORD_SIZE).enumerate() {
+        let address = base + (index * RECORD_SIZE) as u32;
+
+        // a record can't cross into the next 64K
+        let split = (0x10000 - (address & 0xffff) as usize).min(chunk.len());
+        for (address, data) in [
+            (address, &chunk[..split]),
+            (address + split as u32, &chunk[split..]),
+        ] {
+            if data.is_empty() {
+                continue;
+            }
+            if upper != Some(address >> 16) {
+                upper = Some(address >> 16);
+                output.push_str(&hex_record(4, 0, &((address >> 16) as u16).to_be_bytes()));
+            }
+            output.push_str(&hex_record(0, address as u16, data));
+        }
+    }
+
+    output.push_str(&hex_record(1, 0, &[]));
+    output
+}
+
+// Stype count address data checksum, the count covers the address, data and checksum and the
+// checksum is the ones' complement of the sum of the count, address and data
+fn srec_record(record_type: u8, address: u32, address_size: usize, data: &[u8]) -> String {
+    let mut bytes = vec![(address_size + data.len() + 1) as u8];
+    bytes.extend(&address.to_be_bytes()[4 - address_size..]);
+    bytes.extend(data);
+
+    let checksum = !bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    bytes.push(checksum);
+
+    let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!("S{}{}\n", record_type, hex)
+}
+
+// S1, S2 or S3 data records depending on how wide the addresses are, ended by the matching
+// S9, S8 or S7 record with the base as start address
+fn srec(base: u32, image: &[u8]) -> String {
+    let (data_type, end_type, address_size) = match end_address(base, image) {
+        0..=0xffff => (1, 9, 2),
+        0x10000..=0xffffff => (2, 8, 3),
+        _ => (3, 7, 4),
+    };
+
+    let mut output = srec_record(0, 0, 2, b"poli-as");
+
+    let records = image.chunks(RECORD_SIZE).len();
+    for (index, chunk) in image.chunks(RECORD_SIZE).enumerate() {
+        let address = base + (index * RECORD_SIZE) as u32;
+        output.push_str(&srec_record(data_type, address, address_size, chunk));
+    }
+
+    if let Ok(count) = u16::try_from(records) {
+        output.push_str(&srec_record(5, count as u32, 2, &[]));
+    }
+
+    output.push_str(&srec_record(end_type, base, address_size, &[]));
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex_and_srec_records() {
+        let image = [0x01, 0x00, 0xa0, 0xe3];
+
+        assert_eq!(
+            intel_hex(0x4000_fffe, &image),
+            ":020000044000BA\n:02FFFE00010000\n:020000044001B9\n:02000000A0E37B\n:00000001FF\n"
+        );
+        assert_eq!(
+            srec(0x100, &image),
+            "S00A0000706F6C692D617340\nS10701000100A0E373\nS5030001FB\nS9030100FB\n"
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use crate::token::{
-    immediate::Immediate, instruction::Instruction, instruction_name::InstructionName,
+    immediate::Immediate,
+    instruction::{ConditionCode, Instruction},
+    instruction_name::InstructionName,
 };
 
 use super::{
@@ -7,9 +9,15 @@ use super::{
         ls_imm_index::{IndexMode, LoadStoreImmediateExpression},
         ls_multiple::LoadStoreMultipleExpression,
         ls_reg_index::LoadStoreRegisterExpression,
+        reg_literal::check_immediate_possible,
         Expression,
     },
     machine_code_builder::MachineCodeInstruction,
+    media::generate_media,
+    operations::{
+        coprocessor_op::is_coprocessor_op, load_store_op::is_exclusive_op, media_op::is_media_op,
+    },
+    vfp::generate_vfp,
 };
 
 #[derive(Debug)]
@@ -28,7 +36,11 @@ impl CpuOperation {
 
     pub fn to_machine_code(&self) -> MachineCodeInstruction {
         let mut code = MachineCodeInstruction::new();
-        let condition_mask = self.instruction.condition.to_machine_code();
+        let condition_mask = if is_unconditional(&self.instruction) || self.is_blx_immediate() {
+            15 << 28
+        } else {
+            self.instruction.condition.to_machine_code()
+        };
         code.push_mask((15 << 28) as u32, condition_mask);
 
         let machine_code = self.gen_machine_code();
@@ -39,6 +51,18 @@ impl CpuOperation {
     }
 
     fn gen_machine_code(&self) -> u32 {
+        if self.instruction.value.is_vfp() {
+            return generate_vfp(self);
+        };
+
+        if is_coprocessor_op(&self.instruction.value) {
+            return self.generate_coprocessor();
+        };
+
+        if is_media_op(&self.instruction.value) {
+            return generate_media(self);
+        };
+
         if is_load_store_multiple(&self.instruction) {
             return CpuOperation::generate_load_store_multiple(
                 &self.instruction,
@@ -49,6 +73,10 @@ impl CpuOperation {
             );
         };
 
+        if is_exclusive(&self.instruction) {
+            return self.generate_load_store_exclusive();
+        };
+
         if is_load_store(&self.instruction) {
             return self.generate_load_store();
         };
@@ -57,10 +85,14 @@ impl CpuOperation {
             return self.generate_proc();
         };
 
-        if is_bx(&self.instruction) {
+        if is_bx(&self.instruction) && !self.is_blx_immediate() {
             return self.generate_bx();
         };
 
+        if is_system(&self.instruction) {
+            return self.generate_system();
+        };
+
         if is_branch(&self.instruction) {
             return self.generate_b();
         };
@@ -68,8 +100,19 @@ impl CpuOperation {
         panic!("Invalid Instruction");
     }
 
+    // blx label switches to Thumb state, it only exists in the unconditional space
+    fn is_blx_immediate(&self) -> bool {
+        self.instruction.value == InstructionName::BLX
+            && matches!(self.expression, Expression::Immediate(_))
+    }
+
     fn generate_bx(&self) -> u32 {
-        let base: u32 = 0x012fff10;
+        let base: u32 = match self.instruction.value {
+            InstructionName::BX => 0x012fff10,
+            InstructionName::BXJ => 0x012fff20,
+            InstructionName::BLX => 0x012fff30,
+            _ => panic!("Expected branch and exchange instruction"),
+        };
 
         let reg = match self.expression {
             Expression::Register(ref reg) => reg.to_machine_code(),
@@ -79,23 +122,165 @@ impl CpuOperation {
         base | reg
     }
 
+    fn generate_system(&self) -> u32 {
+        use InstructionName::*;
+        match (&self.instruction.value, &self.expression) {
+            (MRS, Expression::StatusRegister(expr)) => {
+                let reg_d = expr.register.expect("mrs needs a destination").to_num() as u32;
+                0x010f0000 | expr.status_register.to_machine_code() | reg_d << 12
+            }
+            (MSR, Expression::StatusRegister(expr)) => {
+                let base = 0x0120f000
+                    | expr.status_register.to_machine_code()
+                    | expr.status_register.field_mask() << 16;
+
+                match (&expr.register, &expr.literal) {
+                    (Some(reg_n), None) => base | reg_n.to_num() as u32,
+                    (None, Some(literal)) => {
+                        let (rotation, lower_byte) = check_immediate_possible(literal.to_num())
+                            .expect("Immediate value is too large");
+                        base | 1 << 25 | (rotation as u32) << 8 | lower_byte as u32
+                    }
+                    _ => panic!("Invalid msr operands"),
+                }
+            }
+            (CPS | CPSIE | CPSID, Expression::ChangeProcessorState(expr)) => {
+                let imod = match self.instruction.value {
+                    CPSIE => 0b10,
+                    CPSID => 0b11,
+                    _ => 0b00,
+                };
+                0x01000000 | imod << 18 | expr.to_machine_code()
+            }
+            (SVC, Expression::Immediate(imm)) => 0x0f000000 | imm.to_machine_code(),
+            (BKPT | UDF, Expression::Immediate(imm)) => {
+                let base = match self.instruction.value {
+                    BKPT => 0x01200070,
+                    _ => 0x07f000f0,
+                };
+                let imm = imm.to_machine_code();
+                base | (imm >> 4) << 8 | (imm & 0xf)
+            }
+            (NOP | YIELD | WFE | WFI | SEV, Expression::Empty) => {
+                let hint = match self.instruction.value {
+                    NOP => 0,
+                    YIELD => 1,
+                    WFE => 2,
+                    WFI => 3,
+                    _ => 4,
+                };
+                0x0320f000 | hint
+            }
+            (DMB | DSB | ISB, Expression::Immediate(option)) => {
+                let base = match self.instruction.value {
+                    DSB => 0x057ff040,
+                    DMB => 0x057ff050,
+                    _ => 0x057ff060,
+                };
+                base | option.to_machine_code()
+            }
+            _ => panic!("Invalid system instruction"),
+        }
+    }
+
+    // The coprocessor number sits in bits 11:8 of all of them
+    pub fn generate_coprocessor(&self) -> u32 {
+        use InstructionName::*;
+
+        match (&self.instruction.value, &self.expression) {
+            (MCR | MCR2 | MRC | MRC2, Expression::Coprocessor(expr)) => {
+                let load = matches!(self.instruction.value, MRC | MRC2) as u32;
+                0x0e000010
+                    | (expr.opc1 as u32) << 21
+                    | load << 20
+                    | (expr.cp_registers[0] as u32) << 16
+                    | (expr.registers[0].to_num() as u32) << 12
+                    | (expr.coprocessor as u32) << 8
+                    | (expr.opc2 as u32) << 5
+                    | expr.cp_registers[1] as u32
+            }
+            (MCRR | MCRR2 | MRRC | MRRC2, Expression::Coprocessor(expr)) => {
+                let load = matches!(self.instruction.value, MRRC | MRRC2) as u32;
+                0x0c400000
+                    | load << 20
+                    | (expr.registers[1].to_num() as u32) << 16
+                    | (expr.registers[0].to_num() as u32) << 12
+                    | (expr.coprocessor as u32) << 8
+                    | (expr.opc1 as u32) << 4
+                    | expr.cp_registers[0] as u32
+            }
+            (CDP | CDP2, Expression::Coprocessor(expr)) => {
+                0x0e000000
+                    | (expr.opc1 as u32) << 20
+                    | (expr.cp_registers[1] as u32) << 16
+                    | (expr.cp_registers[0] as u32) << 12
+                    | (expr.coprocessor as u32) << 8
+                    | (expr.opc2 as u32) << 5
+                    | expr.cp_registers[2] as u32
+            }
+            (_, Expression::CoprocessorLoadStore(expr)) => {
+                let load = matches!(self.instruction.value, LDC | LDC2 | LDCL | LDC2L) as u32;
+                let long = matches!(self.instruction.value, LDCL | LDC2L | STCL | STC2L) as u32;
+
+                let index = match expr.index_mode {
+                    IndexMode::Pre(index) => 1 << 24 | (index.write_back as u32) << 21,
+                    IndexMode::Post => 1 << 21,
+                    IndexMode::None => 0,
+                };
+
+                let (up, imm) = match expr.option {
+                    Some(option) => (1, option as u32),
+                    None => ((expr.offset >= 0) as u32, expr.offset.unsigned_abs() / 4),
+                };
+
+                0x0c000000
+                    | index
+                    | up << 23
+                    | long << 22
+                    | load << 20
+                    | (expr.base.to_num() as u32) << 16
+                    | (expr.crd as u32) << 12
+                    | (expr.coprocessor as u32) << 8
+                    | imm
+            }
+            _ => panic!("Invalid coprocessor instruction"),
+        }
+    }
+
     fn generate_b(&self) -> u32 {
         let base: u32 = 0x0a000000;
 
+        let offset = match self.expression {
+            Expression::Immediate(ref imm) => imm.to_machine_code() as i32,
+            _ => panic!("Expected register"),
+        };
+
+        if !(-(1 << 25)..(1 << 25)).contains(&offset) {
+            panic!("Branch target out of range (+/-32MB)");
+        }
+
+        // blx lands on a Thumb instruction, bit 1 of the offset goes in the H bit
         let link: u32 = match self.instruction.value {
             InstructionName::BL => 0x01000000,
             InstructionName::B => 0,
+            InstructionName::BLX if self.instruction.condition != ConditionCode::Al => {
+                panic!("BLX to a label can't be conditional in ARM state")
+            }
+            InstructionName::BLX if offset % 2 != 0 => {
+                panic!("Branch target must be halfword aligned")
+            }
+            InstructionName::BLX => (offset as u32 >> 1 & 1) << 24,
             _ => panic!("Expected branch instruction"),
         };
 
-        let base = base | link;
+        if self.instruction.value != InstructionName::BLX && offset % 4 != 0 {
+            panic!("Branch target must be word aligned in ARM state");
+        }
 
-        let imm = match self.expression {
-            Expression::Immediate(ref imm) => imm.to_machine_code(),
-            _ => panic!("Expected register"),
-        };
+        // the operand is a byte offset, the instruction holds it in words
+        let imm = (offset >> 2) as u32;
 
-        base | (imm & 0x00ffffff)
+        base | link | (imm & 0x00ffffff)
     }
 
     fn generate_proc(&self) -> u32 {
@@ -138,6 +323,46 @@ impl CpuOperation {
         mask
     }
 
+    // ldrex/strex, lda/stl and ldaex/stlex share one encoding, bits 22:21 give the size and
+    // bits 9:8 the ordering (exclusive, acquire/release or both)
+    fn generate_load_store_exclusive(&self) -> u32 {
+        use InstructionName::*;
+
+        let expr = match self.expression {
+            Expression::LoadStoreExclusive(ref expr) => expr,
+            Expression::Empty => return 0x057ff01f, // clrex
+            _ => panic!("Expected load store exclusive expression"),
+        };
+
+        let load = if load_istr(&self.instruction) {
+            1 << 20
+        } else {
+            0
+        };
+
+        let size = match self.instruction.value {
+            LDREX | STREX | LDA | STL | LDAEX | STLEX => 0b00,
+            LDREXD | STREXD | LDAEXD | STLEXD => 0b01,
+            LDREXB | STREXB | LDAB | STLB | LDAEXB | STLEXB => 0b10,
+            _ => 0b11,
+        } << 21;
+
+        let ordering = match self.instruction.value {
+            LDREX | LDREXB | LDREXH | LDREXD | STREX | STREXB | STREXH | STREXD => 0b11,
+            LDAEX | LDAEXB | LDAEXH | LDAEXD | STLEX | STLEXB | STLEXH | STLEXD => 0b10,
+            _ => 0b00,
+        } << 8;
+
+        // the unused register fields are all ones
+        let unused = match (load != 0, expr.status.is_some()) {
+            (true, _) => 0xf,
+            (false, false) => 0xf << 12,
+            (false, true) => 0,
+        };
+
+        0x01800c90 | load | size | ordering | unused | expr.to_machine_code(load != 0)
+    }
+
     fn generate_load_store_imm(expr: &LoadStoreImmediateExpression) -> u32 {
         let mut istr: u32 = 0;
         let base: u32 = (expr.base.to_num() as u32) << 16;
@@ -153,7 +378,8 @@ impl CpuOperation {
                 }
             }
             IndexMode::Post => 0,
-            IndexMode::None => 0,
+            // [rn] is an offset of zero, not a post-indexed access
+            IndexMode::None => 1 << 24,
         };
 
         let imm = expr
@@ -206,10 +432,10 @@ impl CpuOperation {
         instruction: &Instruction,
         expr: &LoadStoreMultipleExpression,
     ) -> u32 {
-        let istr: u32 = 1 << 27 | 1 << 21;
+        let istr: u32 = 1 << 27;
         let base: u32 = (expr.base.to_num() as u32) << 16;
         let write_back = if expr.write_back { 1 << 21 } else { 0 };
-        let save_spsr = if instruction.set_flags { 1 << 22 } else { 0 };
+        let user_mode = if expr.user_mode { 1 << 22 } else { 0 };
 
         let before_or_after = if after_istr(instruction) { 0 } else { 1 << 24 };
 
@@ -227,7 +453,7 @@ impl CpuOperation {
             reg_to_transfer |= 1 << reg_num;
         }
 
-        istr | base | write_back | save_spsr | increment | reg_to_transfer | load | before_or_after
+        istr | base | write_back | user_mode | increment | reg_to_transfer | load | before_or_after
     }
 }
 
@@ -257,7 +483,26 @@ fn load_istr(instruction: &Instruction) -> bool {
     use InstructionName::*;
     matches!(
         instruction.value,
-        LDMIA | LDMIB | LDMDA | LDMDB | LDR | LDRB | LDRH | LDRSB | LDRSH
+        LDMIA
+            | LDMIB
+            | LDMDA
+            | LDMDB
+            | LDR
+            | LDRB
+            | LDRH
+            | LDRSB
+            | LDRSH
+            | LDREX
+            | LDREXB
+            | LDREXH
+            | LDREXD
+            | LDA
+            | LDAB
+            | LDAH
+            | LDAEX
+            | LDAEXB
+            | LDAEXH
+            | LDAEXD
     )
 }
 
@@ -289,14 +534,63 @@ fn is_load_store_multiple(instruction: &Instruction) -> bool {
     )
 }
 
+fn is_exclusive(instruction: &Instruction) -> bool {
+    is_exclusive_op(&instruction.value)
+}
+
 fn is_branch(instruction: &Instruction) -> bool {
     use InstructionName::*;
-    matches!(instruction.value, B | BL)
+    matches!(instruction.value, B | BL | BLX)
 }
 
 fn is_bx(instruction: &Instruction) -> bool {
     use InstructionName::*;
-    matches!(instruction.value, BX | BLX)
+    matches!(instruction.value, BX | BLX | BXJ)
+}
+
+fn is_system(instruction: &Instruction) -> bool {
+    use InstructionName::*;
+    matches!(
+        instruction.value,
+        MRS | MSR
+            | CPS
+            | CPSIE
+            | CPSID
+            | SVC
+            | BKPT
+            | UDF
+            | NOP
+            | YIELD
+            | WFE
+            | WFI
+            | SEV
+            | DMB
+            | DSB
+            | ISB
+    )
+}
+
+// Instructions whose condition field is 0b1111
+fn is_unconditional(instruction: &Instruction) -> bool {
+    use InstructionName::*;
+    matches!(
+        instruction.value,
+        CPS | CPSIE
+            | CPSID
+            | DMB
+            | DSB
+            | ISB
+            | CLREX
+            | MCR2
+            | MRC2
+            | MCRR2
+            | MRRC2
+            | CDP2
+            | LDC2
+            | LDC2L
+            | STC2
+            | STC2L
+    )
 }
 
 fn is_proc(instruction: &Instruction) -> bool {
@@ -334,7 +628,7 @@ fn get_proc_expression(expression: &Expression, name: &InstructionName) -> u32 {
         Expression::ThreeRegs(expr) => expr.to_machine_code(),
         Expression::TwoRegs(expr) => expr.to_machine_code(name),
         Expression::TwoRegsLiteral(expr) => expr.to_machine_code(),
-        Expression::RegLiteral(expr) => expr.to_machine_code(),
+        Expression::RegLiteral(expr) => expr.to_machine_code(name),
         _ => panic!("Invalid expression"),
     }
 }
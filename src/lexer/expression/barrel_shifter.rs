@@ -3,12 +3,13 @@ use crate::{
     token::{instruction_name::InstructionName, register::Register, Token},
 };
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum BarrelShifterOperation {
     LSL,
     LSR,
     ASR,
     ROR,
+    RRX,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -31,51 +32,83 @@ impl BarrelShifterExpression {
 
         let instruction = match &tokens[0] {
             Token::INSTRUCTION(istr) => &istr.value,
-            _ => panic!("Expected instruction"),
+            _ => panic!("Expected a shift after the register operands"),
         };
 
-        let operation = match instruction {
+        let mut operation = match instruction {
             InstructionName::LSL => BarrelShifterOperation::LSL,
             InstructionName::LSR => BarrelShifterOperation::LSR,
             InstructionName::ASR => BarrelShifterOperation::ASR,
             InstructionName::ROR => BarrelShifterOperation::ROR,
+            InstructionName::RRX => BarrelShifterOperation::RRX,
             _ => panic!("Invalid barrel shifter operation"),
         };
 
-        let shift_amount = match &tokens[1] {
-            Token::REGISTER(reg) => BarrealShifterShiftAmount::Register(*reg),
-            Token::IMMEDIATE(imm) => BarrealShifterShiftAmount::Number(imm.number as u8),
+        if operation == BarrelShifterOperation::RRX {
+            if tokens.len() > 1 {
+                panic!("RRX does not take a shift amount");
+            }
+
+            return Some(Self {
+                operation,
+                shift_amount: BarrealShifterShiftAmount::Number(0),
+            });
+        }
+
+        let shift_amount = match tokens.get(1) {
+            Some(Token::REGISTER(reg)) => BarrealShifterShiftAmount::Register(*reg),
+            // A shift by #0 is lsl #0 whatever the type, as in GNU as and LLVM
+            Some(Token::IMMEDIATE(imm)) if imm.to_num() == 0 => {
+                operation = BarrelShifterOperation::LSL;
+                BarrealShifterShiftAmount::Number(0)
+            }
+            Some(Token::IMMEDIATE(imm)) => {
+                BarrealShifterShiftAmount::Number(check_shift_amount(operation, imm.to_num()))
+            }
+            Some(Token::NUMBER(number)) => {
+                panic!(
+                    "Shift amount {} has to be written as #{}",
+                    number.value, number.value
+                )
+            }
             _ => panic!("Invalid shift amount"),
         };
 
+        if tokens.len() > 2 {
+            panic!("Unexpected operands after shift");
+        }
+
         Some(Self {
             operation,
             shift_amount,
         })
     }
 
+    pub fn is_register_shift(&self) -> bool {
+        matches!(self.shift_amount, BarrealShifterShiftAmount::Register(_))
+    }
+
     pub fn apply(&self, value: u32, regs: &CpuRegisters) -> u32 {
-        match self.shift_amount {
-            BarrealShifterShiftAmount::Number(imm) => match self.operation {
-                BarrelShifterOperation::LSL => value << imm,
-                BarrelShifterOperation::LSR => value >> imm,
-                BarrelShifterOperation::ASR => ((value as i32) >> imm) as u32,
-                BarrelShifterOperation::ROR => value.rotate_right(imm as u32),
-            },
-            BarrealShifterShiftAmount::Register(num) => {
-                let shift_amount = regs.get(num.to_num()) & 0xFF;
-                match self.operation {
-                    BarrelShifterOperation::LSL => value << shift_amount,
-                    BarrelShifterOperation::LSR => value >> shift_amount,
-                    BarrelShifterOperation::ASR => ((value as i32) >> shift_amount) as u32,
-                    BarrelShifterOperation::ROR => value.rotate_right(shift_amount),
-                }
+        let shift_amount = match self.shift_amount {
+            BarrealShifterShiftAmount::Number(imm) => imm as u32,
+            BarrealShifterShiftAmount::Register(num) => regs.get(num.to_num()) & 0xFF,
+        };
+
+        match self.operation {
+            BarrelShifterOperation::LSL => value.checked_shl(shift_amount).unwrap_or(0),
+            BarrelShifterOperation::LSR => value.checked_shr(shift_amount).unwrap_or(0),
+            BarrelShifterOperation::ASR => ((value as i32) >> shift_amount.min(31)) as u32,
+            BarrelShifterOperation::ROR => value.rotate_right(shift_amount),
+            BarrelShifterOperation::RRX => {
+                let carry = (regs.cpsr >> 29) & 1;
+                (carry << 31) | (value >> 1)
             }
         }
     }
 
     pub fn to_machine_code(&self) -> u32 {
         let shifter_opcode = match self.shift_amount {
+            // lsr #32 and asr #32 are encoded as a shift of 0
             BarrealShifterShiftAmount::Number(imm) => ((imm & 0x0000001f) as u32) << 7,
             BarrealShifterShiftAmount::Register(reg) => ((reg.to_num() as u32) << 8) | (1 << 4),
         };
@@ -84,13 +117,35 @@ impl BarrelShifterExpression {
             BarrelShifterOperation::LSL => 0,
             BarrelShifterOperation::LSR => 1,
             BarrelShifterOperation::ASR => 2,
-            BarrelShifterOperation::ROR => 3,
+            BarrelShifterOperation::ROR | BarrelShifterOperation::RRX => 3,
         } << 5;
 
         shifter_opcode | shift_type_code
     }
 }
 
+// Valid amounts are lsl #0-31, lsr/asr #1-32 and ror #1-31
+fn check_shift_amount(operation: BarrelShifterOperation, amount: u32) -> u8 {
+    let range = match operation {
+        BarrelShifterOperation::LSL => 0..=31,
+        BarrelShifterOperation::LSR | BarrelShifterOperation::ASR => 1..=32,
+        BarrelShifterOperation::ROR => 1..=31,
+        BarrelShifterOperation::RRX => 0..=0,
+    };
+
+    if !range.contains(&amount) {
+        panic!(
+            "Shift amount #{} out of range for {:?} (expected #{}-{})",
+            amount as i32,
+            operation,
+            range.start(),
+            range.end()
+        );
+    }
+
+    amount as u8
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -98,7 +153,7 @@ mod tests {
         immediate::Immediate,
         instruction::Instruction,
         register::{Register, RegisterNumbers},
-        Token,
+        Number, Token,
     };
 
     fn create_instruction() -> Token {
@@ -137,4 +192,46 @@ mod tests {
 
         assert_eq!(machine_code, 0b00010011 << 4);
     }
+
+    #[test]
+    fn test_barrel_shifter_shift_of_32_and_rrx() {
+        let lsr_32 = vec![create_instruction(), create_immediate("32")];
+        let expression = BarrelShifterExpression::new(&lsr_32).unwrap();
+        assert_eq!(expression.to_machine_code(), 0b0010 << 4);
+
+        let rrx = vec![Token::INSTRUCTION(
+            Instruction::new("rrx", None, None).unwrap(),
+        )];
+        let expression = BarrelShifterExpression::new(&rrx).unwrap();
+        assert_eq!(expression.to_machine_code(), 0b0110 << 4);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_barrel_shifter_rejects_lsl_32() {
+        let istr = Instruction::new("lsl", None, None).unwrap();
+        let tokens = vec![Token::INSTRUCTION(istr), create_immediate("32")];
+        BarrelShifterExpression::new(&tokens);
+    }
+
+    #[test]
+    fn test_barrel_shifter_zero_shift_is_lsl() {
+        for name in ["lsr", "asr", "ror"] {
+            let istr = Instruction::new(name, None, None).unwrap();
+            let tokens = vec![Token::INSTRUCTION(istr), create_immediate("0")];
+            let expression = BarrelShifterExpression::new(&tokens).unwrap();
+            assert_eq!(expression.operation, BarrelShifterOperation::LSL);
+            assert_eq!(expression.to_machine_code(), 0);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Shift amount 2 has to be written as #2")]
+    fn test_barrel_shifter_missing_hash() {
+        let tokens = vec![
+            create_instruction(),
+            Token::NUMBER(Number::new("2").unwrap()),
+        ];
+        BarrelShifterExpression::new(&tokens);
+    }
 }
@@ -0,0 +1,68 @@
+// Example : mcr p15, 0, r0, c7, c5, 0
+//           mrrc p15, 1, r0, r1, c2
+//           cdp p14, 1, c1, c2, c3, 4
+//           ldc p14, c5, [r1, #4]!
+
+use crate::token::register::Register;
+
+use super::ls_imm_index::IndexMode;
+
+#[derive(Debug, Clone)]
+pub struct CoprocessorExpression {
+    pub coprocessor: u8,
+    pub opc1: u8,
+    // rt of mcr/mrc, rt and rt2 of mcrr/mrrc
+    pub registers: Vec<Register>,
+    // crn and crm of mcr/mrc, crm of mcrr/mrrc, crd, crn and crm of cdp
+    pub cp_registers: Vec<u8>,
+    pub opc2: u8,
+}
+
+impl CoprocessorExpression {
+    pub fn new(
+        coprocessor: u8,
+        opc1: u8,
+        registers: Vec<Register>,
+        cp_registers: Vec<u8>,
+        opc2: u8,
+    ) -> Self {
+        Self {
+            coprocessor,
+            opc1,
+            registers,
+            cp_registers,
+            opc2,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct CoprocessorLoadStoreExpression {
+    pub coprocessor: u8,
+    pub crd: u8,
+    pub base: Register,
+    pub offset: i32,
+    pub index_mode: IndexMode,
+    // ldc p14, c5, [r1], {4}: unindexed, the option is passed on to the coprocessor
+    pub option: Option<u8>,
+}
+
+impl CoprocessorLoadStoreExpression {
+    pub fn new(
+        coprocessor: u8,
+        crd: u8,
+        base: Register,
+        offset: i32,
+        index_mode: IndexMode,
+        option: Option<u8>,
+    ) -> Self {
+        Self {
+            coprocessor,
+            crd,
+            base,
+            offset,
+            index_mode,
+            option,
+        }
+    }
+}
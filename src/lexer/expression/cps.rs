@@ -0,0 +1,25 @@
+// Example : cpsid if
+//           cpsie i, #0x13
+//           cps #0x13
+
+#[derive(Debug, Copy, Clone)]
+pub struct CpsExpression {
+    // a, i and f bits, in the order they are encoded
+    pub flags: u8,
+    pub mode: Option<u8>,
+}
+
+impl CpsExpression {
+    pub fn new(flags: u8, mode: Option<u8>) -> Self {
+        Self { flags, mode }
+    }
+
+    pub fn to_machine_code(&self) -> u32 {
+        let mode = match self.mode {
+            Some(mode) => 1 << 17 | mode as u32,
+            None => 0,
+        };
+
+        ((self.flags as u32) << 6) | mode
+    }
+}
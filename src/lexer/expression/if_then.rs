@@ -0,0 +1,52 @@
+// Example : it eq
+//           itete ne
+
+use crate::token::instruction::ConditionCode;
+
+#[derive(Debug, Clone)]
+pub struct IfThenExpression {
+    pub condition: ConditionCode,
+    // One entry per instruction after the first, true for then (t) and false for else (e)
+    pub pattern: Vec<bool>,
+}
+
+impl IfThenExpression {
+    pub fn new(condition: ConditionCode, pattern: Vec<bool>) -> Self {
+        if pattern.len() > 3 {
+            panic!("An IT block holds at most four instructions");
+        }
+
+        if condition == ConditionCode::Al && pattern.contains(&false) {
+            panic!("An IT block with the al condition can't have else instructions");
+        }
+
+        Self { condition, pattern }
+    }
+
+    // Condition each instruction of the block has to carry, in order
+    pub fn conditions(&self) -> Vec<ConditionCode> {
+        let inverse = self.condition.inverse().unwrap_or(self.condition);
+
+        std::iter::once(self.condition)
+            .chain(
+                self.pattern
+                    .iter()
+                    .map(|then| if *then { self.condition } else { inverse }),
+            )
+            .collect()
+    }
+
+    // firstcond in bits 7:4 and the mask in bits 3:0, the mask ends with a one after the last slot
+    pub fn to_machine_code(&self) -> u32 {
+        let condition = self.condition.to_machine_code() >> 28;
+        let first = condition & 1;
+
+        let mut mask = 1 << (3 - self.pattern.len());
+        for (i, then) in self.pattern.iter().enumerate() {
+            let bit = if *then { first } else { first ^ 1 };
+            mask |= bit << (3 - i);
+        }
+
+        condition << 4 | mask
+    }
+}
@@ -0,0 +1,41 @@
+// Example : ldrex r0, [r1]
+//           strexd r2, r0, r1, [r3]
+
+use crate::token::register::Register;
+
+#[derive(Debug, Copy, Clone)]
+pub struct LoadStoreExclusiveExpression {
+    // Register that receives the result of a store exclusive
+    pub status: Option<Register>,
+    pub destination: Register,
+    pub destination2: Option<Register>,
+    pub base: Register,
+}
+
+impl LoadStoreExclusiveExpression {
+    pub fn new(
+        status: Option<Register>,
+        destination: Register,
+        destination2: Option<Register>,
+        base: Register,
+    ) -> Self {
+        Self {
+            status,
+            destination,
+            destination2,
+            base,
+        }
+    }
+
+    // Loads take rt in bits 15:12, stores in bits 3:0 with the status register in 15:12
+    pub fn to_machine_code(&self, load: bool) -> u32 {
+        let base = (self.base.to_num() as u32) << 16;
+        let destination = self.destination.to_num() as u32;
+
+        match (load, self.status) {
+            (true, _) => base | destination << 12,
+            (false, Some(status)) => base | (status.to_num() as u32) << 12 | destination,
+            (false, None) => base | destination,
+        }
+    }
+}
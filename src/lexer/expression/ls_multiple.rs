@@ -5,14 +5,22 @@ pub struct LoadStoreMultipleExpression {
     pub base: Register,
     pub registers: Vec<Register>,
     pub write_back: bool,
+    // ^ suffix: user mode registers, or the SPSR restored when pc is loaded
+    pub user_mode: bool,
 }
 
 impl LoadStoreMultipleExpression {
-    pub fn new(base: Register, registers: Vec<Register>, write_back: bool) -> Self {
+    pub fn new(
+        base: Register,
+        registers: Vec<Register>,
+        write_back: bool,
+        user_mode: bool,
+    ) -> Self {
         Self {
             base,
             registers,
             write_back,
+            user_mode,
         }
     }
 }
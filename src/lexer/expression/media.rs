@@ -0,0 +1,43 @@
+// Example : sadd16 r0, r1, r2
+//           ssat r0, #8, r1, lsl #4
+//           pkhtb r0, r1, r2, asr #16
+//           uxtab r0, r1, r2, ror #8
+
+use crate::token::register::Register;
+
+use super::barrel_shifter::{BarrealShifterShiftAmount, BarrelShifterExpression};
+
+#[derive(Debug, Clone)]
+pub struct MediaExpression {
+    // In the order they are written
+    pub registers: Vec<Register>,
+    // Bit position of ssat/usat, as written
+    pub saturate: Option<u32>,
+    // Shift of ssat/usat and pkh, rotation of the extends
+    pub shift: Option<BarrelShifterExpression>,
+}
+
+impl MediaExpression {
+    pub fn new(
+        registers: Vec<Register>,
+        saturate: Option<u32>,
+        shift: Option<BarrelShifterExpression>,
+    ) -> Self {
+        Self {
+            registers,
+            saturate,
+            shift,
+        }
+    }
+
+    pub fn reg(&self, index: usize) -> u32 {
+        self.registers[index].to_num() as u32
+    }
+
+    pub fn shift_amount(&self) -> u32 {
+        match self.shift.map(|shift| shift.shift_amount) {
+            Some(BarrealShifterShiftAmount::Number(amount)) => amount as u32,
+            _ => 0,
+        }
+    }
+}
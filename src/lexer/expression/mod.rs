@@ -1,13 +1,20 @@
 pub mod barrel_shifter;
+pub mod coprocessor;
+pub mod cps;
+pub mod if_then;
 pub mod immediate;
+pub mod ls_exclusive;
 pub mod ls_imm_index;
 pub mod ls_multiple;
 pub mod ls_reg_index;
+pub mod media;
 pub mod reg;
 pub mod reg_literal;
+pub mod status_register;
 pub mod three_regs;
 pub mod two_regs;
 pub mod two_regs_literal;
+pub mod vfp;
 
 #[derive(Debug, Clone)]
 pub enum Expression {
@@ -20,4 +27,16 @@ pub enum Expression {
     LoadStoreImmediate(ls_imm_index::LoadStoreImmediateExpression),
     LoadStoreRegister(ls_reg_index::LoadStoreRegisterExpression),
     LoadStoreMultiple(ls_multiple::LoadStoreMultipleExpression),
+    LoadStoreExclusive(ls_exclusive::LoadStoreExclusiveExpression),
+    StatusRegister(status_register::StatusRegisterExpression),
+    ChangeProcessorState(cps::CpsExpression),
+    IfThen(if_then::IfThenExpression),
+    Coprocessor(coprocessor::CoprocessorExpression),
+    CoprocessorLoadStore(coprocessor::CoprocessorLoadStoreExpression),
+    Media(media::MediaExpression),
+    Vfp(vfp::VfpExpression),
+    VfpLoadStore(vfp::VfpLoadStoreExpression),
+    VfpLoadStoreMultiple(vfp::VfpLoadStoreMultipleExpression),
+    // Instructions without operands, like nop or wfi
+    Empty,
 }
@@ -1,30 +1,51 @@
 // Example : mov r0, #0x1234
+//           cmp r1, #0xff, #8
 
-use crate::token::{immediate::Immediate, register::Register};
+use crate::{
+    lexer::is_compare_op,
+    token::{immediate::Immediate, instruction_name::InstructionName, register::Register},
+};
 
 #[derive(Debug, Clone)]
 pub struct RegLiteralExpression {
     pub register: Register,
     pub literal: Immediate,
+    pub rotation: Option<Immediate>,
 }
 
 impl RegLiteralExpression {
-    pub fn new(register: Register, literal: Immediate) -> Self {
-        Self { register, literal }
+    pub fn new(register: Register, literal: Immediate, rotation: Option<Immediate>) -> Self {
+        Self {
+            register,
+            literal,
+            rotation,
+        }
     }
 
-    pub fn to_machine_code(&self) -> u32 {
+    // Compare instructions take the register as rn instead of rd
+    pub fn to_machine_code(&self, name: &InstructionName) -> u32 {
         let register = self.register.to_num() as u32;
         let literal = self.literal.to_num();
 
-        let (rotation, lower_byte) =
-            check_immediate_possible(literal).expect("Impossível representar o valor imediato");
+        let (rotation, lower_byte) = match &self.rotation {
+            Some(rotation) => check_explicit_rotation(literal, rotation.to_num())
+                .expect("Immediate must be #0-255 with an even rotation of #0-30"),
+            None => {
+                check_immediate_possible(literal).expect("Impossível representar o valor imediato")
+            }
+        };
+
+        let register = if is_compare_op(name) {
+            register << 16
+        } else {
+            register << 12
+        };
 
-        (register << 12) | (rotation as u32) << 8 | lower_byte as u32 | 1 << 25
+        register | (rotation as u32) << 8 | lower_byte as u32 | 1 << 25
     }
 }
 
-fn check_immediate_possible(immediate: u32) -> Option<(u8, u8)> {
+pub fn check_immediate_possible(immediate: u32) -> Option<(u8, u8)> {
     for rotation in 0..16 {
         let val: u32 = 0xFF_u32.rotate_right(rotation * 2);
         let val = !val;
@@ -36,3 +57,12 @@ fn check_immediate_possible(immediate: u32) -> Option<(u8, u8)> {
     }
     None
 }
+
+// The "#imm, #rot" form spells out the 8-bit value and its (even) right rotation
+pub fn check_explicit_rotation(immediate: u32, rotation: u32) -> Option<(u8, u8)> {
+    if immediate > 0xff || rotation > 30 || !rotation.is_multiple_of(2) {
+        return None;
+    }
+
+    Some(((rotation / 2) as u8, immediate as u8))
+}
@@ -0,0 +1,26 @@
+// Example : mrs r0, cpsr
+//           msr cpsr_c, r0
+//           msr cpsr_f, #0xf0000000
+
+use crate::token::{immediate::Immediate, register::Register, register::StatusRegister};
+
+#[derive(Debug, Clone)]
+pub struct StatusRegisterExpression {
+    pub status_register: StatusRegister,
+    pub register: Option<Register>,
+    pub literal: Option<Immediate>,
+}
+
+impl StatusRegisterExpression {
+    pub fn new(
+        status_register: StatusRegister,
+        register: Option<Register>,
+        literal: Option<Immediate>,
+    ) -> Self {
+        Self {
+            status_register,
+            register,
+            literal,
+        }
+    }
+}
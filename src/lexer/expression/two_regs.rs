@@ -1,5 +1,6 @@
 // mov r0, r0
 
+use crate::lexer::is_compare_op;
 use crate::token::instruction_name::InstructionName;
 use crate::token::register::Register;
 
@@ -34,10 +35,10 @@ impl TwoRegsExpression {
             .map(|bs| bs.to_machine_code())
             .unwrap_or(0);
 
-        if matches!(name, InstructionName::CMP | InstructionName::CMN) {
-            return (reg_d << 16) | (reg_m) | (barrel_shifter & !0b1111);
+        if is_compare_op(name) {
+            return (reg_d << 16) | (reg_m) | barrel_shifter;
         }
 
-        (reg_d << 12) | (reg_m) | (barrel_shifter << 4)
+        (reg_d << 12) | (reg_m) | barrel_shifter
     }
 }
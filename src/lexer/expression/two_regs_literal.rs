@@ -1,31 +1,42 @@
 // example: add r0 r1 #0x1234
+//          add r0 r1 #0xff, #8
 
-use crate::{
-    token::{immediate::Immediate, register::Register},
-    utils::debug_u32,
-};
+use crate::token::{immediate::Immediate, register::Register};
+
+use super::reg_literal::check_explicit_rotation;
 
 #[derive(Debug, Clone)]
 pub struct TwoRegsLiteralExpression {
     pub reg_d: Register,
     pub reg_m: Register,
     pub literal: Immediate,
+    pub rotation: Option<Immediate>,
 }
 
 impl TwoRegsLiteralExpression {
-    pub fn new(reg_d: Register, reg_m: Register, literal: Immediate) -> Self {
+    pub fn new(
+        reg_d: Register,
+        reg_m: Register,
+        literal: Immediate,
+        rotation: Option<Immediate>,
+    ) -> Self {
         Self {
             reg_d,
             reg_m,
             literal,
+            rotation,
         }
     }
 
     pub fn to_machine_code(&self) -> u32 {
         let reg_d = self.reg_d.to_num() as u32;
         let reg_m = self.reg_m.to_num() as u32;
-        let immediate =
-            check_immediate_possible(self.literal.to_num()).expect("Immediate value is too large");
+        let immediate = match &self.rotation {
+            Some(rotation) => check_explicit_rotation(self.literal.to_num(), rotation.to_num())
+                .expect("Immediate must be #0-255 with an even rotation of #0-30"),
+            None => check_immediate_possible(self.literal.to_num())
+                .expect("Immediate value is too large"),
+        };
         (reg_d << 12) | (reg_m << 16) | (immediate.0 as u32) << 8 | immediate.1 as u32 | 1 << 25
     }
 }
@@ -39,8 +50,6 @@ fn check_immediate_possible(immediate: u32) -> Option<(u8, u8)> {
             let offset = (immediate & !val).rotate_right((16 - rotation) * 2);
             return Some((rotation as u8, offset as u8));
         }
-
-        debug_u32(val);
     }
     None
 }
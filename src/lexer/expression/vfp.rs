@@ -0,0 +1,70 @@
+// Example : vadd.f32 s0, s1, s2
+//           vmov r0, r1, d0
+//           vldr d0, [r0, #8]
+//           vpush {d8-d15}
+
+use crate::token::register::{FpRegister, Register};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VfpOperand {
+    Core(Register),
+    Fp(FpRegister),
+    // d0[1], one word of a double register
+    Scalar(FpRegister, u8),
+    Immediate(f64),
+}
+
+#[derive(Debug, Clone)]
+pub struct VfpExpression {
+    pub operands: Vec<VfpOperand>,
+}
+
+impl VfpExpression {
+    pub fn new(operands: Vec<VfpOperand>) -> Self {
+        Self { operands }
+    }
+
+    pub fn fp_registers(&self) -> impl Iterator<Item = &FpRegister> {
+        self.operands.iter().filter_map(|operand| match operand {
+            VfpOperand::Fp(register) | VfpOperand::Scalar(register, _) => Some(register),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct VfpLoadStoreExpression {
+    pub register: FpRegister,
+    pub base: Register,
+    pub offset: i32,
+}
+
+impl VfpLoadStoreExpression {
+    pub fn new(register: FpRegister, base: Register, offset: i32) -> Self {
+        Self {
+            register,
+            base,
+            offset,
+        }
+    }
+}
+
+// The registers of vldm/vstm are consecutive, so the list is kept as its first register and length
+#[derive(Debug, Copy, Clone)]
+pub struct VfpLoadStoreMultipleExpression {
+    pub base: Register,
+    pub first: FpRegister,
+    pub count: u8,
+    pub write_back: bool,
+}
+
+impl VfpLoadStoreMultipleExpression {
+    pub fn new(base: Register, first: FpRegister, count: u8, write_back: bool) -> Self {
+        Self {
+            base,
+            first,
+            count,
+            write_back,
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::VecDeque;
+
+use crate::token::{
+    instruction::{ConditionCode, Instruction},
+    instruction_name::InstructionName,
+};
+
+use super::expression::if_then::IfThenExpression;
+
+// What happens to conditional Thumb instructions written without an IT block,
+// selected with -mimplicit-it. ARM code never needs IT, so only Thumb is affected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImplicitIt {
+    Always,
+    Never,
+    #[default]
+    Arm,
+    Thumb,
+}
+
+impl ImplicitIt {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "always" => Some(ImplicitIt::Always),
+            "never" => Some(ImplicitIt::Never),
+            "arm" => Some(ImplicitIt::Arm),
+            "thumb" => Some(ImplicitIt::Thumb),
+            _ => None,
+        }
+    }
+
+    // Whether IT instructions are generated for conditional Thumb instructions
+    fn in_thumb(&self) -> bool {
+        matches!(self, ImplicitIt::Always | ImplicitIt::Thumb)
+    }
+}
+
+// IT instruction generated for a conditional Thumb instruction written outside a block
+#[derive(Debug, Clone)]
+pub enum GeneratedIt {
+    // Inserted ahead of the instruction
+    New(IfThenExpression),
+    // The IT generated for the instructions before, which the instruction joined
+    Extended(IfThenExpression),
+}
+
+// Follows the instructions covered by the last IT instruction
+#[derive(Debug, Clone, Default)]
+pub struct ItBlock {
+    // Conditions of the instructions left in the block
+    conditions: VecDeque<ConditionCode>,
+    implicit: ImplicitIt,
+    // Last generated IT, while the next conditional instructions can still join it
+    generated: Option<IfThenExpression>,
+}
+
+impl ItBlock {
+    pub fn new(implicit: ImplicitIt) -> Self {
+        ItBlock {
+            conditions: VecDeque::new(),
+            implicit,
+            generated: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.conditions.is_empty()
+    }
+
+    pub fn begin(&mut self, expr: &IfThenExpression) {
+        self.conditions = expr.conditions().into();
+        self.generated = None;
+    }
+
+    // Ends the generated block, instructions after a label or directive get a new IT
+    pub fn close_generated(&mut self) {
+        self.generated = None;
+    }
+
+    // IT instruction for a conditional Thumb instruction written outside a block. Consecutive
+    // instructions with the same or the inverse condition share one, up to four of them.
+    // Conditional branches have their own encoding.
+    pub fn implicit_it(&mut self, istr: &Instruction) -> Option<GeneratedIt> {
+        let needs_it = self.conditions.is_empty()
+            && istr.condition != ConditionCode::Al
+            && istr.value != InstructionName::B;
+
+        if !needs_it || !self.implicit.in_thumb() {
+            self.generated = None;
+            return None;
+        }
+
+        self.conditions.push_back(istr.condition);
+        if let Some(expr) = &mut self.generated {
+            let joins = istr.condition == expr.condition
+                || Some(istr.condition) == expr.condition.inverse();
+            if joins && expr.pattern.len() < 3 {
+                expr.pattern.push(istr.condition == expr.condition);
+                return Some(GeneratedIt::Extended(expr.clone()));
+            }
+        }
+
+        let expr = IfThenExpression::new(istr.condition, vec![]);
+        self.generated = Some(expr.clone());
+        Some(GeneratedIt::New(expr))
+    }
+
+    // Takes the next slot of the block, checking the instruction against it. Returns whether
+    // the instruction is covered by an IT instruction.
+    pub fn next(&mut self, istr: &Instruction, thumb: bool) -> bool {
+        let condition = match self.conditions.pop_front() {
+            Some(condition) => condition,
+            None => {
+                if thumb && istr.condition != ConditionCode::Al && istr.value != InstructionName::B
+                {
+                    panic!(
+                        "Conditional {:?} outside an IT block in Thumb state, add an IT instruction or use -mimplicit-it=thumb",
+                        istr.value
+                    );
+                }
+                return false;
+            }
+        };
+
+        match istr.value {
+            InstructionName::IT => panic!("IT instruction inside an IT block"),
+            InstructionName::CBZ | InstructionName::CBNZ => {
+                panic!("{:?} can't be used inside an IT block", istr.value)
+            }
+            InstructionName::B
+            | InstructionName::BL
+            | InstructionName::BX
+            | InstructionName::BLX
+            | InstructionName::BXJ
+                if !self.conditions.is_empty() =>
+            {
+                panic!(
+                    "{:?} must be the last instruction of its IT block",
+                    istr.value
+                )
+            }
+            // nothing can follow a branch in its block
+            InstructionName::B
+            | InstructionName::BL
+            | InstructionName::BX
+            | InstructionName::BLX
+            | InstructionName::BXJ => self.generated = None,
+            _ => {}
+        }
+
+        if istr.condition != condition {
+            panic!(
+                "{:?} has condition '{}' but the IT block expects '{}'",
+                istr.value,
+                condition_name(&istr.condition),
+                condition_name(&condition)
+            );
+        }
+
+        true
+    }
+}
+
+// Mnemonics leave out the al condition, messages name it
+fn condition_name(condition: &ConditionCode) -> &str {
+    match condition {
+        ConditionCode::Al => "al",
+        condition => condition.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instruction(name: &str, condition: &str) -> Instruction {
+        Instruction::new(name, None, Some(condition)).unwrap()
+    }
+
+    #[test]
+    fn test_it_block_pattern() {
+        let mut block = ItBlock::new(ImplicitIt::Never);
+        block.begin(&IfThenExpression::new(ConditionCode::Eq, vec![false, true]));
+
+        assert!(block.next(&instruction("add", "eq"), true));
+        assert!(block.next(&instruction("sub", "ne"), true));
+        assert!(block.next(&instruction("b", "eq"), true));
+        assert!(block.is_empty());
+        assert!(!block.next(&instruction("b", "ne"), true));
+    }
+
+    #[test]
+    #[should_panic(expected = "last instruction of its IT block")]
+    fn test_it_block_branch_in_the_middle() {
+        let mut block = ItBlock::new(ImplicitIt::Never);
+        block.begin(&IfThenExpression::new(ConditionCode::Eq, vec![true]));
+
+        block.next(&instruction("bl", "eq"), true);
+    }
+
+    #[test]
+    #[should_panic(expected = "has condition 'al' but the IT block expects 'ne'")]
+    fn test_it_block_unconditional_in_else() {
+        let mut block = ItBlock::new(ImplicitIt::Never);
+        block.begin(&IfThenExpression::new(ConditionCode::Eq, vec![false]));
+
+        block.next(&instruction("add", "eq"), true);
+        block.next(&instruction("add", "al"), true);
+    }
+
+    #[test]
+    fn test_implicit_it() {
+        let mut block = ItBlock::new(ImplicitIt::Always);
+
+        assert!(block.implicit_it(&instruction("b", "eq")).is_none());
+        assert!(block.implicit_it(&instruction("add", "al")).is_none());
+        assert!(block.implicit_it(&instruction("add", "eq")).is_some());
+        assert!(ItBlock::new(ImplicitIt::Arm)
+            .implicit_it(&instruction("add", "eq"))
+            .is_none());
+    }
+
+    // Whether the instruction got a new IT and the IT's firstcond and mask
+    fn generate(block: &mut ItBlock, name: &str, condition: &str) -> Option<(bool, u32)> {
+        let istr = instruction(name, condition);
+        let it = block.implicit_it(&istr);
+        assert_eq!(block.next(&istr, true), it.is_some());
+
+        it.map(|it| match it {
+            GeneratedIt::New(expr) => (true, expr.to_machine_code()),
+            GeneratedIt::Extended(expr) => (false, expr.to_machine_code()),
+        })
+    }
+
+    #[test]
+    fn test_implicit_it_shared() {
+        let mut block = ItBlock::new(ImplicitIt::Always);
+
+        assert_eq!(generate(&mut block, "add", "eq"), Some((true, 0x08)));
+        assert_eq!(generate(&mut block, "sub", "ne"), Some((false, 0x0c)));
+        assert_eq!(generate(&mut block, "mov", "eq"), Some((false, 0x0a)));
+        assert_eq!(generate(&mut block, "add", "eq"), Some((false, 0x09)));
+        // four slots at most
+        assert_eq!(generate(&mut block, "add", "eq"), Some((true, 0x08)));
+
+        block.close_generated();
+        assert_eq!(generate(&mut block, "add", "eq"), Some((true, 0x08)));
+        assert_eq!(generate(&mut block, "bx", "eq"), Some((false, 0x04)));
+        // nothing follows a branch, nor another condition
+        assert_eq!(generate(&mut block, "add", "eq"), Some((true, 0x08)));
+        assert_eq!(generate(&mut block, "add", "gt"), Some((true, 0xc8)));
+        assert_eq!(generate(&mut block, "add", "al"), None);
+        assert_eq!(generate(&mut block, "add", "gt"), Some((true, 0xc8)));
+    }
+}
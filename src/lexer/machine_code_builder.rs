@@ -1,3 +1,5 @@
+use crate::elf::byte_order::ByteOrder;
+
 #[derive(Debug)]
 pub struct MachineCodeBit {
     pub position: u8,
@@ -26,18 +28,14 @@ impl MachineCodeInstruction {
         self.bits[position as usize].value = value;
     }
 
-    pub fn to_u8_buff(&self) -> Vec<u8> {
-        let mut buff = Vec::new();
-        for i in 0..4 {
-            let mut byte = 0;
-            for j in 0..8 {
-                if self.bits[i * 8 + j].value {
-                    byte |= 1 << j;
-                }
+    pub fn to_u8_buff(&self, byte_order: ByteOrder) -> Vec<u8> {
+        let mut code = 0;
+        for (i, bit) in self.bits.iter().enumerate() {
+            if bit.value {
+                code |= 1 << i;
             }
-            buff.push(byte);
         }
-        buff
+        byte_order.code_u32(code).to_vec()
     }
     pub fn push_mask(&mut self, mask: u32, value: u32) {
         for i in 0..32 {
@@ -50,7 +48,7 @@ impl MachineCodeInstruction {
     }
 
     fn sort_code(&mut self) {
-        self.bits.sort_by(|a, b| a.position.cmp(&b.position));
+        self.bits.sort_by_key(|a| a.position);
     }
 
     pub fn to_debug_string(&self) -> String {
@@ -0,0 +1,231 @@
+// ARMv6 media instructions: parallel add/subtract, saturation, packing and extends, and the
+// sdiv/udiv of the idiv extension, which share their encoding space.
+// The ARM and Thumb encodings number the operations differently, so each has its own table.
+
+use crate::token::instruction_name::InstructionName;
+
+use super::{
+    cpu_op::CpuOperation,
+    expression::{barrel_shifter::BarrelShifterOperation, media::MediaExpression, Expression},
+    operations::media_op::is_parallel_op,
+};
+
+// Signed, saturating, halving... prefix of a parallel instruction, as (ARM op1, Thumb U:H:Q)
+pub fn parallel_prefix(name: &InstructionName) -> (u32, u32) {
+    use InstructionName::*;
+    match name {
+        SADD16 | SASX | SSAX | SSUB16 | SADD8 | SSUB8 => (0b001, 0b000),
+        QADD16 | QASX | QSAX | QSUB16 | QADD8 | QSUB8 => (0b010, 0b001),
+        SHADD16 | SHASX | SHSAX | SHSUB16 | SHADD8 | SHSUB8 => (0b011, 0b010),
+        UADD16 | UASX | USAX | USUB16 | UADD8 | USUB8 => (0b101, 0b100),
+        UQADD16 | UQASX | UQSAX | UQSUB16 | UQADD8 | UQSUB8 => (0b110, 0b101),
+        _ => (0b111, 0b110),
+    }
+}
+
+// Operation of a parallel instruction, as (ARM op2, Thumb op1)
+pub fn parallel_operation(name: &InstructionName) -> (u32, u32) {
+    use InstructionName::*;
+    match name {
+        SADD16 | QADD16 | SHADD16 | UADD16 | UQADD16 | UHADD16 => (0b000, 0b001),
+        SASX | QASX | SHASX | UASX | UQASX | UHASX => (0b001, 0b010),
+        SSAX | QSAX | SHSAX | USAX | UQSAX | UHSAX => (0b010, 0b110),
+        SSUB16 | QSUB16 | SHSUB16 | USUB16 | UQSUB16 | UHSUB16 => (0b011, 0b101),
+        SADD8 | QADD8 | SHADD8 | UADD8 | UQADD8 | UHADD8 => (0b100, 0b000),
+        _ => (0b111, 0b100),
+    }
+}
+
+// Extend operation, as (ARM op, Thumb op)
+pub fn extend_operation(name: &InstructionName) -> (u32, u32) {
+    use InstructionName::*;
+    match name {
+        SXTB16 | SXTAB16 => (0b000, 0b010),
+        SXTB | SXTAB => (0b010, 0b100),
+        SXTH | SXTAH => (0b011, 0b000),
+        UXTB16 | UXTAB16 => (0b100, 0b011),
+        UXTB | UXTAB => (0b110, 0b101),
+        _ => (0b111, 0b001),
+    }
+}
+
+fn media_expression(op: &CpuOperation) -> &MediaExpression {
+    match &op.expression {
+        Expression::Media(expr) => expr,
+        _ => panic!("Expected media expression"),
+    }
+}
+
+// Extends without an accumulator read 0b1111 as rn
+fn extend_registers(expr: &MediaExpression) -> (u32, u32, u32) {
+    match expr.registers.len() {
+        2 => (expr.reg(0), 0xf, expr.reg(1)),
+        _ => (expr.reg(0), expr.reg(1), expr.reg(2)),
+    }
+}
+
+// Shift of ssat/usat and pkh as (sh, imm5), asr #32 is encoded as 0
+fn immediate_shift(expr: &MediaExpression) -> (u32, u32) {
+    let sh = match expr.shift.map(|shift| shift.operation) {
+        Some(BarrelShifterOperation::ASR) => 1,
+        _ => 0,
+    };
+
+    (sh, expr.shift_amount() & 0x1f)
+}
+
+// Bits 27:0 of the ARM encoding
+pub fn generate_media(op: &CpuOperation) -> u32 {
+    use InstructionName::*;
+
+    let expr = media_expression(op);
+    let name = op.instruction.value;
+
+    match name {
+        istr if is_parallel_op(&istr) => {
+            let (op1, _) = parallel_prefix(&name);
+            let (op2, _) = parallel_operation(&name);
+            0x06000f10 | op1 << 20 | expr.reg(1) << 16 | expr.reg(0) << 12 | op2 << 5 | expr.reg(2)
+        }
+        // qadd rd, rm, rn
+        QADD | QSUB | QDADD | QDSUB => {
+            let opcode = match name {
+                QADD => 0b00,
+                QSUB => 0b01,
+                QDADD => 0b10,
+                _ => 0b11,
+            };
+            0x01000050 | opcode << 21 | expr.reg(2) << 16 | expr.reg(0) << 12 | expr.reg(1)
+        }
+        SSAT | USAT => {
+            let (sh, amount) = immediate_shift(expr);
+            let (base, position) = match name {
+                SSAT => (0x06a00010, expr.saturate.unwrap() - 1),
+                _ => (0x06e00010, expr.saturate.unwrap()),
+            };
+            base | position << 16 | expr.reg(0) << 12 | amount << 7 | sh << 6 | expr.reg(1)
+        }
+        SSAT16 | USAT16 => {
+            let (base, position) = match name {
+                SSAT16 => (0x06a00f30, expr.saturate.unwrap() - 1),
+                _ => (0x06e00f30, expr.saturate.unwrap()),
+            };
+            base | position << 16 | expr.reg(0) << 12 | expr.reg(1)
+        }
+        PKHBT | PKHTB => {
+            let (tb, amount) = immediate_shift(expr);
+            0x06800010 | expr.reg(1) << 16 | expr.reg(0) << 12 | amount << 7 | tb << 6 | expr.reg(2)
+        }
+        SEL => 0x06800fb0 | expr.reg(1) << 16 | expr.reg(0) << 12 | expr.reg(2),
+        USAD8 => 0x0780f010 | expr.reg(0) << 16 | expr.reg(2) << 8 | expr.reg(1),
+        SDIV => 0x0710f010 | expr.reg(0) << 16 | expr.reg(2) << 8 | expr.reg(1),
+        UDIV => 0x0730f010 | expr.reg(0) << 16 | expr.reg(2) << 8 | expr.reg(1),
+        USADA8 => {
+            0x07800010 | expr.reg(0) << 16 | expr.reg(3) << 12 | expr.reg(2) << 8 | expr.reg(1)
+        }
+        _ => {
+            let (opcode, _) = extend_operation(&name);
+            let (rd, rn, rm) = extend_registers(expr);
+            let rotation = expr.shift_amount() / 8;
+            0x06800070 | opcode << 20 | rn << 16 | rd << 12 | rotation << 10 | rm
+        }
+    }
+}
+
+// 32-bit Thumb encoding, sp can't be used either
+pub fn generate_media_thumb(op: &CpuOperation) -> u32 {
+    use InstructionName::*;
+
+    let expr = media_expression(op);
+    let name = op.instruction.value;
+
+    if expr.registers.iter().any(|reg| reg.to_num() == 13) {
+        panic!("{:?} can't use sp in Thumb state", name);
+    }
+
+    match name {
+        istr if is_parallel_op(&istr) => {
+            let (_, prefix) = parallel_prefix(&name);
+            let (_, op1) = parallel_operation(&name);
+            0xfa80f000
+                | op1 << 20
+                | expr.reg(1) << 16
+                | expr.reg(0) << 8
+                | prefix << 4
+                | expr.reg(2)
+        }
+        QADD | QSUB | QDADD | QDSUB => {
+            let opcode = match name {
+                QADD => 0b00,
+                QDADD => 0b01,
+                QSUB => 0b10,
+                _ => 0b11,
+            };
+            0xfa80f080 | expr.reg(2) << 16 | expr.reg(0) << 8 | opcode << 4 | expr.reg(1)
+        }
+        SSAT | USAT => {
+            let (sh, amount) = immediate_shift(expr);
+            // sh set with a shift of 0 is the encoding of ssat16/usat16
+            if sh == 1 && amount == 0 {
+                panic!("{:?} can't shift by asr #32 in Thumb state", name);
+            }
+            let (base, position) = match name {
+                SSAT => (0xf3000000, expr.saturate.unwrap() - 1),
+                _ => (0xf3800000, expr.saturate.unwrap()),
+            };
+            base | sh << 21
+                | expr.reg(1) << 16
+                | (amount >> 2) << 12
+                | expr.reg(0) << 8
+                | (amount & 3) << 6
+                | position
+        }
+        SSAT16 | USAT16 => {
+            let (base, position) = match name {
+                SSAT16 => (0xf3200000, expr.saturate.unwrap() - 1),
+                _ => (0xf3a00000, expr.saturate.unwrap()),
+            };
+            base | expr.reg(1) << 16 | expr.reg(0) << 8 | position
+        }
+        PKHBT | PKHTB => {
+            let (tb, amount) = immediate_shift(expr);
+            0xeac00000
+                | expr.reg(1) << 16
+                | (amount >> 2) << 12
+                | expr.reg(0) << 8
+                | (amount & 3) << 6
+                | tb << 5
+                | expr.reg(2)
+        }
+        SEL => 0xfaa0f080 | expr.reg(1) << 16 | expr.reg(0) << 8 | expr.reg(2),
+        USAD8 => 0xfb70f000 | expr.reg(1) << 16 | expr.reg(0) << 8 | expr.reg(2),
+        SDIV => 0xfb90f0f0 | expr.reg(1) << 16 | expr.reg(0) << 8 | expr.reg(2),
+        UDIV => 0xfbb0f0f0 | expr.reg(1) << 16 | expr.reg(0) << 8 | expr.reg(2),
+        USADA8 => {
+            0xfb700000 | expr.reg(1) << 16 | expr.reg(3) << 12 | expr.reg(0) << 8 | expr.reg(2)
+        }
+        _ => {
+            let (_, opcode) = extend_operation(&name);
+            let (rd, rn, rm) = extend_registers(expr);
+            let rotation = expr.shift_amount() / 8;
+            0xfa00f080 | opcode << 20 | rn << 16 | rd << 8 | rotation << 4 | rm
+        }
+    }
+}
+
+// 16-bit sxth, sxtb, uxth and uxtb of low registers, without rotation
+pub fn narrow_extend(op: &CpuOperation) -> Option<u32> {
+    use InstructionName::*;
+
+    let expr = media_expression(op);
+    let opcode = match op.instruction.value {
+        SXTH => 0b00,
+        SXTB => 0b01,
+        UXTH => 0b10,
+        UXTB => 0b11,
+        _ => return None,
+    };
+
+    let low = expr.registers.iter().all(|reg| reg.to_num() < 8);
+    (low && expr.shift.is_none()).then(|| 0xb200 | opcode << 6 | expr.reg(1) << 3 | expr.reg(0))
+}
@@ -1,41 +1,158 @@
 use symbolizer::SymbolTable;
 
+use crate::assembler::Section;
+use crate::elf::byte_order::ByteOrder;
 use crate::token::{
     immediate::Immediate,
-    instruction::Instruction,
+    instruction::{Instruction, Width},
     instruction_name::InstructionName,
     register::{Register, RegisterNumbers},
-    Token,
+    Number, Token,
 };
 
 use self::{
     cpu_op::CpuOperation,
     expression::{
-        reg_literal::RegLiteralExpression, three_regs::ThreeRegsExpression,
-        two_regs_literal::TwoRegsLiteralExpression, Expression,
+        barrel_shifter::{BarrealShifterShiftAmount, BarrelShifterExpression},
+        if_then::IfThenExpression,
+        reg_literal::{check_immediate_possible, RegLiteralExpression},
+        three_regs::ThreeRegsExpression,
+        two_regs_literal::TwoRegsLiteralExpression,
+        Expression,
     },
+    it_block::{GeneratedIt, ImplicitIt, ItBlock},
     operations::{
         branch_op::{is_branch_op, parse_branch_op},
-        load_store_op::{is_load_store_op, parse_load_store_op},
+        coprocessor_op::{is_coprocessor_op, parse_coprocessor_op},
+        load_store_op::{canonical_multiple_op, is_load_store_op, parse_load_store_op},
+        media_op::{is_media_op, parse_media_op},
+        system_op::{is_system_op, parse_system_op},
+        vfp_op::{is_vfp_op, parse_vfp_op},
     },
+    target::Target,
+    thumb::{encode_thumb, ThumbCode},
+    vfp::Fpu,
 };
 
 pub mod cpu_op;
 pub mod expression;
+pub mod it_block;
 pub mod machine_code_builder;
+pub mod media;
 pub mod operations;
 pub mod symbolizer;
+pub mod target;
+pub mod thumb;
+pub mod vfp;
+
+// Selected with .arm/.thumb (or .code 32/.code 16)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InstructionSet {
+    Arm,
+    Thumb,
+}
+
+impl InstructionSet {
+    // How far ahead of the instruction the pc reads
+    fn pc_offset(&self) -> i32 {
+        match self {
+            InstructionSet::Arm => 8,
+            InstructionSet::Thumb => 4,
+        }
+    }
+}
+
+// .arm/.code 32 and .thumb/.code 16/.thumb_func switch the instruction set
+pub fn instruction_set_directive(tokens: &[Token]) -> Option<InstructionSet> {
+    let directive = tokens.iter().find_map(|token| token.extract_directive())?;
+
+    match directive.value.as_str() {
+        ".arm" => Some(InstructionSet::Arm),
+        ".thumb" | ".thumb_func" => Some(InstructionSet::Thumb),
+        ".code" => match tokens.iter().find_map(|token| token.extract_number()) {
+            Some(number) if number.value == 32 => Some(InstructionSet::Arm),
+            Some(number) if number.value == 16 => Some(InstructionSet::Thumb),
+            _ => panic!(".code takes 16 or 32"),
+        },
+        _ => None,
+    }
+}
+
+// .org 0x40000000
+pub fn org_directive(tokens: &[Token]) -> Option<u32> {
+    let directive = tokens.iter().find_map(|token| token.extract_directive())?;
+    if directive.value != ".org" {
+        return None;
+    }
+
+    match tokens.iter().find_map(|token| token.extract_number()) {
+        Some(number) => Some(number.value),
+        None => panic!(".org takes an address"),
+    }
+}
+
+// Padding a .org adds to the current section. ELF objects take its address as an offset in
+// the section. Flat images take it as an absolute address from their base, which a .org
+// before anything was assembled gives when --base doesn't.
+pub fn org_padding(
+    org: u32,
+    offset: u32,
+    started: bool,
+    absolute: bool,
+    base: &mut Option<u32>,
+) -> u32 {
+    let origin = match (absolute, *base) {
+        (false, _) => 0,
+        (true, Some(base)) => base,
+        (true, None) if !started => *base.insert(org),
+        (true, None) => panic!(".org after the first bytes needs a base address, see --base"),
+    };
+
+    let address = origin.wrapping_add(offset);
+    if org < address {
+        panic!(".org can't move back from {:#x} to {:#x}", address, org);
+    }
+
+    org - address
+}
 
 pub struct Lexer {
     symbol_table: SymbolTable,
     pub addr: u32,
+    // Section being assembled, pc-relative labels have to be in it
+    pub section: Section,
+    pub instruction_set: InstructionSet,
+    // Set while the symbolizer sizes instructions, before any label is known
+    first_pass: bool,
+    it_block: ItBlock,
+    // Address of the last generated IT, and its bytes once later instructions joined it
+    generated_it_addr: u32,
+    it_patch: Option<(u32, Vec<u8>)>,
+    target: Target,
+    byte_order: ByteOrder,
 }
 
 impl Lexer {
-    pub fn new(symbol_table: SymbolTable) -> Self {
+    pub fn new(symbol_table: SymbolTable, implicit_it: ImplicitIt) -> Self {
         Lexer {
             symbol_table,
             addr: 0,
+            section: Section::Text,
+            instruction_set: InstructionSet::Arm,
+            first_pass: false,
+            it_block: ItBlock::new(implicit_it),
+            generated_it_addr: 0,
+            it_patch: None,
+            target: Target::default(),
+            byte_order: ByteOrder::default(),
+        }
+    }
+
+    // Lexer used by the symbolizer to find out how many bytes each line takes
+    pub fn new_first_pass(implicit_it: ImplicitIt) -> Self {
+        Lexer {
+            first_pass: true,
+            ..Lexer::new(SymbolTable::new(), implicit_it)
         }
     }
 
@@ -43,37 +160,228 @@ impl Lexer {
         self.addr += addr;
     }
 
-    pub fn parse_line(&mut self, mut tokens: Vec<Token>) -> Option<CpuOperation> {
+    // Switches between ARM and Thumb code. ARM instructions are word aligned, so switching
+    // after an odd number of halfwords pads with a Thumb nop, which is returned.
+    pub fn set_instruction_set(&mut self, instruction_set: InstructionSet) -> Vec<u8> {
+        if !self.it_block.is_empty() {
+            panic!("Instruction set changed inside an IT block");
+        }
+
+        let padding = if instruction_set == InstructionSet::Arm && !self.addr.is_multiple_of(4) {
+            vec![0x00, 0xbf]
+        } else {
+            vec![]
+        };
+
+        self.increment_addr(padding.len() as u32);
+        self.instruction_set = instruction_set;
+
+        padding
+    }
+
+    pub fn set_fpu(&mut self, fpu: Fpu) {
+        self.target.set_fpu(fpu);
+    }
+
+    pub fn set_byte_order(&mut self, byte_order: ByteOrder) {
+        self.byte_order = byte_order;
+    }
+
+    pub fn target(&self) -> &Target {
+        &self.target
+    }
+
+    pub fn set_target(&mut self, target: Target) {
+        self.target = target;
+    }
+
+    // Whether the next instruction is covered by an IT instruction
+    pub fn in_it_block(&self) -> bool {
+        !self.it_block.is_empty()
+    }
+
+    // A label or directive ends the IT block generated for the instructions before it, as a
+    // label may be branched to
+    pub fn close_generated_it(&mut self, tokens: &[Token]) {
+        if !matches!(tokens.first(), None | Some(Token::INSTRUCTION(_))) {
+            self.it_block.close_generated();
+        }
+    }
+
+    // Address and new bytes of a generated IT that the last line joined, to write over the
+    // IT already emitted
+    pub fn take_it_patch(&mut self) -> Option<(u32, Vec<u8>)> {
+        self.it_patch.take()
+    }
+
+    // Called at the end of the source, an IT block can't be left open
+    pub fn finish(&self) {
+        if !self.it_block.is_empty() {
+            panic!("IT block is missing instructions at the end of the file");
+        }
+    }
+
+    // Parses and encodes the instructions of a line in the current instruction set
+    pub fn assemble_line(&mut self, tokens: Vec<Token>) -> Vec<u8> {
         if tokens.is_empty() {
-            return None;
+            return vec![];
         }
 
-        // first replace any labels with their addresses
-        replace_label_ref(&mut tokens, &self.symbol_table, &mut self.addr);
-        let mut tokens = replace_pseudo_ops(tokens);
+        // pseudo ops may expand to more than one instruction
+        let lines = replace_pseudo_ops(
+            tokens,
+            &self.symbol_table,
+            self.addr,
+            &self.section,
+            self.instruction_set,
+            self.first_pass,
+        );
+
+        let mut buffer = vec![];
+        for line in lines {
+            if let Some(op) = self.parse_instruction(line) {
+                let code = self.encode(&op);
+                self.increment_addr(code.len() as u32);
+                buffer.extend(code);
+            }
+        }
+
+        buffer
+    }
+
+    fn encode(&mut self, op: &CpuOperation) -> Vec<u8> {
+        self.target.check(op, self.instruction_set);
+        if is_vfp_op(&op.instruction.value) {
+            self.target.fpu.unwrap_or_default().check(op);
+        }
+
+        match self.instruction_set {
+            InstructionSet::Arm => {
+                if op.instruction.width == Some(Width::Narrow) {
+                    panic!(
+                        "Narrow encoding (.n) of {:?} is not available in ARM state",
+                        op.instruction.value
+                    );
+                }
+
+                if matches!(
+                    op.instruction.value,
+                    InstructionName::CBZ | InstructionName::CBNZ
+                ) {
+                    panic!(
+                        "{:?} is only available in Thumb state",
+                        op.instruction.value
+                    );
+                }
+
+                // IT instructions assemble to nothing in ARM code, the block is still checked
+                self.it_block.next(&op.instruction, false);
+                if let Expression::IfThen(expr) = &op.expression {
+                    self.it_block.begin(expr);
+                    return vec![];
+                }
+
+                op.to_machine_code().to_u8_buff(self.byte_order)
+            }
+            InstructionSet::Thumb => {
+                let mut buffer = vec![];
+
+                match self.it_block.implicit_it(&op.instruction) {
+                    Some(GeneratedIt::New(expr)) => {
+                        self.generated_it_addr = self.addr;
+                        buffer.extend(self.encode_it(expr));
+                    }
+                    Some(GeneratedIt::Extended(expr)) => {
+                        self.it_patch = Some((self.generated_it_addr, self.encode_it(expr)));
+                    }
+                    None => {}
+                }
+
+                let in_it_block = self.it_block.next(&op.instruction, true);
+                if let Expression::IfThen(expr) = &op.expression {
+                    self.it_block.begin(expr);
+                }
+
+                let code = encode_thumb(op, in_it_block);
+                if matches!(code, ThumbCode::Wide(..)) {
+                    self.target.check_thumb_width(op);
+                }
+
+                buffer.extend(code.to_u8_buff(self.byte_order));
+                buffer
+            }
+        }
+    }
+
+    fn encode_it(&self, expr: IfThenExpression) -> Vec<u8> {
+        let it = Instruction::new("it", None, None).unwrap();
+        let it = CpuOperation::new(it, Expression::IfThen(expr));
+        encode_thumb(&it, false).to_u8_buff(self.byte_order)
+    }
+
+    fn parse_instruction(&mut self, mut tokens: Vec<Token>) -> Option<CpuOperation> {
+        let has_label = tokens
+            .iter()
+            .any(|token| matches!(token, Token::LABELREF(_)));
+
+        interwork_branch(&mut tokens, &self.symbol_table, self.instruction_set);
+
+        // first replace any labels with their offsets from the pc
+        replace_label_ref(
+            &mut tokens,
+            &self.symbol_table,
+            self.addr,
+            &self.section,
+            self.instruction_set,
+            self.first_pass,
+        );
 
         let index = tokens
             .iter()
             .position(|token| matches!(token, Token::INSTRUCTION(_)));
 
-        self.increment_addr(4);
-
         if let Some(index) = index {
             let (instruction, operands) = tokens.split_at_mut(index + 1);
             let instruction = instruction.last().unwrap();
             if let Token::INSTRUCTION(instruction) = instruction {
+                let mut instruction = *instruction;
+
+                // Label offsets aren't known in the first pass, so in Thumb code they get the
+                // 32-bit encoding unless .n asks otherwise. Both passes then agree on the size.
+                // Unlike GNU as there is no relaxation: a reference whose offset would fit the
+                // 16-bit encoding stays wide. CBZ and CBNZ only have the 16-bit one.
+                let narrow_only = matches!(
+                    instruction.value,
+                    InstructionName::CBZ | InstructionName::CBNZ
+                );
+                if has_label && instruction.width.is_none() && !narrow_only {
+                    instruction.width = Some(Width::Wide);
+                }
+
                 if is_logical_arithmatic_op(&instruction.value) {
                     let expr = parse_logical_arithmatic_op(operands);
-                    return Some(CpuOperation::new(*instruction, expr));
+                    return Some(CpuOperation::new(instruction, expr));
                 } else if is_move_op(&instruction.value) {
                     let expr = parse_move_op(operands);
-                    return Some(CpuOperation::new(*instruction, expr));
+                    return Some(CpuOperation::new(instruction, expr));
                 } else if is_branch_op(&instruction.value) {
                     let expr = parse_branch_op(&instruction.value, operands);
-                    return Some(CpuOperation::new(*instruction, expr));
+                    return Some(CpuOperation::new(instruction, expr));
                 } else if is_load_store_op(&instruction.value) {
                     let expr = parse_load_store_op(&instruction.value, operands);
-                    return Some(CpuOperation::new(*instruction, expr));
+                    return Some(CpuOperation::new(instruction, expr));
+                } else if is_system_op(&instruction.value) {
+                    let expr = parse_system_op(&instruction.value, operands);
+                    return Some(CpuOperation::new(instruction, expr));
+                } else if is_media_op(&instruction.value) {
+                    let expr = parse_media_op(&instruction.value, operands);
+                    return Some(CpuOperation::new(instruction, expr));
+                } else if is_coprocessor_op(&instruction.value) {
+                    let expr = parse_coprocessor_op(&instruction.value, operands);
+                    return Some(CpuOperation::new(instruction, expr));
+                } else if is_vfp_op(&instruction.value) {
+                    let expr = parse_vfp_op(&instruction.value, operands);
+                    return Some(CpuOperation::new(instruction, expr));
                 } else {
                     panic!("Instruction {:?} not supported", instruction.value)
                 }
@@ -86,8 +394,10 @@ impl Lexer {
 // Keep in mind we make a copy of the expressions in memory
 fn parse_logical_arithmatic_op(operands: &[Token]) -> Expression {
     match operands {
+        [.., Token::IMMEDIATE(_), Token::NUMBER(rot)] => missing_rotation_hash(rot),
         [Token::REGISTER(reg_d), Token::REGISTER(reg_m), Token::REGISTER(reg_n), rest @ ..] => {
             let barrel_shifter = expression::barrel_shifter::BarrelShifterExpression::new(rest);
+            check_register_shift(&[*reg_d, *reg_m, *reg_n], &barrel_shifter);
             Expression::ThreeRegs(ThreeRegsExpression::new(
                 reg_d.to_owned(),
                 reg_m.to_owned(),
@@ -100,29 +410,93 @@ fn parse_logical_arithmatic_op(operands: &[Token]) -> Expression {
                 reg_d.to_owned(),
                 reg_m.to_owned(),
                 imm.clone(),
+                None,
+            ))
+        }
+        [Token::REGISTER(reg_d), Token::REGISTER(reg_m), Token::IMMEDIATE(imm), Token::IMMEDIATE(rot)] => {
+            Expression::TwoRegsLiteral(TwoRegsLiteralExpression::new(
+                reg_d.to_owned(),
+                reg_m.to_owned(),
+                imm.clone(),
+                Some(rot.clone()),
+            ))
+        }
+        // two operand forms, rd is also the first source: add r0, r1 / add r0, r1, lsl #2
+        [Token::REGISTER(reg_d), Token::REGISTER(reg_n), rest @ ..] => {
+            let barrel_shifter = expression::barrel_shifter::BarrelShifterExpression::new(rest);
+            check_register_shift(&[*reg_d, *reg_n], &barrel_shifter);
+            Expression::ThreeRegs(ThreeRegsExpression::new(
+                reg_d.to_owned(),
+                reg_d.to_owned(),
+                reg_n.to_owned(),
+                barrel_shifter,
+            ))
+        }
+        [Token::REGISTER(reg_d), Token::IMMEDIATE(imm)] => Expression::TwoRegsLiteral(
+            TwoRegsLiteralExpression::new(reg_d.to_owned(), reg_d.to_owned(), imm.clone(), None),
+        ),
+        [Token::REGISTER(reg_d), Token::IMMEDIATE(imm), Token::IMMEDIATE(rot)] => {
+            Expression::TwoRegsLiteral(TwoRegsLiteralExpression::new(
+                reg_d.to_owned(),
+                reg_d.to_owned(),
+                imm.clone(),
+                Some(rot.clone()),
             ))
         }
         _ => panic!("Invalid operands"),
     }
 }
 
+// mov/mvn rd, <operand2> and cmp/cmn/tst/teq rn, <operand2>
+fn missing_rotation_hash(rotation: &Number) -> ! {
+    panic!(
+        "Rotation {} has to be written as #{}",
+        rotation.value, rotation.value
+    )
+}
+
 fn parse_move_op(operands: &[Token]) -> Expression {
     match operands {
+        [.., Token::IMMEDIATE(_), Token::NUMBER(rot)] => missing_rotation_hash(rot),
         [Token::REGISTER(reg_d), Token::REGISTER(reg_m), rest @ ..] => {
             let barrel_shifter = expression::barrel_shifter::BarrelShifterExpression::new(rest);
+            check_register_shift(&[*reg_d, *reg_m], &barrel_shifter);
             Expression::TwoRegs(expression::two_regs::TwoRegsExpression::new(
                 reg_d.to_owned(),
                 reg_m.to_owned(),
                 barrel_shifter,
             ))
         }
-        [Token::REGISTER(reg_d), Token::IMMEDIATE(imm)] => {
-            Expression::RegLiteral(RegLiteralExpression::new(reg_d.to_owned(), imm.clone()))
+        [Token::REGISTER(reg_d), Token::IMMEDIATE(imm)] => Expression::RegLiteral(
+            RegLiteralExpression::new(reg_d.to_owned(), imm.clone(), None),
+        ),
+        [Token::REGISTER(reg_d), Token::IMMEDIATE(imm), Token::IMMEDIATE(rot)] => {
+            Expression::RegLiteral(RegLiteralExpression::new(
+                reg_d.to_owned(),
+                imm.clone(),
+                Some(rot.clone()),
+            ))
         }
         _ => panic!("Invalid operands"),
     }
 }
 
+// Register-shifted register operands can't use the pc anywhere
+fn check_register_shift(registers: &[Register], barrel_shifter: &Option<BarrelShifterExpression>) {
+    let shift_register = match barrel_shifter.map(|bs| bs.shift_amount) {
+        Some(BarrealShifterShiftAmount::Register(reg)) => reg,
+        _ => return,
+    };
+
+    if registers
+        .iter()
+        .chain(std::iter::once(&shift_register))
+        .any(|reg| reg.to_num() == 15)
+    {
+        panic!("r15 cannot be used with a register-controlled shift");
+    }
+}
+
 pub fn is_logical_arithmatic_op(token: &InstructionName) -> bool {
     matches!(
         token,
@@ -134,8 +508,6 @@ pub fn is_logical_arithmatic_op(token: &InstructionName) -> bool {
             | InstructionName::ADC
             | InstructionName::SBC
             | InstructionName::RSC
-            | InstructionName::TST
-            | InstructionName::TEQ
             | InstructionName::ORR
             | InstructionName::BIC
     )
@@ -144,68 +516,190 @@ pub fn is_logical_arithmatic_op(token: &InstructionName) -> bool {
 pub fn is_move_op(token: &InstructionName) -> bool {
     matches!(
         token,
-        InstructionName::MOV | InstructionName::MVN | InstructionName::CMP | InstructionName::CMN
+        InstructionName::MOV
+            | InstructionName::MVN
+            | InstructionName::CMP
+            | InstructionName::CMN
+            | InstructionName::TST
+            | InstructionName::TEQ
     )
 }
 
-fn replace_label_ref(tokens: &mut [Token], symbol_table: &SymbolTable, current_addr: &mut u32) {
+// Compare instructions have no destination, their first operand goes in rn
+pub fn is_compare_op(token: &InstructionName) -> bool {
+    matches!(
+        token,
+        InstructionName::CMP | InstructionName::CMN | InstructionName::TST | InstructionName::TEQ
+    )
+}
+
+// bl and blx to a label of this file take the form that lands in the state of the target.
+// Calls to other files are left to the linker.
+fn interwork_branch(
+    tokens: &mut [Token],
+    symbol_table: &SymbolTable,
+    instruction_set: InstructionSet,
+) {
+    let target = tokens.iter().find_map(|token| match token {
+        Token::LABELREF(label) => symbol_table.get_instruction_set(label),
+        _ => None,
+    });
+
+    let switch = match target {
+        Some(target) => target != instruction_set,
+        None => return,
+    };
+
+    for token in tokens.iter_mut() {
+        if let Token::INSTRUCTION(istr) = token {
+            istr.value = match istr.value {
+                InstructionName::BL | InstructionName::BLX if switch => InstructionName::BLX,
+                InstructionName::BL | InstructionName::BLX => InstructionName::BL,
+                InstructionName::B if switch => {
+                    panic!("B can't change the instruction set, use BL, BLX or BX")
+                }
+                value => value,
+            };
+        }
+    }
+}
+
+// Labels become byte offsets from the pc. Labels this file doesn't define, or defines in another
+// section, get the offset of the instruction itself, which is the addend their relocation
+// expects. In the first pass no label is known yet and they all read as the pc.
+fn replace_label_ref(
+    tokens: &mut [Token],
+    symbol_table: &SymbolTable,
+    current_addr: u32,
+    section: &Section,
+    instruction_set: InstructionSet,
+    first_pass: bool,
+) {
+    let mut pc = current_addr as i32 + instruction_set.pc_offset();
+
+    // Thumb literal loads and blx address from the word aligned pc
+    let aligned_pc = tokens.iter().any(|token| {
+        matches!(token, Token::INSTRUCTION(istr)
+            if matches!(istr.value, InstructionName::VLDR | InstructionName::BLX))
+    });
+    if instruction_set == InstructionSet::Thumb && aligned_pc {
+        pc &= !3;
+    }
+
     for token in tokens.iter_mut() {
         if let Token::LABELREF(label) = token {
-            let address = symbol_table.get_address(label);
-            if address.is_none() {
-                let immediate = Immediate::new(0.to_string()).unwrap();
-                *token = Token::IMMEDIATE(immediate);
-                return;
-            }
+            let offset = match symbol_table.get_address(label) {
+                Some(address) if symbol_table.get_section(label) == Some(section) => {
+                    address.value as i32 - pc
+                }
+                _ if first_pass => 0,
+                _ => -instruction_set.pc_offset(),
+            };
 
-            let immediate = Immediate::new(
-                ((address.unwrap().value as i32 - current_addr.to_owned() as i32 - 8) / 4)
-                    .to_string(),
-            )
-            .unwrap();
+            let immediate = Immediate::new(offset.to_string()).unwrap();
 
             *token = Token::IMMEDIATE(immediate);
         }
     }
 }
 
-fn replace_pseudo_ops(mut tokens: Vec<Token>) -> Vec<Token> {
+// push/pop of one register are a str/ldr to the stack in ARM state, as GNU as encodes them.
+// stmfd/ldmfd stay multiple transfers, Thumb picks its encodings in wide_load_store_multiple.
+fn single_register_stack_op(
+    tokens: &mut Vec<Token>,
+    index: usize,
+    instruction_set: InstructionSet,
+) -> bool {
+    let Token::INSTRUCTION(instruction) = &tokens[index] else {
+        return false;
+    };
+    let register = match &tokens[index + 1..] {
+        [Token::LBRACE, Token::REGISTER(register), Token::RBRACE] => *register,
+        _ => return false,
+    };
+    if instruction_set != InstructionSet::Arm {
+        return false;
+    }
+
+    let sp = Token::REGISTER(Register::new(RegisterNumbers::THIRTEEN));
+    let immediate = |value: &str| Token::IMMEDIATE(Immediate::new(value.to_string()).unwrap());
+    let (name, address) = match instruction.value {
+        InstructionName::PUSH => (
+            "str",
+            vec![
+                Token::LPAREN,
+                sp,
+                immediate("-4"),
+                Token::RPAREN,
+                Token::BANG,
+            ],
+        ),
+        InstructionName::POP => (
+            "ldr",
+            vec![Token::LPAREN, sp, Token::RPAREN, immediate("4")],
+        ),
+        _ => return false,
+    };
+
+    let istr = Instruction::new(name, None, Some(instruction.condition.to_string())).unwrap();
+    tokens.truncate(index);
+    tokens.push(Token::INSTRUCTION(istr));
+    tokens.push(Token::REGISTER(register));
+    tokens.extend(address);
+    true
+}
+
+fn replace_pseudo_ops(
+    mut tokens: Vec<Token>,
+    symbol_table: &SymbolTable,
+    current_addr: u32,
+    section: &Section,
+    instruction_set: InstructionSet,
+    first_pass: bool,
+) -> Vec<Vec<Token>> {
     let index = tokens
         .iter()
         .position(|token| matches!(token, Token::INSTRUCTION(_)));
 
     if let Some(index) = index {
+        if single_register_stack_op(&mut tokens, index, instruction_set) {
+            return vec![tokens];
+        }
+
         let (instruction, _) = tokens.split_at_mut(index + 1);
         let instruction = instruction.last().unwrap();
         if let Token::INSTRUCTION(instruction) = instruction {
             if is_pseudo_istr(&instruction.value) {
                 match instruction.value {
                     InstructionName::PUSH => {
-                        let istr = Instruction::new(
+                        let mut istr = Instruction::new(
                             "stmdb",
                             None,
                             Some(instruction.condition.to_string()),
                         )
                         .unwrap();
+                        istr.width = instruction.width;
 
                         tokens[index] = Token::INSTRUCTION(istr);
                         tokens.insert(
                             index + 1,
                             Token::REGISTER(Register::new(RegisterNumbers::THIRTEEN)),
                         );
+                        tokens.insert(index + 2, Token::BANG);
 
-                        return tokens;
+                        return vec![tokens];
                     }
                     InstructionName::POP => {
                         // let mut new_tokens = vec![Token::INSTRUCTION(InstructionName::LDMIA)];
                         // new_tokens.extend(operands);
                         // new_tokens
-                        let istr = Instruction::new(
+                        let mut istr = Instruction::new(
                             "ldmia",
                             None,
                             Some(instruction.condition.to_string()),
                         )
                         .unwrap();
+                        istr.width = instruction.width;
 
                         tokens[index] = Token::INSTRUCTION(istr);
 
@@ -213,23 +707,83 @@ fn replace_pseudo_ops(mut tokens: Vec<Token>) -> Vec<Token> {
                             index + 1,
                             Token::REGISTER(Register::new(RegisterNumbers::THIRTEEN)),
                         );
+                        tokens.insert(index + 2, Token::BANG);
+
+                        return vec![tokens];
+                    }
+                    InstructionName::VPUSH | InstructionName::VPOP => {
+                        let name = match instruction.value {
+                            InstructionName::VPUSH => "vstmdb",
+                            _ => "vldmia",
+                        };
+                        let mut istr =
+                            Instruction::new(name, None, Some(instruction.condition.to_string()))
+                                .unwrap();
+                        istr.data_type = instruction.data_type;
+
+                        tokens[index] = Token::INSTRUCTION(istr);
+                        tokens.insert(
+                            index + 1,
+                            Token::REGISTER(Register::new(RegisterNumbers::THIRTEEN)),
+                        );
+                        tokens.insert(index + 2, Token::BANG);
 
-                        return tokens;
+                        return vec![tokens];
+                    }
+                    name if canonical_multiple_op(&name).is_some() => {
+                        let mut istr = *instruction;
+                        istr.value = canonical_multiple_op(&name).unwrap();
+
+                        tokens[index] = Token::INSTRUCTION(istr);
+
+                        return vec![tokens];
                     }
                     InstructionName::LSL
                     | InstructionName::LSR
                     | InstructionName::ROR
-                    | InstructionName::ASR => {
-                        let mov =
-                            Instruction::new("mov", None, Some(instruction.condition.to_string()))
-                                .unwrap();
+                    | InstructionName::ASR
+                    | InstructionName::RRX => {
+                        let mov = Instruction::new(
+                            "mov",
+                            instruction.set_flags.then_some("s"),
+                            Some(instruction.condition.to_string()),
+                        )
+                        .unwrap();
 
                         let istr = *instruction;
 
                         tokens[index] = Token::INSTRUCTION(mov);
-                        tokens.insert(tokens.len() - 1, Token::INSTRUCTION(istr));
 
-                        return tokens;
+                        // lsl rd, #n is short for lsl rd, rd, #n
+                        let operands = tokens.len() - index - 1;
+                        let two_operands = (istr.value == InstructionName::RRX && operands == 1)
+                            || (istr.value != InstructionName::RRX && operands == 2);
+                        if two_operands {
+                            if let Token::REGISTER(reg_d) = tokens[index + 1] {
+                                tokens.insert(index + 2, Token::REGISTER(reg_d));
+                            }
+                        }
+
+                        if istr.value == InstructionName::RRX {
+                            tokens.push(Token::INSTRUCTION(istr));
+                        } else {
+                            tokens.insert(tokens.len() - 1, Token::INSTRUCTION(istr));
+                        }
+
+                        return vec![tokens];
+                    }
+                    InstructionName::ADR | InstructionName::ADRL => {
+                        let istr = *instruction;
+
+                        return expand_adr(
+                            &istr,
+                            &tokens[index + 1..],
+                            symbol_table,
+                            current_addr,
+                            section,
+                            instruction_set,
+                            first_pass,
+                        );
                     }
                     _ => panic!("Invalid instruction"),
                 };
@@ -237,7 +791,116 @@ fn replace_pseudo_ops(mut tokens: Vec<Token>) -> Vec<Token> {
         }
     }
 
-    tokens
+    vec![tokens]
+}
+
+// adr rd, label   -> add/sub rd, pc, #offset
+// adrl rd, label  -> add/sub rd, pc, #offset_lo ; add/sub rd, rd, #offset_hi
+fn expand_adr(
+    instruction: &Instruction,
+    operands: &[Token],
+    symbol_table: &SymbolTable,
+    current_addr: u32,
+    section: &Section,
+    instruction_set: InstructionSet,
+    first_pass: bool,
+) -> Vec<Vec<Token>> {
+    let (reg_d, label) = match operands {
+        [Token::REGISTER(reg_d), Token::LABELREF(label)] => (*reg_d, label),
+        _ => panic!("Invalid operands"),
+    };
+
+    if instruction_set == InstructionSet::Thumb && instruction.value == InstructionName::ADRL {
+        panic!("ADRL is not available in Thumb state");
+    }
+
+    // Sections are placed by the linker, an offset into another one means nothing here
+    if symbol_table
+        .get_section(label)
+        .is_some_and(|target| target != section)
+    {
+        panic!("ADR target {} is in a different section", label);
+    }
+
+    // Thumb adr is relative to the word aligned pc
+    let pc_relative_offset = match instruction_set {
+        InstructionSet::Arm => symbol_table.pc_relative_offset(label, current_addr),
+        InstructionSet::Thumb => symbol_table
+            .get_address(label)
+            .map(|address| address.value as i32 - ((current_addr + 4) & !3) as i32),
+    };
+
+    // the size doesn't depend on the offset, so any value does in the first pass
+    let offset = match pc_relative_offset {
+        Some(offset) => offset,
+        None if first_pass => 0,
+        None => panic!("ADR target {} is not defined in this file", label),
+    };
+
+    let operation = if offset < 0 { "sub" } else { "add" };
+    let magnitude = offset.unsigned_abs();
+
+    let chunks = match instruction.value {
+        InstructionName::ADR if instruction_set == InstructionSet::Thumb => {
+            if magnitude > 0xfff {
+                panic!("ADR target {} is out of range (offset {})", label, offset);
+            }
+            vec![magnitude]
+        }
+        InstructionName::ADR => {
+            if check_immediate_possible(magnitude).is_none() {
+                panic!(
+                    "ADR target {} is out of range (offset {}), use ADRL instead",
+                    label, offset
+                );
+            }
+            vec![magnitude]
+        }
+        _ => {
+            let (low, high) = split_adrl_offset(magnitude).unwrap_or_else(|| {
+                panic!("ADRL target {} is out of range (offset {})", label, offset)
+            });
+            vec![low, high]
+        }
+    };
+
+    let pc = Register::new(RegisterNumbers::FIFTEEN);
+
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut istr =
+                Instruction::new(operation, None, Some(instruction.condition.to_string())).unwrap();
+            // like other label operands, Thumb adr is wide unless .n asks otherwise
+            istr.width = match instruction_set {
+                InstructionSet::Thumb => instruction.width.or(Some(Width::Wide)),
+                InstructionSet::Arm => instruction.width,
+            };
+            let base = if i == 0 { pc } else { reg_d };
+
+            vec![
+                Token::INSTRUCTION(istr),
+                Token::REGISTER(reg_d),
+                Token::REGISTER(base),
+                Token::IMMEDIATE(Immediate::new(chunk.to_string()).unwrap()),
+            ]
+        })
+        .collect()
+}
+
+// Splits an offset into two rotated immediates. The first takes 8 bits from the lowest set
+// one, then from each other even rotation until the rest is encodable too.
+fn split_adrl_offset(offset: u32) -> Option<(u32, u32)> {
+    let start = offset.trailing_zeros() & !1;
+
+    (0..32).step_by(2).find_map(|rotation| {
+        let low = offset & 0xff_u32.rotate_left(start + rotation);
+        let high = offset & !low;
+        check_immediate_possible(high)?;
+
+        Some((low, high))
+    })
 }
 
 fn is_pseudo_istr(token: &InstructionName) -> bool {
@@ -245,9 +908,92 @@ fn is_pseudo_istr(token: &InstructionName) -> bool {
         token,
         InstructionName::PUSH
             | InstructionName::POP
+            | InstructionName::ADR
+            | InstructionName::ADRL
             | InstructionName::LSL
             | InstructionName::LSR
             | InstructionName::ROR
             | InstructionName::ASR
-    )
+            | InstructionName::RRX
+            | InstructionName::VPUSH
+            | InstructionName::VPOP
+    ) || canonical_multiple_op(token).is_some()
+}
+
+// Assembles source lines for -march=`arch`, switching with .arm and .thumb. The operand checks
+// of each instruction class are tested through it
+#[cfg(test)]
+pub fn assemble_for_arch(source: &str, arch: &str) -> Vec<u8> {
+    let mut tokenizer =
+        crate::tokenizer::Tokenizer::new(crate::reader::Reader::from_source(source));
+    let mut lexer = Lexer::new(SymbolTable::new(), ImplicitIt::default());
+    lexer.set_target(Target::from_arch(arch).unwrap());
+
+    let mut bytes = vec![];
+    while !tokenizer.is_eof() {
+        let line = tokenizer.consume_line();
+        match instruction_set_directive(&line) {
+            Some(instruction_set) => bytes.extend(lexer.set_instruction_set(instruction_set)),
+            None => bytes.extend(lexer.assemble_line(line)),
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{reader::Reader, tokenizer::Tokenizer};
+
+    use super::*;
+
+    #[test]
+    fn test_split_adrl_offset() {
+        assert_eq!(split_adrl_offset(0x1234), Some((0x234, 0x1000)));
+        assert_eq!(split_adrl_offset(0xff), Some((0xff, 0)));
+        assert_eq!(split_adrl_offset(0x12345), None);
+        // only with the second chunk wrapping around bit 31
+        assert_eq!(split_adrl_offset(0x4000101), Some((0x100, 0x4000001)));
+    }
+
+    #[test]
+    fn test_single_register_stack_ops() {
+        let source = "push {r1}\npop {pc}\nstmfd sp!, {r1}\nldmfd sp!, {r1}\n";
+        let mut tokenizer = Tokenizer::new(Reader::from_source(source));
+        let mut lexer = Lexer::new(SymbolTable::new(), ImplicitIt::default());
+
+        // Only push and pop become a str/ldr, the stack forms of stm/ldm stay multiple
+        for word in [0xe52d1004u32, 0xe49df004, 0xe92d0002, 0xe8bd0002] {
+            assert_eq!(
+                lexer.assemble_line(tokenizer.consume_line()),
+                word.to_le_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn test_generated_it_patch() {
+        let source = "nop\naddeq r0, r1\nsubne r0, r1\n";
+        let mut tokenizer = Tokenizer::new(Reader::from_source(source));
+        let mut lexer = Lexer::new(SymbolTable::new(), ImplicitIt::Always);
+        lexer.set_instruction_set(InstructionSet::Thumb);
+
+        lexer.assemble_line(tokenizer.consume_line());
+        assert_eq!(
+            lexer.assemble_line(tokenizer.consume_line()),
+            [0x08, 0xbf, 0x40, 0x18]
+        );
+        assert_eq!(lexer.take_it_patch(), None);
+
+        // subne joins the IT of addeq, which becomes ite eq
+        assert_eq!(lexer.assemble_line(tokenizer.consume_line()), [0x40, 0x1a]);
+        assert_eq!(lexer.take_it_patch(), Some((2, vec![0x0c, 0xbf])));
+    }
+
+    #[test]
+    #[should_panic(expected = "Rotation 8 has to be written as #8")]
+    fn test_rotation_without_hash() {
+        let mut tokenizer = Tokenizer::new(Reader::from_source("add r0, r1, #255, 8\n"));
+        let mut lexer = Lexer::new(SymbolTable::new(), ImplicitIt::default());
+        lexer.assemble_line(tokenizer.consume_line());
+    }
 }
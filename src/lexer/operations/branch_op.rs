@@ -1,12 +1,16 @@
 use crate::{
     lexer::expression::Expression,
-    lexer::expression::{immediate::ImmediateExpression, reg::RegExpression},
+    lexer::expression::{
+        immediate::ImmediateExpression, reg::RegExpression, reg_literal::RegLiteralExpression,
+    },
     token::{instruction_name::InstructionName, Token},
 };
 
 pub fn parse_branch_op(instruction: &InstructionName, operands: &[Token]) -> Expression {
     match instruction {
-        InstructionName::B | InstructionName::BL => {
+        InstructionName::B | InstructionName::BL | InstructionName::BLX
+            if matches!(operands, [Token::IMMEDIATE(_)]) =>
+        {
             if operands.len() == 1 {
                 if let Token::IMMEDIATE(imm) = &operands[0] {
                     Expression::Immediate(ImmediateExpression::new(imm.clone()))
@@ -17,9 +21,12 @@ pub fn parse_branch_op(instruction: &InstructionName, operands: &[Token]) -> Exp
                 panic!("Invalid operands");
             }
         }
-        InstructionName::BX => {
+        InstructionName::BX | InstructionName::BLX | InstructionName::BXJ => {
             if operands.len() == 1 {
                 if let Token::REGISTER(reg) = operands[0] {
+                    if reg.to_num() == 15 && *instruction != InstructionName::BX {
+                        panic!("{:?} can't branch to pc", instruction);
+                    }
                     Expression::Register(RegExpression::new(reg.to_owned()))
                 } else {
                     panic!("Invalid operands");
@@ -28,6 +35,13 @@ pub fn parse_branch_op(instruction: &InstructionName, operands: &[Token]) -> Exp
                 panic!("Invalid operands");
             }
         }
+        // cbz/cbnz rn, label
+        InstructionName::CBZ | InstructionName::CBNZ => match operands {
+            [Token::REGISTER(reg), Token::IMMEDIATE(imm)] => {
+                Expression::RegLiteral(RegLiteralExpression::new(*reg, imm.clone(), None))
+            }
+            _ => panic!("Invalid operands"),
+        },
         _ => panic!("Invalid instruction"),
     }
 }
@@ -35,6 +49,29 @@ pub fn parse_branch_op(instruction: &InstructionName, operands: &[Token]) -> Exp
 pub fn is_branch_op(token: &InstructionName) -> bool {
     matches!(
         token,
-        InstructionName::B | InstructionName::BL | InstructionName::BX
+        InstructionName::B
+            | InstructionName::BL
+            | InstructionName::BLX
+            | InstructionName::BX
+            | InstructionName::BXJ
+            | InstructionName::CBZ
+            | InstructionName::CBNZ
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::assemble_for_arch;
+
+    #[test]
+    #[should_panic(expected = "BLX is not available on armv4t (needs V5)")]
+    fn test_blx_needs_v5() {
+        assemble_for_arch("blx r0\n", "armv4t");
+    }
+
+    #[test]
+    #[should_panic(expected = "BLX can't branch to pc")]
+    fn test_blx_pc() {
+        assemble_for_arch("blx pc\n", "armv7-a");
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{
+    lexer::expression::{
+        coprocessor::{CoprocessorExpression, CoprocessorLoadStoreExpression},
+        ls_imm_index::{IndexMode, PreIndex},
+        Expression,
+    },
+    token::{
+        instruction_name::InstructionName,
+        register::{Register, RegisterNumbers},
+        Token,
+    },
+};
+
+pub fn is_coprocessor_op(token: &InstructionName) -> bool {
+    use InstructionName::*;
+    matches!(
+        token,
+        MCR | MCR2
+            | MRC
+            | MRC2
+            | MCRR
+            | MCRR2
+            | MRRC
+            | MRRC2
+            | CDP
+            | CDP2
+            | LDC
+            | LDC2
+            | LDCL
+            | LDC2L
+            | STC
+            | STC2
+            | STCL
+            | STC2L
+    )
+}
+
+pub fn parse_coprocessor_op(instruction: &InstructionName, operands: &[Token]) -> Expression {
+    use InstructionName::*;
+
+    let (coprocessor, operands) = match operands {
+        [Token::COPROCESSOR(coprocessor), rest @ ..] => (*coprocessor, rest),
+        _ => panic!("{:?} needs a coprocessor (p0-p15) first", instruction),
+    };
+
+    match instruction {
+        // mcr p15, 0, r0, c7, c5{, 0}
+        MCR | MCR2 | MRC | MRC2 => {
+            let (opc1, rt, crn, crm, opc2) = match operands {
+                [opc1, rt, Token::CPREGISTER(crn), Token::CPREGISTER(crm)] => {
+                    (opc1, rt, *crn, *crm, 0)
+                }
+                [opc1, rt, Token::CPREGISTER(crn), Token::CPREGISTER(crm), opc2] => {
+                    (opc1, rt, *crn, *crm, opcode(opc2, 7))
+                }
+                _ => panic!("Invalid operands"),
+            };
+
+            let rt = match rt {
+                Token::REGISTER(rt) => *rt,
+                // mrc p14, 0, APSR_nzcv, c0, c1, 0 copies bits 31:28 to the flags
+                Token::OPTION(option)
+                    if option == "apsr_nzcv" && matches!(instruction, MRC | MRC2) =>
+                {
+                    Register::new(RegisterNumbers::FIFTEEN)
+                }
+                _ => panic!("Invalid operands"),
+            };
+
+            if matches!(instruction, MCR | MCR2) && rt.to_num() == 15 {
+                panic!("{:?} can't transfer pc", instruction);
+            }
+
+            Expression::Coprocessor(CoprocessorExpression::new(
+                coprocessor,
+                opcode(opc1, 7),
+                vec![rt],
+                vec![crn, crm],
+                opc2,
+            ))
+        }
+        // mcrr p15, 0, r0, r1, c2
+        MCRR | MCRR2 | MRRC | MRRC2 => match operands {
+            [opc1, Token::REGISTER(rt), Token::REGISTER(rt2), Token::CPREGISTER(crm)] => {
+                if rt.to_num() == 15 || rt2.to_num() == 15 {
+                    panic!("{:?} can't transfer pc", instruction);
+                }
+                if matches!(instruction, MRRC | MRRC2) && rt == rt2 {
+                    panic!("{:?} needs two different registers", instruction);
+                }
+
+                Expression::Coprocessor(CoprocessorExpression::new(
+                    coprocessor,
+                    opcode(opc1, 15),
+                    vec![*rt, *rt2],
+                    vec![*crm],
+                    0,
+                ))
+            }
+            _ => panic!("Invalid operands"),
+        },
+        // cdp p14, 1, c1, c2, c3{, 4}
+        CDP | CDP2 => {
+            let (opc1, registers, opc2) = match operands {
+                [opc1, registers @ .., opc2 @ (Token::NUMBER(_) | Token::IMMEDIATE(_))] => {
+                    (opc1, registers, opcode(opc2, 7))
+                }
+                [opc1, registers @ ..] => (opc1, registers, 0),
+                _ => panic!("Invalid operands"),
+            };
+
+            let registers = match registers {
+                [Token::CPREGISTER(crd), Token::CPREGISTER(crn), Token::CPREGISTER(crm)] => {
+                    vec![*crd, *crn, *crm]
+                }
+                _ => panic!("Invalid operands"),
+            };
+
+            Expression::Coprocessor(CoprocessorExpression::new(
+                coprocessor,
+                opcode(opc1, 15),
+                vec![],
+                registers,
+                opc2,
+            ))
+        }
+        _ => parse_load_store(coprocessor, operands),
+    }
+}
+
+// ldc p14, c5, [r1{, #offset}]{!}, [r1], #offset or [r1], {option}
+fn parse_load_store(coprocessor: u8, operands: &[Token]) -> Expression {
+    let (crd, address) = match operands {
+        [Token::CPREGISTER(crd), rest @ ..] => (*crd, rest),
+        _ => panic!("Invalid operands"),
+    };
+
+    let pre_index = |write_back| IndexMode::Pre(PreIndex { write_back });
+
+    let (base, offset, index_mode, option) = match address {
+        [Token::LPAREN, Token::REGISTER(base), Token::RPAREN] => (base, 0, pre_index(false), None),
+        [Token::LPAREN, Token::REGISTER(base), Token::IMMEDIATE(offset), Token::RPAREN] => {
+            (base, offset.to_num() as i32, pre_index(false), None)
+        }
+        [Token::LPAREN, Token::REGISTER(base), Token::IMMEDIATE(offset), Token::RPAREN, Token::BANG] => {
+            (base, offset.to_num() as i32, pre_index(true), None)
+        }
+        [Token::LPAREN, Token::REGISTER(base), Token::RPAREN, Token::IMMEDIATE(offset)] => {
+            (base, offset.to_num() as i32, IndexMode::Post, None)
+        }
+        [Token::LPAREN, Token::REGISTER(base), Token::RPAREN, Token::LBRACE, option, Token::RBRACE] => {
+            (base, 0, IndexMode::None, Some(opcode(option, 255)))
+        }
+        _ => panic!("Invalid operands"),
+    };
+
+    let magnitude = offset.unsigned_abs();
+    if magnitude % 4 != 0 || magnitude > 1020 {
+        panic!(
+            "Offset {} out of range, it must be a multiple of 4 up to 1020",
+            offset
+        );
+    }
+
+    Expression::CoprocessorLoadStore(CoprocessorLoadStoreExpression::new(
+        coprocessor,
+        crd,
+        *base,
+        offset,
+        index_mode,
+        option,
+    ))
+}
+
+// Opcodes are plain numbers, #imm is accepted as well
+fn opcode(token: &Token, max: u32) -> u8 {
+    let value = match token {
+        Token::NUMBER(number) => number.value,
+        Token::IMMEDIATE(imm) => imm.to_num(),
+        _ => panic!("Expected a coprocessor opcode"),
+    };
+
+    if value > max {
+        panic!("Coprocessor opcode {} out of range (0-{})", value, max);
+    }
+
+    value as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::assemble_for_arch;
+
+    #[test]
+    fn test_cp15_transfers() {
+        let bytes = assemble_for_arch(
+            "mcr p15, 0, r0, c7, c5, 0\nmrc p15, 0, r1, c1, c0, 0\n",
+            "armv7-a",
+        );
+        assert_eq!(bytes, [0x15, 0x0f, 0x07, 0xee, 0x10, 0x1f, 0x11, 0xee]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Coprocessor opcode 8 out of range (0-7)")]
+    fn test_coprocessor_opcode_range() {
+        assemble_for_arch("mcr p15, 8, r0, c7, c5, 0\n", "armv7-a");
+    }
+
+    #[test]
+    #[should_panic(expected = "MCR needs a coprocessor (p0-p15) first")]
+    fn test_coprocessor_number() {
+        assemble_for_arch("mcr p16, 0, r0, c7, c5, 0\n", "armv7-a");
+    }
+
+    #[test]
+    #[should_panic(expected = "MCR2 is not available on armv4t (needs V5)")]
+    fn test_mcr2_needs_v5() {
+        assemble_for_arch("mcr2 p15, 0, r0, c7, c5, 0\n", "armv4t");
+    }
+}
@@ -1,8 +1,10 @@
 use crate::lexer::expression::barrel_shifter::BarrelShifterExpression;
+use crate::lexer::expression::ls_exclusive::LoadStoreExclusiveExpression;
 use crate::lexer::expression::ls_imm_index::{IndexMode, LoadStoreImmediateExpression, PreIndex};
 use crate::lexer::expression::ls_multiple::LoadStoreMultipleExpression;
 use crate::lexer::expression::ls_reg_index::LoadStoreRegisterExpression;
 use crate::token::register::Register;
+use crate::utils::warning;
 use crate::{
     lexer::expression::Expression,
     token::{instruction_name::InstructionName, Token},
@@ -23,6 +25,52 @@ pub fn is_load_store_op(token: &InstructionName) -> bool {
             | InstructionName::LDMDA
             | InstructionName::LDMIB
             | InstructionName::LDMIA
+    ) || is_exclusive_op(token)
+}
+
+// Stack and bare names of the load/store multiple instructions, as the addressing mode they stand for
+pub fn canonical_multiple_op(token: &InstructionName) -> Option<InstructionName> {
+    use InstructionName::*;
+    match token {
+        LDM | LDMFD => Some(LDMIA),
+        LDMEA => Some(LDMDB),
+        LDMED => Some(LDMIB),
+        LDMFA => Some(LDMDA),
+        STM | STMEA => Some(STMIA),
+        STMFD => Some(STMDB),
+        STMED => Some(STMDA),
+        STMFA => Some(STMIB),
+        _ => None,
+    }
+}
+
+pub fn is_exclusive_op(token: &InstructionName) -> bool {
+    use InstructionName::*;
+    matches!(
+        token,
+        LDREX
+            | LDREXB
+            | LDREXH
+            | LDREXD
+            | STREX
+            | STREXB
+            | STREXH
+            | STREXD
+            | LDA
+            | LDAB
+            | LDAH
+            | STL
+            | STLB
+            | STLH
+            | LDAEX
+            | LDAEXB
+            | LDAEXH
+            | LDAEXD
+            | STLEX
+            | STLEXB
+            | STLEXH
+            | STLEXD
+            | CLREX
     )
 }
 
@@ -38,7 +86,8 @@ pub fn parse_load_store_op(instruction: &InstructionName, operands: &[Token]) ->
         | InstructionName::LDMDB
         | InstructionName::LDMDA
         | InstructionName::LDMIB
-        | InstructionName::LDMIA => parse_multiple_op(operands),
+        | InstructionName::LDMIA => parse_multiple_op(instruction, operands),
+        istr if is_exclusive_op(istr) => parse_exclusive_op(istr, operands),
         _ => panic!("Invalid instruction"),
     }
 }
@@ -201,29 +250,66 @@ fn parse_single_op(operands: &[Token]) -> Expression {
     }
 }
 
-fn parse_multiple_op(operands: &[Token]) -> Expression {
-    match operands {
-        [Token::REGISTER(dest), Token::LBRACE, rest @ .., Token::RBRACE] => {
-            let registers = parse_multiple_regs(rest);
-            Expression::LoadStoreMultiple(LoadStoreMultipleExpression::new(
-                dest.to_owned(),
-                registers,
-                false,
-            ))
-        }
+// ldm r0{!}, {r1-r3, lr}{^}
+fn parse_multiple_op(instruction: &InstructionName, operands: &[Token]) -> Expression {
+    let (operands, user_mode) = match operands {
+        [rest @ .., Token::CARET] => (rest, true),
+        _ => (operands, false),
+    };
 
-        [Token::REGISTER(dest), Token::BANG, Token::LBRACE, rest @ .., Token::RBRACE] => {
-            let registers = parse_multiple_regs(rest);
-            Expression::LoadStoreMultiple(LoadStoreMultipleExpression::new(
-                dest.to_owned(),
-                registers,
-                true,
-            ))
+    let (base, write_back, list) = match operands {
+        [Token::REGISTER(base), Token::LBRACE, rest @ .., Token::RBRACE] => (base, false, rest),
+        [Token::REGISTER(base), Token::BANG, Token::LBRACE, rest @ .., Token::RBRACE] => {
+            (base, true, rest)
         }
         _ => panic!("Invalid operands"),
+    };
+
+    if base.to_num() == 15 {
+        panic!("pc can't be the base register of {:?}", instruction);
+    }
+
+    let registers = parse_multiple_regs(list);
+    let load = matches!(
+        instruction,
+        InstructionName::LDMIA
+            | InstructionName::LDMIB
+            | InstructionName::LDMDA
+            | InstructionName::LDMDB
+    );
+
+    // ^ with pc loaded returns from an exception, otherwise it transfers the user mode registers
+    let exception_return = load && registers.iter().any(|reg| reg.to_num() == 15);
+    if user_mode && write_back && !exception_return {
+        warning(&format!(
+            "writeback is UNPREDICTABLE when {:?} transfers user mode registers",
+            instruction
+        ));
     }
+
+    if write_back && registers.contains(base) {
+        if load {
+            warning(&format!(
+                "writeback of base register r{} is UNPREDICTABLE when it is also loaded",
+                base.to_num()
+            ));
+        } else if registers[0] != *base {
+            warning(&format!(
+                "value stored for r{} is UNKNOWN, it is not the lowest register of the list",
+                base.to_num()
+            ));
+        }
+    }
+
+    Expression::LoadStoreMultiple(LoadStoreMultipleExpression::new(
+        base.to_owned(),
+        registers,
+        write_back,
+        user_mode,
+    ))
 }
 
+// The registers are returned in ascending order, without duplicates
 fn parse_multiple_regs(operands: &[Token]) -> Vec<Register> {
     let mut registers = Vec::new();
 
@@ -241,10 +327,33 @@ fn parse_multiple_regs(operands: &[Token]) -> Vec<Register> {
                 }
             }
             registers.push(reg.to_owned());
+        } else {
+            panic!("Invalid register list");
         }
         i += 1;
     }
 
+    if registers.is_empty() {
+        panic!("Empty register list");
+    }
+
+    let mut list = 0u32;
+    for reg in &registers {
+        let bit = 1 << reg.to_num();
+        if list & bit != 0 {
+            warning(&format!(
+                "register r{} appears more than once in the list",
+                reg.to_num()
+            ));
+        } else if list > bit {
+            warning("register list not in ascending order");
+        }
+        list |= bit;
+    }
+
+    registers.sort_by_key(|reg| reg.to_num());
+    registers.dedup();
+
     registers
 }
 
@@ -259,3 +368,139 @@ fn generate_reg_range(start: &Register, end: &Register) -> Vec<Register> {
         .map(|num| Register::from_num(num).unwrap())
         .collect()
 }
+
+fn parse_exclusive_op(instruction: &InstructionName, operands: &[Token]) -> Expression {
+    use InstructionName::*;
+
+    if matches!(instruction, CLREX) {
+        if !operands.is_empty() {
+            panic!("Invalid operands");
+        }
+        return Expression::Empty;
+    }
+
+    // the address is always a plain [rn], or [rn, #0]
+    let (registers, base) = match operands {
+        [registers @ .., Token::LPAREN, Token::REGISTER(base), Token::RPAREN] => (registers, base),
+        [registers @ .., Token::LPAREN, Token::REGISTER(base), Token::IMMEDIATE(imm), Token::RPAREN]
+            if imm.to_num() == 0 =>
+        {
+            (registers, base)
+        }
+        _ => panic!("Invalid operands"),
+    };
+
+    let registers = registers
+        .iter()
+        .map(|token| match token {
+            Token::REGISTER(reg) => *reg,
+            _ => panic!("Invalid operands"),
+        })
+        .collect::<Vec<Register>>();
+
+    let is_store_exclusive = matches!(
+        instruction,
+        STREX | STREXB | STREXH | STREXD | STLEX | STLEXB | STLEXH | STLEXD
+    );
+    let is_double = matches!(instruction, LDREXD | STREXD | LDAEXD | STLEXD);
+
+    let expected = 1 + is_store_exclusive as usize + is_double as usize;
+    if registers.len() != expected {
+        panic!(
+            "{:?} expects {} registers before the address",
+            instruction, expected
+        );
+    }
+
+    let (status, registers) = if is_store_exclusive {
+        (Some(registers[0]), &registers[1..])
+    } else {
+        (None, &registers[..])
+    };
+
+    let destination = registers[0];
+    let destination2 = registers.get(1).copied();
+
+    if let Some(destination2) = destination2 {
+        if destination.to_num() % 2 != 0 || destination.to_num() == 14 {
+            panic!(
+                "{:?} needs an even first register other than r14",
+                instruction
+            );
+        }
+        if destination2.to_num() != destination.to_num() + 1 {
+            panic!("{:?} needs two consecutive registers", instruction);
+        }
+    }
+
+    if let Some(status) = status {
+        if status == destination || status == *base || Some(status) == destination2 {
+            panic!(
+                "{:?} status register must differ from the transferred and base registers",
+                instruction
+            );
+        }
+    }
+
+    if registers
+        .iter()
+        .chain(status.iter())
+        .any(|reg| reg.to_num() == 15)
+        || base.to_num() == 15
+    {
+        panic!("{:?} cannot use r15", instruction);
+    }
+
+    Expression::LoadStoreExclusive(LoadStoreExclusiveExpression::new(
+        status,
+        destination,
+        destination2,
+        *base,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::assemble_for_arch;
+
+    fn register(num: u8) -> Token {
+        Token::REGISTER(Register::from_num(num).unwrap())
+    }
+
+    #[test]
+    fn test_register_list_is_sorted_without_duplicates() {
+        let list = [
+            register(4),
+            register(1),
+            Token::MINUS,
+            register(3),
+            register(1),
+        ];
+
+        let registers: Vec<u8> = parse_multiple_regs(&list)
+            .iter()
+            .map(|reg| reg.to_num())
+            .collect();
+
+        assert_eq!(registers, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    #[should_panic(expected = "STREX status register must differ")]
+    fn test_strex_status_register_overlap() {
+        assemble_for_arch("strex r0, r0, [r1]\n", "armv7-a");
+    }
+
+    #[test]
+    #[should_panic(expected = "LDREXD needs an even first register")]
+    fn test_ldrexd_odd_register() {
+        assemble_for_arch("ldrexd r1, r2, [r3]\n", "armv7-a");
+    }
+
+    #[test]
+    #[should_panic(expected = "LDA is not available on armv7-a (needs V8)")]
+    fn test_acquire_needs_v8() {
+        assemble_for_arch("lda r0, [r1]\n", "armv7-a");
+    }
+}
@@ -0,0 +1,254 @@
+use crate::{
+    lexer::expression::{
+        barrel_shifter::{BarrelShifterExpression, BarrelShifterOperation},
+        media::MediaExpression,
+        Expression,
+    },
+    token::{instruction_name::InstructionName, register::Register, Token},
+};
+
+pub fn is_media_op(token: &InstructionName) -> bool {
+    is_parallel_op(token)
+        || is_extend_op(token)
+        || matches!(
+            token,
+            InstructionName::QADD
+                | InstructionName::QSUB
+                | InstructionName::QDADD
+                | InstructionName::QDSUB
+                | InstructionName::SSAT
+                | InstructionName::USAT
+                | InstructionName::SSAT16
+                | InstructionName::USAT16
+                | InstructionName::SEL
+                | InstructionName::USAD8
+                | InstructionName::USADA8
+                | InstructionName::PKHBT
+                | InstructionName::PKHTB
+                | InstructionName::SDIV
+                | InstructionName::UDIV
+        )
+}
+
+// Parallel add and subtract, on the halfwords or bytes of the registers
+pub fn is_parallel_op(token: &InstructionName) -> bool {
+    use InstructionName::*;
+    matches!(
+        token,
+        SADD16
+            | SASX
+            | SSAX
+            | SSUB16
+            | SADD8
+            | SSUB8
+            | QADD16
+            | QASX
+            | QSAX
+            | QSUB16
+            | QADD8
+            | QSUB8
+            | SHADD16
+            | SHASX
+            | SHSAX
+            | SHSUB16
+            | SHADD8
+            | SHSUB8
+            | UADD16
+            | UASX
+            | USAX
+            | USUB16
+            | UADD8
+            | USUB8
+            | UQADD16
+            | UQASX
+            | UQSAX
+            | UQSUB16
+            | UQADD8
+            | UQSUB8
+            | UHADD16
+            | UHASX
+            | UHSAX
+            | UHSUB16
+            | UHADD8
+            | UHSUB8
+    )
+}
+
+// Sign and zero extends, the ones with an A add the result to a register
+pub fn is_extend_op(token: &InstructionName) -> bool {
+    use InstructionName::*;
+    matches!(
+        token,
+        SXTB | SXTH
+            | SXTB16
+            | UXTB
+            | UXTH
+            | UXTB16
+            | SXTAB
+            | SXTAH
+            | SXTAB16
+            | UXTAB
+            | UXTAH
+            | UXTAB16
+    )
+}
+
+pub fn parse_media_op(instruction: &InstructionName, operands: &[Token]) -> Expression {
+    use InstructionName::*;
+
+    let expression = match instruction {
+        // ssat rd, #imm, rn{, lsl #n | asr #n}
+        SSAT | USAT | SSAT16 | USAT16 => match operands {
+            [Token::REGISTER(rd), Token::IMMEDIATE(imm), Token::REGISTER(rn), rest @ ..] => {
+                let range = match instruction {
+                    SSAT => 1..=32,
+                    USAT => 0..=31,
+                    SSAT16 => 1..=16,
+                    _ => 0..=15,
+                };
+                if !range.contains(&imm.to_num()) {
+                    panic!(
+                        "{:?} saturates to bit positions {}-{}",
+                        instruction,
+                        range.start(),
+                        range.end()
+                    );
+                }
+
+                let shift = BarrelShifterExpression::new(rest);
+                let allowed = match shift.map(|shift| shift.operation) {
+                    None => true,
+                    Some(BarrelShifterOperation::LSL | BarrelShifterOperation::ASR) => {
+                        matches!(instruction, SSAT | USAT)
+                    }
+                    _ => false,
+                };
+                if !allowed || shift.is_some_and(|shift| shift.is_register_shift()) {
+                    panic!(
+                        "{:?} only takes a lsl or asr shift by an immediate",
+                        instruction
+                    );
+                }
+
+                MediaExpression::new(vec![*rd, *rn], Some(imm.to_num()), shift)
+            }
+            _ => panic!("Invalid operands"),
+        },
+        // pkhbt rd, rn, rm{, lsl #n} and pkhtb rd, rn, rm{, asr #n}
+        PKHBT | PKHTB => match operands {
+            [Token::REGISTER(rd), Token::REGISTER(rn), Token::REGISTER(rm), rest @ ..] => {
+                let shift = BarrelShifterExpression::new(rest);
+                let expected = match instruction {
+                    PKHBT => BarrelShifterOperation::LSL,
+                    _ => BarrelShifterOperation::ASR,
+                };
+                if let Some(shift) = shift {
+                    if shift.operation != expected || shift.is_register_shift() {
+                        panic!(
+                            "{:?} only takes a {:?} shift by an immediate",
+                            instruction, expected
+                        );
+                    }
+                }
+
+                // without a shift, pkhtb rd, rn, rm is pkhbt rd, rm, rn
+                match (instruction, shift) {
+                    (PKHTB, None) => MediaExpression::new(vec![*rd, *rm, *rn], None, None),
+                    _ => MediaExpression::new(vec![*rd, *rn, *rm], None, shift),
+                }
+            }
+            _ => panic!("Invalid operands"),
+        },
+        // usada8 rd, rn, rm, ra
+        USADA8 => match operands {
+            [Token::REGISTER(rd), Token::REGISTER(rn), Token::REGISTER(rm), Token::REGISTER(ra)] => {
+                MediaExpression::new(vec![*rd, *rn, *rm, *ra], None, None)
+            }
+            _ => panic!("Invalid operands"),
+        },
+        // sxtb rd, rm{, ror #n} and sxtab rd, rn, rm{, ror #n}
+        istr if is_extend_op(istr) => {
+            let adds = matches!(istr, SXTAB | SXTAH | SXTAB16 | UXTAB | UXTAH | UXTAB16);
+            let count = if adds { 3 } else { 2 };
+            if operands.len() < count {
+                panic!("Invalid operands");
+            }
+
+            let (registers, rest) = operands.split_at(count);
+            let expression = MediaExpression::new(
+                parse_registers(registers, count),
+                None,
+                BarrelShifterExpression::new(rest),
+            );
+
+            let rotation = expression.shift.map(|rotation| rotation.operation);
+            let valid = match rotation {
+                None => true,
+                Some(BarrelShifterOperation::ROR) => expression.shift_amount().is_multiple_of(8),
+                _ => false,
+            };
+            if !valid
+                || expression
+                    .shift
+                    .is_some_and(|shift| shift.is_register_shift())
+            {
+                panic!("{:?} only rotates by ror #8, #16 or #24", instruction);
+            }
+
+            expression
+        }
+        // the rest are rd, rn, rm. The saturating ones take rd, rm, rn.
+        _ => MediaExpression::new(parse_registers(operands, 3), None, None),
+    };
+
+    if expression.registers.iter().any(|reg| reg.to_num() == 15) {
+        panic!("{:?} can't use pc", instruction);
+    }
+
+    Expression::Media(expression)
+}
+
+fn parse_registers(operands: &[Token], count: usize) -> Vec<Register> {
+    let registers: Vec<Register> = operands
+        .iter()
+        .map(|token| match token {
+            Token::REGISTER(reg) => *reg,
+            _ => panic!("Invalid operands"),
+        })
+        .collect();
+
+    if registers.len() != count {
+        panic!("Invalid operands");
+    }
+
+    registers
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::assemble_for_arch;
+
+    #[test]
+    #[should_panic(expected = "SSAT saturates to bit positions 1-32")]
+    fn test_ssat_position_range() {
+        assemble_for_arch("ssat r0, #33, r1\n", "armv7-a");
+    }
+
+    #[test]
+    #[should_panic(expected = "SXTB only rotates by ror #8, #16 or #24")]
+    fn test_extend_rotation() {
+        assemble_for_arch("sxtb r0, r1, ror #4\n", "armv7-a");
+    }
+
+    #[test]
+    #[should_panic(expected = "PKHTB only takes a ASR shift by an immediate")]
+    fn test_pack_shift_type() {
+        assemble_for_arch("pkhtb r0, r1, r2, lsl #3\n", "armv7-a");
+    }
+
+    #[test]
+    #[should_panic(expected = "QADD is not available on armv4t (needs V5E)")]
+    fn test_saturating_add_needs_v5e() {
+        assemble_for_arch("qadd r0, r1, r2\n", "armv4t");
+    }
+}
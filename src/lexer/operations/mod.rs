@@ -1,2 +1,6 @@
 pub mod branch_op;
+pub mod coprocessor_op;
 pub mod load_store_op;
+pub mod media_op;
+pub mod system_op;
+pub mod vfp_op;
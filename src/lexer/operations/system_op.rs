@@ -0,0 +1,221 @@
+use crate::{
+    lexer::expression::{
+        cps::CpsExpression, if_then::IfThenExpression, immediate::ImmediateExpression,
+        status_register::StatusRegisterExpression, Expression,
+    },
+    token::{
+        immediate::Immediate, instruction::ConditionCode, instruction_name::InstructionName, Token,
+    },
+};
+
+pub fn is_system_op(token: &InstructionName) -> bool {
+    matches!(
+        token,
+        InstructionName::MRS
+            | InstructionName::MSR
+            | InstructionName::CPS
+            | InstructionName::CPSIE
+            | InstructionName::CPSID
+            | InstructionName::SVC
+            | InstructionName::BKPT
+            | InstructionName::UDF
+            | InstructionName::NOP
+            | InstructionName::YIELD
+            | InstructionName::WFE
+            | InstructionName::WFI
+            | InstructionName::SEV
+            | InstructionName::DMB
+            | InstructionName::DSB
+            | InstructionName::ISB
+            | InstructionName::IT
+    )
+}
+
+pub fn parse_system_op(instruction: &InstructionName, operands: &[Token]) -> Expression {
+    match instruction {
+        InstructionName::MRS => match operands {
+            [Token::REGISTER(reg_d), Token::STATUSREGISTER(status_register)] => {
+                if status_register.fields.is_some() {
+                    panic!("mrs reads the whole status register, fields are not allowed");
+                }
+
+                Expression::StatusRegister(StatusRegisterExpression::new(
+                    *status_register,
+                    Some(*reg_d),
+                    None,
+                ))
+            }
+            _ => panic!("Invalid operands"),
+        },
+        InstructionName::MSR => match operands {
+            [Token::STATUSREGISTER(status_register), Token::REGISTER(reg_n)] => {
+                Expression::StatusRegister(StatusRegisterExpression::new(
+                    *status_register,
+                    Some(*reg_n),
+                    None,
+                ))
+            }
+            [Token::STATUSREGISTER(status_register), Token::IMMEDIATE(imm)] => {
+                Expression::StatusRegister(StatusRegisterExpression::new(
+                    *status_register,
+                    None,
+                    Some(imm.clone()),
+                ))
+            }
+            _ => panic!("Invalid operands"),
+        },
+        InstructionName::CPS | InstructionName::CPSIE | InstructionName::CPSID => {
+            parse_cps(instruction, operands)
+        }
+        InstructionName::SVC => Expression::Immediate(parse_bounded_immediate(operands, 0xffffff)),
+        InstructionName::BKPT | InstructionName::UDF => {
+            Expression::Immediate(parse_bounded_immediate(operands, 0xffff))
+        }
+        InstructionName::NOP
+        | InstructionName::YIELD
+        | InstructionName::WFE
+        | InstructionName::WFI
+        | InstructionName::SEV => {
+            if !operands.is_empty() {
+                panic!("Invalid operands");
+            }
+            Expression::Empty
+        }
+        InstructionName::DMB | InstructionName::DSB | InstructionName::ISB => {
+            let option = match operands {
+                [] => 0b1111,
+                [Token::OPTION(option)] => barrier_option(option)
+                    .unwrap_or_else(|| panic!("Invalid barrier option {}", option)),
+                [Token::IMMEDIATE(_)] => parse_bounded_immediate(operands, 0xf).literal.to_num(),
+                _ => panic!("Invalid operands"),
+            };
+
+            Expression::Immediate(ImmediateExpression::new(
+                Immediate::new(option.to_string()).unwrap(),
+            ))
+        }
+        InstructionName::IT => parse_it(operands),
+        _ => panic!("Invalid instruction"),
+    }
+}
+
+// it{x{y{z}}} <cond>, the tokenizer hands over the t/e pattern as the first option
+fn parse_it(operands: &[Token]) -> Expression {
+    let (pattern, condition) = match operands {
+        [Token::OPTION(pattern), Token::OPTION(condition)] => (pattern, condition),
+        _ => panic!("Invalid operands"),
+    };
+
+    let condition = ConditionCode::from_name(condition)
+        .unwrap_or_else(|| panic!("Invalid IT condition {}", condition));
+
+    let pattern = pattern.chars().map(|slot| slot == 't').collect();
+
+    Expression::IfThen(IfThenExpression::new(condition, pattern))
+}
+
+// cps #mode, cpsie <aif> and cpsid <aif>{, #mode}
+fn parse_cps(instruction: &InstructionName, operands: &[Token]) -> Expression {
+    let (flags, mode) = match operands {
+        [Token::IMMEDIATE(_)] if matches!(instruction, InstructionName::CPS) => {
+            (None, Some(operands))
+        }
+        [Token::OPTION(flags)] => (Some(flags), None),
+        [Token::OPTION(flags), mode @ ..] => (Some(flags), Some(mode)),
+        _ => panic!("Invalid operands"),
+    };
+
+    if matches!(instruction, InstructionName::CPS) == flags.is_some() {
+        panic!("cps takes a mode, cpsie and cpsid take the a, i, f flags");
+    }
+
+    let flags = flags.map_or(0, |flags| {
+        parse_interrupt_flags(flags).unwrap_or_else(|| panic!("Invalid cps flags {}", flags))
+    });
+
+    let mode = mode.map(|mode| parse_bounded_immediate(mode, 0x1f).literal.to_num() as u8);
+
+    Expression::ChangeProcessorState(CpsExpression::new(flags, mode))
+}
+
+fn parse_interrupt_flags(flags: &str) -> Option<u8> {
+    let mut mask = 0;
+    for flag in flags.chars() {
+        let bit = match flag {
+            'a' => 0b100,
+            'i' => 0b010,
+            'f' => 0b001,
+            _ => return None,
+        };
+
+        if mask & bit != 0 {
+            return None;
+        }
+        mask |= bit;
+    }
+
+    Some(mask)
+}
+
+fn barrier_option(option: &str) -> Option<u32> {
+    match option {
+        "sy" => Some(0b1111),
+        "st" => Some(0b1110),
+        "ld" => Some(0b1101),
+        "ish" => Some(0b1011),
+        "ishst" => Some(0b1010),
+        "ishld" => Some(0b1001),
+        "nsh" => Some(0b0111),
+        "nshst" => Some(0b0110),
+        "nshld" => Some(0b0101),
+        "osh" => Some(0b0011),
+        "oshst" => Some(0b0010),
+        "oshld" => Some(0b0001),
+        _ => None,
+    }
+}
+
+fn parse_bounded_immediate(operands: &[Token], max: u32) -> ImmediateExpression {
+    match operands {
+        [Token::IMMEDIATE(imm)] => {
+            if imm.to_num() > max {
+                panic!("Immediate #{} out of range (#0-{:#x})", imm.value, max);
+            }
+
+            ImmediateExpression::new(imm.clone())
+        }
+        _ => panic!("Invalid operands"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::assemble_for_arch;
+
+    #[test]
+    fn test_barriers_and_hints() {
+        let bytes = assemble_for_arch("dmb ish\nwfi\nsvc #0xffffff\n", "armv7-a");
+        assert_eq!(
+            bytes,
+            [0x5b, 0xf0, 0x7f, 0xf5, 0x03, 0xf0, 0x20, 0xe3, 0xff, 0xff, 0xff, 0xef]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Immediate #0x1000000 out of range (#0-0xffffff)")]
+    fn test_svc_immediate_range() {
+        assemble_for_arch("svc #0x1000000\n", "armv7-a");
+    }
+
+    #[test]
+    #[should_panic(expected = "DMB is not available on armv6 (needs V7)")]
+    fn test_barrier_needs_v7() {
+        assemble_for_arch("dmb sy\n", "armv6");
+    }
+
+    #[test]
+    #[should_panic(expected = "mrs reads the whole status register")]
+    fn test_mrs_rejects_fields() {
+        assemble_for_arch("mrs r0, cpsr_c\n", "armv7-a");
+    }
+}
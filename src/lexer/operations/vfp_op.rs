@@ -0,0 +1,156 @@
+use crate::{
+    lexer::expression::{
+        vfp::{VfpExpression, VfpLoadStoreExpression, VfpLoadStoreMultipleExpression, VfpOperand},
+        Expression,
+    },
+    token::{
+        instruction_name::InstructionName,
+        register::{FpRegister, Register, RegisterNumbers},
+        Token,
+    },
+};
+
+pub fn is_vfp_op(token: &InstructionName) -> bool {
+    token.is_vfp()
+}
+
+pub fn parse_vfp_op(instruction: &InstructionName, operands: &[Token]) -> Expression {
+    use InstructionName::*;
+
+    match instruction {
+        VLDR | VSTR => parse_load_store(operands),
+        VLDM | VLDMIA | VLDMDB | VSTM | VSTMIA | VSTMDB => match operands {
+            [Token::REGISTER(base), Token::LBRACE, rest @ .., Token::RBRACE] => {
+                parse_load_store_multiple(*base, false, rest)
+            }
+            [Token::REGISTER(base), Token::BANG, Token::LBRACE, rest @ .., Token::RBRACE] => {
+                parse_load_store_multiple(*base, true, rest)
+            }
+            _ => panic!("Invalid operands"),
+        },
+        _ => Expression::Vfp(VfpExpression::new(parse_operands(operands))),
+    }
+}
+
+// vldr d0, [r0], vldr d0, [r0, #-8] or vldr d0, label (already an offset from the pc)
+fn parse_load_store(operands: &[Token]) -> Expression {
+    let pc = Register::new(RegisterNumbers::FIFTEEN);
+
+    let (register, base, offset) = match operands {
+        [Token::FPREGISTER(register), Token::LPAREN, Token::REGISTER(base), Token::RPAREN] => {
+            (register, *base, 0)
+        }
+        [Token::FPREGISTER(register), Token::LPAREN, Token::REGISTER(base), Token::IMMEDIATE(offset), Token::RPAREN] => {
+            (register, *base, offset.to_num() as i32)
+        }
+        [Token::FPREGISTER(register), Token::IMMEDIATE(offset)] => {
+            (register, pc, offset.to_num() as i32)
+        }
+        _ => panic!("Invalid operands"),
+    };
+
+    Expression::VfpLoadStore(VfpLoadStoreExpression::new(*register, base, offset))
+}
+
+fn parse_load_store_multiple(base: Register, write_back: bool, operands: &[Token]) -> Expression {
+    let registers = parse_register_list(operands);
+
+    let first = registers[0];
+    let limit = match first {
+        FpRegister::Single(_) => 32,
+        FpRegister::Double(_) => 16,
+        _ => panic!("vldm and vstm transfer single or double registers"),
+    };
+
+    if registers.len() > limit {
+        panic!("At most {} registers can be transferred", limit);
+    }
+
+    for (previous, register) in registers.iter().zip(registers.iter().skip(1)) {
+        let consecutive = match (previous, register) {
+            (FpRegister::Single(a), FpRegister::Single(b))
+            | (FpRegister::Double(a), FpRegister::Double(b)) => a + 1 == *b,
+            _ => false,
+        };
+
+        if !consecutive {
+            panic!("The register list must be consecutive registers of the same size");
+        }
+    }
+
+    Expression::VfpLoadStoreMultiple(VfpLoadStoreMultipleExpression::new(
+        base,
+        first,
+        registers.len() as u8,
+        write_back,
+    ))
+}
+
+// {d8-d15} or {s0, s1, s2}
+pub fn parse_register_list(operands: &[Token]) -> Vec<FpRegister> {
+    let mut registers = vec![];
+
+    let mut i = 0;
+    while i < operands.len() {
+        match (&operands[i], operands.get(i + 1), operands.get(i + 2)) {
+            (Token::FPREGISTER(start), Some(Token::MINUS), Some(Token::FPREGISTER(end))) => {
+                let range = match (start, end) {
+                    (FpRegister::Single(start), FpRegister::Single(end)) if start <= end => {
+                        (*start..=*end).map(FpRegister::Single).collect::<Vec<_>>()
+                    }
+                    (FpRegister::Double(start), FpRegister::Double(end)) if start <= end => {
+                        (*start..=*end).map(FpRegister::Double).collect::<Vec<_>>()
+                    }
+                    _ => panic!("Invalid register range"),
+                };
+                registers.extend(range);
+                i += 3;
+            }
+            (Token::FPREGISTER(register), _, _) => {
+                registers.push(*register);
+                i += 1;
+            }
+            _ => panic!("Invalid register list"),
+        }
+    }
+
+    if registers.is_empty() {
+        panic!("Empty register list");
+    }
+
+    registers
+}
+
+fn parse_operands(operands: &[Token]) -> Vec<VfpOperand> {
+    let mut result = vec![];
+
+    let mut i = 0;
+    while i < operands.len() {
+        let operand = match (&operands[i], operands.get(i + 1)) {
+            // d0[1]
+            (Token::FPREGISTER(register), Some(Token::LPAREN)) => {
+                match (operands.get(i + 2), operands.get(i + 3)) {
+                    (Some(Token::NUMBER(index)), Some(Token::RPAREN)) => {
+                        i += 3;
+                        VfpOperand::Scalar(*register, index.value as u8)
+                    }
+                    _ => panic!("Invalid scalar"),
+                }
+            }
+            (Token::FPREGISTER(register), _) => VfpOperand::Fp(*register),
+            (Token::REGISTER(register), _) => VfpOperand::Core(*register),
+            // vmrs APSR_nzcv, fpscr moves the flags, which is encoded as pc
+            (Token::OPTION(option), _) if option == "apsr_nzcv" => {
+                VfpOperand::Core(Register::new(RegisterNumbers::FIFTEEN))
+            }
+            (Token::FLOATIMMEDIATE(value), _) => VfpOperand::Immediate(*value),
+            (Token::IMMEDIATE(imm), _) => VfpOperand::Immediate(imm.to_num() as i32 as f64),
+            _ => panic!("Invalid operands"),
+        };
+
+        result.push(operand);
+        i += 1;
+    }
+
+    result
+}
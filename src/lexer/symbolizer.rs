@@ -1,6 +1,20 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use crate::{assembler::Section, token::Directive, tokenizer::Tokenizer};
+use crate::{
+    assembler::Section,
+    image::OutputFormat,
+    lexer::{
+        instruction_set_directive,
+        it_block::ImplicitIt,
+        org_directive, org_padding,
+        target::{target_directive, Target},
+        vfp::fpu_directive,
+        InstructionSet, Lexer,
+    },
+    token::Directive,
+    tokenizer::Tokenizer,
+    utils::set_warnings_enabled,
+};
 
 #[derive(Debug, Clone)]
 pub enum Scope {
@@ -24,6 +38,12 @@ pub struct TableRow {
     pub address: Address,
     pub scope: Scope,
     pub section: Section,
+    // Marked with .thumb_func, the symbol gets its Thumb bit set
+    pub thumb_function: bool,
+    // State of the code at the label, calls into the other one go through blx
+    pub instruction_set: InstructionSet,
+    // Source line of the label
+    pub line: u32,
 }
 
 #[derive(Debug, Clone)]
@@ -51,6 +71,31 @@ impl SymbolTable {
         self.0.get(&symbol).map(|row| &row.address)
     }
 
+    pub fn get_section(&self, symbol: &str) -> Option<&Section> {
+        let symbol = Symbol::new(symbol.to_string());
+
+        self.0.get(&symbol).map(|row| &row.section)
+    }
+
+    pub fn is_thumb_function(&self, symbol: &str) -> bool {
+        let symbol = Symbol::new(symbol.to_string());
+
+        self.0.get(&symbol).is_some_and(|row| row.thumb_function)
+    }
+
+    pub fn get_instruction_set(&self, symbol: &str) -> Option<InstructionSet> {
+        let symbol = Symbol::new(symbol.to_string());
+
+        self.0.get(&symbol).map(|row| row.instruction_set)
+    }
+
+    // Offset from the PC seen by the instruction at `addr` (which reads as addr + 8)
+    pub fn pc_relative_offset(&self, symbol: &str, addr: u32) -> Option<i32> {
+        let address = self.get_address(symbol)?;
+
+        Some(address.value as i32 - (addr as i32 + 8))
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&Symbol, &TableRow)> {
         self.0.iter()
     }
@@ -66,41 +111,119 @@ pub struct Symbolizer {
     pub symbol_table: SymbolTable,
     tokenizer: Tokenizer,
     addr: u32,
+    // Where the sections switched away from continue
+    section_addrs: HashMap<Section, u32>,
     current_scope: Scope,
     current_section: Section,
+    // Sizes the instructions, which depends on the instruction set and IT blocks in Thumb code
+    lexer: Lexer,
+    thumb_function: bool,
+    // Set once any section has bytes, see org_padding
+    started: bool,
+    // .org addresses are absolute in flat images, from their base
+    format: OutputFormat,
+    base: Option<u32>,
+    // Names given to .global, which may come before or after their labels
+    globals: HashSet<String>,
+    // From .handlerdata to .fnend words go to .ARM.extab instead of the section
+    handler_data: bool,
 }
 
 impl Symbolizer {
-    pub fn new(tokenizer: Tokenizer) -> Self {
+    pub fn new(tokenizer: Tokenizer, implicit_it: ImplicitIt, target: Target) -> Self {
+        let mut lexer = Lexer::new_first_pass(implicit_it);
+        lexer.set_target(target);
+
         Symbolizer {
             symbol_table: SymbolTable(HashMap::new()),
             tokenizer,
             addr: 0,
+            section_addrs: HashMap::new(),
             current_section: Section::Text,
             current_scope: Scope::Local,
+            lexer,
+            thumb_function: false,
+            started: false,
+            format: OutputFormat::default(),
+            base: None,
+            globals: HashSet::new(),
+            handler_data: false,
         }
     }
 
+    pub fn set_output_format(&mut self, format: OutputFormat, base: Option<u32>) {
+        self.format = format;
+        self.base = base;
+    }
+
     pub fn symbolize(&mut self) {
+        set_warnings_enabled(false);
         while !self.tokenizer.is_eof() {
             self.symbolize_line();
         }
+        set_warnings_enabled(true);
+
+        for (symbol, row) in self.symbol_table.0.iter_mut() {
+            if self.globals.contains(&symbol.name) {
+                row.scope = Scope::Global;
+            }
+        }
     }
 
     fn symbolize_line(&mut self) {
         let tokens = self.tokenizer.consume_line();
         use crate::token::Token;
 
+        self.lexer.close_generated_it(&tokens);
+
+        if let Some(instruction_set) = instruction_set_directive(&tokens) {
+            self.lexer.addr = self.addr;
+            self.addr += self.lexer.set_instruction_set(instruction_set).len() as u32;
+        }
+
+        if let Some(fpu) = fpu_directive(&tokens) {
+            self.lexer.set_fpu(fpu);
+        }
+
+        if let Some(org) = org_directive(&tokens) {
+            let absolute = self.format != OutputFormat::Elf;
+            if absolute && self.current_section != Section::Text {
+                panic!(".org is only supported in .text of flat images");
+            }
+            let started = self.started || self.addr > 0;
+            self.addr += org_padding(org, self.addr, started, absolute, &mut self.base);
+        }
+
+        if let Some(target) = target_directive(&tokens, self.lexer.target()) {
+            self.lexer.set_target(target);
+        }
+
         for token in &tokens {
             if let Token::DIRECTIVE(label) = token {
-                if label.value == ".global" || label.value == "._global" {
+                if label.value == ".global" || label.value == "._global" || label.value == ".globl"
+                {
                     self.current_scope = Scope::Global;
+                    self.globals
+                        .extend(tokens.iter().filter_map(|token| match token {
+                            Token::LABELREF(name) => Some(name.clone()),
+                            _ => None,
+                        }));
                 } else if label.value == ".text" || label.value == ".data" || label.value == ".bss"
                 {
                     self.change_section(label);
                     self.current_scope = Scope::Local;
-                } else if label.value == ".word" {
-                    self.addr += (4 * (tokens.len() - 1)) as u32;
+                } else if label.value == ".handlerdata" {
+                    self.handler_data = true;
+                } else if label.value == ".fnend" {
+                    self.handler_data = false;
+                } else if label.value == ".word" && !self.handler_data {
+                    let words = tokens
+                        .iter()
+                        .filter(|token| token.is_number() || matches!(token, Token::LABELREF(_)))
+                        .count();
+                    self.addr += 4 * words as u32;
+                } else if label.value == ".thumb_func" {
+                    self.thumb_function = true;
                 }
             }
             if let Token::LABEL(label) = token {
@@ -112,11 +235,9 @@ impl Symbolizer {
 
         self.current_scope = Scope::Local;
 
-        if tokens
-            .iter()
-            .any(|token| matches!(token, Token::INSTRUCTION(_)))
-        {
-            self.addr += 4;
+        if tokens.iter().any(|token| token.is_instruction()) {
+            self.lexer.addr = self.addr;
+            self.addr += self.lexer.assemble_line(tokens).len() as u32;
         }
     }
 
@@ -129,13 +250,22 @@ impl Symbolizer {
             address,
             scope: self.current_scope.clone(),
             section: self.current_section.clone(),
+            thumb_function: self.thumb_function,
+            instruction_set: self.lexer.instruction_set,
+            line: self.tokenizer.line_number(),
         };
 
+        self.thumb_function = false;
+
         self.symbol_table.0.insert(symbol, row);
     }
 
+    // Each section is addressed from 0, going back to one continues where it was left
     fn change_section(&mut self, directive: &Directive) {
         let Directive { value } = directive;
+        self.started |= self.addr > 0;
+        self.section_addrs
+            .insert(self.current_section.clone(), self.addr);
 
         match value.as_str() {
             ".text" => self.current_section = Section::Text,
@@ -143,5 +273,11 @@ impl Symbolizer {
             ".bss" => self.current_section = Section::Bss,
             _ => panic!("Unknown section directive"),
         };
+        self.addr = self
+            .section_addrs
+            .get(&self.current_section)
+            .copied()
+            .unwrap_or(0);
+        self.lexer.section = self.current_section.clone();
     }
 }
@@ -0,0 +1,527 @@
+// Target architecture selected with -march/-mcpu or .arch/.cpu. It decides which instructions
+// are accepted and is recorded in the build attributes of the object file.
+
+use object::elf::{EF_ARM_ABI_FLOAT_HARD, EF_ARM_ABI_FLOAT_SOFT};
+
+use crate::{
+    elf::attributes::{
+        AttributeValue, Attributes, TAG_ABI_VFP_ARGS, TAG_ADVANCED_SIMD_ARCH, TAG_ARM_ISA_USE,
+        TAG_CPU_ARCH, TAG_CPU_ARCH_PROFILE, TAG_CPU_NAME, TAG_DIV_USE, TAG_FP_ARCH,
+        TAG_MPEXTENSION_USE, TAG_THUMB_ISA_USE,
+    },
+    token::{instruction_name::InstructionName, Token},
+};
+
+use super::{cpu_op::CpuOperation, operations::media_op::is_parallel_op, vfp::Fpu, InstructionSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    V4T,
+    V5TE,
+    V6,
+    V6M,
+    V7A,
+    V7M,
+    V7EM,
+    V8A,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    // ARM state, the M profiles only run Thumb code
+    Arm,
+    // 32-bit Thumb instructions other than bl and blx
+    Thumb2,
+    V5,
+    V5E,
+    V6,
+    V6K,
+    V7,
+    V8,
+    // Saturating arithmetic, halfword multiplies and SIMD within registers
+    Dsp,
+    // Exception modes and their instructions: srs, rfe, setend, smc, hvc
+    AProfile,
+    // Optional extensions, see .arch_extension
+    Idiv,
+    Mp,
+    Vfp,
+    Neon,
+}
+
+impl Arch {
+    pub fn from_name(name: &str) -> Option<Arch> {
+        match name {
+            "armv4t" => Some(Arch::V4T),
+            "armv5te" | "armv5tej" => Some(Arch::V5TE),
+            "armv6" | "armv6j" => Some(Arch::V6),
+            "armv6-m" | "armv6s-m" => Some(Arch::V6M),
+            "armv7-a" | "armv7a" => Some(Arch::V7A),
+            "armv7-m" | "armv7m" => Some(Arch::V7M),
+            "armv7e-m" | "armv7em" => Some(Arch::V7EM),
+            "armv8-a" | "armv8a" => Some(Arch::V8A),
+            _ => None,
+        }
+    }
+
+    // Name used in diagnostics and as Tag_CPU_name when no CPU is selected
+    pub fn name(&self) -> &'static str {
+        match self {
+            Arch::V4T => "4T",
+            Arch::V5TE => "5TE",
+            Arch::V6 => "6",
+            Arch::V6M => "6-M",
+            Arch::V7A => "7-A",
+            Arch::V7M => "7-M",
+            Arch::V7EM => "7E-M",
+            Arch::V8A => "8-A",
+        }
+    }
+
+    // Tag_CPU_arch
+    pub fn cpu_arch(&self) -> u32 {
+        match self {
+            Arch::V4T => 2,
+            Arch::V5TE => 4,
+            Arch::V6 => 6,
+            Arch::V6M => 11,
+            Arch::V7A | Arch::V7M => 10,
+            Arch::V7EM => 13,
+            Arch::V8A => 14,
+        }
+    }
+
+    // Tag_CPU_arch_profile, older architectures have none
+    pub fn profile(&self) -> Option<u8> {
+        match self {
+            Arch::V7A | Arch::V8A => Some(b'A'),
+            Arch::V6M | Arch::V7M | Arch::V7EM => Some(b'M'),
+            _ => None,
+        }
+    }
+
+    fn has(&self, feature: Feature) -> bool {
+        use Feature::*;
+        match self {
+            Arch::V4T => matches!(feature, Arm),
+            Arch::V5TE => matches!(feature, Arm | V5 | V5E | Dsp),
+            Arch::V6 => matches!(feature, Arm | V5 | V5E | V6 | Dsp | AProfile),
+            // Thumb-1 plus the system instructions in V6M_SYSTEM, and only bl, dmb, dsb, isb,
+            // mrs and msr have a 32-bit encoding, see check_thumb_width
+            Arch::V6M => matches!(feature, V5 | V6),
+            Arch::V7M => matches!(feature, Thumb2 | V5 | V5E | V6 | V6K | V7),
+            Arch::V7EM => matches!(feature, Thumb2 | V5 | V5E | V6 | V6K | V7 | Dsp),
+            Arch::V7A => matches!(
+                feature,
+                Arm | Thumb2 | V5 | V5E | V6 | V6K | V7 | Dsp | AProfile
+            ),
+            Arch::V8A => matches!(
+                feature,
+                Arm | Thumb2 | V5 | V5E | V6 | V6K | V7 | V8 | Dsp | AProfile
+            ),
+        }
+    }
+}
+
+// How floating point arguments are passed, -mfloat-abi
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatAbi {
+    Soft,
+    SoftFp,
+    Hard,
+}
+
+impl FloatAbi {
+    pub fn from_name(name: &str) -> Option<FloatAbi> {
+        match name {
+            "soft" => Some(FloatAbi::Soft),
+            "softfp" => Some(FloatAbi::SoftFp),
+            "hard" => Some(FloatAbi::Hard),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Target {
+    // Until an architecture or CPU is selected every instruction is accepted
+    pub arch: Option<Arch>,
+    pub cpu: Option<String>,
+    pub idiv: bool,
+    pub mp: bool,
+    pub vfp: bool,
+    pub neon: bool,
+    // Selected with .fpu or -mfpu, once an architecture is selected VFP instructions need one
+    pub fpu: Option<Fpu>,
+    pub float_abi: Option<FloatAbi>,
+    // -meabi, version 5 unless told otherwise
+    pub eabi_version: Option<u32>,
+}
+
+impl Target {
+    // -march=armv7-a+idiv+mp
+    pub fn from_arch(name: &str) -> Option<Target> {
+        let mut parts = name.split('+');
+        let arch = Arch::from_name(&parts.next()?.to_lowercase())?;
+
+        let mut target = Target {
+            arch: Some(arch),
+            cpu: None,
+            idiv: matches!(arch, Arch::V7M | Arch::V7EM | Arch::V8A),
+            mp: arch == Arch::V8A,
+            ..Target::default()
+        };
+
+        for extension in parts {
+            target.set_extension(extension);
+        }
+
+        Some(target)
+    }
+
+    // -mcpu=cortex-a9+nomp
+    pub fn from_cpu(name: &str) -> Option<Target> {
+        let mut parts = name.split('+');
+        let cpu = parts.next()?.to_lowercase();
+
+        let (arch, extensions): (&str, &[&str]) = match cpu.as_str() {
+            "arm7tdmi" => ("armv4t", &[]),
+            "arm926ej-s" | "arm946e-s" => ("armv5te", &[]),
+            "arm1136j-s" | "arm1176jz-s" | "arm1136jf-s" | "arm1176jzf-s" => ("armv6", &[]),
+            "cortex-m0" | "cortex-m0plus" | "cortex-m1" => ("armv6-m", &[]),
+            "cortex-m3" => ("armv7-m", &[]),
+            "cortex-m4" | "cortex-m7" => ("armv7e-m", &[]),
+            // NEON comes with the FPU, see set_fpu
+            "cortex-a8" => ("armv7-a", &[]),
+            "cortex-a5" | "cortex-a9" => ("armv7-a", &["mp"]),
+            "cortex-a7" | "cortex-a15" | "cortex-a17" => ("armv7-a", &["idiv", "mp"]),
+            "cortex-a53" | "cortex-a57" | "cortex-a72" => ("armv8-a", &[]),
+            _ => return None,
+        };
+
+        let mut target = Target::from_arch(arch)?;
+        target.cpu = Some(cpu);
+
+        for extension in extensions.iter().copied().chain(parts) {
+            target.set_extension(extension);
+        }
+
+        Some(target)
+    }
+
+    // idiv, mp, vfp and neon, or their no- forms to turn them off
+    pub fn set_extension(&mut self, name: &str) {
+        let name = name.to_lowercase();
+        let (name, enabled) = match name.strip_prefix("no") {
+            Some(name) => (name, false),
+            None => (name.as_str(), true),
+        };
+
+        match name {
+            "idiv" => self.idiv = enabled,
+            "mp" => self.mp = enabled,
+            "vfp" | "fp" => self.vfp = enabled,
+            "neon" | "simd" => self.neon = enabled,
+            _ => panic!("Unknown architecture extension {}", name),
+        }
+    }
+
+    pub fn is_selected(&self) -> bool {
+        self.arch.is_some()
+    }
+
+    // Switches to the architecture of another target, as .arch and .cpu do. The FPU and
+    // ABI options stay.
+    pub fn select(&self, target: Target) -> Target {
+        Target {
+            vfp: self.fpu.map_or(target.vfp, |fpu| fpu.is_present()),
+            neon: self.fpu.map_or(target.neon, |fpu| fpu.has_neon()),
+            fpu: self.fpu,
+            float_abi: self.float_abi,
+            eabi_version: self.eabi_version,
+            ..target
+        }
+    }
+
+    // A floating point unit also makes the VFP instructions available, and NEON if it has it
+    pub fn set_fpu(&mut self, fpu: Fpu) {
+        self.fpu = Some(fpu);
+        self.vfp = fpu.is_present();
+        self.neon = fpu.has_neon();
+    }
+
+    pub fn e_flags(&self) -> u32 {
+        let float_abi = match self.float_abi {
+            Some(FloatAbi::Hard) => EF_ARM_ABI_FLOAT_HARD,
+            Some(FloatAbi::Soft | FloatAbi::SoftFp) => EF_ARM_ABI_FLOAT_SOFT,
+            None => 0,
+        };
+
+        self.eabi_version.unwrap_or(5) << 24 | float_abi
+    }
+
+    pub fn name(&self) -> String {
+        match (&self.cpu, self.arch) {
+            (Some(cpu), _) => cpu.clone(),
+            (None, Some(arch)) => format!("armv{}", arch.name().to_lowercase()),
+            (None, None) => "any architecture".to_string(),
+        }
+    }
+
+    pub fn has(&self, feature: Feature) -> bool {
+        let arch = match self.arch {
+            Some(arch) => arch,
+            None => return true,
+        };
+
+        match feature {
+            Feature::Idiv => self.idiv,
+            Feature::Mp => self.mp,
+            Feature::Vfp => self.vfp,
+            Feature::Neon => self.neon,
+            feature => arch.has(feature),
+        }
+    }
+
+    // Build attributes describing the target and ABI, .eabi_attribute can override them
+    pub fn attributes(&self) -> Attributes {
+        let mut attributes = Attributes::new();
+
+        if let Some(fp_arch) = self.fpu.and_then(|fpu| fpu.fp_arch()) {
+            attributes.set(TAG_FP_ARCH, AttributeValue::Number(fp_arch));
+        }
+        if self.float_abi == Some(FloatAbi::Hard) {
+            attributes.set(TAG_ABI_VFP_ARGS, AttributeValue::Number(1));
+        }
+
+        // The default target takes ARM and Thumb-2 code, but names no architecture
+        attributes.set(
+            TAG_ARM_ISA_USE,
+            AttributeValue::Number(self.has(Feature::Arm) as u32),
+        );
+        let thumb = if self.has(Feature::Thumb2) { 2 } else { 1 };
+        attributes.set(TAG_THUMB_ISA_USE, AttributeValue::Number(thumb));
+
+        // +simd without a NEON FPU gets the version of the architecture
+        let simd = self
+            .fpu
+            .and_then(|fpu| fpu.simd_arch())
+            .unwrap_or(if self.arch == Some(Arch::V8A) { 3 } else { 1 });
+        if self.neon {
+            attributes.set(TAG_ADVANCED_SIMD_ARCH, AttributeValue::Number(simd));
+        }
+
+        let arch = match self.arch {
+            Some(arch) => arch,
+            None => return attributes,
+        };
+
+        let name = match &self.cpu {
+            Some(cpu) => cpu.to_uppercase(),
+            None => arch.name().to_string(),
+        };
+        attributes.set(TAG_CPU_NAME, AttributeValue::Text(name));
+        attributes.set(TAG_CPU_ARCH, AttributeValue::Number(arch.cpu_arch()));
+        if let Some(profile) = arch.profile() {
+            attributes.set(TAG_CPU_ARCH_PROFILE, AttributeValue::Number(profile as u32));
+        }
+        // the v7-A extensions, later architectures always have them
+        if arch == Arch::V7A && self.mp {
+            attributes.set(TAG_MPEXTENSION_USE, AttributeValue::Number(1));
+        }
+        if arch == Arch::V7A && self.idiv {
+            attributes.set(TAG_DIV_USE, AttributeValue::Number(2));
+        }
+
+        attributes
+    }
+
+    // Panics if the instruction doesn't exist on the target
+    pub fn check(&self, op: &CpuOperation, instruction_set: InstructionSet) {
+        let name = op.instruction.value;
+
+        if instruction_set == InstructionSet::Arm && !self.has(Feature::Arm) {
+            panic!("{} has no ARM state, use .thumb", self.name());
+        }
+
+        if self.arch == Some(Arch::V6M) && V6M_SYSTEM.contains(&name) {
+            return;
+        }
+
+        if let Some(feature) = required_features(&name)
+            .iter()
+            .find(|feature| !self.has(**feature))
+        {
+            panic!(
+                "{:?} is not available on {} (needs {:?})",
+                name,
+                self.name(),
+                feature
+            );
+        }
+    }
+
+    // Without Thumb-2, Thumb code only has 16-bit instructions apart from a few
+    pub fn check_thumb_width(&self, op: &CpuOperation) {
+        use InstructionName::*;
+
+        let name = op.instruction.value;
+        if !self.has(Feature::Thumb2) && !matches!(name, BL | BLX | DMB | DSB | ISB | MRS | MSR) {
+            panic!("{} has no 32-bit Thumb encoding of {:?}", self.name(), name);
+        }
+    }
+}
+
+// .arch armv7-a, .cpu cortex-m4 and .arch_extension idiv
+pub fn target_directive(tokens: &[Token], target: &Target) -> Option<Target> {
+    let directive = tokens.iter().find_map(|token| token.extract_directive())?;
+
+    let name = tokens.iter().find_map(|token| match token {
+        Token::OPTION(name) => Some(name.as_str()),
+        _ => None,
+    });
+
+    match directive.value.as_str() {
+        ".arch" => {
+            let name = name.unwrap_or_default();
+            let selected =
+                Target::from_arch(name).unwrap_or_else(|| panic!("Unknown .arch {}", name));
+            Some(target.select(selected))
+        }
+        ".cpu" => {
+            let name = name.unwrap_or_default();
+            let selected =
+                Target::from_cpu(name).unwrap_or_else(|| panic!("Unknown .cpu {}", name));
+            Some(target.select(selected))
+        }
+        ".arch_extension" => {
+            let mut target = target.clone();
+            target.set_extension(name.unwrap_or_default());
+            Some(target)
+        }
+        _ => None,
+    }
+}
+
+// ARMv6-M takes these from v6K and v7, but none of the other instructions they added
+const V6M_SYSTEM: [InstructionName; 7] = [
+    InstructionName::YIELD,
+    InstructionName::WFE,
+    InstructionName::WFI,
+    InstructionName::SEV,
+    InstructionName::DMB,
+    InstructionName::DSB,
+    InstructionName::ISB,
+];
+
+fn required_features(name: &InstructionName) -> &'static [Feature] {
+    use Feature::*;
+    use InstructionName::*;
+
+    match name {
+        istr if istr.is_vfp() => &[Vfp],
+        istr if is_parallel_op(istr) => &[V6, Dsp],
+        BLX | CLZ | BKPT | CDP2 | LDC2 | LDC2L | STC2 | STC2L | MCR2 | MRC2 => &[V5],
+        LDRD | STRD | PLD | MCRR | MRRC => &[V5E],
+        QADD | QSUB | QDADD | QDSUB | SMLABB | SMLABT | SMLATB | SMLATT | SMLAWB | SMLAWT
+        | SMULBB | SMULBT | SMULTB | SMULTT | SMULWB | SMULWT | SMLALBB | SMLALBT | SMLALTB
+        | SMLALTT => &[V5E, Dsp],
+        SSAT | USAT | REV | REV16 | REVSH | SXTB | SXTH | UXTB | UXTH | LDREX | STREX | CPS
+        | CPSIE | CPSID | MCRR2 | MRRC2 => &[V6],
+        SRS | SRSDA | SRSDB | SRSIA | SRSIB | RFE | RFEDA | RFEDB | RFEIA | RFEIB | SETEND => {
+            &[V6, AProfile]
+        }
+        SSAT16 | USAT16 | PKHBT | PKHTB | SEL | USAD8 | USADA8 | SXTAB | SXTAH | SXTAB16
+        | SXTB16 | UXTAB | UXTAH | UXTAB16 | UXTB16 | SMLAD | SMLADX | SMLSD | SMLSDX | SMLALD
+        | SMLALDX | SMLSLD | SMLSLDX | SMMLA | SMMLAR | SMMLS | SMMLSR | SMMUL | SMMULR | SMUAD
+        | SMUADX | SMUSD | SMUSDX | UMAAL => &[V6, Dsp],
+        LDREXB | LDREXH | LDREXD | STREXB | STREXH | STREXD | CLREX | YIELD | WFE | WFI | SEV => {
+            &[V6K]
+        }
+        // introduced with Thumb-2 in ARMv6T2
+        MOVT | BFC | BFI | SBFX | UBFX | MLS | RBIT | ORN | LDRHT | LDRSBT | LDRSHT | STRHT
+        | TBB | TBH | CBZ | CBNZ | IT => &[Thumb2],
+        DMB | DSB | ISB | PLI | DBG => &[V7],
+        SMC => &[AProfile],
+        HVC | ERET => &[V7, AProfile],
+        SDIV | UDIV => &[Idiv],
+        PLDW => &[V7, Mp],
+        LDA | LDAB | LDAH | LDAEX | LDAEXB | LDAEXH | LDAEXD | STL | STLB | STLH | STLEX
+        | STLEXB | STLEXH | STLEXD | SEVL | HLT | DCPS1 | DCPS2 | DCPS3 | CRC32 | CRC32C | SB
+        | CSDB | ESB | SSBB | PSSBB | TSB | CLRBHB | SETPAN => &[V8],
+        _ => &[],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{elf::byte_order::ByteOrder, lexer::assemble_for_arch};
+
+    #[test]
+    fn test_target_extensions() {
+        let target = Target::from_cpu("cortex-a9+idiv").unwrap();
+        assert_eq!(target.arch, Some(Arch::V7A));
+        assert!(target.has(Feature::Mp) && target.has(Feature::Idiv));
+
+        let target = Target::from_arch("armv7-m+noidiv").unwrap();
+        assert!(!target.has(Feature::Idiv) && !target.has(Feature::Arm));
+        assert!(Target::default().has(Feature::V8));
+        assert!(Target::from_arch("armv7-r").is_none());
+
+        // VFP instructions need -mfpu or .fpu once an architecture is selected
+        let mut target = Target::from_cpu("cortex-a9").unwrap();
+        assert!(!target.has(Feature::Vfp));
+        target.set_fpu(Fpu::from_name("vfpv3-d16").unwrap());
+        assert!(target.has(Feature::Vfp));
+        assert!(Target::default().has(Feature::Vfp));
+    }
+
+    #[test]
+    fn test_neon_comes_from_the_fpu() {
+        let attributes = |target: &Target, tags: &[(u32, u32)]| {
+            let mut expected = Attributes::new();
+            for (tag, value) in tags {
+                expected.set(*tag, AttributeValue::Number(*value));
+            }
+            let bytes = target.attributes().to_bytes(ByteOrder::Little);
+            assert_eq!(bytes, expected.to_bytes(ByteOrder::Little));
+        };
+
+        // Neither the default target nor a plain VFP unit have NEON
+        attributes(
+            &Target::default(),
+            &[(TAG_ARM_ISA_USE, 1), (TAG_THUMB_ISA_USE, 2)],
+        );
+        let mut target = Target::default();
+        target.set_fpu(Fpu::from_name("neon-vfpv4").unwrap());
+        attributes(
+            &target,
+            &[
+                (TAG_ARM_ISA_USE, 1),
+                (TAG_THUMB_ISA_USE, 2),
+                (TAG_FP_ARCH, 5),
+                (TAG_ADVANCED_SIMD_ARCH, 2),
+            ],
+        );
+
+        let mut target = Target::from_cpu("cortex-a9").unwrap();
+        target.set_fpu(Fpu::from_name("vfpv3").unwrap());
+        assert!(!target.has(Feature::Neon));
+        target.set_fpu(Fpu::from_name("neon").unwrap());
+        assert!(target.has(Feature::Neon));
+    }
+
+    #[test]
+    fn test_v6m_system_instructions() {
+        let bytes = assemble_for_arch(".thumb\nwfi\ndmb sy\ncpsid i\n", "armv6-m");
+        assert_eq!(bytes, [0x30, 0xbf, 0xbf, 0xf3, 0x5f, 0x8f, 0x72, 0xb6]);
+    }
+
+    #[test]
+    #[should_panic(expected = "CLREX is not available on armv6-m (needs V6K)")]
+    fn test_v6m_rejects_later_instructions() {
+        assert!(!Target::from_arch("armv6-m").unwrap().has(Feature::V7));
+        assemble_for_arch(".thumb\nclrex\n", "armv6-m");
+    }
+}
@@ -0,0 +1,939 @@
+// Thumb/Thumb-2 encodings. The 16-bit form is used whenever the operands allow it, unless the
+// instruction carries a .w qualifier; .n makes the lack of a 16-bit form an error.
+
+use crate::{
+    elf::byte_order::ByteOrder,
+    token::{
+        instruction::{ConditionCode, Width},
+        instruction_name::InstructionName,
+        register::Register,
+    },
+};
+
+use super::{
+    cpu_op::CpuOperation,
+    expression::{
+        barrel_shifter::{
+            BarrealShifterShiftAmount, BarrelShifterExpression, BarrelShifterOperation,
+        },
+        ls_imm_index::{IndexMode, LoadStoreImmediateExpression},
+        Expression,
+    },
+    is_compare_op,
+    media::{generate_media_thumb, narrow_extend},
+    operations::{coprocessor_op::is_coprocessor_op, media_op::is_media_op},
+    vfp::generate_vfp,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbCode {
+    Narrow(u16),
+    // First and second halfword
+    Wide(u16, u16),
+}
+
+impl ThumbCode {
+    fn wide(code: u32) -> ThumbCode {
+        ThumbCode::Wide((code >> 16) as u16, code as u16)
+    }
+
+    // The first halfword is stored at the lower address
+    pub fn to_u8_buff(&self, byte_order: ByteOrder) -> Vec<u8> {
+        match self {
+            ThumbCode::Narrow(halfword) => byte_order.code_u16(*halfword).to_vec(),
+            ThumbCode::Wide(first, second) => {
+                [byte_order.code_u16(*first), byte_order.code_u16(*second)].concat()
+            }
+        }
+    }
+}
+
+// `in_it_block` is set for instructions made conditional by an IT instruction: they take their
+// condition from the block, and the 16-bit data processing forms stop setting the flags.
+pub fn encode_thumb(op: &CpuOperation, in_it_block: bool) -> ThumbCode {
+    let width = op.instruction.width;
+
+    let narrow = match width {
+        Some(Width::Wide) => None,
+        _ => encode_narrow(op, in_it_block),
+    };
+
+    if let Some(code) = narrow {
+        return ThumbCode::Narrow(code);
+    }
+
+    if width == Some(Width::Narrow) {
+        panic!(
+            "No 16-bit encoding of {:?} for these operands",
+            op.instruction.value
+        );
+    }
+
+    match encode_wide(op, in_it_block) {
+        Some(code) => ThumbCode::wide(code),
+        None => panic!(
+            "{:?} can't be encoded in Thumb state with these operands",
+            op.instruction.value
+        ),
+    }
+}
+
+fn encode_narrow(op: &CpuOperation, in_it_block: bool) -> Option<u16> {
+    use InstructionName::*;
+
+    let code = match op.instruction.value {
+        AND | EOR | SUB | RSB | ADD | ADC | SBC | ORR | BIC | MOV | MVN | CMP | CMN | TST => {
+            narrow_data_processing(op, in_it_block)
+        }
+        LDR | STR => narrow_load_store(op),
+        STMDB | STMIA | LDMIA => narrow_load_store_multiple(op),
+        B | BX | BLX | CBZ | CBNZ => narrow_branch(op, in_it_block),
+        SXTB | SXTH | UXTB | UXTH => narrow_extend(op),
+        SVC | BKPT | UDF => match &op.expression {
+            Expression::Immediate(imm) if imm.to_machine_code() <= 0xff => {
+                let base = match op.instruction.value {
+                    SVC => 0xdf00,
+                    BKPT => 0xbe00,
+                    _ => 0xde00,
+                };
+                Some(base | imm.to_machine_code())
+            }
+            _ => None,
+        },
+        NOP | YIELD | WFE | WFI | SEV => Some(0xbf00 | hint(&op.instruction.value) << 4),
+        CPSIE | CPSID => match &op.expression {
+            Expression::ChangeProcessorState(expr) if expr.mode.is_none() => {
+                let imod = (op.instruction.value == CPSID) as u32;
+                Some(0xb660 | imod << 4 | expr.flags as u32)
+            }
+            _ => None,
+        },
+        IT => match &op.expression {
+            Expression::IfThen(expr) => Some(0xbf00 | expr.to_machine_code()),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    code.map(|code| code as u16)
+}
+
+fn narrow_data_processing(op: &CpuOperation, in_it_block: bool) -> Option<u32> {
+    use InstructionName::*;
+
+    let istr = &op.instruction;
+    // Outside an IT block the 16-bit forms always set the flags, inside one they never do
+    let flags_ok = is_compare_op(&istr.value) || istr.set_flags != in_it_block;
+
+    match &op.expression {
+        Expression::ThreeRegs(expr) => {
+            if expr.barrel_shifter.is_some() {
+                return None;
+            }
+
+            let (rd, rn, rm) = (reg(&expr.reg_d), reg(&expr.reg_m), reg(&expr.reg_n));
+            let low = all_low(&[rd, rn, rm]);
+
+            match istr.value {
+                ADD | SUB if flags_ok && low => {
+                    let base = if istr.value == ADD { 0x1800 } else { 0x1a00 };
+                    Some(base | rm << 6 | rn << 3 | rd)
+                }
+                // add rdn, rm works on any register, but never sets the flags
+                ADD if !istr.set_flags && (rd == rn || rd == rm) && !(rn == 15 && rm == 15) => {
+                    let other = if rd == rn { rm } else { rn };
+                    Some(0x4400 | (rd & 8) << 4 | other << 3 | (rd & 7))
+                }
+                AND | EOR | ADC | SBC | ORR | BIC if flags_ok && low => {
+                    let commutative = matches!(istr.value, AND | EOR | ADC | ORR);
+                    let other = if rd == rn {
+                        rm
+                    } else if rd == rm && commutative {
+                        rn
+                    } else {
+                        return None;
+                    };
+                    Some(0x4000 | narrow_opcode(&istr.value) << 6 | other << 3 | rd)
+                }
+                _ => None,
+            }
+        }
+        Expression::TwoRegsLiteral(expr) => {
+            if expr.rotation.is_some() {
+                return None;
+            }
+
+            let (rd, rn, imm) = (reg(&expr.reg_d), reg(&expr.reg_m), expr.literal.to_num());
+            let sub = (istr.value == SUB) as u32;
+
+            match istr.value {
+                ADD | SUB if flags_ok && all_low(&[rd, rn]) && imm <= 7 => {
+                    Some(0x1c00 | sub << 9 | imm << 6 | rn << 3 | rd)
+                }
+                ADD | SUB if flags_ok && rd == rn && rd < 8 && imm <= 0xff => {
+                    Some(0x3000 | sub << 11 | rd << 8 | imm)
+                }
+                ADD | SUB
+                    if !istr.set_flags && rd == 13 && rn == 13 && imm % 4 == 0 && imm <= 508 =>
+                {
+                    Some(0xb000 | sub << 7 | imm >> 2)
+                }
+                // add rd, sp, #imm and adr rd, label (add rd, pc, #imm)
+                ADD if !istr.set_flags
+                    && rd < 8
+                    && (rn == 13 || rn == 15)
+                    && imm % 4 == 0
+                    && imm <= 1020 =>
+                {
+                    let base = if rn == 13 { 0xa800 } else { 0xa000 };
+                    Some(base | rd << 8 | imm >> 2)
+                }
+                // rsbs rd, rn, #0, also known as negs
+                RSB if flags_ok && all_low(&[rd, rn]) && imm == 0 => Some(0x4240 | rn << 3 | rd),
+                _ => None,
+            }
+        }
+        Expression::TwoRegs(expr) => {
+            let (rd, rm) = (reg(&expr.reg_d), reg(&expr.reg_m));
+            let low = all_low(&[rd, rm]);
+
+            match (istr.value, expr.barrel_shifter) {
+                (MOV, None) if !istr.set_flags => Some(0x4600 | (rd & 8) << 4 | rm << 3 | (rd & 7)),
+                // movs rd, rm is lsls rd, rm, #0
+                (MOV, None) if !in_it_block && low => Some(rm << 3 | rd),
+                (MOV, Some(bs)) if flags_ok && low => narrow_shift(&bs, rd, rm),
+                (MVN, None) if flags_ok && low => Some(0x43c0 | rm << 3 | rd),
+                (CMP, None) if low => Some(0x4280 | rm << 3 | rd),
+                (CMP, None) if rd != 15 && rm != 15 => {
+                    Some(0x4500 | (rd & 8) << 4 | rm << 3 | (rd & 7))
+                }
+                (CMN, None) if low => Some(0x42c0 | rm << 3 | rd),
+                (TST, None) if low => Some(0x4200 | rm << 3 | rd),
+                _ => None,
+            }
+        }
+        Expression::RegLiteral(expr) => {
+            if expr.rotation.is_some() {
+                return None;
+            }
+
+            let (rd, imm) = (reg(&expr.register), expr.literal.to_num());
+
+            match istr.value {
+                MOV if flags_ok && rd < 8 && imm <= 0xff => Some(0x2000 | rd << 8 | imm),
+                CMP if rd < 8 && imm <= 0xff => Some(0x2800 | rd << 8 | imm),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// lsls/lsrs/asrs rd, rm, #imm and the register-controlled shifts, which need rd == rm
+fn narrow_shift(bs: &BarrelShifterExpression, rd: u32, rm: u32) -> Option<u32> {
+    match bs.shift_amount {
+        BarrealShifterShiftAmount::Number(amount) => {
+            let opcode = match bs.operation {
+                BarrelShifterOperation::LSL => 0,
+                BarrelShifterOperation::LSR => 1,
+                BarrelShifterOperation::ASR => 2,
+                _ => return None,
+            };
+            Some(opcode << 11 | (amount as u32 & 0x1f) << 6 | rm << 3 | rd)
+        }
+        BarrealShifterShiftAmount::Register(rs) if rd == rm && reg(&rs) < 8 => {
+            let opcode = match bs.operation {
+                BarrelShifterOperation::LSL => 0b0010,
+                BarrelShifterOperation::LSR => 0b0011,
+                BarrelShifterOperation::ASR => 0b0100,
+                BarrelShifterOperation::ROR => 0b0111,
+                BarrelShifterOperation::RRX => return None,
+            };
+            Some(0x4000 | opcode << 6 | reg(&rs) << 3 | rd)
+        }
+        _ => None,
+    }
+}
+
+// Opcodes of the 16-bit two register data processing group (0b010000)
+fn narrow_opcode(name: &InstructionName) -> u32 {
+    use InstructionName::*;
+    match name {
+        AND => 0b0000,
+        EOR => 0b0001,
+        ADC => 0b0101,
+        SBC => 0b0110,
+        ORR => 0b1100,
+        BIC => 0b1110,
+        _ => panic!("Invalid operation"),
+    }
+}
+
+fn narrow_load_store(op: &CpuOperation) -> Option<u32> {
+    let load = (op.instruction.value == InstructionName::LDR) as u32;
+
+    match &op.expression {
+        Expression::LoadStoreImmediate(expr) => {
+            let (rt, rn) = (reg(&expr.destination), reg(&expr.base));
+            let offset = plain_offset(expr)?;
+
+            if offset % 4 != 0 {
+                return None;
+            }
+
+            if all_low(&[rt, rn]) && offset <= 124 {
+                Some(0x6000 | load << 11 | (offset >> 2) << 6 | rn << 3 | rt)
+            } else if rn == 13 && rt < 8 && offset <= 1020 {
+                Some(0x9000 | load << 11 | rt << 8 | offset >> 2)
+            } else {
+                None
+            }
+        }
+        Expression::LoadStoreRegister(expr) => {
+            let (rt, rn, rm) = (reg(&expr.destination), reg(&expr.base), reg(&expr.offset));
+            let plain = matches!(expr.index_mode, IndexMode::Pre(index) if !index.write_back);
+
+            if plain && !expr.negative && expr.barrel_shifter.is_none() && all_low(&[rt, rn, rm]) {
+                Some(0x5000 | load << 11 | rm << 6 | rn << 3 | rt)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+fn narrow_load_store_multiple(op: &CpuOperation) -> Option<u32> {
+    use InstructionName::*;
+
+    let expr = match &op.expression {
+        Expression::LoadStoreMultiple(expr) => expr,
+        _ => return None,
+    };
+
+    if expr.user_mode {
+        return None;
+    }
+
+    let base = reg(&expr.base);
+    let list = register_list(&expr.registers);
+
+    match op.instruction.value {
+        // push and pop can also transfer lr and pc respectively
+        STMDB if base == 13 && expr.write_back && list & !0x40ff == 0 => {
+            Some(0xb400 | (list >> 14 & 1) << 8 | (list & 0xff))
+        }
+        LDMIA if base == 13 && expr.write_back && list & !0x80ff == 0 => {
+            Some(0xbc00 | (list >> 15 & 1) << 8 | (list & 0xff))
+        }
+        // the 16-bit ldm writes back exactly when the base isn't loaded
+        LDMIA if base < 8 && list & !0xff == 0 && expr.write_back != (list >> base & 1 == 1) => {
+            Some(0xc800 | base << 8 | list)
+        }
+        STMIA if base < 8 && list & !0xff == 0 && expr.write_back => {
+            Some(0xc000 | base << 8 | list)
+        }
+        _ => None,
+    }
+}
+
+fn narrow_branch(op: &CpuOperation, in_it_block: bool) -> Option<u32> {
+    use InstructionName::*;
+
+    match (&op.instruction.value, &op.expression) {
+        (B, Expression::Immediate(imm)) => {
+            let offset = imm.to_machine_code() as i32;
+            if offset % 2 != 0 {
+                return None;
+            }
+
+            if in_it_block || op.instruction.condition == ConditionCode::Al {
+                (-2048..=2046)
+                    .contains(&offset)
+                    .then_some(0xe000 | (offset as u32 >> 1 & 0x7ff))
+            } else {
+                let condition = op.instruction.condition.to_machine_code() >> 28;
+                (-256..=254)
+                    .contains(&offset)
+                    .then_some(0xd000 | condition << 8 | (offset as u32 >> 1 & 0xff))
+            }
+        }
+        (BX, Expression::Register(rm)) => Some(0x4700 | rm.to_machine_code() << 3),
+        (BLX, Expression::Register(rm)) => Some(0x4780 | rm.to_machine_code() << 3),
+        (CBZ | CBNZ, Expression::RegLiteral(expr)) => {
+            let (rn, offset) = (reg(&expr.register), expr.literal.to_num() as i32);
+            if rn >= 8 || !(0..=126).contains(&offset) || offset % 2 != 0 {
+                panic!(
+                    "{:?} needs a low register and a target 0-126 bytes ahead",
+                    op.instruction.value
+                );
+            }
+
+            let base = if op.instruction.value == CBZ {
+                0xb100
+            } else {
+                0xb900
+            };
+            let offset = offset as u32;
+            Some(base | (offset >> 6) << 9 | (offset >> 1 & 0x1f) << 3 | rn)
+        }
+        _ => None,
+    }
+}
+
+fn encode_wide(op: &CpuOperation, in_it_block: bool) -> Option<u32> {
+    use InstructionName::*;
+
+    match op.instruction.value {
+        AND | EOR | SUB | RSB | ADD | ADC | SBC | ORR | BIC | MOV | MVN | CMP | CMN | TST | TEQ => {
+            wide_data_processing(op)
+        }
+        LDR | STR => wide_load_store(op),
+        STMDB | STMIA | LDMDB | LDMIA => wide_load_store_multiple(op),
+        B | BL | BLX => wide_branch(op, in_it_block),
+        BXJ => match &op.expression {
+            Expression::Register(rm) if ![13, 15].contains(&rm.to_machine_code()) => {
+                Some(0xf3c08f00 | rm.to_machine_code() << 16)
+            }
+            _ => panic!("BXJ can't branch to sp or pc in Thumb state"),
+        },
+        MRS | MSR | CPS | CPSIE | CPSID | NOP | YIELD | WFE | WFI | SEV | DMB | DSB | ISB | UDF => {
+            wide_system(op)
+        }
+        istr if super::operations::load_store_op::is_exclusive_op(&istr) => wide_exclusive(op),
+        istr if is_media_op(&istr) => Some(generate_media_thumb(op)),
+        // the coprocessor encodings are the ARM ones, with 0b1111 for the "2" variants
+        istr if is_coprocessor_op(&istr) => {
+            let prefix = if op.instruction.value.is_conditional() {
+                0xe0000000
+            } else {
+                0xf0000000
+            };
+            Some(prefix | op.generate_coprocessor())
+        }
+        // the VFP encodings only differ from ARM in the condition, which comes from the IT block
+        istr if istr.is_vfp() => Some(0xe0000000 | generate_vfp(op)),
+        _ => None,
+    }
+}
+
+fn wide_data_processing(op: &CpuOperation) -> Option<u32> {
+    use InstructionName::*;
+
+    let name = op.instruction.value;
+    let set_flags = op.instruction.set_flags as u32;
+
+    // mov/mvn have no rn and the compares no rd, both are encoded as 0b1111
+    let (opcode, has_rd, has_rn) = match name {
+        AND => (0b0000, true, true),
+        BIC => (0b0001, true, true),
+        ORR => (0b0010, true, true),
+        EOR => (0b0100, true, true),
+        ADD => (0b1000, true, true),
+        ADC => (0b1010, true, true),
+        SBC => (0b1011, true, true),
+        SUB => (0b1101, true, true),
+        RSB => (0b1110, true, true),
+        MOV => (0b0010, true, false),
+        MVN => (0b0011, true, false),
+        TST => (0b0000, false, true),
+        TEQ => (0b0100, false, true),
+        CMN => (0b1000, false, true),
+        CMP => (0b1101, false, true),
+        _ => return None,
+    };
+
+    let pick = |first: &Register, second: &Register| match (has_rd, has_rn) {
+        (true, true) => (reg(first), reg(second)),
+        (true, false) => (reg(first), 15),
+        _ => (15, reg(first)),
+    };
+
+    match &op.expression {
+        Expression::ThreeRegs(expr) => {
+            let (rd, rn) = pick(&expr.reg_d, &expr.reg_m);
+            wide_shifted_register(opcode, set_flags, rd, rn, &expr.reg_n, &expr.barrel_shifter)
+        }
+        Expression::TwoRegs(expr) => {
+            let (rd, rn) = pick(&expr.reg_d, &expr.reg_d);
+            if let Some(bs) = expr.barrel_shifter.filter(|bs| bs.is_register_shift()) {
+                return wide_register_shift(&name, set_flags, rd, &expr.reg_m, &bs);
+            }
+            wide_shifted_register(opcode, set_flags, rd, rn, &expr.reg_m, &expr.barrel_shifter)
+        }
+        Expression::TwoRegsLiteral(expr) => {
+            if expr.rotation.is_some() {
+                panic!("The #imm, #rot immediate form is only available in ARM state");
+            }
+            let (rd, rn) = pick(&expr.reg_d, &expr.reg_m);
+            wide_immediate(&name, opcode, set_flags, rd, rn, expr.literal.to_num())
+        }
+        Expression::RegLiteral(expr) => {
+            if expr.rotation.is_some() {
+                panic!("The #imm, #rot immediate form is only available in ARM state");
+            }
+            let (rd, rn) = pick(&expr.register, &expr.register);
+            wide_immediate(&name, opcode, set_flags, rd, rn, expr.literal.to_num())
+        }
+        _ => None,
+    }
+}
+
+fn wide_immediate(
+    name: &InstructionName,
+    opcode: u32,
+    set_flags: u32,
+    rd: u32,
+    rn: u32,
+    value: u32,
+) -> Option<u32> {
+    // add/sub can't take the pc as rn in the modified immediate forms, adr uses addw/subw
+    let adr = rn == 15 && matches!(name, InstructionName::ADD | InstructionName::SUB);
+    if !adr {
+        if let Some(imm12) = thumb_expand_imm(value) {
+            return Some(
+                0xf0000000
+                    | split_imm12(imm12)
+                    | opcode << 21
+                    | set_flags << 20
+                    | rn << 16
+                    | rd << 8,
+            );
+        }
+    }
+
+    match name {
+        // addw/subw take a plain 12-bit immediate
+        InstructionName::ADD | InstructionName::SUB if set_flags == 0 && value <= 0xfff => {
+            let base = if *name == InstructionName::ADD {
+                0xf2000000
+            } else {
+                0xf2a00000
+            };
+            Some(base | split_imm12(value) | rn << 16 | rd << 8)
+        }
+        // movw takes a plain 16-bit immediate
+        InstructionName::MOV if set_flags == 0 && value <= 0xffff => {
+            Some(0xf2400000 | (value >> 12) << 16 | split_imm12(value & 0xfff) | rd << 8)
+        }
+        _ => panic!(
+            "Immediate value {:#x} can't be encoded in Thumb state",
+            value
+        ),
+    }
+}
+
+fn wide_shifted_register(
+    opcode: u32,
+    set_flags: u32,
+    rd: u32,
+    rn: u32,
+    rm: &Register,
+    barrel_shifter: &Option<BarrelShifterExpression>,
+) -> Option<u32> {
+    let (shift_type, amount) = match barrel_shifter {
+        None => (0, 0),
+        Some(bs) => match bs.shift_amount {
+            BarrealShifterShiftAmount::Number(amount) => {
+                (shift_type(&bs.operation), amount as u32 & 0x1f)
+            }
+            BarrealShifterShiftAmount::Register(_) => {
+                panic!("Register-controlled shifts are only available with mov in Thumb state")
+            }
+        },
+    };
+
+    Some(
+        0xea000000
+            | opcode << 21
+            | set_flags << 20
+            | rn << 16
+            | (amount >> 2) << 12
+            | rd << 8
+            | (amount & 3) << 6
+            | shift_type << 4
+            | reg(rm),
+    )
+}
+
+// mov rd, rm, <shift> rs is lsl.w/lsr.w/asr.w/ror.w rd, rm, rs
+fn wide_register_shift(
+    name: &InstructionName,
+    set_flags: u32,
+    rd: u32,
+    rm: &Register,
+    bs: &BarrelShifterExpression,
+) -> Option<u32> {
+    let rs = match bs.shift_amount {
+        BarrealShifterShiftAmount::Register(rs) => reg(&rs),
+        _ => return None,
+    };
+
+    if *name != InstructionName::MOV {
+        panic!("Register-controlled shifts are only available with mov in Thumb state");
+    }
+
+    Some(
+        0xfa00f000
+            | shift_type(&bs.operation) << 21
+            | set_flags << 20
+            | reg(rm) << 16
+            | rd << 8
+            | rs,
+    )
+}
+
+fn shift_type(operation: &BarrelShifterOperation) -> u32 {
+    match operation {
+        BarrelShifterOperation::LSL => 0,
+        BarrelShifterOperation::LSR => 1,
+        BarrelShifterOperation::ASR => 2,
+        BarrelShifterOperation::ROR | BarrelShifterOperation::RRX => 3,
+    }
+}
+
+fn wide_load_store(op: &CpuOperation) -> Option<u32> {
+    let load = (op.instruction.value == InstructionName::LDR) as u32;
+    // ldr.w/str.w, bits 22:21 hold the size (0b10 for words)
+    let base = 0xf8000000 | 0b10 << 21 | load << 20;
+
+    match &op.expression {
+        Expression::LoadStoreImmediate(expr) => {
+            let (rt, rn) = (reg(&expr.destination), reg(&expr.base));
+            let offset = expr.offset.as_ref().map_or(0, |imm| imm.to_num() as i32);
+
+            if let Some(offset) = plain_offset(expr).filter(|offset| *offset <= 0xfff) {
+                return Some(base | 1 << 23 | rn << 16 | rt << 12 | offset);
+            }
+
+            let (pre, write_back) = match expr.index_mode {
+                IndexMode::Pre(index) => (1, index.write_back as u32),
+                IndexMode::Post => (0, 1),
+                IndexMode::None => (1, 0),
+            };
+
+            if offset.unsigned_abs() > 0xff {
+                panic!("Offset #{} out of range for Thumb (#-255 to #255)", offset);
+            }
+
+            let up = (offset >= 0) as u32;
+            Some(
+                base | rn << 16
+                    | rt << 12
+                    | 1 << 11
+                    | pre << 10
+                    | up << 9
+                    | write_back << 8
+                    | offset.unsigned_abs(),
+            )
+        }
+        Expression::LoadStoreRegister(expr) => {
+            let plain = matches!(expr.index_mode, IndexMode::Pre(index) if !index.write_back);
+            if !plain || expr.negative {
+                panic!("Thumb register offsets can't be negative, pre or post-indexed");
+            }
+
+            let amount = match expr.barrel_shifter {
+                None => 0,
+                Some(BarrelShifterExpression {
+                    operation: BarrelShifterOperation::LSL,
+                    shift_amount: BarrealShifterShiftAmount::Number(amount),
+                }) if amount <= 3 => amount as u32,
+                _ => panic!("Thumb register offsets only take lsl #0-3"),
+            };
+
+            Some(
+                base | reg(&expr.base) << 16
+                    | reg(&expr.destination) << 12
+                    | amount << 4
+                    | reg(&expr.offset),
+            )
+        }
+        _ => None,
+    }
+}
+
+fn wide_load_store_multiple(op: &CpuOperation) -> Option<u32> {
+    use InstructionName::*;
+
+    let expr = match &op.expression {
+        Expression::LoadStoreMultiple(expr) => expr,
+        _ => return None,
+    };
+
+    if expr.user_mode {
+        panic!("^ is not available in Thumb state");
+    }
+
+    let load = matches!(op.instruction.value, LDMDB | LDMIA) as u32;
+    let base = reg(&expr.base);
+    let list = register_list(&expr.registers);
+
+    if list & 1 << 13 != 0 {
+        panic!("sp can't be in the register list in Thumb state");
+    }
+    match load {
+        0 if list & 1 << 15 != 0 => panic!("pc can't be stored in Thumb state"),
+        1 if list & 0xc000 == 0xc000 => panic!("pc and lr can't both be loaded in Thumb state"),
+        _ => (),
+    }
+
+    // push/pop of a single register are a str/ldr to the stack
+    if base == 13 && expr.write_back && expr.registers.len() == 1 {
+        let rt = reg(&expr.registers[0]);
+        match op.instruction.value {
+            STMDB => return Some(0xf84d0d04 | rt << 12),
+            LDMIA => return Some(0xf85d0b04 | rt << 12),
+            _ => (),
+        }
+    }
+
+    let decrement = match op.instruction.value {
+        STMDB | LDMDB => 0xe9000000,
+        _ => 0xe8800000,
+    };
+
+    Some(decrement | (expr.write_back as u32) << 21 | load << 20 | base << 16 | list)
+}
+
+fn wide_branch(op: &CpuOperation, in_it_block: bool) -> Option<u32> {
+    let offset = match &op.expression {
+        Expression::Immediate(imm) => imm.to_machine_code() as i32,
+        _ => return None,
+    };
+
+    if offset % 2 != 0 {
+        panic!("Branch target must be halfword aligned");
+    }
+
+    let conditional = !in_it_block
+        && op.instruction.condition != ConditionCode::Al
+        && op.instruction.value == InstructionName::B;
+
+    if conditional {
+        if !(-(1 << 20)..(1 << 20)).contains(&offset) {
+            panic!("Conditional branch target out of range (+/-1MB)");
+        }
+
+        let value = (offset >> 1) as u32;
+        let condition = op.instruction.condition.to_machine_code() >> 28;
+        let (sign, j2, j1) = (value >> 19 & 1, value >> 18 & 1, value >> 17 & 1);
+
+        return Some(
+            0xf0008000
+                | sign << 26
+                | condition << 22
+                | (value >> 11 & 0x3f) << 16
+                | j1 << 13
+                | j2 << 11
+                | (value & 0x7ff),
+        );
+    }
+
+    if !(-(1 << 24)..(1 << 24)).contains(&offset) {
+        panic!("Branch target out of range (+/-16MB)");
+    }
+
+    let value = (offset >> 1) as u32;
+    let sign = value >> 23 & 1;
+    let j1 = !(value >> 22 ^ sign) & 1;
+    let j2 = !(value >> 21 ^ sign) & 1;
+    // blx switches to ARM state, its target is word aligned from the aligned pc
+    let kind = match op.instruction.value {
+        InstructionName::BL => 0xd000,
+        InstructionName::BLX if offset % 4 != 0 => panic!("BLX target must be word aligned"),
+        InstructionName::BLX => 0xc000,
+        _ => 0x9000,
+    };
+
+    Some(
+        0xf0000000
+            | kind
+            | sign << 26
+            | (value >> 11 & 0x3ff) << 16
+            | j1 << 13
+            | j2 << 11
+            | (value & 0x7ff),
+    )
+}
+
+fn wide_system(op: &CpuOperation) -> Option<u32> {
+    use InstructionName::*;
+
+    match (&op.instruction.value, &op.expression) {
+        (MRS, Expression::StatusRegister(expr)) => {
+            let reg_d = reg(&expr.register.expect("mrs needs a destination"));
+            Some(0xf3ef8000 | expr.status_register.to_machine_code() >> 2 | reg_d << 8)
+        }
+        (MSR, Expression::StatusRegister(expr)) => {
+            let reg_n = match expr.register {
+                Some(reg_n) => reg(&reg_n),
+                None => panic!("msr only takes a register in Thumb state"),
+            };
+            Some(
+                0xf3808000
+                    | expr.status_register.to_machine_code() >> 2
+                    | reg_n << 16
+                    | expr.status_register.field_mask() << 8,
+            )
+        }
+        (CPS | CPSIE | CPSID, Expression::ChangeProcessorState(expr)) => {
+            let imod = match op.instruction.value {
+                CPSIE => 0b10,
+                CPSID => 0b11,
+                _ => 0b00,
+            };
+            let mode = expr.mode.map_or(0, |mode| 1 << 8 | mode as u32);
+            Some(0xf3af8000 | imod << 9 | (expr.flags as u32) << 5 | mode)
+        }
+        (NOP | YIELD | WFE | WFI | SEV, Expression::Empty) => {
+            Some(0xf3af8000 | hint(&op.instruction.value))
+        }
+        (DMB | DSB | ISB, Expression::Immediate(option)) => {
+            let base = match op.instruction.value {
+                DSB => 0xf3bf8f40,
+                DMB => 0xf3bf8f50,
+                _ => 0xf3bf8f60,
+            };
+            Some(base | option.to_machine_code())
+        }
+        (UDF, Expression::Immediate(imm)) => {
+            let imm = imm.to_machine_code();
+            Some(0xf7f0a000 | (imm >> 12) << 16 | (imm & 0xfff))
+        }
+        _ => None,
+    }
+}
+
+// ldrex/strex take an offset, the others encode the access size and ordering in bits 7:4
+fn wide_exclusive(op: &CpuOperation) -> Option<u32> {
+    use InstructionName::*;
+
+    let expr = match &op.expression {
+        Expression::LoadStoreExclusive(expr) => expr,
+        Expression::Empty => return Some(0xf3bf8f2f), // clrex
+        _ => return None,
+    };
+
+    let (rt, rn) = (reg(&expr.destination), reg(&expr.base));
+
+    match op.instruction.value {
+        LDREX => return Some(0xe8500f00 | rn << 16 | rt << 12),
+        STREX => {
+            let rd = reg(&expr.status.expect("strex needs a status register"));
+            return Some(0xe8400000 | rn << 16 | rt << 12 | rd << 8);
+        }
+        _ => (),
+    }
+
+    let (load, kind) = match op.instruction.value {
+        LDREXB => (1, 0b0100),
+        LDREXH => (1, 0b0101),
+        LDREXD => (1, 0b0111),
+        LDAB => (1, 0b1000),
+        LDAH => (1, 0b1001),
+        LDA => (1, 0b1010),
+        LDAEXB => (1, 0b1100),
+        LDAEXH => (1, 0b1101),
+        LDAEX => (1, 0b1110),
+        LDAEXD => (1, 0b1111),
+        STREXB => (0, 0b0100),
+        STREXH => (0, 0b0101),
+        STREXD => (0, 0b0111),
+        STLB => (0, 0b1000),
+        STLH => (0, 0b1001),
+        STL => (0, 0b1010),
+        STLEXB => (0, 0b1100),
+        STLEXH => (0, 0b1101),
+        STLEX => (0, 0b1110),
+        STLEXD => (0, 0b1111),
+        _ => return None,
+    };
+
+    // unused register fields are all ones
+    let rt2 = expr.destination2.map_or(0xf, |rt2| reg(&rt2));
+    let rd = expr.status.map_or(0xf, |rd| reg(&rd));
+
+    Some(0xe8c00000 | load << 20 | rn << 16 | rt << 12 | rt2 << 8 | kind << 4 | rd)
+}
+
+// Offset of a plain [rn, #imm], the forms without writeback or a negative offset
+fn plain_offset(expr: &LoadStoreImmediateExpression) -> Option<u32> {
+    let offset = expr.offset.as_ref().map_or(0, |imm| imm.to_num() as i32);
+
+    match expr.index_mode {
+        IndexMode::None => Some(offset as u32).filter(|_| offset >= 0),
+        IndexMode::Pre(index) if !index.write_back && offset >= 0 => Some(offset as u32),
+        _ => None,
+    }
+}
+
+// Thumb-2 modified immediates: a byte, a byte repeated in a pattern, or a rotated byte with its
+// top bit set. Returns the 12-bit i:imm3:imm8 field.
+pub fn thumb_expand_imm(value: u32) -> Option<u32> {
+    let byte = value & 0xff;
+
+    if value <= 0xff {
+        return Some(value);
+    }
+    if byte != 0 && value == byte << 16 | byte {
+        return Some(0x100 | byte);
+    }
+    let high_byte = value >> 8 & 0xff;
+    if high_byte != 0 && value == high_byte << 24 | high_byte << 8 {
+        return Some(0x200 | high_byte);
+    }
+    if byte != 0 && value == byte * 0x01010101 {
+        return Some(0x300 | byte);
+    }
+
+    (8..32).find_map(|rotation| {
+        let unrotated = value.rotate_left(rotation);
+        (unrotated <= 0xff && unrotated & 0x80 != 0).then_some(rotation << 7 | (unrotated & 0x7f))
+    })
+}
+
+// i at bit 26, imm3 at bits 14:12 and imm8 at bits 7:0
+fn split_imm12(imm12: u32) -> u32 {
+    (imm12 >> 11 & 1) << 26 | (imm12 >> 8 & 0b111) << 12 | (imm12 & 0xff)
+}
+
+fn hint(name: &InstructionName) -> u32 {
+    match name {
+        InstructionName::NOP => 0,
+        InstructionName::YIELD => 1,
+        InstructionName::WFE => 2,
+        InstructionName::WFI => 3,
+        _ => 4,
+    }
+}
+
+fn register_list(registers: &[Register]) -> u32 {
+    registers
+        .iter()
+        .fold(0, |list, register| list | 1 << register.to_num())
+}
+
+fn reg(register: &Register) -> u32 {
+    register.to_num() as u32
+}
+
+fn all_low(registers: &[u32]) -> bool {
+    registers.iter().all(|register| *register < 8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thumb_expand_imm() {
+        assert_eq!(thumb_expand_imm(0xab), Some(0xab));
+        assert_eq!(thumb_expand_imm(0x00ab00ab), Some(0x1ab));
+        assert_eq!(thumb_expand_imm(0xab00ab00), Some(0x2ab));
+        assert_eq!(thumb_expand_imm(0xabababab), Some(0x3ab));
+        assert_eq!(thumb_expand_imm(0x80000000), Some(0x400));
+        assert_eq!(thumb_expand_imm(0x3fc00), Some(0xb7f));
+        assert_eq!(thumb_expand_imm(0x101), None);
+    }
+}
@@ -0,0 +1,629 @@
+// VFP encodings. They are the same in ARM and Thumb state apart from the condition field,
+// which Thumb code always sets to 0b1110, so only bits 27:0 are built here.
+
+use crate::token::{
+    instruction::{DataType, Instruction},
+    instruction_name::InstructionName,
+    register::{FpRegister, Register},
+    Token,
+};
+
+use super::{
+    cpu_op::CpuOperation,
+    expression::{
+        vfp::{VfpExpression, VfpLoadStoreExpression, VfpLoadStoreMultipleExpression, VfpOperand},
+        Expression,
+    },
+};
+
+// Floating point unit selected with .fpu, it decides which VFP instructions are accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fpu {
+    // VFP architecture version, 0 when there is no floating point unit
+    version: u8,
+    double_precision: bool,
+    // d16-d31 exist
+    d32: bool,
+    // Tag_Advanced_SIMD_arch of the NEON unit that comes with it, 0 without one
+    simd: u8,
+}
+
+impl Fpu {
+    pub fn from_name(name: &str) -> Option<Fpu> {
+        let (version, double_precision, d32, simd) = match name.to_lowercase().as_str() {
+            "none" | "softvfp" => (0, false, false, 0),
+            "vfp" | "vfpv2" | "vfp9" | "vfp10" => (2, true, false, 0),
+            "vfpv3" | "vfpv3-fp16" => (3, true, true, 0),
+            "neon" | "neon-fp16" => (3, true, true, 1),
+            "vfpv3-d16" | "vfpv3-d16-fp16" => (3, true, false, 0),
+            "vfpv3xd" | "vfpv3xd-fp16" => (3, false, false, 0),
+            "vfpv4" => (4, true, true, 0),
+            "neon-vfpv4" => (4, true, true, 2),
+            "vfpv4-d16" => (4, true, false, 0),
+            "fpv4-sp-d16" => (4, false, false, 0),
+            "fpv5-d16" => (5, true, false, 0),
+            "fpv5-sp-d16" => (5, false, false, 0),
+            "fp-armv8" => (5, true, true, 0),
+            "neon-fp-armv8" | "crypto-neon-fp-armv8" => (5, true, true, 3),
+            _ => return None,
+        };
+
+        Some(Fpu {
+            version,
+            double_precision,
+            d32,
+            simd,
+        })
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.version > 0
+    }
+
+    pub fn has_neon(&self) -> bool {
+        self.simd > 0
+    }
+
+    // Tag_Advanced_SIMD_arch
+    pub fn simd_arch(&self) -> Option<u32> {
+        self.has_neon().then_some(self.simd as u32)
+    }
+
+    // Tag_FP_arch, the d16 variants have their own values
+    pub fn fp_arch(&self) -> Option<u32> {
+        match (self.version, self.d32) {
+            (0, _) => None,
+            (2, _) => Some(2),
+            (3, true) => Some(3),
+            (3, false) => Some(4),
+            (4, true) => Some(5),
+            (4, false) => Some(6),
+            (_, true) => Some(7),
+            (_, false) => Some(8),
+        }
+    }
+
+    // Panics if the unit can't execute the instruction
+    pub fn check(&self, op: &CpuOperation) {
+        let name = op.instruction.value;
+
+        if self.version == 0 {
+            panic!("{:?} needs a floating point unit, see .fpu", name);
+        }
+
+        let registers: Vec<FpRegister> = match &op.expression {
+            Expression::Vfp(expr) => expr.fp_registers().copied().collect(),
+            Expression::VfpLoadStore(expr) => vec![expr.register],
+            Expression::VfpLoadStoreMultiple(expr) => {
+                let last = expr.first.to_num() + expr.count - 1;
+                match expr.first {
+                    FpRegister::Double(_) => vec![expr.first, FpRegister::Double(last)],
+                    _ => vec![expr.first],
+                }
+            }
+            _ => vec![],
+        };
+
+        if !self.d32
+            && registers
+                .iter()
+                .any(|register| register.is_double() && register.to_num() >= 16)
+        {
+            panic!("The selected FPU only has d0-d15");
+        }
+
+        let data_types = [op.instruction.data_type, op.instruction.source_type];
+        if !self.double_precision && data_types.contains(&Some(DataType::Float(64))) {
+            panic!("The selected FPU has no double precision {:?}", name);
+        }
+
+        // vmov immediate and the fixed point conversions came with VFPv3
+        let vfpv3 = match &op.expression {
+            Expression::Vfp(expr) => matches!(
+                (name, expr.operands.last()),
+                (
+                    InstructionName::VMOV | InstructionName::VCVT,
+                    Some(VfpOperand::Immediate(_))
+                )
+            ),
+            _ => false,
+        };
+        if vfpv3 && self.version < 3 {
+            panic!("{:?} with an immediate needs VFPv3 or later", name);
+        }
+    }
+}
+
+impl Default for Fpu {
+    // Without a .fpu directive every instruction is accepted
+    fn default() -> Self {
+        Fpu {
+            version: 5,
+            double_precision: true,
+            d32: true,
+            simd: 3,
+        }
+    }
+}
+
+// .fpu vfpv3-d16
+pub fn fpu_directive(tokens: &[Token]) -> Option<Fpu> {
+    let is_fpu = tokens.iter().any(|token| {
+        token
+            .extract_directive()
+            .is_some_and(|directive| directive.value == ".fpu")
+    });
+
+    if !is_fpu {
+        return None;
+    }
+
+    let name = tokens.iter().find_map(|token| match token {
+        Token::OPTION(name) => Some(name.clone()),
+        _ => None,
+    });
+
+    match name.as_deref().and_then(Fpu::from_name) {
+        Some(fpu) => Some(fpu),
+        None => panic!("Unknown .fpu {}", name.unwrap_or_default()),
+    }
+}
+
+pub fn generate_vfp(op: &CpuOperation) -> u32 {
+    use InstructionName::*;
+
+    let istr = &op.instruction;
+
+    match (&istr.value, &op.expression) {
+        (VLDR | VSTR, Expression::VfpLoadStore(expr)) => generate_load_store(istr, expr),
+        (
+            VLDM | VLDMIA | VLDMDB | VSTM | VSTMIA | VSTMDB,
+            Expression::VfpLoadStoreMultiple(expr),
+        ) => generate_load_store_multiple(istr, expr),
+        (
+            VADD | VSUB | VMUL | VNMUL | VDIV | VMLA | VMLS | VNMLA | VNMLS,
+            Expression::Vfp(expr),
+        ) => generate_three_regs(istr, expr),
+        (VABS | VNEG | VSQRT, Expression::Vfp(expr)) => generate_two_regs(istr, expr),
+        (VCMP | VCMPE, Expression::Vfp(expr)) => generate_compare(istr, expr),
+        (VMOV, Expression::Vfp(expr)) => generate_move(istr, expr),
+        (VCVT | VCVTR, Expression::Vfp(expr)) => generate_convert(istr, expr),
+        (VMRS | VMSR, Expression::Vfp(expr)) => match expr.operands[..] {
+            [VfpOperand::Core(rt), VfpOperand::Fp(FpRegister::Fpscr)] if istr.value == VMRS => {
+                0x0ef10a10 | reg(rt) << 12
+            }
+            [VfpOperand::Fp(FpRegister::Fpscr), VfpOperand::Core(rt)] if istr.value == VMSR => {
+                0x0ee10a10 | reg(rt) << 12
+            }
+            _ => panic!("Invalid operands"),
+        },
+        _ => panic!("Invalid VFP instruction"),
+    }
+}
+
+fn reg(register: Register) -> u32 {
+    register.to_num() as u32
+}
+
+// The register fields: Vd with D at bit 22, Vn with N at bit 7 and Vm with M at bit 5
+fn vd(register: &FpRegister) -> u32 {
+    let (field, bit) = register.split();
+    bit << 22 | field << 12
+}
+
+fn vn(register: &FpRegister) -> u32 {
+    let (field, bit) = register.split();
+    bit << 7 | field << 16
+}
+
+fn vm(register: &FpRegister) -> u32 {
+    let (field, bit) = register.split();
+    bit << 5 | field
+}
+
+// sz bit of the arithmetic instructions, from the data type, which the registers have to match
+fn precision(istr: &Instruction, registers: &[&FpRegister]) -> u32 {
+    let double = match istr.data_type {
+        Some(DataType::Float(32)) => false,
+        Some(DataType::Float(64)) => true,
+        Some(data_type) => panic!("Invalid data type {:?} for {:?}", data_type, istr.value),
+        None => panic!("{:?} needs a .f32 or .f64 data type", istr.value),
+    };
+
+    check_registers(registers, double);
+    double as u32
+}
+
+fn check_registers(registers: &[&FpRegister], double: bool) {
+    for register in registers {
+        let valid = match register {
+            FpRegister::Single(_) => !double,
+            FpRegister::Double(_) => double,
+            _ => false,
+        };
+
+        if !valid {
+            let expected = if double { "double" } else { "single" };
+            panic!(
+                "Expected a {} precision register, found {:?}",
+                expected, register
+            );
+        }
+    }
+}
+
+fn fp_registers(expr: &VfpExpression) -> Vec<&FpRegister> {
+    expr.operands
+        .iter()
+        .map(|operand| match operand {
+            VfpOperand::Fp(register) => register,
+            _ => panic!("Invalid operands"),
+        })
+        .collect()
+}
+
+fn generate_load_store(istr: &Instruction, expr: &VfpLoadStoreExpression) -> u32 {
+    let double = match expr.register {
+        FpRegister::Single(_) => false,
+        FpRegister::Double(_) => true,
+        _ => panic!("vldr and vstr transfer a single or double register"),
+    };
+
+    if let Some(data_type) = istr.data_type {
+        if data_type.size() != if double { 64 } else { 32 } {
+            panic!(
+                "Data type {:?} doesn't match {:?}",
+                data_type, expr.register
+            );
+        }
+    }
+
+    let offset = expr.offset.unsigned_abs();
+    if !offset.is_multiple_of(4) || offset > 1020 {
+        panic!(
+            "Offset {} out of range for {:?}, it must be a multiple of 4 up to 1020",
+            expr.offset, istr.value
+        );
+    }
+
+    let load = (istr.value == InstructionName::VLDR) as u32;
+    let up = (expr.offset >= 0) as u32;
+
+    0x0d000a00
+        | up << 23
+        | load << 20
+        | reg(expr.base) << 16
+        | vd(&expr.register)
+        | (double as u32) << 8
+        | (offset / 4)
+}
+
+fn generate_load_store_multiple(istr: &Instruction, expr: &VfpLoadStoreMultipleExpression) -> u32 {
+    use InstructionName::*;
+
+    let load = matches!(istr.value, VLDM | VLDMIA | VLDMDB) as u32;
+    let decrement = matches!(istr.value, VLDMDB | VSTMDB);
+
+    if decrement && !expr.write_back {
+        panic!("{:?} needs writeback (!)", istr.value);
+    }
+
+    let (double, words) = match expr.first {
+        FpRegister::Single(_) => (0, expr.count as u32),
+        FpRegister::Double(_) => (1, expr.count as u32 * 2),
+        _ => panic!("Invalid register list"),
+    };
+
+    let mode = if decrement { 1 << 24 } else { 1 << 23 };
+
+    0x0c000a00
+        | mode
+        | (expr.write_back as u32) << 21
+        | load << 20
+        | reg(expr.base) << 16
+        | vd(&expr.first)
+        | double << 8
+        | words
+}
+
+fn generate_three_regs(istr: &Instruction, expr: &VfpExpression) -> u32 {
+    use InstructionName::*;
+
+    let registers = fp_registers(expr);
+    // vadd.f32 s0, s1 is short for vadd.f32 s0, s0, s1
+    let (d, n, m) = match registers[..] {
+        [d, n, m] => (d, n, m),
+        [d, m] => (d, d, m),
+        _ => panic!("Invalid operands"),
+    };
+
+    let base = match istr.value {
+        VMLA => 0x0e000a00,
+        VMLS => 0x0e000a40,
+        VNMLS => 0x0e100a00,
+        VNMLA => 0x0e100a40,
+        VMUL => 0x0e200a00,
+        VNMUL => 0x0e200a40,
+        VADD => 0x0e300a00,
+        VSUB => 0x0e300a40,
+        _ => 0x0e800a00,
+    };
+
+    base | precision(istr, &[d, n, m]) << 8 | vd(d) | vn(n) | vm(m)
+}
+
+fn generate_two_regs(istr: &Instruction, expr: &VfpExpression) -> u32 {
+    let (d, m) = match fp_registers(expr)[..] {
+        [d, m] => (d, m),
+        _ => panic!("Invalid operands"),
+    };
+
+    let base = match istr.value {
+        InstructionName::VABS => 0x0eb00ac0,
+        InstructionName::VNEG => 0x0eb10a40,
+        _ => 0x0eb10ac0,
+    };
+
+    base | precision(istr, &[d, m]) << 8 | vd(d) | vm(m)
+}
+
+// vcmp compares with a register or with #0, vcmpe also raises on quiet NaNs
+fn generate_compare(istr: &Instruction, expr: &VfpExpression) -> u32 {
+    let exception = (istr.value == InstructionName::VCMPE) as u32;
+
+    match expr.operands[..] {
+        [VfpOperand::Fp(d), VfpOperand::Fp(m)] => {
+            0x0eb40a40 | exception << 7 | precision(istr, &[&d, &m]) << 8 | vd(&d) | vm(&m)
+        }
+        [VfpOperand::Fp(d), VfpOperand::Immediate(0.0)] => {
+            0x0eb50a40 | exception << 7 | precision(istr, &[&d]) << 8 | vd(&d)
+        }
+        _ => panic!(
+            "Invalid operands, {:?} compares with a register or #0",
+            istr.value
+        ),
+    }
+}
+
+fn generate_move(istr: &Instruction, expr: &VfpExpression) -> u32 {
+    use FpRegister::*;
+    use VfpOperand::*;
+
+    match expr.operands[..] {
+        [Fp(d), Fp(m)] => {
+            let double = match istr.data_type {
+                Some(_) => precision(istr, &[&d, &m]),
+                None => {
+                    check_registers(&[&m], d.is_double());
+                    d.is_double() as u32
+                }
+            };
+            0x0eb00a40 | double << 8 | vd(&d) | vm(&m)
+        }
+        [Fp(d), Immediate(value)] => {
+            let double = match istr.data_type {
+                Some(_) => precision(istr, &[&d]),
+                None => {
+                    check_registers(&[&d], d.is_double());
+                    d.is_double() as u32
+                }
+            };
+            let imm = vfp_expand_imm(value).unwrap_or_else(|| {
+                panic!("{} can't be encoded as a VFP immediate", value);
+            }) as u32;
+            0x0eb00a00 | double << 8 | vd(&d) | (imm >> 4) << 16 | imm & 0xf
+        }
+        [Core(rt), Fp(n @ Single(_))] => 0x0e100a10 | core(rt) << 12 | vn(&n),
+        [Fp(n @ Single(_)), Core(rt)] => 0x0e000a10 | core(rt) << 12 | vn(&n),
+        [Core(rt), Core(rt2), Fp(m @ Double(_))] => {
+            if rt == rt2 {
+                panic!("vmov to core registers needs two different registers");
+            }
+            0x0c500b10 | core(rt2) << 16 | core(rt) << 12 | vm(&m)
+        }
+        [Fp(m @ Double(_)), Core(rt), Core(rt2)] => {
+            0x0c400b10 | core(rt2) << 16 | core(rt) << 12 | vm(&m)
+        }
+        [Core(rt), Core(rt2), Fp(m @ Single(_)), Fp(m2)] => {
+            check_consecutive(&m, &m2);
+            if rt == rt2 {
+                panic!("vmov to core registers needs two different registers");
+            }
+            0x0c500a10 | core(rt2) << 16 | core(rt) << 12 | vm(&m)
+        }
+        [Fp(m @ Single(_)), Fp(m2), Core(rt), Core(rt2)] => {
+            check_consecutive(&m, &m2);
+            0x0c400a10 | core(rt2) << 16 | core(rt) << 12 | vm(&m)
+        }
+        [Scalar(d @ Double(_), index), Core(rt)] => {
+            0x0e000b10 | scalar_index(index) << 21 | core(rt) << 12 | vn(&d)
+        }
+        [Core(rt), Scalar(n @ Double(_), index)] => {
+            0x0e100b10 | scalar_index(index) << 21 | core(rt) << 12 | vn(&n)
+        }
+        _ => panic!("Invalid operands"),
+    }
+}
+
+// sp and pc can't be transferred to or from the floating point registers
+fn core(register: Register) -> u32 {
+    let num = reg(register);
+    if num >= 13 {
+        panic!("vmov can't transfer sp or pc");
+    }
+    num
+}
+
+fn scalar_index(index: u8) -> u32 {
+    if index > 1 {
+        panic!(
+            "Invalid scalar index [{}], a double register holds two words",
+            index
+        );
+    }
+    index as u32
+}
+
+fn check_consecutive(first: &FpRegister, second: &FpRegister) {
+    match (first, second) {
+        (FpRegister::Single(first), FpRegister::Single(second)) if first + 1 == *second => {}
+        _ => panic!("vmov needs two consecutive single registers"),
+    }
+}
+
+fn generate_convert(istr: &Instruction, expr: &VfpExpression) -> u32 {
+    use DataType::*;
+
+    let (to, from) = match (istr.data_type, istr.source_type) {
+        (Some(to), Some(from)) => (to, from),
+        _ => panic!("{:?} needs destination and source data types", istr.value),
+    };
+
+    // vcvtr rounds with the fpscr mode, it only exists for conversions to integer
+    let round_to_zero = (istr.value == InstructionName::VCVT) as u32;
+    if round_to_zero == 0 && !matches!((to, from), (Signed(32) | Unsigned(32), Float(_))) {
+        panic!("vcvtr only converts floating point to integer");
+    }
+
+    match (&expr.operands[..], to, from) {
+        ([VfpOperand::Fp(d), VfpOperand::Fp(m)], Float(64), Float(32)) => {
+            check_registers(&[d], true);
+            check_registers(&[m], false);
+            0x0eb70ac0 | vd(d) | vm(m)
+        }
+        ([VfpOperand::Fp(d), VfpOperand::Fp(m)], Float(32), Float(64)) => {
+            check_registers(&[d], false);
+            check_registers(&[m], true);
+            0x0eb70bc0 | vd(d) | vm(m)
+        }
+        ([VfpOperand::Fp(d), VfpOperand::Fp(m)], Float(size), Signed(32) | Unsigned(32)) => {
+            let double = float_size(size);
+            check_registers(&[d], double);
+            check_registers(&[m], false);
+            let signed = matches!(from, Signed(_)) as u32;
+            0x0eb80a40 | (double as u32) << 8 | signed << 7 | vd(d) | vm(m)
+        }
+        ([VfpOperand::Fp(d), VfpOperand::Fp(m)], Signed(32) | Unsigned(32), Float(size)) => {
+            let double = float_size(size);
+            check_registers(&[d], false);
+            check_registers(&[m], double);
+            let signed = matches!(to, Signed(_)) as u32;
+            0x0ebc0a40 | signed << 16 | (double as u32) << 8 | round_to_zero << 7 | vd(d) | vm(m)
+        }
+        // fixed point, converted in place: vcvt.f32.s32 s0, s0, #16
+        ([VfpOperand::Fp(d), VfpOperand::Fp(m), VfpOperand::Immediate(fraction_bits)], _, _) => {
+            if d != m {
+                panic!("Fixed point conversions use the same source and destination register");
+            }
+
+            let (to_fixed, float, fixed) = match (to, from) {
+                (Float(size), fixed @ (Signed(16 | 32) | Unsigned(16 | 32))) => (0, size, fixed),
+                (fixed @ (Signed(16 | 32) | Unsigned(16 | 32)), Float(size)) => (1, size, fixed),
+                _ => panic!("Invalid data types for a fixed point conversion"),
+            };
+
+            let double = float_size(float);
+            check_registers(&[d], double);
+
+            let fraction_bits = *fraction_bits;
+            let size = fixed.size() as f64;
+            let minimum = if size == 16.0 { 0.0 } else { 1.0 };
+            if fraction_bits.fract() != 0.0 || fraction_bits < minimum || fraction_bits > size {
+                panic!(
+                    "Fixed point conversions take #{}-{} fraction bits",
+                    minimum, size
+                );
+            }
+
+            let imm = (size - fraction_bits) as u32;
+            let unsigned = matches!(fixed, Unsigned(_)) as u32;
+            let word = (size == 32.0) as u32;
+
+            0x0eba0a40
+                | to_fixed << 18
+                | unsigned << 16
+                | (double as u32) << 8
+                | word << 7
+                | (imm & 1) << 5
+                | imm >> 1
+                | vd(d)
+        }
+        _ => panic!("Invalid conversion {:?} to {:?}", from, to),
+    }
+}
+
+fn float_size(size: u8) -> bool {
+    match size {
+        32 => false,
+        64 => true,
+        _ => panic!("Half precision conversions are not supported"),
+    }
+}
+
+// Floating point immediates hold a sign, a 3 bit exponent and a 4 bit fraction:
+// +/- n/16 * 2^e with 16 <= n <= 31 and -3 <= e <= 4
+pub fn vfp_expand_imm(value: f64) -> Option<u8> {
+    (0..=255u8).find(|imm| {
+        let sign = if imm & 0x80 != 0 { -1.0 } else { 1.0 };
+        let exponent = if imm & 0x40 != 0 {
+            (imm >> 4 & 3) as i32 - 3
+        } else {
+            (imm >> 4 & 3) as i32 + 1
+        };
+        let fraction = 1.0 + (imm & 0xf) as f64 / 16.0;
+
+        sign * fraction * 2f64.powi(exponent) == value
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lexer::{it_block::ImplicitIt, symbolizer::SymbolTable, Lexer},
+        reader::Reader,
+        tokenizer::Tokenizer,
+    };
+
+    fn assemble_for_fpu(line: &str, fpu: &str) -> Vec<u8> {
+        let mut tokenizer = Tokenizer::new(Reader::from_source(line));
+        let mut lexer = Lexer::new(SymbolTable::new(), ImplicitIt::default());
+        lexer.set_fpu(Fpu::from_name(fpu).unwrap());
+        lexer.assemble_line(tokenizer.consume_line())
+    }
+
+    #[test]
+    fn test_vfp_expand_imm() {
+        assert_eq!(vfp_expand_imm(1.0), Some(0x70));
+        assert_eq!(vfp_expand_imm(2.0), Some(0x00));
+        assert_eq!(vfp_expand_imm(-0.5), Some(0xe0));
+        assert_eq!(vfp_expand_imm(31.0), Some(0x3f));
+        assert_eq!(vfp_expand_imm(0.125), Some(0x40));
+        assert_eq!(vfp_expand_imm(0.0), None);
+        assert_eq!(vfp_expand_imm(0.1), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "The selected FPU only has d0-d15")]
+    fn test_fpu_register_count() {
+        assemble_for_fpu("vadd.f64 d16, d1, d2\n", "vfpv3-d16");
+    }
+
+    #[test]
+    #[should_panic(expected = "The selected FPU has no double precision")]
+    fn test_fpu_single_precision_only() {
+        assemble_for_fpu("vadd.f64 d0, d1, d2\n", "vfpv3xd");
+    }
+
+    #[test]
+    #[should_panic(expected = "Offset 1022 out of range for VLDR")]
+    fn test_vldr_offset_range() {
+        assemble_for_fpu("vldr s0, [r0, #1022]\n", "vfpv3");
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected a single precision register")]
+    fn test_mixed_precision_registers() {
+        assemble_for_fpu("vadd.f32 s0, s1, d2\n", "vfpv3");
+    }
+}
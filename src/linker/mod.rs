@@ -0,0 +1,579 @@
+// Links the relocatable objects of the assembler into an executable: sections of the same name
+// are merged, placed at the addresses of the linker script, globals are resolved across the
+// objects and the relocations applied. The result has one PT_LOAD segment per section, the
+// .debug_* sections are kept unloaded at address 0 so debuggers still find the source.
+
+pub mod relocation;
+pub mod script;
+
+use std::{collections::HashMap, fs};
+
+use object::{
+    elf::{
+        ELFOSABI_SYSV, EM_ARM, ET_EXEC, PF_R, PF_W, PF_X, PT_ARM_EXIDX, PT_LOAD, R_ARM_NONE,
+        SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, SHT_ARM_EXIDX, SHT_NOBITS, SHT_PROGBITS, STB_GLOBAL,
+        STB_LOCAL, STT_FILE, STT_SECTION,
+    },
+    write::elf::{FileHeader, ProgramHeader, SectionHeader, Sym, Writer},
+    FileFlags, Object, ObjectKind, ObjectSection, ObjectSymbol, RelocationFlags, RelocationTarget,
+    SectionFlags, SectionIndex, SectionKind, SymbolFlags, SymbolIndex, SymbolSection,
+};
+
+use crate::{elf::byte_order::ByteOrder, token::Number, utils::warning};
+
+use relocation::Relocation;
+use script::LinkerScript;
+
+struct OutputSection {
+    name: String,
+    address: Option<u32>,
+    align: u32,
+    data: Vec<u8>,
+    sh_flags: u32,
+    // Only .bss like inputs, there is nothing to store in the file
+    nobits: bool,
+}
+
+impl OutputSection {
+    fn new(name: &str, address: Option<u32>) -> Self {
+        OutputSection {
+            name: name.to_string(),
+            address,
+            align: 1,
+            data: vec![],
+            sh_flags: 0,
+            nobits: true,
+        }
+    }
+
+    fn address(&self) -> u32 {
+        self.address.expect("Output sections are placed before use")
+    }
+
+    fn is_alloc(&self) -> bool {
+        self.sh_flags & SHF_ALLOC != 0
+    }
+
+    fn is_exidx(&self) -> bool {
+        self.name == ".ARM.exidx"
+    }
+}
+
+struct OutputSymbol {
+    name: String,
+    value: u32,
+    section: usize,
+    st_info: u8,
+}
+
+pub struct Linker {
+    script: LinkerScript,
+    // Set with -e, before the ENTRY of the script
+    entry: Option<String>,
+    objects: Vec<(String, Vec<u8>)>,
+}
+
+impl Linker {
+    pub fn new(script: LinkerScript) -> Self {
+        Linker {
+            script,
+            entry: None,
+            objects: vec![],
+        }
+    }
+
+    pub fn set_entry(&mut self, entry: &str) {
+        self.entry = Some(entry.to_string());
+    }
+
+    pub fn add_object(&mut self, file_name: &str) {
+        let data = fs::read(file_name).unwrap_or_else(|_| panic!("File {} not found", file_name));
+        self.objects.push((file_name.to_string(), data));
+    }
+
+    pub fn link(&self, output: &str) {
+        let files: Vec<object::File> = self
+            .objects
+            .iter()
+            .map(|(name, data)| {
+                let file = object::File::parse(data.as_slice())
+                    .unwrap_or_else(|error| panic!("Invalid object {}: {}", name, error));
+                if file.architecture() != object::Architecture::Arm
+                    || file.kind() != ObjectKind::Relocatable
+                {
+                    panic!("{} is not an ARM relocatable object", name);
+                }
+                file
+            })
+            .collect();
+
+        if files.is_empty() {
+            panic!("No objects to link");
+        }
+
+        let e_flags = match files[0].flags() {
+            FileFlags::Elf { e_flags, .. } => e_flags,
+            _ => 0,
+        };
+        let byte_order = ByteOrder::from_elf(!files[0].is_little_endian(), e_flags);
+        for (file, (name, _)) in files.iter().zip(&self.objects) {
+            let flags = match file.flags() {
+                FileFlags::Elf { e_flags, .. } => e_flags,
+                _ => 0,
+            };
+            if ByteOrder::from_elf(!file.is_little_endian(), flags) != byte_order {
+                panic!(
+                    "{} has a different byte order than {}",
+                    name, self.objects[0].0
+                );
+            }
+        }
+
+        let (mut sections, placements) = self.place_sections(&files);
+        assign_addresses(&mut sections);
+
+        let section_address = |object: usize, index: SectionIndex| {
+            placements
+                .get(&(object, index))
+                .map(|(section, offset)| sections[*section].address() + offset)
+        };
+
+        // Address of every defined symbol of each object, and the globals by name
+        let mut addresses: Vec<HashMap<SymbolIndex, u32>> = vec![];
+        let mut globals: HashMap<String, (u32, usize)> = HashMap::new();
+        let mut symbols: Vec<OutputSymbol> = vec![];
+
+        for (object, file) in files.iter().enumerate() {
+            let mut defined = HashMap::new();
+            for symbol in file.symbols() {
+                let index = match symbol.section() {
+                    SymbolSection::Section(index) => index,
+                    _ => continue,
+                };
+                let Some(base) = section_address(object, index) else {
+                    continue;
+                };
+                let value = base + symbol.address() as u32;
+                defined.insert(symbol.index(), value);
+
+                let st_info = match symbol.flags() {
+                    SymbolFlags::Elf { st_info, .. } => st_info,
+                    _ => 0,
+                };
+                let name = symbol.name().unwrap_or_default().to_string();
+                if st_info & 0xf == STT_SECTION || st_info & 0xf == STT_FILE {
+                    continue;
+                }
+
+                if symbol.is_global() {
+                    if let Some((_, other)) = globals.insert(name.clone(), (value, object)) {
+                        panic!(
+                            "Symbol {} is defined in both {} and {}",
+                            name, self.objects[other].0, self.objects[object].0
+                        );
+                    }
+                }
+
+                symbols.push(OutputSymbol {
+                    name,
+                    value,
+                    section: placements[&(object, index)].0,
+                    st_info,
+                });
+            }
+            addresses.push(defined);
+        }
+
+        // Locals have to come before globals in the symbol table
+        symbols.sort_by_key(|symbol| symbol.st_info >> 4 != STB_LOCAL);
+
+        let mut relocations: Vec<(usize, u32, Relocation)> = vec![];
+        for (object, file) in files.iter().enumerate() {
+            for section in file.sections() {
+                let Some(&(output, offset)) = placements.get(&(object, section.index())) else {
+                    continue;
+                };
+
+                for (r_offset, reloc) in section.relocations() {
+                    let r_type = match reloc.flags() {
+                        RelocationFlags::Elf { r_type } => r_type,
+                        flags => panic!("Unsupported relocation {:?}", flags),
+                    };
+                    // Only marks a dependency, like the unwind routines of the index table
+                    if r_type == R_ARM_NONE {
+                        continue;
+                    }
+                    let RelocationTarget::Symbol(index) = reloc.target() else {
+                        panic!("Relocation without a symbol in {}", self.objects[object].0);
+                    };
+                    let symbol = file.symbol_by_index(index).expect("Invalid symbol index");
+                    let name = symbol.name().unwrap_or_default().to_string();
+
+                    let target = match addresses[object].get(&index) {
+                        Some(address) => *address,
+                        None => match globals.get(&name) {
+                            Some((address, _)) => *address,
+                            None => panic!(
+                                "Undefined reference to {} in {}",
+                                name, self.objects[object].0
+                            ),
+                        },
+                    };
+
+                    let offset = offset + r_offset as u32;
+                    let place = sections[output].address() + offset;
+                    relocations.push((
+                        output,
+                        offset,
+                        Relocation {
+                            r_type,
+                            symbol: name,
+                            target,
+                            place,
+                        },
+                    ));
+                }
+            }
+        }
+
+        for (output, offset, relocation) in relocations {
+            let bytes = &mut sections[output].data[offset as usize..];
+            relocation::apply(&relocation, bytes, byte_order);
+        }
+
+        let entry = self.entry_address(&globals, &sections);
+        write_executable(output, &sections, &symbols, entry, e_flags, byte_order);
+    }
+
+    // Input sections in the order of the script, those it doesn't name go after in their own
+    // output sections
+    #[allow(clippy::type_complexity)]
+    fn place_sections(
+        &self,
+        files: &[object::File],
+    ) -> (
+        Vec<OutputSection>,
+        HashMap<(usize, SectionIndex), (usize, u32)>,
+    ) {
+        let mut sections: Vec<OutputSection> = self
+            .script
+            .sections
+            .iter()
+            .map(|rule| OutputSection::new(&rule.name, rule.address))
+            .collect();
+        let mut placements = HashMap::new();
+
+        let (inputs, debug): (Vec<(usize, object::Section)>, Vec<_>) = files
+            .iter()
+            .enumerate()
+            .flat_map(|(object, file)| file.sections().map(move |section| (object, section)))
+            .filter(|(_, section)| match section.flags() {
+                SectionFlags::Elf { sh_flags } => {
+                    sh_flags & SHF_ALLOC as u64 != 0
+                        || section.name().is_ok_and(|name| name.starts_with(".debug"))
+                }
+                _ => false,
+            })
+            .partition(|(_, section)| match section.flags() {
+                SectionFlags::Elf { sh_flags } => sh_flags & SHF_ALLOC as u64 != 0,
+                _ => false,
+            });
+
+        let mut add = |sections: &mut Vec<OutputSection>, output: usize, input: &(usize, _)| {
+            let (object, section): &(usize, object::Section) = input;
+            if placements.contains_key(&(*object, section.index())) {
+                return;
+            }
+
+            let output_section = &mut sections[output];
+            let align = section.align().max(1) as u32;
+            let offset = output_section.data.len().next_multiple_of(align as usize);
+            let nobits = section.kind() == SectionKind::UninitializedData;
+
+            output_section.data.resize(offset, 0);
+            match section.data() {
+                Ok(data) if !nobits => output_section.data.extend(data),
+                _ => output_section
+                    .data
+                    .resize(offset + section.size() as usize, 0),
+            }
+            output_section.align = output_section.align.max(align);
+            output_section.nobits &= nobits;
+            if let SectionFlags::Elf { sh_flags } = section.flags() {
+                output_section.sh_flags |= sh_flags as u32;
+            }
+
+            placements.insert((*object, section.index()), (output, offset as u32));
+        };
+
+        for (output, rule) in self.script.sections.iter().enumerate() {
+            for pattern in &rule.patterns {
+                for input in &inputs {
+                    if script::pattern_matches(pattern, input.1.name().unwrap_or_default()) {
+                        add(&mut sections, output, input);
+                    }
+                }
+            }
+        }
+
+        // Debug sections aren't loaded, their addresses are offsets from the start
+        for input in inputs.iter().chain(&debug) {
+            let name = input.1.name().unwrap_or_default();
+            let output = match sections.iter().position(|section| section.name == name) {
+                Some(output) => output,
+                None => {
+                    let address = name.starts_with(".debug").then_some(0);
+                    sections.push(OutputSection::new(name, address));
+                    sections.len() - 1
+                }
+            };
+            add(&mut sections, output, input);
+        }
+
+        // ld leaves out the sections nothing went into
+        let mut kept = vec![];
+        let mut index = HashMap::new();
+        for (old, section) in sections.into_iter().enumerate() {
+            if !section.data.is_empty() {
+                index.insert(old, kept.len());
+                kept.push(section);
+            }
+        }
+        let placements = placements
+            .into_iter()
+            .map(|(input, (output, offset))| (input, (index[&output], offset)))
+            .collect();
+
+        (kept, placements)
+    }
+
+    // -e, then ENTRY of the script, then _start, then the start of the first section
+    fn entry_address(
+        &self,
+        globals: &HashMap<String, (u32, usize)>,
+        sections: &[OutputSection],
+    ) -> u32 {
+        let name = self.entry.as_ref().or(self.script.entry.as_ref());
+        if let Some(name) = name {
+            if let Some((address, _)) = globals.get(name) {
+                return *address;
+            }
+            if let Some(number) = Number::new(name) {
+                return number.value;
+            }
+            panic!("Entry symbol {} is not defined", name);
+        }
+
+        if let Some((address, _)) = globals.get("_start") {
+            return *address;
+        }
+
+        let address = sections
+            .iter()
+            .find(|section| section.sh_flags & SHF_EXECINSTR != 0)
+            .or(sections.first())
+            .map(|section| section.address())
+            .unwrap_or(0);
+        warning(&format!(
+            "cannot find entry symbol _start; defaulting to 0x{:08x}",
+            address
+        ));
+        address
+    }
+}
+
+// Sections without an address follow the one before, aligned as their inputs need
+fn assign_addresses(sections: &mut [OutputSection]) {
+    let mut location = script::DEFAULT_TEXT_ADDRESS;
+    for section in sections.iter_mut().filter(|section| section.is_alloc()) {
+        let address = section
+            .address
+            .unwrap_or(location.next_multiple_of(section.align));
+        if address % section.align != 0 {
+            panic!(
+                "Address 0x{:08x} of {} isn't aligned to {}",
+                address, section.name, section.align
+            );
+        }
+        section.address = Some(address);
+        location = address
+            .checked_add(section.data.len() as u32)
+            .unwrap_or_else(|| panic!("{} doesn't fit in the address space", section.name));
+    }
+
+    let mut ranges: Vec<(u32, u32, &str)> = sections
+        .iter()
+        .filter(|section| section.is_alloc())
+        .map(|section| {
+            let start = section.address();
+            (
+                start,
+                start + section.data.len() as u32,
+                section.name.as_str(),
+            )
+        })
+        .collect();
+    ranges.sort();
+    for pair in ranges.windows(2) {
+        if pair[0].1 > pair[1].0 {
+            panic!("Section {} overlaps section {}", pair[0].2, pair[1].2);
+        }
+    }
+}
+
+fn write_executable(
+    file_name: &str,
+    sections: &[OutputSection],
+    symbols: &[OutputSymbol],
+    entry: u32,
+    e_flags: u32,
+    byte_order: ByteOrder,
+) {
+    let mut buffer = vec![];
+    let mut writer = Writer::new(byte_order.endianness(), false, &mut buffer);
+
+    writer.reserve_file_header();
+    // The unwinder finds the index table through its own segment
+    let loaded = sections.iter().filter(|section| section.is_alloc()).count();
+    let exidx = sections.iter().position(OutputSection::is_exidx);
+    writer.reserve_program_headers((loaded + exidx.iter().count()) as u32);
+
+    writer.reserve_null_section_index();
+    let section_ids: Vec<_> = sections
+        .iter()
+        .map(|section| {
+            let name = writer.add_section_name(section.name.as_bytes());
+            (name, writer.reserve_section_index())
+        })
+        .collect();
+    writer.reserve_symtab_section_index();
+    writer.reserve_strtab_section_index();
+    writer.reserve_shstrtab_section_index();
+
+    writer.reserve_null_symbol_index();
+    let symbol_names: Vec<_> = symbols
+        .iter()
+        .map(|symbol| {
+            writer.reserve_symbol_index(Some(section_ids[symbol.section].1));
+            writer.add_string(symbol.name.as_bytes())
+        })
+        .collect();
+
+    let offsets: Vec<usize> = sections
+        .iter()
+        .map(|section| match section.nobits {
+            true => writer.reserved_len(),
+            false => writer.reserve(section.data.len(), section.align as usize),
+        })
+        .collect();
+    writer.reserve_symtab();
+    writer.reserve_strtab();
+    writer.reserve_shstrtab();
+    writer.reserve_section_headers();
+
+    writer
+        .write_file_header(&FileHeader {
+            os_abi: ELFOSABI_SYSV,
+            abi_version: 0,
+            e_type: ET_EXEC,
+            e_machine: EM_ARM,
+            e_entry: entry as u64,
+            e_flags,
+        })
+        .expect("Was not able to write the file header");
+
+    writer.write_align_program_headers();
+    for (section, offset) in sections.iter().zip(&offsets) {
+        if !section.is_alloc() {
+            continue;
+        }
+        let mut p_flags = PF_R;
+        if section.sh_flags & SHF_EXECINSTR != 0 {
+            p_flags |= PF_X;
+        }
+        if section.sh_flags & SHF_WRITE != 0 {
+            p_flags |= PF_W;
+        }
+        let size = section.data.len() as u64;
+
+        writer.write_program_header(&ProgramHeader {
+            p_type: PT_LOAD,
+            p_flags,
+            p_offset: *offset as u64,
+            p_vaddr: section.address() as u64,
+            p_paddr: section.address() as u64,
+            p_filesz: if section.nobits { 0 } else { size },
+            p_memsz: size,
+            p_align: section.align as u64,
+        });
+    }
+    if let Some(exidx) = exidx {
+        let section = &sections[exidx];
+        let size = section.data.len() as u64;
+        writer.write_program_header(&ProgramHeader {
+            p_type: PT_ARM_EXIDX,
+            p_flags: PF_R,
+            p_offset: offsets[exidx] as u64,
+            p_vaddr: section.address() as u64,
+            p_paddr: section.address() as u64,
+            p_filesz: size,
+            p_memsz: size,
+            p_align: section.align as u64,
+        });
+    }
+
+    for section in sections.iter().filter(|section| !section.nobits) {
+        writer.write_align(section.align as usize);
+        writer.write(&section.data);
+    }
+
+    writer.write_null_symbol();
+    for (symbol, name) in symbols.iter().zip(symbol_names) {
+        writer.write_symbol(&Sym {
+            name: Some(name),
+            section: Some(section_ids[symbol.section].1),
+            st_info: symbol.st_info,
+            st_other: 0,
+            st_shndx: 0,
+            st_value: symbol.value as u64,
+            st_size: 0,
+        });
+    }
+    writer.write_strtab();
+    writer.write_shstrtab();
+
+    // The index table links to the code it describes
+    let text = sections
+        .iter()
+        .position(|section| section.sh_flags & SHF_EXECINSTR != 0)
+        .map_or(0, |text| section_ids[text].1 .0);
+
+    writer.write_null_section_header();
+    for ((section, offset), (name, _)) in sections.iter().zip(&offsets).zip(&section_ids) {
+        writer.write_section_header(&SectionHeader {
+            name: Some(*name),
+            sh_type: match section {
+                section if section.nobits => SHT_NOBITS,
+                section if section.is_exidx() => SHT_ARM_EXIDX,
+                _ => SHT_PROGBITS,
+            },
+            sh_flags: section.sh_flags as u64,
+            sh_addr: section.address() as u64,
+            sh_offset: *offset as u64,
+            sh_size: section.data.len() as u64,
+            sh_link: if section.is_exidx() { text } else { 0 },
+            sh_info: 0,
+            sh_addralign: section.align as u64,
+            sh_entsize: 0,
+        });
+    }
+    let num_local = 1 + symbols
+        .iter()
+        .filter(|symbol| symbol.st_info >> 4 != STB_GLOBAL)
+        .count() as u32;
+    writer.write_symtab_section_header(num_local);
+    writer.write_strtab_section_header();
+    writer.write_shstrtab_section_header();
+
+    fs::write(file_name, buffer).expect("Was not able to create output file");
+}
@@ -0,0 +1,252 @@
+// Applies the REL relocations the assembler emits. The addend is stored in the field being
+// relocated, the result is (S + A) | T - P as AAELF describes: S the symbol address, A the
+// addend, T set when the symbol is a Thumb function and P the address of the place.
+
+use object::elf::{
+    R_ARM_ABS32, R_ARM_CALL, R_ARM_JUMP24, R_ARM_LDC_PC_G0, R_ARM_PREL31, R_ARM_REL32,
+    R_ARM_THM_JUMP19, R_ARM_THM_JUMP24, R_ARM_THM_PC11, R_ARM_THM_PC22, R_ARM_THM_PC9,
+};
+
+use crate::elf::byte_order::ByteOrder;
+
+pub struct Relocation {
+    pub r_type: u32,
+    pub symbol: String,
+    // S, with bit 0 set for Thumb functions
+    pub target: u32,
+    // P
+    pub place: u32,
+}
+
+fn sign_extend(value: u32, bits: u32) -> i32 {
+    ((value << (32 - bits)) as i32) >> (32 - bits)
+}
+
+fn check_range(relocation: &Relocation, value: i32, bits: u32) {
+    let limit = 1i64 << (bits - 1);
+    if !(-limit..limit).contains(&(value as i64)) {
+        panic!(
+            "Relocation against {} out of range at 0x{:08x}",
+            relocation.symbol, relocation.place
+        );
+    }
+}
+
+fn interworking_error(relocation: &Relocation) -> ! {
+    panic!(
+        "Branch at 0x{:08x} can't change instruction set to reach {}, use bl or blx",
+        relocation.place, relocation.symbol
+    );
+}
+
+pub fn apply(relocation: &Relocation, bytes: &mut [u8], byte_order: ByteOrder) {
+    let thumb = relocation.target & 1 == 1;
+    let target = relocation.target & !1;
+    let place = relocation.place;
+
+    match relocation.r_type {
+        R_ARM_ABS32 => {
+            let addend = byte_order.read_data_u32(bytes);
+            let value = relocation.target.wrapping_add(addend);
+            bytes[..4].copy_from_slice(&byte_order.data_u32(value));
+        }
+        // Offsets of the EHABI tables, bit 31 belongs to the entry
+        R_ARM_PREL31 => {
+            let word = byte_order.read_data_u32(bytes);
+            let addend = sign_extend(word & 0x7fff_ffff, 31);
+            let value = relocation
+                .target
+                .wrapping_add(addend as u32)
+                .wrapping_sub(place) as i32;
+            check_range(relocation, value, 31);
+            let word = word & 0x8000_0000 | value as u32 & 0x7fff_ffff;
+            bytes[..4].copy_from_slice(&byte_order.data_u32(word));
+        }
+        R_ARM_REL32 => {
+            let addend = byte_order.read_data_u32(bytes);
+            let value = relocation.target.wrapping_add(addend).wrapping_sub(place);
+            bytes[..4].copy_from_slice(&byte_order.data_u32(value));
+        }
+        R_ARM_CALL | R_ARM_JUMP24 => {
+            let code = byte_order.read_code_u32(bytes);
+            let addend = sign_extend(code & 0xff_ffff, 24) << 2;
+            let value = target.wrapping_add(addend as u32).wrapping_sub(place) as i32;
+            check_range(relocation, value, 26);
+
+            // bl becomes blx when the target is Thumb code and the other way around
+            let code = match (relocation.r_type, thumb) {
+                (R_ARM_CALL, true) => 0xfa00_0000 | ((value as u32 & 2) << 23),
+                (R_ARM_CALL, false) if code >> 28 == 0xf => 0xeb00_0000,
+                (_, true) => interworking_error(relocation),
+                _ => code & 0xff00_0000,
+            };
+            let code = code | ((value as u32 >> 2) & 0xff_ffff);
+            bytes[..4].copy_from_slice(&byte_order.code_u32(code));
+        }
+        R_ARM_THM_PC22 | R_ARM_THM_JUMP24 => {
+            let (first, second) = thumb_halfwords(bytes, byte_order);
+            let addend = thumb_branch_offset(first, second);
+            let call = relocation.r_type == R_ARM_THM_PC22;
+
+            // blx to ARM code is relative to the word aligned PC
+            let (second, place) = match (call, thumb) {
+                (true, true) => (second | 0x1000, place),
+                (true, false) => (second & !0x1000, place & !3),
+                (false, true) => (second, place),
+                (false, false) => interworking_error(relocation),
+            };
+            let value = target.wrapping_add(addend as u32).wrapping_sub(place) as i32;
+            check_range(relocation, value, 25);
+
+            let (first, second) = encode_thumb_branch(first, second, value);
+            set_thumb_halfwords(bytes, byte_order, first, second);
+        }
+        R_ARM_THM_JUMP19 => {
+            let (first, second) = thumb_halfwords(bytes, byte_order);
+            let (first, second) = (first as u32, second as u32);
+            let (s, j1, j2) = ((first >> 10) & 1, (second >> 13) & 1, (second >> 11) & 1);
+            let imm = s << 20 | j2 << 19 | j1 << 18 | (first & 0x3f) << 12 | (second & 0x7ff) << 1;
+            let addend = sign_extend(imm, 21);
+            if !thumb {
+                interworking_error(relocation);
+            }
+            let value = target.wrapping_add(addend as u32).wrapping_sub(place) as i32;
+            check_range(relocation, value, 21);
+
+            let imm = value as u32;
+            let first = (first & 0xfbc0) | ((imm >> 20) & 1) << 10 | ((imm >> 12) & 0x3f);
+            let second = (second & 0xd000)
+                | ((imm >> 18) & 1) << 13
+                | ((imm >> 19) & 1) << 11
+                | ((imm >> 1) & 0x7ff);
+            set_thumb_halfwords(bytes, byte_order, first as u16, second as u16);
+        }
+        R_ARM_THM_PC11 | R_ARM_THM_PC9 => {
+            let code = byte_order.read_code_u16(bytes);
+            let bits = if relocation.r_type == R_ARM_THM_PC11 {
+                11
+            } else {
+                8
+            };
+            let mask = (1 << bits) - 1;
+            let addend = sign_extend((code & mask) as u32, bits) << 1;
+            if !thumb {
+                interworking_error(relocation);
+            }
+            let value = target.wrapping_add(addend as u32).wrapping_sub(place) as i32;
+            check_range(relocation, value, bits + 1);
+
+            let code = (code & !mask) | ((value >> 1) as u16 & mask);
+            bytes[..2].copy_from_slice(&byte_order.code_u16(code));
+        }
+        R_ARM_LDC_PC_G0 => {
+            // vldr and vstr in ARM code, imm8 counts words and U gives the direction
+            let code = byte_order.read_code_u32(bytes);
+            let imm = (code & 0xff) << 2;
+            let addend = if code & (1 << 23) != 0 {
+                imm as i32
+            } else {
+                -(imm as i32)
+            };
+            let value = target.wrapping_add(addend as u32).wrapping_sub(place) as i32;
+            if value % 4 != 0 || value.unsigned_abs() > 1020 {
+                panic!(
+                    "Relocation against {} out of range at 0x{:08x}",
+                    relocation.symbol, relocation.place
+                );
+            }
+
+            let up = if value >= 0 { 1 << 23 } else { 0 };
+            let code = (code & !(0xff | 1 << 23)) | up | (value.unsigned_abs() >> 2);
+            bytes[..4].copy_from_slice(&byte_order.code_u32(code));
+        }
+        r_type => panic!("Unsupported relocation type {}", r_type),
+    }
+}
+
+fn thumb_halfwords(bytes: &[u8], byte_order: ByteOrder) -> (u16, u16) {
+    (
+        byte_order.read_code_u16(bytes),
+        byte_order.read_code_u16(&bytes[2..]),
+    )
+}
+
+fn set_thumb_halfwords(bytes: &mut [u8], byte_order: ByteOrder, first: u16, second: u16) {
+    bytes[..2].copy_from_slice(&byte_order.code_u16(first));
+    bytes[2..4].copy_from_slice(&byte_order.code_u16(second));
+}
+
+// S:I1:I2:imm10:imm11:0 of bl, blx and b.w, with I1 = !(J1 ^ S) and I2 = !(J2 ^ S)
+fn thumb_branch_offset(first: u16, second: u16) -> i32 {
+    let (first, second) = (first as u32, second as u32);
+    let s = (first >> 10) & 1;
+    let i1 = !((second >> 13) ^ s) & 1;
+    let i2 = !((second >> 11) ^ s) & 1;
+    let imm = s << 24 | i1 << 23 | i2 << 22 | (first & 0x3ff) << 12 | (second & 0x7ff) << 1;
+    sign_extend(imm, 25)
+}
+
+fn encode_thumb_branch(first: u16, second: u16, value: i32) -> (u16, u16) {
+    let imm = value as u32;
+    let s = (imm >> 24) & 1;
+    let j1 = !((imm >> 23) ^ s) & 1;
+    let j2 = !((imm >> 22) ^ s) & 1;
+
+    let first = (first as u32 & 0xf800) | s << 10 | ((imm >> 12) & 0x3ff);
+    // blx has to leave bit 0 of its offset clear
+    let low = if second & 0x1000 == 0 {
+        (imm >> 1) & 0x7fe
+    } else {
+        (imm >> 1) & 0x7ff
+    };
+    let second = (second as u32 & 0xd000) | j1 << 13 | j2 << 11 | low;
+    (first as u16, second as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_to_thumb_becomes_blx() {
+        // bl with the -8 addend at 0x8000, calling a Thumb function at 0x8106
+        let mut bytes = 0xebff_fffeu32.to_le_bytes();
+        let relocation = Relocation {
+            r_type: R_ARM_CALL,
+            symbol: "f".to_string(),
+            target: 0x8107,
+            place: 0x8000,
+        };
+
+        apply(&relocation, &mut bytes, ByteOrder::Little);
+        assert_eq!(u32::from_le_bytes(bytes), 0xfb00_003f);
+    }
+
+    #[test]
+    #[should_panic(expected = "Relocation against far out of range at 0x00008000")]
+    fn test_branch_out_of_range() {
+        let mut bytes = 0xeaff_fffeu32.to_le_bytes();
+        let relocation = Relocation {
+            r_type: R_ARM_JUMP24,
+            symbol: "far".to_string(),
+            target: 0x0200_8008,
+            place: 0x8000,
+        };
+
+        apply(&relocation, &mut bytes, ByteOrder::Little);
+    }
+
+    #[test]
+    #[should_panic(expected = "can't change instruction set to reach f, use bl or blx")]
+    fn test_jump_to_thumb() {
+        let mut bytes = 0xeaff_fffeu32.to_le_bytes();
+        let relocation = Relocation {
+            r_type: R_ARM_JUMP24,
+            symbol: "f".to_string(),
+            target: 0x8107,
+            place: 0x8000,
+        };
+
+        apply(&relocation, &mut bytes, ByteOrder::Little);
+    }
+}
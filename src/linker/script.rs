@@ -0,0 +1,226 @@
+// The part of the GNU ld script language needed to place sections:
+//
+//     ENTRY(_start)
+//     SECTIONS {
+//         . = 0x40000000;
+//         .text : { *(.text) }
+//         .data 0x40100000 : { *(.data) }
+//     }
+//
+// Sections without an address follow the previous one.
+
+use std::fs;
+
+use crate::token::Number;
+
+// ld for ARM starts executables here when nothing says otherwise
+pub const DEFAULT_TEXT_ADDRESS: u32 = 0x8000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputSectionRule {
+    pub name: String,
+    pub address: Option<u32>,
+    // Input section names, a trailing * matches any suffix
+    pub patterns: Vec<String>,
+}
+
+impl OutputSectionRule {
+    pub fn matches(&self, input: &str) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| pattern_matches(pattern, input))
+    }
+}
+
+pub fn pattern_matches(pattern: &str, input: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => input.starts_with(prefix),
+        None => input == pattern,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkerScript {
+    pub entry: Option<String>,
+    pub sections: Vec<OutputSectionRule>,
+}
+
+impl LinkerScript {
+    pub fn from_file(file_name: &str) -> LinkerScript {
+        let text = fs::read_to_string(file_name).expect("Linker script not found");
+        LinkerScript::parse(&text)
+    }
+
+    // The layout of -Ttext and -Tdata: .text, .data and .bss one after the other
+    pub fn with_addresses(text: Option<u32>, data: Option<u32>) -> LinkerScript {
+        let rule = |name: &str, address| OutputSectionRule {
+            name: name.to_string(),
+            address,
+            patterns: vec![name.to_string()],
+        };
+
+        LinkerScript {
+            entry: None,
+            sections: vec![
+                rule(".text", Some(text.unwrap_or(DEFAULT_TEXT_ADDRESS))),
+                rule(".data", data),
+                rule(".bss", None),
+            ],
+        }
+    }
+
+    pub fn parse(text: &str) -> LinkerScript {
+        let tokens = script_tokens(text);
+        let mut tokens = tokens.iter().map(String::as_str).peekable();
+        let mut script = LinkerScript {
+            entry: None,
+            sections: vec![],
+        };
+
+        while let Some(token) = tokens.next() {
+            match token {
+                "ENTRY" => {
+                    expect(&mut tokens, "(");
+                    script.entry = Some(next(&mut tokens).to_string());
+                    expect(&mut tokens, ")");
+                }
+                "SECTIONS" => {
+                    expect(&mut tokens, "{");
+                    let mut location = None;
+                    loop {
+                        match next(&mut tokens) {
+                            "}" => break,
+                            "." => {
+                                expect(&mut tokens, "=");
+                                location = Some(address(next(&mut tokens)));
+                                expect(&mut tokens, ";");
+                            }
+                            name => {
+                                let mut rule = OutputSectionRule {
+                                    name: name.to_string(),
+                                    address: location.take(),
+                                    patterns: vec![],
+                                };
+                                if tokens.peek() != Some(&":") {
+                                    rule.address = Some(address(next(&mut tokens)));
+                                }
+                                expect(&mut tokens, ":");
+                                expect(&mut tokens, "{");
+                                section_patterns(&mut tokens, &mut rule.patterns);
+                                script.sections.push(rule);
+                            }
+                        }
+                    }
+                }
+                ";" => {}
+                _ => panic!("Unsupported linker script command {}", token),
+            }
+        }
+
+        script
+    }
+}
+
+// *(.text .text.*) lists, up to the closing brace of the output section
+fn section_patterns<'a>(tokens: &mut impl Iterator<Item = &'a str>, patterns: &mut Vec<String>) {
+    loop {
+        match next(tokens) {
+            "}" => return,
+            "*" | "KEEP" => {}
+            "(" => loop {
+                match next(tokens) {
+                    ")" => break,
+                    "(" | "*" => {}
+                    pattern => patterns.push(pattern.to_string()),
+                }
+            },
+            ")" | ";" => {}
+            token => panic!("Unsupported input section description {}", token),
+        }
+    }
+}
+
+// Words and the punctuation between them, without /* comments */
+fn script_tokens(text: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut word = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let comment = c == '/' && chars.peek() == Some(&'*');
+        if (c.is_whitespace() || comment || "{}():;=,".contains(c)) && !word.is_empty() {
+            tokens.push(std::mem::take(&mut word));
+        }
+
+        if comment {
+            chars.next();
+            let mut last = ' ';
+            for c in chars.by_ref() {
+                if last == '*' && c == '/' {
+                    break;
+                }
+                last = c;
+            }
+        } else if "{}():;=,".contains(c) {
+            if c != ',' {
+                tokens.push(c.to_string());
+            }
+        } else if !c.is_whitespace() {
+            word.push(c);
+        }
+    }
+
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+fn next<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> &'a str {
+    tokens
+        .next()
+        .unwrap_or_else(|| panic!("Unexpected end of linker script"))
+}
+
+fn expect<'a>(tokens: &mut impl Iterator<Item = &'a str>, expected: &str) {
+    let token = next(tokens);
+    if token != expected {
+        panic!("Expected {} in linker script, found {}", expected, token);
+    }
+}
+
+fn address(token: &str) -> u32 {
+    Number::new(token)
+        .unwrap_or_else(|| panic!("Invalid address {} in linker script", token))
+        .value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_script() {
+        let script = LinkerScript::parse(
+            "ENTRY(reset) /* vectors first */
+             SECTIONS {
+                 . = 0x40000000;
+                 .text : { *(.text .text.*) }
+                 .data 0x40100000 : { *(.data) }
+             }",
+        );
+
+        assert_eq!(script.entry.as_deref(), Some("reset"));
+        assert_eq!(script.sections.len(), 2);
+        assert_eq!(script.sections[0].address, Some(0x4000_0000));
+        assert!(script.sections[0].matches(".text.startup"));
+        assert_eq!(script.sections[1].address, Some(0x4010_0000));
+        assert!(!script.sections[1].matches(".text"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unsupported linker script command MEMORY")]
+    fn test_unsupported_command() {
+        LinkerScript::parse("MEMORY { ram : ORIGIN = 0, LENGTH = 4K }");
+    }
+}
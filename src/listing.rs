@@ -0,0 +1,169 @@
+// Assembly listing selected with -a[hls][=file]: every source line with the section offset
+// and bytes it assembled to, followed by the symbols.
+
+use std::{collections::BTreeMap, fs};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListingOptions {
+    // l: the source with addresses and encodings
+    pub assembly: bool,
+    // s: defined and undefined symbols
+    pub symbols: bool,
+    // Standard output without one
+    pub file: Option<String>,
+}
+
+impl ListingOptions {
+    // What follows -a: "" and "=file" list everything, "ls=file" picks the parts. h, c, d
+    // and n are accepted, there are no high-level sources, conditionals or forms to hide.
+    pub fn from_flags(flags: &str) -> Option<ListingOptions> {
+        let (flags, file) = match flags.split_once('=') {
+            Some((flags, file)) => (flags, Some(file.to_string())),
+            None => (flags, None),
+        };
+
+        if !flags.chars().all(|flag| "lshcdn".contains(flag)) {
+            return None;
+        }
+
+        let everything = !flags.contains(['l', 's']);
+        Some(ListingOptions {
+            assembly: everything || flags.contains('l'),
+            symbols: everything || flags.contains('s'),
+            file,
+        })
+    }
+}
+
+// A symbol as listed: name, section, offset and source line
+pub struct ListedSymbol {
+    pub name: String,
+    pub section: String,
+    pub value: u32,
+    pub line: u32,
+}
+
+pub struct Listing {
+    options: ListingOptions,
+    source: String,
+    text: String,
+    // Section offsets and bytes emitted by each source line
+    lines: BTreeMap<u32, Vec<(u32, Vec<u8>)>>,
+}
+
+impl Listing {
+    // The source is named after the first input, text is every input and include read
+    pub fn new(options: ListingOptions, source: &str, text: &str) -> Self {
+        Listing {
+            options,
+            source: source.to_string(),
+            text: text.to_string(),
+            lines: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_line(&mut self, line: u32, offset: u32, bytes: &[u8]) {
+        self.lines
+            .entry(line)
+            .or_default()
+            .push((offset, bytes.to_vec()));
+    }
+
+    // Writes over listed bytes a later line changed, the IT generated ahead of the instructions
+    // that joined it. It's in the last line that listed bytes up to its offset.
+    pub fn patch(&mut self, offset: u32, patch: &[u8]) {
+        let listed = self
+            .lines
+            .values_mut()
+            .rev()
+            .flat_map(|entries| entries.iter_mut().rev())
+            .filter(|(_, bytes)| !bytes.is_empty())
+            .find(|(start, _)| *start <= offset);
+
+        if let Some((start, bytes)) = listed {
+            let at = (offset - *start) as usize;
+            if let Some(bytes) = bytes.get_mut(at..at + patch.len()) {
+                bytes.copy_from_slice(patch);
+            }
+        }
+    }
+
+    pub fn write(&self, defined: &[ListedSymbol], undefined: &[String]) {
+        let mut output = format!("ARM GAS  {}\n\n\n", self.source);
+
+        if self.options.assembly {
+            for (index, source_line) in self.text.lines().enumerate() {
+                self.write_line(&mut output, index as u32 + 1, source_line);
+            }
+        }
+
+        if self.options.symbols {
+            output.push_str("\nDEFINED SYMBOLS\n");
+            for symbol in defined {
+                output.push_str(&format!(
+                    "{:>20}:{:<6} {}:{:08x} {}\n",
+                    self.source, symbol.line, symbol.section, symbol.value, symbol.name
+                ));
+            }
+
+            output.push_str("\nUNDEFINED SYMBOLS\n");
+            for name in undefined {
+                output.push_str(&format!("{}\n", name));
+            }
+        }
+
+        match &self.options.file {
+            Some(file) => fs::write(file, output).expect("Was not able to create listing file"),
+            None => print!("{}", output),
+        }
+    }
+
+    // At most four bytes go on a line, the rest continue on the following ones
+    fn write_line(&self, output: &mut String, number: u32, source_line: &str) {
+        let chunks: Vec<(u32, &[u8])> = self
+            .lines
+            .get(&number)
+            .into_iter()
+            .flatten()
+            .flat_map(|(offset, bytes)| {
+                bytes
+                    .chunks(4)
+                    .enumerate()
+                    .map(move |(index, chunk)| (offset + 4 * index as u32, chunk))
+            })
+            .collect();
+
+        let hex = |bytes: &[u8]| bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        match chunks.split_first() {
+            None => output.push_str(&format!("{:>4}              \t{}\n", number, source_line)),
+            Some(((offset, bytes), rest)) => {
+                let bytes: String = hex(bytes);
+                output.push_str(&format!(
+                    "{:>4} {:04x} {:<8} \t{}\n",
+                    number, offset, bytes, source_line
+                ));
+                for (offset, bytes) in rest {
+                    let bytes: String = hex(bytes);
+                    output.push_str(&format!("{:>4} {:04x} {}\n", number, offset, bytes));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listing_flags() {
+        let everything = ListingOptions::from_flags("").unwrap();
+        assert!(everything.assembly && everything.symbols && everything.file.is_none());
+
+        let symbols = ListingOptions::from_flags("s=out.lst").unwrap();
+        assert!(!symbols.assembly && symbols.symbols);
+        assert_eq!(symbols.file.as_deref(), Some("out.lst"));
+
+        assert!(ListingOptions::from_flags("x").is_none());
+    }
+}
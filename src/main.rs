@@ -1,21 +1,45 @@
+use std::env;
+
 use lexer::symbolizer::Symbolizer;
 use reader::Reader;
 
 use crate::tokenizer::Tokenizer;
 
 pub mod assembler;
+pub mod disassembler;
 pub mod elf;
 pub mod emulator;
+pub mod image;
 pub mod lexer;
+pub mod linker;
+pub mod listing;
 pub mod reader;
+pub mod source;
 pub mod token;
 pub mod tokenizer;
 pub mod utils;
 
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use elf::byte_order::ByteOrder;
+use image::OutputFormat;
+use lexer::{
+    it_block::ImplicitIt,
+    target::{Arch, FloatAbi, Target},
+    vfp::Fpu,
+};
+use linker::{script::LinkerScript, Linker};
+use listing::{Listing, ListingOptions};
+use source::Source;
+use token::Number;
+use utils::{set_warning_mode, WarningMode};
 
 fn main() {
-    let matches = Command::new("poli-as")
+    let matches = command().get_matches_from(listing_args(env::args()));
+    assemble(&matches);
+}
+
+fn command() -> Command {
+    Command::new("poli-as")
         .version("1.0")
         .author("Thiago Souza e Igor Pontes Tresolavy")
         .about("Assembler para o armv7")
@@ -23,31 +47,432 @@ fn main() {
             Arg::new("input")
                 .short('i')
                 .long("input")
-                .value_name("FILE"),
+                .value_name("FILE")
+                .help("Source file, read before the positional ones"),
         )
+        .arg(Arg::new("inputs").value_name("FILES").num_args(1..).help(
+            "Sources assembled as one, - or none for the standard input. \
+                     The objects to link with --link",
+        ))
         .arg(
             Arg::new("output")
                 .short('o')
                 .long("output")
                 .value_name("FILE"),
         )
-        .get_matches();
+        .arg(
+            Arg::new("include")
+                .short('I')
+                .value_name("DIR")
+                .action(ArgAction::Append)
+                .help("Directory searched for .include files"),
+        )
+        .arg(
+            Arg::new("defsym")
+                .long("defsym")
+                .value_name("SYM=VALUE")
+                .action(ArgAction::Append)
+                .help("Absolute symbol, usable as a number or #SYM immediate"),
+        )
+        .arg(
+            Arg::new("no_warn")
+                .short('W')
+                .long("no-warn")
+                .action(ArgAction::SetTrue)
+                .help("Don't print warnings"),
+        )
+        .arg(
+            Arg::new("fatal_warnings")
+                .long("fatal-warnings")
+                .action(ArgAction::SetTrue)
+                .help("Treat warnings as errors"),
+        )
+        .arg(
+            Arg::new("dependencies")
+                .long("MD")
+                .value_name("FILE")
+                .help("Make rule listing the sources of the object"),
+        )
+        .arg(
+            Arg::new("machine")
+                .short('m')
+                .value_name("OPTION")
+                .action(ArgAction::Append)
+                .help(
+                    "Target options, -mimplicit-it=always|never|arm|thumb, -march=ARCH, \
+                     -mcpu=CPU, -mfpu=FPU, -mfloat-abi=soft|softfp|hard, -meabi=gnu|4|5, \
+                     -mbe8, -mbe32",
+                ),
+        )
+        .arg(
+            Arg::new("listing")
+                .long("listing-options")
+                .value_name("[hls][=FILE]")
+                .require_equals(true)
+                .help(
+                    "Listing of the source with its encodings, and of the symbols, given as \
+                     -a[hls][=FILE]",
+                ),
+        )
+        .arg(
+            Arg::new("listing_file")
+                .long("listing")
+                .value_name("FILE")
+                .help("Full listing written to FILE, the same as -a=FILE"),
+        )
+        .arg(
+            Arg::new("debug")
+                .short('g')
+                .long("gen-debug")
+                .action(ArgAction::SetTrue)
+                .help("DWARF line information, to step through the source in a debugger"),
+        )
+        .arg(
+            Arg::new("format")
+                .short('O')
+                .long("format")
+                .value_name("elf|binary|ihex|srec")
+                .help("Output format, flat images need a base address"),
+        )
+        .arg(
+            Arg::new("base")
+                .long("base")
+                .value_name("ADDRESS")
+                .help("Load address of binary, ihex and srec output"),
+        )
+        .arg(
+            Arg::new("endian")
+                .short('E')
+                .value_name("B|L")
+                .help("Byte order, -EB for big-endian and -EL for little-endian"),
+        )
+        .arg(
+            Arg::new("link")
+                .long("link")
+                .action(ArgAction::SetTrue)
+                .help("Link the given objects into an executable instead of assembling"),
+        )
+        .arg(
+            Arg::new("disassemble")
+                .short('d')
+                .long("disassemble")
+                .value_name("FILE")
+                .help("Disassemble the code sections of an object or executable"),
+        )
+        .arg(
+            Arg::new("script")
+                .short('T')
+                .value_name("SCRIPT")
+                .action(ArgAction::Append)
+                .help("Linker script, or -Ttext=ADDRESS and -Tdata=ADDRESS"),
+        )
+        .arg(
+            Arg::new("entry")
+                .short('e')
+                .long("entry")
+                .value_name("SYMBOL")
+                .help("Entry point of the executable"),
+        )
+}
+
+// -a takes its flags and file attached, as in -a, -als or -al=out.lst, which clap can't tell
+// from a separate value. They are passed on as --listing-options.
+fn listing_args(args: impl Iterator<Item = String>) -> Vec<String> {
+    let mut options_end = false;
+    args.map(|arg| {
+        options_end |= arg == "--";
+        match arg.strip_prefix("-a") {
+            Some(flags) if !options_end => format!("--listing-options={}", flags),
+            _ => arg,
+        }
+    })
+    .collect()
+}
+
+fn assemble(matches: &ArgMatches) {
+    if let Some(file_name) = matches.get_one::<String>("disassemble") {
+        disassembler::disassemble(file_name);
+        return;
+    }
+
+    if matches.get_flag("fatal_warnings") {
+        set_warning_mode(WarningMode::Fatal);
+    } else if matches.get_flag("no_warn") {
+        set_warning_mode(WarningMode::Silent);
+    }
 
     let output_file_name = matches.get_one::<String>("output");
-    // Accessing values
-    if let Some(input) = matches.get_one::<String>("input") {
-        let reader = Reader::new(input);
+    if matches.get_flag("link") {
+        link(matches, output_file_name);
+        return;
+    }
+
+    let mut implicit_it = ImplicitIt::default();
+    let mut target = Target::default();
+    let mut be8 = None;
+    for option in matches.get_many::<String>("machine").unwrap_or_default() {
+        match option.split_once('=') {
+            Some(("implicit-it", value)) => {
+                implicit_it = ImplicitIt::from_name(value)
+                    .unwrap_or_else(|| panic!("Invalid -mimplicit-it value {}", value));
+            }
+            Some(("arch", value)) => {
+                let selected = Target::from_arch(value)
+                    .unwrap_or_else(|| panic!("Unknown architecture {}", value));
+                target = target.select(selected);
+            }
+            Some(("cpu", value)) => {
+                let selected =
+                    Target::from_cpu(value).unwrap_or_else(|| panic!("Unknown CPU {}", value));
+                target = target.select(selected);
+            }
+            Some(("fpu", value)) => {
+                let fpu = Fpu::from_name(value).unwrap_or_else(|| panic!("Unknown FPU {}", value));
+                target.set_fpu(fpu);
+            }
+            Some(("float-abi", value)) => {
+                target.float_abi = Some(
+                    FloatAbi::from_name(value)
+                        .unwrap_or_else(|| panic!("Invalid -mfloat-abi value {}", value)),
+                );
+            }
+            Some(("eabi", value)) => {
+                target.eabi_version = match value {
+                    "gnu" => Some(0),
+                    "4" => Some(4),
+                    "5" => Some(5),
+                    _ => panic!("Invalid -meabi value {}", value),
+                };
+            }
+            None if option == "be8" => be8 = Some(true),
+            None if option == "be32" => be8 = Some(false),
+            _ => panic!("Unknown option -m{}", option),
+        }
+    }
+
+    // Big-endian code is BE8 from ARMv6 on, older cores only know BE32
+    let byte_order = match matches.get_one::<String>("endian").map(String::as_str) {
+        None | Some("L") => ByteOrder::Little,
+        Some("B") => {
+            let legacy = matches!(target.arch, Some(Arch::V4T | Arch::V5TE));
+            if be8.unwrap_or(!legacy) {
+                ByteOrder::Be8
+            } else {
+                ByteOrder::Be32
+            }
+        }
+        Some(value) => panic!("Unknown option -E{}", value),
+    };
+
+    let listing = match (
+        matches.get_one::<String>("listing"),
+        matches.get_one::<String>("listing_file"),
+    ) {
+        (Some(flags), _) => Some(
+            ListingOptions::from_flags(flags)
+                .unwrap_or_else(|| panic!("Invalid listing option -a{}", flags)),
+        ),
+        (None, Some(file)) => ListingOptions::from_flags(&format!("={}", file)),
+        (None, None) => None,
+    };
+
+    let format = matches
+        .get_one::<String>("format")
+        .map(|name| {
+            OutputFormat::from_name(name)
+                .unwrap_or_else(|| panic!("Unknown output format {}", name))
+        })
+        .unwrap_or_default();
+    let base = matches.get_one::<String>("base").map(|base| {
+        Number::new(base)
+            .unwrap_or_else(|| panic!("Invalid base address {}", base))
+            .value
+    });
+
+    // Like GNU as, without input files the source is the standard input
+    let mut inputs: Vec<String> = matches
+        .get_one::<String>("input")
+        .into_iter()
+        .chain(matches.get_many::<String>("inputs").unwrap_or_default())
+        .cloned()
+        .collect();
+    if inputs.is_empty() {
+        inputs.push("-".to_string());
+    }
+    let include_dirs: Vec<String> = matches
+        .get_many::<String>("include")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+    let source = Source::read(&inputs, &include_dirs);
+
+    let mut tokenizer = Tokenizer::new(Reader::from_source(&source.text));
+    for definition in matches.get_many::<String>("defsym").unwrap_or_default() {
+        let value = definition
+            .split_once('=')
+            .and_then(|(name, value)| Some((name, Number::new(value)?.value)));
+        match value {
+            Some((name, value)) if !name.is_empty() => tokenizer.define_symbol(name, value),
+            _ => panic!("Invalid --defsym {}, expected SYM=VALUE", definition),
+        }
+    }
+
+    let mut symbolizer = Symbolizer::new(tokenizer.clone(), implicit_it, target.clone());
+
+    symbolizer.set_output_format(format, base);
+    symbolizer.symbolize();
+
+    let mut assembler =
+        assembler::Assembler::new(tokenizer, symbolizer.symbol_table, implicit_it, target);
+    assembler.set_byte_order(byte_order);
+    assembler.set_output_format(format, base);
+    if let Some(options) = listing {
+        assembler.set_listing(Listing::new(options, source.name(), &source.text));
+    }
+    if matches.get_flag("debug") {
+        assembler.set_debug_info(source.clone());
+    }
+
+    assembler.assemble(output_file_name);
+
+    if let Some(depfile) = matches.get_one::<String>("dependencies") {
+        let object = output_file_name.map_or("a.out", String::as_str);
+        source.write_dependencies(depfile, object);
+    }
+}
 
-        let tokenizer = Tokenizer::new(reader);
+fn link(matches: &ArgMatches, output: Option<&String>) {
+    let mut script = None;
+    let (mut text, mut data) = (None, None);
+    for option in matches.get_many::<String>("script").unwrap_or_default() {
+        let address = |value: &str| {
+            Some(
+                Number::new(value)
+                    .unwrap_or_else(|| panic!("Invalid address {}", value))
+                    .value,
+            )
+        };
+        match option.split_once('=') {
+            Some(("text", value)) => text = address(value),
+            Some(("data", value)) => data = address(value),
+            _ => script = Some(LinkerScript::from_file(option)),
+        }
+    }
+
+    let script = match script {
+        Some(script) if text.is_none() && data.is_none() => script,
+        Some(_) => panic!("-Ttext and -Tdata can't be used with a linker script"),
+        None => LinkerScript::with_addresses(text, data),
+    };
+
+    let mut linker = Linker::new(script);
+    if let Some(entry) = matches.get_one::<String>("entry") {
+        linker.set_entry(entry);
+    }
+    for object in matches.get_many::<String>("inputs").unwrap_or_default() {
+        linker.add_object(object);
+    }
+
+    linker.link(output.map(String::as_str).unwrap_or("a.out"));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process};
+
+    use object::{Object, ObjectSection};
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> ArgMatches {
+        let args = args.iter().map(|arg| arg.to_string());
+        command().try_get_matches_from(listing_args(args)).unwrap()
+    }
+
+    fn values(matches: &ArgMatches, id: &str) -> Vec<String> {
+        matches
+            .get_many::<String>(id)
+            .unwrap_or_default()
+            .cloned()
+            .collect()
+    }
+
+    fn listing(matches: &ArgMatches) -> ListingOptions {
+        let flags = matches.get_one::<String>("listing").unwrap();
+        ListingOptions::from_flags(flags).unwrap()
+    }
+
+    #[test]
+    fn test_listing_args() {
+        let matches = parse(&["as", "-a", "foo.s", "-o", "foo.o"]);
+        assert_eq!(listing(&matches).file, None);
+        assert_eq!(values(&matches, "inputs"), ["foo.s"]);
+
+        let matches = parse(&["as", "-al=out.lst", "foo.s"]);
+        let options = listing(&matches);
+        assert!(options.assembly && !options.symbols);
+        assert_eq!(options.file.as_deref(), Some("out.lst"));
+        assert_eq!(values(&matches, "inputs"), ["foo.s"]);
+
+        let matches = parse(&["as", "-a=out.lst", "foo.s"]);
+        let options = listing(&matches);
+        assert!(options.assembly && options.symbols);
+        assert_eq!(options.file.as_deref(), Some("out.lst"));
+    }
 
-        let mut symbolizer = Symbolizer::new(tokenizer.clone());
+    #[test]
+    fn test_gas_command_line() {
+        let matches = parse(&[
+            "as",
+            "-march=armv7-a",
+            "-mfloat-abi=hard",
+            "-EL",
+            "-g",
+            "-I",
+            "include",
+            "--defsym",
+            "DEBUG=1",
+            "-W",
+            "--MD",
+            "obj.d",
+            "-ahls=obj.lst",
+            "-o",
+            "obj.o",
+            "a.s",
+            "-",
+        ]);
 
-        symbolizer.symbolize();
+        assert_eq!(
+            values(&matches, "machine"),
+            ["arch=armv7-a", "float-abi=hard"]
+        );
+        assert_eq!(values(&matches, "endian"), ["L"]);
+        assert!(matches.get_flag("debug") && matches.get_flag("no_warn"));
+        assert_eq!(values(&matches, "include"), ["include"]);
+        assert_eq!(values(&matches, "defsym"), ["DEBUG=1"]);
+        assert_eq!(values(&matches, "dependencies"), ["obj.d"]);
+        assert_eq!(values(&matches, "listing"), ["hls=obj.lst"]);
+        assert_eq!(values(&matches, "output"), ["obj.o"]);
+        assert_eq!(values(&matches, "inputs"), ["a.s", "-"]);
 
-        let mut assembler = assembler::Assembler::new(tokenizer, symbolizer.symbol_table);
+        // Sources without a section directive assemble into .text
+        let path =
+            |extension| env::temp_dir().join(format!("no_section_{}.{}", process::id(), extension));
+        let (input, output) = (path("s"), path("o"));
+        fs::write(&input, "start: b start\n").unwrap();
+        assemble(&parse(&[
+            "as",
+            "-march=armv7-a",
+            "-o",
+            output.to_str().unwrap(),
+            input.to_str().unwrap(),
+        ]));
 
-        assembler.assemble(output_file_name);
-    } else {
-        println!("No input file provided");
+        let object = fs::read(&output).unwrap();
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+        let file = object::File::parse(&*object).unwrap();
+        let text = file.section_by_name(".text").unwrap();
+        assert_eq!(text.data().unwrap(), [0xfe, 0xff, 0xff, 0xea]);
     }
 }
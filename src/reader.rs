@@ -1,29 +1,38 @@
-use std::{fs::File, os::unix::fs::FileExt};
+use std::{fs, rc::Rc};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Reader {
-    file: File,
-    position: u64,
-}
-
-impl Clone for Reader {
-    fn clone(&self) -> Self {
-        Reader {
-            file: self.file.try_clone().unwrap(),
-            position: self.position,
-        }
-    }
+    // Shared with the clones the symbolizer reads on its first pass
+    text: Rc<[u8]>,
+    position: usize,
+    // Newlines read so far
+    newlines: u32,
+    // 1-based number of the last line returned by consume_line
+    line_number: u32,
 }
 
 impl Reader {
     pub fn new(path: &str) -> Reader {
-        let file = File::open(path).expect("File not found");
+        Reader::from_source(&fs::read_to_string(path).expect("File not found"))
+    }
 
-        Reader { file, position: 0 }
+    pub fn from_source(text: &str) -> Reader {
+        Reader {
+            text: text.as_bytes().into(),
+            position: 0,
+            newlines: 0,
+            line_number: 0,
+        }
     }
 
     pub fn reset(&mut self) {
         self.position = 0;
+        self.newlines = 0;
+        self.line_number = 0;
+    }
+
+    pub fn line_number(&self) -> u32 {
+        self.line_number
     }
 
     pub fn is_eof(&self) -> bool {
@@ -41,6 +50,7 @@ impl Reader {
 
     pub fn consume_line(&mut self) -> String {
         self.consume_whitespace();
+        self.line_number = self.newlines + 1;
 
         let mut acc = String::new();
 
@@ -64,6 +74,9 @@ impl Reader {
     fn next_char(&mut self) -> Option<char> {
         let curr_char = self.read_at_position(self.position);
         self.position += 1;
+        if curr_char == Some('\n') {
+            self.newlines += 1;
+        }
 
         curr_char
     }
@@ -72,14 +85,10 @@ impl Reader {
         self.read_at_position(self.position)
     }
 
-    fn read_at_position(&self, position: u64) -> Option<char> {
-        let mut buf = [0; 1];
-        let result = self.file.read_at(&mut buf, position);
-        result.ok()?;
-
-        match buf[0] {
-            0 => None,
-            _ => Some(buf[0] as char),
+    fn read_at_position(&self, position: usize) -> Option<char> {
+        match self.text.get(position) {
+            None | Some(0) => None,
+            Some(byte) => Some(*byte as char),
         }
     }
 }
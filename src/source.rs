@@ -0,0 +1,171 @@
+// The text one run assembles: the input files concatenated like GNU as does, "-" reading
+// standard input, with each .include "file" line replaced by the file. Included files are
+// looked up next to the file including them, then in the -I directories.
+
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+pub const STDIN_NAME: &str = "{standard input}";
+
+// Deep enough for real sources, low enough to catch a file including itself
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub text: String,
+    // Every file read, inputs and includes, in the order they were first read
+    files: Vec<String>,
+    // File index and line in that file of each line of the text
+    lines: Vec<(usize, u32)>,
+}
+
+impl Source {
+    pub fn read(inputs: &[String], include_dirs: &[String]) -> Self {
+        let mut source = Source {
+            text: String::new(),
+            files: vec![],
+            lines: vec![],
+        };
+
+        for input in inputs {
+            if input == "-" {
+                let mut text = String::new();
+                io::stdin()
+                    .read_to_string(&mut text)
+                    .expect("Was not able to read the standard input");
+                source.add_file(STDIN_NAME, &text, include_dirs, 0);
+            } else {
+                let text = fs::read_to_string(input)
+                    .unwrap_or_else(|_| panic!("Can't open {} for reading", input));
+                source.add_file(input, &text, include_dirs, 0);
+            }
+        }
+
+        source
+    }
+
+    // The first input, which names the listing and the DWARF compile unit
+    pub fn name(&self) -> &str {
+        self.files.first().map_or(STDIN_NAME, String::as_str)
+    }
+
+    // File and line a 1-based line of the text came from
+    pub fn location(&self, line: u32) -> (&str, u32) {
+        match self.lines.get(line as usize - 1) {
+            Some((file, line)) => (&self.files[*file], *line),
+            None => (self.name(), line),
+        }
+    }
+
+    // Make rule naming every file read as a prerequisite of the object, for --MD
+    pub fn write_dependencies(&self, depfile: &str, object: &str) {
+        let escape = |name: &str| name.replace(' ', "\\ ");
+        let mut rule = escape(object) + ":";
+        for file in self.files.iter().filter(|file| *file != STDIN_NAME) {
+            rule.push_str(" \\\n  ");
+            rule.push_str(&escape(file));
+        }
+        rule.push('\n');
+
+        fs::write(depfile, rule).expect("Was not able to create dependency file");
+    }
+
+    fn add_file(&mut self, name: &str, text: &str, include_dirs: &[String], depth: usize) {
+        if depth > MAX_INCLUDE_DEPTH {
+            panic!("Too many nested .include, {} includes itself?", name);
+        }
+
+        let file = match self.files.iter().position(|file| file == name) {
+            Some(file) => file,
+            None => {
+                self.files.push(name.to_string());
+                self.files.len() - 1
+            }
+        };
+
+        for (index, line) in text.lines().enumerate() {
+            match include_directive(line) {
+                Some(included) => {
+                    let path = find_include(included, name, include_dirs).unwrap_or_else(|| {
+                        panic!(
+                            "Can't open {} for reading, included from {}",
+                            included, name
+                        )
+                    });
+                    let path = path.to_string_lossy().into_owned();
+                    let text = fs::read_to_string(&path).expect("File not found");
+                    self.add_file(&path, &text, include_dirs, depth + 1);
+                }
+                None => {
+                    self.text.push_str(line);
+                    self.text.push('\n');
+                    self.lines.push((file, index as u32 + 1));
+                }
+            }
+        }
+    }
+}
+
+// The quoted file name of a .include line
+fn include_directive(line: &str) -> Option<&str> {
+    let rest = line.trim().strip_prefix(".include")?;
+    let name = rest.trim().strip_prefix('"')?.split_once('"')?.0;
+    Some(name)
+}
+
+fn find_include(name: &str, including: &str, include_dirs: &[String]) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.is_absolute() {
+        return Some(path.to_path_buf()).filter(|path| path.is_file());
+    }
+
+    let including_dir = match including {
+        STDIN_NAME => Path::new("."),
+        file => Path::new(file).parent().unwrap_or(Path::new(".")),
+    };
+
+    std::iter::once(including_dir)
+        .chain(include_dirs.iter().map(Path::new))
+        .map(|dir| dir.join(path))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn test_concatenated_includes() {
+        let dir = env::temp_dir().join(format!("source_{}", process::id()));
+        let include_dir = dir.join("include");
+        fs::create_dir_all(&include_dir).unwrap();
+
+        let main = dir.join("main.s").to_string_lossy().into_owned();
+        let second = dir.join("second.s").to_string_lossy().into_owned();
+        let header = include_dir.join("header.s").to_string_lossy().into_owned();
+        fs::write(&main, "mov r0, #1\n  .include \"header.s\"\nmov r1, #2\n").unwrap();
+        fs::write(&second, "bx lr").unwrap();
+        fs::write(&header, ".word 3\n").unwrap();
+
+        let include_dirs = [include_dir.to_string_lossy().into_owned()];
+        let source = Source::read(&[main.clone(), second.clone()], &include_dirs);
+        assert_eq!(source.text, "mov r0, #1\n.word 3\nmov r1, #2\nbx lr\n");
+        assert_eq!(source.location(2), (header.as_str(), 1));
+        assert_eq!(source.location(3), (main.as_str(), 3));
+        assert_eq!(source.location(4), (second.as_str(), 1));
+
+        let depfile = dir.join("main.d").to_string_lossy().into_owned();
+        source.write_dependencies(&depfile, "main.o");
+        assert_eq!(
+            fs::read_to_string(&depfile).unwrap(),
+            format!("main.o: \\\n  {} \\\n  {} \\\n  {}\n", main, header, second)
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -35,7 +35,11 @@ impl Immediate {
 
         let number: u32 = match base {
             ImmediateBase::HEX => u32::from_str_radix(value.trim_start_matches("0x"), 16).ok()?,
-            ImmediateBase::DEC => (value.parse::<i32>().ok()?) as u32,
+            // negative values and unsigned ones past i32::MAX, like #4026531840
+            ImmediateBase::DEC => match value.parse::<i32>() {
+                Ok(number) => number as u32,
+                Err(_) => value.parse::<u32>().ok()?,
+            },
             ImmediateBase::OCT => u32::from_str_radix(value.trim_start_matches("0o"), 8).ok()?,
             ImmediateBase::BIN => u32::from_str_radix(value.trim_start_matches("0b"), 2).ok()?,
         };
@@ -1,10 +1,64 @@
-use super::instruction_name::InstructionName;
+use super::instruction_name::{FlagSuffix, InstructionName};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Instruction {
     pub value: InstructionName,
     pub set_flags: bool,
     pub condition: ConditionCode,
+    pub width: Option<Width>,
+    // vadd.f32, vcvt.f64.s32: the destination type comes first
+    pub data_type: Option<DataType>,
+    pub source_type: Option<DataType>,
+}
+
+// .n/.w qualifiers, used to force the size of a Thumb encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Narrow,
+    Wide,
+}
+
+// Data type qualifiers of the VFP instructions, with their size in bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    Float(u8),
+    Signed(u8),
+    Unsigned(u8),
+    Integer(u8),
+    Untyped(u8),
+}
+
+impl DataType {
+    pub fn from_name(name: &str) -> Option<DataType> {
+        let (kind, size) = match name.find(|c: char| c.is_ascii_digit()) {
+            Some(index) => name.split_at(index),
+            None => return None,
+        };
+
+        let size = match size.parse::<u8>().ok()? {
+            size @ (8 | 16 | 32 | 64) => size,
+            _ => return None,
+        };
+
+        match kind {
+            "f" if size >= 16 => Some(DataType::Float(size)),
+            "s" => Some(DataType::Signed(size)),
+            "u" => Some(DataType::Unsigned(size)),
+            "i" => Some(DataType::Integer(size)),
+            "" => Some(DataType::Untyped(size)),
+            _ => None,
+        }
+    }
+
+    pub fn size(&self) -> u8 {
+        match self {
+            DataType::Float(size)
+            | DataType::Signed(size)
+            | DataType::Unsigned(size)
+            | DataType::Integer(size)
+            | DataType::Untyped(size) => *size,
+        }
+    }
 }
 
 impl Instruction {
@@ -15,10 +69,17 @@ impl Instruction {
     ) -> Option<Instruction> {
         let value = InstructionName::from_name(operation)?;
 
-        let save_register = if matches!(value, InstructionName::CMP | InstructionName::CMN) {
-            true
-        } else {
-            matches!(set_flags, Some("s"))
+        let save_register = match set_flags {
+            None | Some("") => false,
+            Some("s") => true,
+            Some(_) => return None,
+        };
+
+        let set_flags = match value.flag_suffix() {
+            FlagSuffix::Implied => true,
+            FlagSuffix::Optional => save_register,
+            FlagSuffix::NotAllowed if save_register => return None,
+            FlagSuffix::NotAllowed => false,
         };
 
         let condition = match condition {
@@ -26,15 +87,109 @@ impl Instruction {
             None => ConditionCode::Al,
         };
 
+        if !value.is_conditional() && !matches!(condition, ConditionCode::Al) {
+            return None;
+        }
+
         Some(Instruction {
             value,
-            set_flags: save_register,
+            set_flags,
             condition,
+            width: None,
+            data_type: None,
+            source_type: None,
         })
     }
+
+    // Decodes a full mnemonic: base name, condition, S suffix, width and data type qualifiers.
+    // Both UAL (addseq, ldrbeq) and pre-UAL (addeqs, ldreqb) orderings are accepted.
+    pub fn from_mnemonic(literal: &str) -> Result<Instruction, String> {
+        let literal = literal.to_lowercase();
+
+        let mut qualifiers = literal.split('.');
+        let name = qualifiers.next().unwrap_or_default();
+
+        let mut width = None;
+        let mut data_types = vec![];
+        for qualifier in qualifiers {
+            match (qualifier, DataType::from_name(qualifier)) {
+                ("w", _) if width.is_none() => width = Some(Width::Wide),
+                ("n", _) if width.is_none() => width = Some(Width::Narrow),
+                (_, Some(data_type)) if data_types.len() < 2 => data_types.push(data_type),
+                _ => return Err(format!("Invalid qualifier .{} in {}", qualifier, literal)),
+            }
+        }
+
+        let mut longest_base = None;
+
+        // Longest base first, so that "bls" is only read as b + ls once bl + s fails
+        for split in (1..=name.len()).rev() {
+            let (base, suffix) = name.split_at(split);
+
+            if InstructionName::from_name(base).is_none() {
+                continue;
+            }
+            longest_base.get_or_insert(base);
+
+            let decoded = suffix_orderings(suffix)
+                .into_iter()
+                .find_map(|(set_flags, condition)| Instruction::new(base, set_flags, condition))
+                .or_else(|| decode_legacy_infix(base, suffix));
+
+            if let Some(mut instruction) = decoded {
+                if !data_types.is_empty() && !instruction.value.is_vfp() {
+                    return Err(format!("{} doesn't take a data type", base));
+                }
+
+                instruction.width = width;
+                instruction.data_type = data_types.first().copied();
+                instruction.source_type = data_types.get(1).copied();
+                return Ok(instruction);
+            }
+        }
+
+        match longest_base {
+            Some(base) => Err(format!(
+                "Invalid suffix '{}' for instruction {}",
+                &name[base.len()..],
+                base
+            )),
+            None => Err(format!("Unknown instruction {}", literal)),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+// Possible (set flags, condition) readings of the text after the base mnemonic
+fn suffix_orderings(suffix: &str) -> Vec<(Option<&str>, Option<&str>)> {
+    if !suffix.is_ascii() {
+        return vec![];
+    }
+
+    match suffix.len() {
+        0 => vec![(None, None)],
+        1 => vec![(Some(suffix), None)],
+        2 => vec![(None, Some(suffix))],
+        // UAL: adds<c>, pre-UAL: add<c>s
+        3 => vec![
+            (Some(&suffix[..1]), Some(&suffix[1..])),
+            (Some(&suffix[2..]), Some(&suffix[..2])),
+        ],
+        _ => vec![],
+    }
+}
+
+// Pre-UAL loads and stores put the condition between the base and the type: ldreqb, stmnefd
+fn decode_legacy_infix(base: &str, suffix: &str) -> Option<Instruction> {
+    if !matches!(base, "ldr" | "str" | "ldm" | "stm") || suffix.len() <= 2 || !suffix.is_ascii() {
+        return None;
+    }
+
+    let (condition, tail) = suffix.split_at(2);
+
+    Instruction::new(&format!("{}{}", base, tail), None, Some(condition))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConditionCode {
     Eq, // Equal
     Ne, // Not equal
@@ -58,8 +213,8 @@ impl ConditionCode {
         match s {
             "eq" => Some(Self::Eq),
             "ne" => Some(Self::Ne),
-            "cs" => Some(Self::Cs),
-            "cc" => Some(Self::Cc),
+            "cs" | "hs" => Some(Self::Cs),
+            "cc" | "lo" => Some(Self::Cc),
             "mi" => Some(Self::Mi),
             "pl" => Some(Self::Pl),
             "vs" => Some(Self::Vs),
@@ -96,6 +251,27 @@ impl ConditionCode {
         }
     }
 
+    // Opposite condition, used for the else slots of an IT block
+    pub fn inverse(&self) -> Option<ConditionCode> {
+        match self {
+            ConditionCode::Eq => Some(ConditionCode::Ne),
+            ConditionCode::Ne => Some(ConditionCode::Eq),
+            ConditionCode::Cs => Some(ConditionCode::Cc),
+            ConditionCode::Cc => Some(ConditionCode::Cs),
+            ConditionCode::Mi => Some(ConditionCode::Pl),
+            ConditionCode::Pl => Some(ConditionCode::Mi),
+            ConditionCode::Vs => Some(ConditionCode::Vc),
+            ConditionCode::Vc => Some(ConditionCode::Vs),
+            ConditionCode::Hi => Some(ConditionCode::Ls),
+            ConditionCode::Ls => Some(ConditionCode::Hi),
+            ConditionCode::Ge => Some(ConditionCode::Lt),
+            ConditionCode::Lt => Some(ConditionCode::Ge),
+            ConditionCode::Gt => Some(ConditionCode::Le),
+            ConditionCode::Le => Some(ConditionCode::Gt),
+            ConditionCode::Al => None,
+        }
+    }
+
     pub fn to_machine_code(&self) -> u32 {
         match self {
             ConditionCode::Eq => 0 << 28,
@@ -116,3 +292,61 @@ impl ConditionCode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(mnemonic: &str) -> (InstructionName, bool, ConditionCode) {
+        let istr = Instruction::from_mnemonic(mnemonic).expect("should decode");
+        (istr.value, istr.set_flags, istr.condition)
+    }
+
+    #[test]
+    fn test_ual_and_pre_ual_orderings() {
+        assert_eq!(
+            decode("subsne"),
+            (InstructionName::SUB, true, ConditionCode::Ne)
+        );
+        assert_eq!(
+            decode("subnes"),
+            (InstructionName::SUB, true, ConditionCode::Ne)
+        );
+        assert_eq!(
+            decode("ldrbeq"),
+            (InstructionName::LDRB, false, ConditionCode::Eq)
+        );
+        assert_eq!(
+            decode("ldreqb"),
+            (InstructionName::LDRB, false, ConditionCode::Eq)
+        );
+        assert_eq!(
+            decode("ldrhs"),
+            (InstructionName::LDR, false, ConditionCode::Cs)
+        );
+        assert_eq!(
+            decode("blo"),
+            (InstructionName::B, false, ConditionCode::Cc)
+        );
+        assert_eq!(
+            decode("bls"),
+            (InstructionName::B, false, ConditionCode::Ls)
+        );
+        assert_eq!(
+            decode("blt"),
+            (InstructionName::B, false, ConditionCode::Lt)
+        );
+        assert_eq!(
+            decode("tst"),
+            (InstructionName::TST, true, ConditionCode::Al)
+        );
+    }
+
+    #[test]
+    fn test_invalid_suffixes_are_rejected() {
+        assert!(Instruction::from_mnemonic("bs").is_err());
+        assert!(Instruction::from_mnemonic("pushs").is_err());
+        assert!(Instruction::from_mnemonic("dmbeq").is_err());
+        assert!(Instruction::from_mnemonic("add.x").is_err());
+    }
+}
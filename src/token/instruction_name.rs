@@ -1,21 +1,15 @@
-use regex::Regex;
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InstructionName {
     ADC,
-    ADCS,
     ADD,
-    ADDS,
     ADR,
+    ADRL,
     AND,
-    ANDS,
     ASR,
-    ASRS,
     B,
     BFC,
     BFI,
     BIC,
-    BICS,
     BKPT,
     BL,
     BLX,
@@ -23,6 +17,8 @@ pub enum InstructionName {
     BXJ,
     CBNZ,
     CBZ,
+    CDP,
+    CDP2,
     CLRBHB,
     CLREX,
     CLZ,
@@ -41,7 +37,6 @@ pub enum InstructionName {
     DMB,
     DSB,
     EOR,
-    EORS,
     ERET,
     ESB,
     HLT,
@@ -56,6 +51,9 @@ pub enum InstructionName {
     LDAEXH,
     LDAH,
     LDC,
+    LDC2,
+    LDCL,
+    LDC2L,
     LDM,
     LDMIA,
     LDMFD,
@@ -81,30 +79,26 @@ pub enum InstructionName {
     LDRSHT,
     LDRT,
     LSL,
-    LSLS,
     LSR,
-    LSRS,
     MCR,
+    MCR2,
     MCRR,
+    MCRR2,
     MLA,
-    MLAS,
     MLS,
     MOV,
-    MOVS,
     MOVT,
     MRC,
+    MRC2,
     MRRC,
+    MRRC2,
     MRS,
     MSR,
     MUL,
-    MULS,
     MVN,
-    MVNS,
     NOP,
     ORN,
-    ORNS,
     ORR,
-    ORRS,
     PKHBT,
     PKHTB,
     PLD,
@@ -133,19 +127,14 @@ pub enum InstructionName {
     RFEIA,
     RFEIB,
     ROR,
-    RORS,
     RRX,
-    RRXS,
     RSB,
-    RSBS,
     RSC,
-    RSCS,
     SADD16,
     SADD8,
     SASX,
     SB,
     SBC,
-    SBCS,
     SBFX,
     SDIV,
     SEL,
@@ -167,7 +156,6 @@ pub enum InstructionName {
     SMLAD,
     SMLADX,
     SMLAL,
-    SMLALS,
     SMLALBB,
     SMLALBT,
     SMLALTB,
@@ -193,7 +181,6 @@ pub enum InstructionName {
     SMULTB,
     SMULTT,
     SMULL,
-    SMULLS,
     SMULWB,
     SMULWT,
     SMUSD,
@@ -210,6 +197,9 @@ pub enum InstructionName {
     SSUB16,
     SSUB8,
     STC,
+    STC2,
+    STCL,
+    STC2L,
     STL,
     STLB,
     STLEX,
@@ -238,7 +228,6 @@ pub enum InstructionName {
     STRHT,
     STRT,
     SUB,
-    SUBS,
     SVC,
     SXTAB,
     SXTAB16,
@@ -265,9 +254,7 @@ pub enum InstructionName {
     UHSUB8,
     UMAAL,
     UMLAL,
-    UMLALS,
     UMULL,
-    UMULLS,
     UQADD16,
     UQADD8,
     UQASX,
@@ -287,30 +274,52 @@ pub enum InstructionName {
     UXTB,
     UXTB16,
     UXTH,
+    VABS,
+    VADD,
+    VCMP,
+    VCMPE,
+    VCVT,
+    VCVTR,
+    VDIV,
+    VLDM,
+    VLDMDB,
+    VLDMIA,
+    VLDR,
+    VMLA,
+    VMLS,
+    VMOV,
+    VMRS,
+    VMSR,
+    VMUL,
+    VNEG,
+    VNMLA,
+    VNMLS,
+    VNMUL,
+    VPOP,
+    VPUSH,
+    VSQRT,
+    VSTM,
+    VSTMDB,
+    VSTMIA,
+    VSTR,
+    VSUB,
     WFE,
     WFI,
     YIELD,
 }
-// Get ready for the most cursed Regex you will ever see
-// Basically all istrs + condition + save register
-// Do not try and modify this as you will not be able to :thumbs_up:
-pub fn get_istr_regex() -> regex::Regex {
-    Regex::new("^(adc|adcs|add|adds|adr|and|ands|asr|asrs|b|bfc|bfi|bic|bics|bkpt|bl|blx|bx|bxj|cbnz|cbz|clrbhb|clrex|clz|cmn|cmp|cps|cpsid|cpsie|crc32|crc32c|csdb|dbg|dcps1|dcps2|dcps3|dmb|dsb|eor|eors|eret|esb|hlt|hvc|isb|it|lda|ldab|ldaex|ldaexb|ldaexd|ldaexh|ldah|ldc|ldm|ldmia|ldmfd|ldmda|ldmfa|ldmdb|ldmea|ldmib|ldmed|ldr|ldrb|ldrbt|ldrd|ldrex|ldrexb|ldrexd|ldrexh|ldrh|ldrht|ldrsb|ldrsbt|ldrsh|ldrsht|ldrt|lsl|lsls|lsr|lsrs|mcr|mcrr|mla|mlas|mls|mov|movs|movt|mrc|mrrc|mrs|msr|mul|muls|mvn|mvns|nop|orn|orns|orr|orrs|pkhbt|pkhtb|pld|pldw|pli|pop|pssbb|push|qadd|qadd16|qadd8|qasx|qdadd|qdsub|qsax|qsub|qsub16|qsub8|rbit|rev|rev16|revsh|rfe|rfeda|rfedb|rfeia|rfeib|ror|rors|rrx|rrxs|rsb|rsbs|rsc|rscs|sadd16|sadd8|sasx|sb|sbc|sbcs|sbfx|sdiv|sel|setend|setpan|sev|sevl|shadd16|shadd8|shasx|shsax|shsub16|shsub8|smc|smlabb|smlabt|smlatb|smlatt|smlad|smladx|smlal|smlals|smlalbb|smlalbt|smlaltb|smlaltt|smlald|smlaldx|smlawb|smlawt|smlsd|smlsdx|smlsld|smlsldx|smmla|smmlar|smmls|smmlsr|smmul|smmulr|smuad|smuadx|smulbb|smulbt|smultb|smultt|smull|smulls|smulwb|smulwt|smusd|smusdx|srs|srsda|srsdb|srsia|srsib|ssat|ssat16|ssax|ssbb|ssub16|ssub8|stc|stl|stlb|stlex|stlexb|stlexd|stlexh|stlh|stm|stmia|stmea|stmda|stmed|stmdb|stmfd|stmib|stmfa|str|strb|strbt|strd|strex|strexb|strexd|strexh|strh|strht|strt|sub|subs|svc|sxtab|sxtab16|sxtah|sxtb|sxtb16|sxth|tbb|tbh|teq|tsb|tst|uadd16|uadd8|uasx|ubfx|udf|udiv|uhadd16|uhadd8|uhasx|uhsax|uhsub16|uhsub8|umaal|umlal|umlals|umull|umulls|uqadd16|uqadd8|uqasx|uqsax|uqsub16|uqsub8|usad8|usada8|usat|usat16|usax|usub16|usub8|uxtab|uxtab16|uxtah|uxtb|uxtb16|uxth|wfe|wfi|yield)(eq|ne|cs|hs|cc|lo|mi|pl|vs|vc|hi|ls|ge|lt|gt|le|al)?(s)?$").expect("the regex should always be valid")
-}
-
 impl InstructionName {
     pub fn from_name(name: &str) -> Option<InstructionName> {
         match name.to_lowercase().as_str() {
             "adc" => Some(InstructionName::ADC),
             "add" => Some(InstructionName::ADD),
             "adr" => Some(InstructionName::ADR),
+            "adrl" => Some(InstructionName::ADRL),
             "and" => Some(InstructionName::AND),
             "asr" => Some(InstructionName::ASR),
             "b" => Some(InstructionName::B),
             "bfc" => Some(InstructionName::BFC),
             "bfi" => Some(InstructionName::BFI),
             "bic" => Some(InstructionName::BIC),
-            "bics" => Some(InstructionName::BICS),
             "bkpt" => Some(InstructionName::BKPT),
             "bl" => Some(InstructionName::BL),
             "blx" => Some(InstructionName::BLX),
@@ -318,6 +327,8 @@ impl InstructionName {
             "bxj" => Some(InstructionName::BXJ),
             "cbnz" => Some(InstructionName::CBNZ),
             "cbz" => Some(InstructionName::CBZ),
+            "cdp" => Some(InstructionName::CDP),
+            "cdp2" => Some(InstructionName::CDP2),
             "clrbhb" => Some(InstructionName::CLRBHB),
             "clrex" => Some(InstructionName::CLREX),
             "clz" => Some(InstructionName::CLZ),
@@ -336,7 +347,6 @@ impl InstructionName {
             "dmb" => Some(InstructionName::DMB),
             "dsb" => Some(InstructionName::DSB),
             "eor" => Some(InstructionName::EOR),
-            "eors" => Some(InstructionName::EORS),
             "eret" => Some(InstructionName::ERET),
             "esb" => Some(InstructionName::ESB),
             "hlt" => Some(InstructionName::HLT),
@@ -351,6 +361,9 @@ impl InstructionName {
             "ldaexh" => Some(InstructionName::LDAEXH),
             "ldah" => Some(InstructionName::LDAH),
             "ldc" => Some(InstructionName::LDC),
+            "ldc2" => Some(InstructionName::LDC2),
+            "ldcl" => Some(InstructionName::LDCL),
+            "ldc2l" => Some(InstructionName::LDC2L),
             "ldm" => Some(InstructionName::LDM),
             "ldmia" => Some(InstructionName::LDMIA),
             "ldmfd" => Some(InstructionName::LDMFD),
@@ -378,26 +391,24 @@ impl InstructionName {
             "lsl" => Some(InstructionName::LSL),
             "lsr" => Some(InstructionName::LSR),
             "mcr" => Some(InstructionName::MCR),
+            "mcr2" => Some(InstructionName::MCR2),
             "mcrr" => Some(InstructionName::MCRR),
+            "mcrr2" => Some(InstructionName::MCRR2),
             "mla" => Some(InstructionName::MLA),
-            "mlas" => Some(InstructionName::MLAS),
             "mls" => Some(InstructionName::MLS),
             "mov" => Some(InstructionName::MOV),
-            "movs" => Some(InstructionName::MOVS),
             "movt" => Some(InstructionName::MOVT),
             "mrc" => Some(InstructionName::MRC),
+            "mrc2" => Some(InstructionName::MRC2),
             "mrrc" => Some(InstructionName::MRRC),
+            "mrrc2" => Some(InstructionName::MRRC2),
             "mrs" => Some(InstructionName::MRS),
             "msr" => Some(InstructionName::MSR),
             "mul" => Some(InstructionName::MUL),
-            "muls" => Some(InstructionName::MULS),
             "mvn" => Some(InstructionName::MVN),
-            "mvns" => Some(InstructionName::MVNS),
             "nop" => Some(InstructionName::NOP),
             "orn" => Some(InstructionName::ORN),
-            "orns" => Some(InstructionName::ORNS),
             "orr" => Some(InstructionName::ORR),
-            "orrs" => Some(InstructionName::ORRS),
             "pkhbt" => Some(InstructionName::PKHBT),
             "pkhtb" => Some(InstructionName::PKHTB),
             "pld" => Some(InstructionName::PLD),
@@ -426,19 +437,14 @@ impl InstructionName {
             "rfeia" => Some(InstructionName::RFEIA),
             "rfeib" => Some(InstructionName::RFEIB),
             "ror" => Some(InstructionName::ROR),
-            "rors" => Some(InstructionName::RORS),
             "rrx" => Some(InstructionName::RRX),
-            "rrxs" => Some(InstructionName::RRXS),
             "rsb" => Some(InstructionName::RSB),
-            "rsbs" => Some(InstructionName::RSBS),
             "rsc" => Some(InstructionName::RSC),
-            "rscs" => Some(InstructionName::RSCS),
             "sadd16" => Some(InstructionName::SADD16),
             "sadd8" => Some(InstructionName::SADD8),
             "sasx" => Some(InstructionName::SASX),
             "sb" => Some(InstructionName::SB),
             "sbc" => Some(InstructionName::SBC),
-            "sbcs" => Some(InstructionName::SBCS),
             "sbfx" => Some(InstructionName::SBFX),
             "sdiv" => Some(InstructionName::SDIV),
             "sel" => Some(InstructionName::SEL),
@@ -460,7 +466,6 @@ impl InstructionName {
             "smlad" => Some(InstructionName::SMLAD),
             "smladx" => Some(InstructionName::SMLADX),
             "smlal" => Some(InstructionName::SMLAL),
-            "smlals" => Some(InstructionName::SMLALS),
             "smlalbb" => Some(InstructionName::SMLALBB),
             "smlalbt" => Some(InstructionName::SMLALBT),
             "smlaltb" => Some(InstructionName::SMLALTB),
@@ -486,7 +491,6 @@ impl InstructionName {
             "smultb" => Some(InstructionName::SMULTB),
             "smultt" => Some(InstructionName::SMULTT),
             "smull" => Some(InstructionName::SMULL),
-            "smulls" => Some(InstructionName::SMULLS),
             "smulwb" => Some(InstructionName::SMULWB),
             "smulwt" => Some(InstructionName::SMULWT),
             "smusd" => Some(InstructionName::SMUSD),
@@ -503,6 +507,9 @@ impl InstructionName {
             "ssub16" => Some(InstructionName::SSUB16),
             "ssub8" => Some(InstructionName::SSUB8),
             "stc" => Some(InstructionName::STC),
+            "stc2" => Some(InstructionName::STC2),
+            "stcl" => Some(InstructionName::STCL),
+            "stc2l" => Some(InstructionName::STC2L),
             "stl" => Some(InstructionName::STL),
             "stlb" => Some(InstructionName::STLB),
             "stlex" => Some(InstructionName::STLEX),
@@ -531,7 +538,6 @@ impl InstructionName {
             "strht" => Some(InstructionName::STRHT),
             "strt" => Some(InstructionName::STRT),
             "sub" => Some(InstructionName::SUB),
-            "subs" => Some(InstructionName::SUBS),
             "svc" => Some(InstructionName::SVC),
             "sxtab" => Some(InstructionName::SXTAB),
             "sxtab16" => Some(InstructionName::SXTAB16),
@@ -558,9 +564,7 @@ impl InstructionName {
             "uhsub8" => Some(InstructionName::UHSUB8),
             "umaal" => Some(InstructionName::UMAAL),
             "umlal" => Some(InstructionName::UMLAL),
-            "umlals" => Some(InstructionName::UMLALS),
             "umull" => Some(InstructionName::UMULL),
-            "umulls" => Some(InstructionName::UMULLS),
             "uqadd16" => Some(InstructionName::UQADD16),
             "uqadd8" => Some(InstructionName::UQADD8),
             "uqasx" => Some(InstructionName::UQASX),
@@ -580,6 +584,35 @@ impl InstructionName {
             "uxtb" => Some(InstructionName::UXTB),
             "uxtb16" => Some(InstructionName::UXTB16),
             "uxth" => Some(InstructionName::UXTH),
+            "vabs" => Some(InstructionName::VABS),
+            "vadd" => Some(InstructionName::VADD),
+            "vcmp" => Some(InstructionName::VCMP),
+            "vcmpe" => Some(InstructionName::VCMPE),
+            "vcvt" => Some(InstructionName::VCVT),
+            "vcvtr" => Some(InstructionName::VCVTR),
+            "vdiv" => Some(InstructionName::VDIV),
+            "vldm" => Some(InstructionName::VLDM),
+            "vldmdb" => Some(InstructionName::VLDMDB),
+            "vldmia" => Some(InstructionName::VLDMIA),
+            "vldr" => Some(InstructionName::VLDR),
+            "vmla" => Some(InstructionName::VMLA),
+            "vmls" => Some(InstructionName::VMLS),
+            "vmov" => Some(InstructionName::VMOV),
+            "vmrs" => Some(InstructionName::VMRS),
+            "vmsr" => Some(InstructionName::VMSR),
+            "vmul" => Some(InstructionName::VMUL),
+            "vneg" => Some(InstructionName::VNEG),
+            "vnmla" => Some(InstructionName::VNMLA),
+            "vnmls" => Some(InstructionName::VNMLS),
+            "vnmul" => Some(InstructionName::VNMUL),
+            "vpop" => Some(InstructionName::VPOP),
+            "vpush" => Some(InstructionName::VPUSH),
+            "vsqrt" => Some(InstructionName::VSQRT),
+            "vstm" => Some(InstructionName::VSTM),
+            "vstmdb" => Some(InstructionName::VSTMDB),
+            "vstmia" => Some(InstructionName::VSTMIA),
+            "vstr" => Some(InstructionName::VSTR),
+            "vsub" => Some(InstructionName::VSUB),
             "wfe" => Some(InstructionName::WFE),
             "wfi" => Some(InstructionName::WFI),
             "yield" => Some(InstructionName::YIELD),
@@ -587,3 +620,116 @@ impl InstructionName {
         }
     }
 }
+
+// How an instruction treats the S (update flags) suffix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagSuffix {
+    NotAllowed,
+    Optional,
+    // The flags are always updated, "s" is accepted for compatibility (cmps, tsts...)
+    Implied,
+}
+
+impl InstructionName {
+    pub fn flag_suffix(&self) -> FlagSuffix {
+        use InstructionName::*;
+        match self {
+            ADC | ADD | AND | ASR | BIC | EOR | LSL | LSR | MLA | MOV | MUL | MVN | ORN | ORR
+            | ROR | RRX | RSB | RSC | SBC | SMLAL | SMULL | SUB | UMLAL | UMULL => {
+                FlagSuffix::Optional
+            }
+            CMN | CMP | TEQ | TST => FlagSuffix::Implied,
+            _ => FlagSuffix::NotAllowed,
+        }
+    }
+
+    // Instructions that only exist in the unconditional encoding space
+    pub fn is_conditional(&self) -> bool {
+        use InstructionName::*;
+        !matches!(
+            self,
+            BKPT | CBNZ
+                | CBZ
+                | CDP2
+                | CLRBHB
+                | CLREX
+                | CPS
+                | CPSID
+                | CPSIE
+                | CRC32
+                | CRC32C
+                | DCPS1
+                | DCPS2
+                | DCPS3
+                | DMB
+                | DSB
+                | HLT
+                | HVC
+                | ISB
+                | IT
+                | LDC2
+                | LDC2L
+                | MCR2
+                | MCRR2
+                | MRC2
+                | MRRC2
+                | PLD
+                | PLDW
+                | PLI
+                | PSSBB
+                | RFE
+                | RFEDA
+                | RFEDB
+                | RFEIA
+                | RFEIB
+                | SB
+                | SETEND
+                | SETPAN
+                | SRS
+                | SRSDA
+                | SRSDB
+                | SRSIA
+                | SRSIB
+                | SSBB
+                | STC2
+                | STC2L
+                | UDF
+        )
+    }
+
+    // Floating point instructions, they take .f32/.f64 style data type qualifiers
+    pub fn is_vfp(&self) -> bool {
+        use InstructionName::*;
+        matches!(
+            self,
+            VABS | VADD
+                | VCMP
+                | VCMPE
+                | VCVT
+                | VCVTR
+                | VDIV
+                | VLDM
+                | VLDMDB
+                | VLDMIA
+                | VLDR
+                | VMLA
+                | VMLS
+                | VMOV
+                | VMRS
+                | VMSR
+                | VMUL
+                | VNEG
+                | VNMLA
+                | VNMLS
+                | VNMUL
+                | VPOP
+                | VPUSH
+                | VSQRT
+                | VSTM
+                | VSTMDB
+                | VSTMIA
+                | VSTR
+                | VSUB
+        )
+    }
+}
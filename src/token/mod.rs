@@ -1,6 +1,10 @@
 use immediate::ImmediateBase;
 
-use self::{immediate::Immediate, instruction::Instruction, register::Register};
+use self::{
+    immediate::Immediate,
+    instruction::Instruction,
+    register::{FpRegister, Register, StatusRegister},
+};
 
 pub mod immediate;
 pub mod instruction;
@@ -10,12 +14,20 @@ pub mod register;
 #[derive(Debug)]
 pub enum Token {
     REGISTER(Register),
+    FPREGISTER(FpRegister),
+    COPROCESSOR(u8),
+    CPREGISTER(u8),
+    STATUSREGISTER(StatusRegister),
     INSTRUCTION(Instruction),
     IMMEDIATE(Immediate),
+    // vmov.f32 s0, #0.5
+    FLOATIMMEDIATE(f64),
     LABEL(Label),
     LABELREF(String),
     DIRECTIVE(Directive),
     NUMBER(Number),
+    // Operand keywords that only mean something to one instruction: dmb ish, cpsie if
+    OPTION(String),
     MINUS,
     LPAREN,
     RPAREN,
@@ -24,6 +36,7 @@ pub enum Token {
     EQUAL,
     COMMA,
     BANG,
+    CARET,
     ILLEGAL,
     EOF,
 }
@@ -73,7 +86,7 @@ pub struct Number {
 
 impl Number {
     pub fn new(value: &str) -> Option<Self> {
-        let base = determine_base(&value)?;
+        let base = determine_base(value)?;
 
         let number: u32 = match base {
             ImmediateBase::HEX => u32::from_str_radix(value.trim_start_matches("0x"), 16).ok()?,
@@ -81,3 +81,150 @@ impl Register {
         RegisterNumbers::from_num(num as u32).map(|register| Register { register })
     }
 }
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StatusRegisterName {
+    CPSR,
+    SPSR,
+}
+
+// cpsr/spsr/apsr, optionally with the fields written by msr (cpsr_fc, apsr_nzcvq)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StatusRegister {
+    pub register: StatusRegisterName,
+    pub fields: Option<u8>,
+}
+
+impl StatusRegister {
+    pub fn from_name(name: &str) -> Option<StatusRegister> {
+        let name = name.to_lowercase();
+        let (register, fields) = match name.split_once('_') {
+            Some((register, fields)) => (register, Some(fields)),
+            None => (name.as_str(), None),
+        };
+
+        let (register, fields) = match register {
+            "cpsr" => (StatusRegisterName::CPSR, fields.map(parse_psr_fields)),
+            "spsr" => (StatusRegisterName::SPSR, fields.map(parse_psr_fields)),
+            "apsr" => (StatusRegisterName::CPSR, fields.map(parse_apsr_fields)),
+            _ => return None,
+        };
+
+        let fields = match fields {
+            Some(fields) => Some(fields?),
+            None => None,
+        };
+
+        Some(StatusRegister { register, fields })
+    }
+
+    // R bit of mrs/msr
+    pub fn to_machine_code(&self) -> u32 {
+        match self.register {
+            StatusRegisterName::CPSR => 0,
+            StatusRegisterName::SPSR => 1 << 22,
+        }
+    }
+
+    // Field mask of msr, a bare cpsr/spsr means the control and flags fields
+    pub fn field_mask(&self) -> u32 {
+        self.fields.unwrap_or(0b1001) as u32
+    }
+}
+
+// c = control, x = extension, s = status, f = flags
+fn parse_psr_fields(fields: &str) -> Option<u8> {
+    let mut mask = 0;
+    for field in fields.chars() {
+        let bit = match field {
+            'c' => 0b0001,
+            'x' => 0b0010,
+            's' => 0b0100,
+            'f' => 0b1000,
+            _ => return None,
+        };
+
+        if mask & bit != 0 {
+            return None;
+        }
+        mask |= bit;
+    }
+
+    Some(mask)
+}
+
+fn parse_apsr_fields(fields: &str) -> Option<u8> {
+    match fields {
+        "nzcvq" => Some(0b1000),
+        "g" => Some(0b0100),
+        "nzcvqg" => Some(0b1100),
+        _ => None,
+    }
+}
+
+// VFP/NEON registers. The single precision registers overlap the doubles (s0/s1 are d0),
+// and each quad register is a pair of doubles (q1 is d2/d3).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FpRegister {
+    Single(u8),
+    Double(u8),
+    Quad(u8),
+    Fpscr,
+}
+
+impl FpRegister {
+    pub fn from_name(name: &str) -> Option<FpRegister> {
+        let name = name.to_lowercase();
+        if name == "fpscr" {
+            return Some(FpRegister::Fpscr);
+        }
+
+        let (kind, number) = name.split_at_checked(1)?;
+        if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let number = number.parse::<u8>().ok()?;
+
+        match kind {
+            "s" if number < 32 => Some(FpRegister::Single(number)),
+            "d" if number < 32 => Some(FpRegister::Double(number)),
+            "q" if number < 16 => Some(FpRegister::Quad(number)),
+            _ => None,
+        }
+    }
+
+    pub fn is_double(&self) -> bool {
+        matches!(self, FpRegister::Double(_))
+    }
+
+    // Register number as encoded, quads go by their first double
+    pub fn to_num(&self) -> u8 {
+        match self {
+            FpRegister::Single(number) | FpRegister::Double(number) => *number,
+            FpRegister::Quad(number) => number * 2,
+            FpRegister::Fpscr => panic!("fpscr is only accessed with vmrs and vmsr"),
+        }
+    }
+
+    // Splits the register number into the 4-bit field and the extra bit (D, N or M).
+    // Singles keep the low bit apart, doubles the high one.
+    pub fn split(&self) -> (u32, u32) {
+        let number = self.to_num() as u32;
+        match self {
+            FpRegister::Single(_) => (number >> 1, number & 1),
+            _ => (number & 0xf, number >> 4),
+        }
+    }
+}
+
+// p0-p15 and c0-c15, the coprocessors and their registers: mcr p15, 0, r0, c7, c5, 0
+pub fn coprocessor_from_name(name: &str, prefix: char) -> Option<u8> {
+    let name = name.to_lowercase();
+    let number = name.strip_prefix(prefix)?;
+
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    number.parse::<u8>().ok().filter(|number| *number < 16)
+}
@@ -1,3 +1,5 @@
+use std::{collections::BTreeMap, sync::OnceLock};
+
 use regex::Regex;
 
 use crate::{
@@ -5,8 +7,8 @@ use crate::{
     token::{
         immediate::Immediate,
         instruction::Instruction,
-        instruction_name::get_istr_regex,
-        register::{Register, RegisterNumbers},
+        instruction_name::InstructionName,
+        register::{coprocessor_from_name, FpRegister, Register, RegisterNumbers, StatusRegister},
         Directive, Label, Number, Token,
     },
 };
@@ -14,29 +16,70 @@ use crate::{
 #[derive(Debug, Clone)]
 pub struct Tokenizer {
     reader: Reader,
+    // Absolute symbols of --defsym, read as their value wherever they are referenced
+    symbols: BTreeMap<String, u32>,
 }
 
 impl Tokenizer {
     pub fn new(reader: Reader) -> Tokenizer {
-        Tokenizer { reader }
+        Tokenizer {
+            reader,
+            symbols: BTreeMap::new(),
+        }
+    }
+
+    pub fn define_symbol(&mut self, name: &str, value: u32) {
+        self.symbols.insert(name.to_string(), value);
+    }
+
+    pub fn defined_symbols(&self) -> impl Iterator<Item = (&String, &u32)> {
+        self.symbols.iter()
     }
 
     pub fn is_eof(&self) -> bool {
         self.reader.is_eof()
     }
 
+    // Source line of the tokens last returned by consume_line
+    pub fn line_number(&self) -> u32 {
+        self.reader.line_number()
+    }
+
     pub fn consume_line(&mut self) -> Vec<Token> {
         let line = self.reader.consume_line();
 
+        if let Some(tokens) = name_directive(&line) {
+            return tokens;
+        }
+
+        if let Some(tokens) = eabi_attribute(&line) {
+            return tokens;
+        }
+
         let literals = self.split_at_separators(&line);
 
         let mut line_tokens: Vec<Token> = vec![];
 
         for literal in literals {
+            // it, itt, ite...: the then/else pattern is an operand of the IT instruction
+            let is_mnemonic = line_tokens
+                .iter()
+                .all(|token| matches!(token, Token::LABEL(_)));
+
+            if let Some(pattern) = it_pattern(&literal).filter(|_| is_mnemonic) {
+                let it = Instruction::new("it", None, None).unwrap();
+                line_tokens.push(Token::INSTRUCTION(it));
+                line_tokens.push(Token::OPTION(pattern));
+                continue;
+            }
+
             let token = self.create_token_from_literal(Some(literal));
             line_tokens.push(token);
         }
 
+        check_mnemonic(&line_tokens);
+        mark_options(&mut line_tokens);
+
         line_tokens
     }
 
@@ -45,7 +88,8 @@ impl Tokenizer {
     }
 
     fn split_at_separators(&self, line: &str) -> Vec<String> {
-        let re = Regex::new(r"(r\d+)|(\{|\})|(\[|\])|(-)|(!)|(=)|(\.[a-zA-Z]+)|(#0x\d+|#0b\d+|#0d\d+|#-?\d+)|(0x\d+|0b\d+|0d\d+|-?\d+)|([a-zA-Z\_\-]+:)|([a-zA-Z\_\-]+)").unwrap();
+        static RE: OnceLock<Regex> = OnceLock::new();
+        let re = RE.get_or_init(|| Regex::new(r"(r\d+)|(\{|\})|(\[|\])|(-)|(!)|(\^)|(=)|(\.[a-zA-Z_][a-zA-Z0-9_]*)|(#0x[0-9a-fA-F]+|#0b[01]+|#-?\d+\.\d+(?:[eE][-+]?\d+)?|#-?\d+|#[a-zA-Z_][a-zA-Z0-9_]*)|(0x[0-9a-fA-F]+|0b[01]+|-?\d+)|([a-zA-Z_][a-zA-Z0-9_]*:)|([a-zA-Z_][a-zA-Z0-9_]*(?:\.[a-zA-Z0-9]+)*)").expect("regex should be valid"));
         let matches: Vec<String> = re
             .captures_iter(line)
             .filter_map(|caps| {
@@ -72,6 +116,10 @@ impl Tokenizer {
             return Token::BANG;
         }
 
+        if literal == "^" {
+            return Token::CARET;
+        }
+
         if literal == "[" {
             return Token::LPAREN;
         }
@@ -109,6 +157,27 @@ impl Tokenizer {
             return Token::ILLEGAL;
         }
 
+        // #symbol is the value of a defined symbol, other names keep reading as references
+        if let Some(name) = literal
+            .strip_prefix('#')
+            .filter(|name| is_symbol_name(name))
+        {
+            return match self.symbols.get(name) {
+                Some(value) => match Immediate::new((*value as i32).to_string()) {
+                    Some(immediate) => Token::IMMEDIATE(immediate),
+                    None => Token::ILLEGAL,
+                },
+                None => self.create_token_from_literal(Some(name.to_string())),
+            };
+        }
+
+        if literal.starts_with('#') && literal.contains('.') {
+            return match literal[1..].parse::<f64>() {
+                Ok(value) => Token::FLOATIMMEDIATE(value),
+                Err(_) => Token::ILLEGAL,
+            };
+        }
+
         if literal.starts_with('#') {
             let immediate = Immediate::new(literal.chars().skip(1).collect::<String>());
 
@@ -129,45 +198,157 @@ impl Tokenizer {
             return reg_from_literal(&literal);
         }
 
+        if let Some(fp_register) = FpRegister::from_name(&literal) {
+            return Token::FPREGISTER(fp_register);
+        }
+
+        if let Some(coprocessor) = coprocessor_from_name(&literal, 'p') {
+            return Token::COPROCESSOR(coprocessor);
+        }
+
+        if let Some(register) = coprocessor_from_name(&literal, 'c') {
+            return Token::CPREGISTER(register);
+        }
+
+        if let Some(status_register) = StatusRegister::from_name(&literal) {
+            return Token::STATUSREGISTER(status_register);
+        }
+
         if literal.ends_with(':') {
             return Token::LABEL(Label::new(
                 literal.chars().take(literal.len() - 1).collect::<String>(),
             ));
         }
 
-        let istr_regex = get_istr_regex();
+        if let Ok(instruction) = Instruction::from_mnemonic(&literal) {
+            return Token::INSTRUCTION(instruction);
+        }
 
-        if istr_regex.is_match(&literal) {
-            let istr = istr_regex
-                .captures(&literal)
-                .expect("if match should be able to capture");
+        if let Some(value) = self.symbols.get(&literal) {
+            return Token::NUMBER(Number { value: *value });
+        }
 
-            let operation = istr
-                .get(1)
-                .expect("if captured should be able to get the first")
-                .as_str();
+        if literal.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Token::LABELREF(literal);
+        }
 
-            let condition = istr.get(2).map(|condition| condition.as_str());
-            let save_register = istr.get(3).map(|save_reg| save_reg.as_str());
+        Token::ILLEGAL
+    }
+}
 
-            let instruction_token = Instruction::new(operation, save_register, condition);
+fn is_symbol_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
 
-            if let Some(instruction) = instruction_token {
-                return Token::INSTRUCTION(instruction);
-            } else {
-                return Token::ILLEGAL;
-            }
-        }
+// Whatever follows the labels of a line must be a directive or a valid instruction
+fn check_mnemonic(tokens: &[Token]) {
+    let first = tokens
+        .iter()
+        .find(|token| !matches!(token, Token::LABEL(_)));
 
-        if literal
-            .chars()
-            .all(|c| c.is_alphabetic() || c == '_' || c == '-')
-        {
-            return Token::LABELREF(literal);
+    if let Some(Token::LABELREF(name)) = first {
+        if let Err(error) = Instruction::from_mnemonic(name) {
+            panic!("{}", error);
         }
+    }
+}
 
-        Token::ILLEGAL
+// Barrier options, cps flags and apsr_nzcv read like label references, but aren't
+fn mark_options(tokens: &mut [Token]) {
+    let index = tokens
+        .iter()
+        .position(|token| matches!(token, Token::INSTRUCTION(_)));
+
+    let index = match index {
+        Some(index) => index,
+        None => return,
+    };
+
+    let takes_options = match &tokens[index] {
+        Token::INSTRUCTION(istr) => matches!(
+            istr.value,
+            InstructionName::DMB
+                | InstructionName::DSB
+                | InstructionName::ISB
+                | InstructionName::CPS
+                | InstructionName::CPSIE
+                | InstructionName::CPSID
+                | InstructionName::IT
+                | InstructionName::VMRS
+                | InstructionName::MRC
+                | InstructionName::MRC2
+        ),
+        _ => false,
+    };
+
+    if !takes_options {
+        return;
     }
+
+    for token in tokens[index + 1..].iter_mut() {
+        if let Token::LABELREF(option) = token {
+            *token = Token::OPTION(option.to_lowercase());
+        }
+    }
+}
+
+// .fpu and .arch take names like vfpv3-d16 that don't split into tokens, the name is kept whole
+fn name_directive(line: &str) -> Option<Vec<Token>> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| {
+        Regex::new(r"^\s*(\.fpu|\.arch_extension|\.arch|\.cpu)\s+([a-zA-Z0-9_.+-]+)\s*$")
+            .expect("regex should be valid")
+    });
+
+    re.captures(line).map(|captures| {
+        vec![
+            Token::DIRECTIVE(Directive::new(captures[1].to_string())),
+            Token::OPTION(captures[2].to_lowercase()),
+        ]
+    })
+}
+
+// .eabi_attribute tag, value: the tag is a name or number, the value a number or a string
+fn eabi_attribute(line: &str) -> Option<Vec<Token>> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| {
+        Regex::new(r#"^\s*(\.eabi_attribute)\s+(\w+)\s*,\s*(?:"([^"]*)"|(\w+))\s*(?:@.*)?$"#)
+            .expect("regex should be valid")
+    });
+
+    re.captures(line).map(|captures| {
+        let tag = match Number::new(&captures[2]) {
+            Some(number) if captures[2].starts_with(|c: char| c.is_ascii_digit()) => {
+                Token::NUMBER(number)
+            }
+            _ => Token::OPTION(captures[2].to_lowercase()),
+        };
+
+        let value = match (captures.get(3), captures.get(4)) {
+            (Some(text), _) => Token::OPTION(text.as_str().to_string()),
+            (_, Some(number)) => Number::new(number.as_str())
+                .map(Token::NUMBER)
+                .unwrap_or_else(|| panic!("Invalid .eabi_attribute value {}", number.as_str())),
+            _ => Token::ILLEGAL,
+        };
+
+        vec![
+            Token::DIRECTIVE(Directive::new(captures[1].to_string())),
+            tag,
+            Token::COMMA,
+            value,
+        ]
+    })
+}
+
+fn it_pattern(literal: &str) -> Option<String> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re =
+        RE.get_or_init(|| Regex::new(r"^[iI][tT]([tTeE]{0,3})$").expect("regex should be valid"));
+
+    re.captures(literal)
+        .map(|captures| captures[1].to_lowercase())
 }
 
 fn reg_from_literal(literal: &str) -> Token {
@@ -204,7 +385,8 @@ fn parse_special_reg(str: &str) -> Option<RegisterNumbers> {
 }
 
 fn parse_regex_number(reg_num: &str) -> Option<u8> {
-    let re = Regex::new(r"^r(\d{1,2})$").expect("regex should be valid");
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| Regex::new(r"^r(\d{1,2})$").expect("regex should be valid"));
 
     let capture = re.captures(reg_num).unwrap();
     let capture = capture.get(1).unwrap().as_str().parse::<u8>().unwrap();
@@ -224,6 +406,9 @@ fn is_special_reg(str: &str) -> bool {
 }
 
 fn is_number(str: &str) -> bool {
-    let re = Regex::new(r"^(0x\d+|0b\d+|0d\d+|-?\d+)$").expect("regex should be valid");
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| {
+        Regex::new(r"^(0x[0-9a-fA-F]+|0b[01]+|-?\d+)$").expect("regex should be valid")
+    });
     re.is_match(str)
 }
@@ -1,12 +1,42 @@
+use std::cell::Cell;
+
 pub fn negate_u32(value: u32) -> u32 {
     // Convert to i32, negate, then convert back to u32
     let negated = -(value as i32);
     negated as u32
 }
 
-pub fn debug_u32(num: u32) {
-    for i in (0..32).rev() {
-        print!("{}", (num >> i) & 1);
+// -W silences the warnings and --fatal-warnings turns them into errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningMode {
+    Print,
+    Silent,
+    Fatal,
+}
+
+thread_local! {
+    static WARNINGS_ENABLED: Cell<bool> = const { Cell::new(true) };
+    static WARNING_MODE: Cell<WarningMode> = const { Cell::new(WarningMode::Print) };
+}
+
+pub fn set_warning_mode(mode: WarningMode) {
+    WARNING_MODE.with(|cell| cell.set(mode));
+}
+
+// The symbolizer parses every line before the assembler does, it silences the warnings
+// so they are only printed once
+pub fn set_warnings_enabled(enabled: bool) {
+    WARNINGS_ENABLED.with(|cell| cell.set(enabled));
+}
+
+pub fn warning(message: &str) {
+    if !WARNINGS_ENABLED.with(|cell| cell.get()) {
+        return;
+    }
+
+    match WARNING_MODE.with(|cell| cell.get()) {
+        WarningMode::Print => eprintln!("Warning: {}", message),
+        WarningMode::Silent => {}
+        WarningMode::Fatal => panic!("Error: {}", message),
     }
-    println!();
 }